@@ -0,0 +1,98 @@
+//! `--bundle`: one zip holding everything about a run, so other tools can take
+//! the flattened context, what went into it, and how it was made as a unit.
+//!
+//! Member names are fixed; the manifest's `bundle_version` changes if they do.
+
+use crate::config::Config;
+use crate::config_file;
+use crate::export::entry_name;
+use crate::manifest;
+use crate::output::Statistics;
+use crate::overrides;
+use crate::report::Decision;
+use crate::schema::SCHEMA_VERSION;
+use crate::tree;
+use anyhow::{Context, Result};
+use serde_json::json;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+
+/// Version of the bundle layout (member names and their contents)
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// The flattened output, in whichever `--format` the run used
+pub const CONTEXT_MEMBER: &str = "context.txt";
+/// What flat decided for every file it saw, plus the run summary
+pub const MANIFEST_MEMBER: &str = "manifest.json";
+/// Directory tree of the files written to the context
+pub const TREE_MEMBER: &str = "tree.txt";
+/// The resolved command line and the project config files that shaped the run
+pub const CONFIG_MEMBER: &str = "config.json";
+
+/// Write the bundle for a finished run whose output was `context`
+pub fn write_bundle(
+    path: &Path,
+    config: &Config,
+    context: &[u8],
+    stats: &Statistics,
+) -> Result<()> {
+    let file = fs::File::create(path)
+        .with_context(|| format!("Failed to create bundle: {}", path.display()))?;
+    let mut zip = zip::ZipWriter::new(file);
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    let members = [
+        (CONTEXT_MEMBER, context.to_vec()),
+        (MANIFEST_MEMBER, manifest(config, stats)?.into_bytes()),
+        (TREE_MEMBER, tree_of_written(config, stats).into_bytes()),
+        (CONFIG_MEMBER, config_used(config)?.into_bytes()),
+    ];
+    for (name, bytes) in members {
+        zip.start_file(name, options)?;
+        zip.write_all(&bytes)?;
+    }
+    zip.finish()
+        .with_context(|| format!("Failed to write bundle: {}", path.display()))?;
+    Ok(())
+}
+
+fn manifest(config: &Config, stats: &Statistics) -> Result<String> {
+    let mut manifest = manifest::manifest(config, stats)?;
+    manifest["bundle_version"] = BUNDLE_VERSION.into();
+    manifest["members"] = json!({
+        "context": CONTEXT_MEMBER,
+        "manifest": MANIFEST_MEMBER,
+        "tree": TREE_MEMBER,
+        "config": CONFIG_MEMBER,
+    });
+    Ok(serde_json::to_string_pretty(&manifest)?)
+}
+
+fn config_used(config: &Config) -> Result<String> {
+    let read = |found: Option<std::path::PathBuf>| found.and_then(|p| fs::read_to_string(p).ok());
+    let used = json!({
+        "flat_schema": SCHEMA_VERSION,
+        "command": config.to_command_line(),
+        config_file::CONFIG_FILE_NAME: read(config_file::discover(&config.path)),
+        overrides::OVERRIDES_FILE_NAME: read(overrides::discover(&config.path)),
+    });
+    Ok(serde_json::to_string_pretty(&used)?)
+}
+
+/// Directory tree of the files that made it into the output
+fn tree_of_written(config: &Config, stats: &Statistics) -> String {
+    let files: Vec<(String, Option<String>)> = stats
+        .file_reports
+        .iter()
+        .filter(|r| {
+            matches!(
+                r.decision,
+                Decision::Full | Decision::Compressed | Decision::Truncated
+            )
+        })
+        .map(|r| (entry_name(&r.path, &config.path), None))
+        .collect();
+    tree::render(&files)
+}
@@ -0,0 +1,277 @@
+//! On-disk cache of compressed file contents for `--cache`.
+//!
+//! Entries live under `<cache dir>/compress/<flat version>/`, named by a hash of
+//! everything that shapes the compressed text: the grammar fingerprint, the
+//! language and its keep/drop rules, whether the file is a generated stub, and
+//! the source itself. An
+//! upgrade of flat or of a grammar therefore never reads an old entry; the old
+//! version directories linger until `flat cache clear`, and `flat cache stats`
+//! reports them as stale.
+
+use crate::compress::{grammar_fingerprint, CompressLanguage, CompressLevel, NodeRules};
+use crate::filters::is_generated_stub;
+use crate::lock::{acquire, LockMode};
+use anyhow::{Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// The flat version entries are written under
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Default cache location: `$FLAT_CACHE_DIR`, else `$XDG_CACHE_HOME/flat`,
+/// else `~/.cache/flat`
+pub fn default_dir() -> Option<PathBuf> {
+    let env_dir = |name: &str| std::env::var_os(name).filter(|v| !v.is_empty());
+    if let Some(dir) = env_dir("FLAT_CACHE_DIR") {
+        return Some(PathBuf::from(dir));
+    }
+    if let Some(dir) = env_dir("XDG_CACHE_HOME") {
+        return Some(PathBuf::from(dir).join("flat"));
+    }
+    env_dir("HOME").map(|home| PathBuf::from(home).join(".cache").join("flat"))
+}
+
+/// Lock a cache root: runs using the cache hold it shared, `flat cache clear`
+/// exclusively, so a clear never pulls entries out from under a run
+pub fn lock(root: &Path, mode: LockMode, timeout: Duration) -> Result<File> {
+    fs::create_dir_all(root)
+        .with_context(|| format!("Failed to create cache directory: {}", root.display()))?;
+    acquire(&root.join(".lock"), mode, timeout)
+}
+
+/// Compressed-output cache for the running flat version
+pub struct CompressionCache {
+    dir: PathBuf,
+}
+
+impl CompressionCache {
+    /// Cache rooted at `root` (e.g. from [`default_dir`])
+    pub fn new(root: &Path) -> Self {
+        Self {
+            dir: root.join("compress").join(VERSION),
+        }
+    }
+
+    /// Previously compressed output for this exact source, if cached
+    pub fn get(
+        &self,
+        path: &Path,
+        source: &str,
+        lang: CompressLanguage,
+        rules: &NodeRules,
+        level: CompressLevel,
+    ) -> Option<String> {
+        let stored = fs::read_to_string(self.entry_path(path, source, lang, rules, level)).ok()?;
+        // The length line guards against the (unlikely) hash collision
+        let (len, compressed) = stored.split_once('\n')?;
+        (len.parse::<usize>().ok()? == source.len()).then(|| compressed.to_string())
+    }
+
+    /// Store compressed output. Best effort: a cache that can't be written just misses.
+    pub fn put(
+        &self,
+        path: &Path,
+        source: &str,
+        lang: CompressLanguage,
+        rules: &NodeRules,
+        level: CompressLevel,
+        compressed: &str,
+    ) {
+        let entry = self.entry_path(path, source, lang, rules, level);
+        if fs::create_dir_all(&self.dir).is_err() {
+            return;
+        }
+        // Write then rename, so a concurrent run never reads a half-written entry
+        let tmp = entry.with_extension(format!("tmp{}", std::process::id()));
+        let stored = format!("{}\n{}", source.len(), compressed);
+        if fs::write(&tmp, stored).is_err() || fs::rename(&tmp, &entry).is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+
+    fn entry_path(
+        &self,
+        path: &Path,
+        source: &str,
+        lang: CompressLanguage,
+        rules: &NodeRules,
+        level: CompressLevel,
+    ) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        grammar_fingerprint(lang).hash(&mut hasher);
+        format!("{:?}", lang).hash(&mut hasher);
+        rules.hash(&mut hasher);
+        level.hash(&mut hasher);
+        is_generated_stub(path).hash(&mut hasher);
+        source.hash(&mut hasher);
+        self.dir.join(format!("{:016x}", hasher.finish()))
+    }
+}
+
+/// Entry counts for `flat cache stats`
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub bytes: u64,
+    /// Entries written by other flat versions, which are never read again
+    pub stale_entries: usize,
+    pub stale_bytes: u64,
+}
+
+/// Count the entries under a cache root
+pub fn stats(root: &Path) -> Result<CacheStats> {
+    let mut stats = CacheStats::default();
+    let compress_dir = root.join("compress");
+    if !compress_dir.is_dir() {
+        return Ok(stats);
+    }
+    for version_dir in read_dir(&compress_dir)? {
+        let is_current = version_dir.file_name() == VERSION;
+        for entry in read_dir(&version_dir.path())? {
+            let bytes = entry.metadata().map(|m| m.len()).unwrap_or(0);
+            if is_current {
+                stats.entries += 1;
+                stats.bytes += bytes;
+            } else {
+                stats.stale_entries += 1;
+                stats.stale_bytes += bytes;
+            }
+        }
+    }
+    Ok(stats)
+}
+
+/// Remove every cached entry, current and stale, returning what was removed
+pub fn clear(root: &Path) -> Result<CacheStats> {
+    let removed = stats(root)?;
+    let compress_dir = root.join("compress");
+    if compress_dir.is_dir() {
+        fs::remove_dir_all(&compress_dir)
+            .with_context(|| format!("Failed to clear cache: {}", compress_dir.display()))?;
+    }
+    Ok(removed)
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<fs::DirEntry>> {
+    fs::read_dir(dir)
+        .with_context(|| format!("Failed to read cache directory: {}", dir.display()))?
+        .collect::<std::io::Result<_>>()
+        .with_context(|| format!("Failed to read cache directory: {}", dir.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_roundtrip_keyed_by_source() {
+        let dir = TempDir::new().unwrap();
+        let cache = CompressionCache::new(dir.path());
+        let path = Path::new("src/lib.rs");
+        let source = "fn a() { 1 }\n";
+        let rules = NodeRules::default();
+        assert_eq!(
+            cache.get(
+                path,
+                source,
+                CompressLanguage::Rust,
+                &rules,
+                CompressLevel::default()
+            ),
+            None
+        );
+
+        cache.put(
+            path,
+            source,
+            CompressLanguage::Rust,
+            &rules,
+            CompressLevel::default(),
+            "fn a() { ... }\n",
+        );
+        assert_eq!(
+            cache
+                .get(
+                    path,
+                    source,
+                    CompressLanguage::Rust,
+                    &rules,
+                    CompressLevel::default()
+                )
+                .as_deref(),
+            Some("fn a() { ... }\n")
+        );
+        assert_eq!(
+            cache.get(
+                path,
+                "fn b() {}\n",
+                CompressLanguage::Rust,
+                &rules,
+                CompressLevel::default()
+            ),
+            None
+        );
+        assert_eq!(
+            cache.get(
+                path,
+                source,
+                CompressLanguage::Go,
+                &rules,
+                CompressLevel::default()
+            ),
+            None
+        );
+        let keep_all = NodeRules {
+            keep: vec!["function_item".to_string()],
+            drop: vec![],
+        };
+        assert_eq!(
+            cache.get(
+                path,
+                source,
+                CompressLanguage::Rust,
+                &keep_all,
+                CompressLevel::default()
+            ),
+            None
+        );
+        assert_eq!(
+            cache.get(
+                path,
+                source,
+                CompressLanguage::Rust,
+                &rules,
+                CompressLevel::Minimal
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_other_versions_count_as_stale_and_clear_removes_all() {
+        let dir = TempDir::new().unwrap();
+        let cache = CompressionCache::new(dir.path());
+        let rules = NodeRules::default();
+        cache.put(
+            Path::new("a.rs"),
+            "fn a() {}",
+            CompressLanguage::Rust,
+            &rules,
+            CompressLevel::default(),
+            "x",
+        );
+        let old = dir.path().join("compress").join("0.0.1");
+        fs::create_dir_all(&old).unwrap();
+        fs::write(old.join("deadbeef"), "3\nabc").unwrap();
+
+        let counted = stats(dir.path()).unwrap();
+        assert_eq!((counted.entries, counted.stale_entries), (1, 1));
+
+        assert_eq!(clear(dir.path()).unwrap(), counted);
+        assert_eq!(stats(dir.path()).unwrap(), CacheStats::default());
+    }
+}
@@ -0,0 +1,172 @@
+use crate::config::Config;
+use crate::encoding::read_text;
+use crate::report::Decision;
+use crate::tokens::TokenEstimator;
+use crate::walker::analyze;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Fewest bytes of a language `flat calibrate` measures before it trusts the ratio
+pub const MIN_SAMPLE_BYTES: usize = 4 * 1024;
+
+/// Header of the `flat.toml` table the ratios go in
+const SECTION: &str = "[token-ratios]";
+
+/// What a reference tokenizer made of one extension's files
+#[derive(Debug, Clone, PartialEq)]
+pub struct LanguageSample {
+    /// Lowercased file extension
+    pub extension: String,
+    pub files: usize,
+    pub bytes: usize,
+    pub tokens: usize,
+}
+
+impl LanguageSample {
+    pub fn bytes_per_token(&self) -> f64 {
+        self.bytes as f64 / self.tokens.max(1) as f64
+    }
+
+    /// Whether there was enough text to go by (`MIN_SAMPLE_BYTES`)
+    pub fn is_reliable(&self) -> bool {
+        self.bytes >= MIN_SAMPLE_BYTES && self.tokens > 0
+    }
+}
+
+/// Count every file `config` would consider with `reference`, and total the
+/// bytes and tokens per extension. Files a filter skips, and files without an
+/// extension, are left out.
+pub fn calibrate(config: &Config, reference: &dyn TokenEstimator) -> Result<Vec<LanguageSample>> {
+    let mut samples: BTreeMap<String, LanguageSample> = BTreeMap::new();
+    for report in analyze(config)? {
+        if report.decision == Decision::Skipped {
+            continue;
+        }
+        let Some(ext) = report.path.extension().and_then(|e| e.to_str()) else {
+            continue;
+        };
+        let Ok(content) = read_text(&report.path) else {
+            continue;
+        };
+        let extension = ext.to_ascii_lowercase();
+        let sample = samples
+            .entry(extension.clone())
+            .or_insert_with(|| LanguageSample {
+                extension,
+                files: 0,
+                bytes: 0,
+                tokens: 0,
+            });
+        sample.files += 1;
+        sample.bytes += content.len();
+        sample.tokens += reference.estimate(&content, ext);
+    }
+    Ok(samples.into_values().collect())
+}
+
+/// Write the reliable samples' ratios to the `[token-ratios]` table of the
+/// `flat.toml` at `path`, replacing the table if there is one and creating the
+/// file if there isn't. Returns how many ratios were written.
+pub fn write_ratios(path: &Path, samples: &[LanguageSample], reference: &str) -> Result<usize> {
+    let existing = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to read {}", path.display()));
+        }
+    };
+    let reliable: Vec<&LanguageSample> = samples.iter().filter(|s| s.is_reliable()).collect();
+    let mut section = format!(
+        "{}\n# Bytes per token, measured with {} by `flat calibrate`\n",
+        SECTION, reference
+    );
+    for sample in &reliable {
+        section.push_str(&format!(
+            "{} = {:.2}\n",
+            toml_key(&sample.extension),
+            sample.bytes_per_token()
+        ));
+    }
+    fs::write(path, replace_section(&existing, &section))
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(reliable.len())
+}
+
+/// `text` with its `[token-ratios]` table (up to the next table header) swapped
+/// for `section`, or with `section` appended when it has none
+fn replace_section(text: &str, section: &str) -> String {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let Some(start) = lines.iter().position(|line| line.trim() == SECTION) else {
+        if text.is_empty() {
+            return section.to_string();
+        }
+        let separator = if text.ends_with('\n') { "\n" } else { "\n\n" };
+        return format!("{}{}{}", text, separator, section);
+    };
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| line.trim_start().starts_with('['))
+        .map_or(lines.len(), |offset| start + 1 + offset);
+    let mut replaced = lines[..start].concat();
+    replaced.push_str(section);
+    if end < lines.len() {
+        replaced.push('\n');
+        replaced.push_str(&lines[end..].concat());
+    }
+    replaced
+}
+
+/// An extension as a TOML key, quoted unless it is a bare key
+fn toml_key(extension: &str) -> String {
+    let bare = !extension.is_empty()
+        && extension
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        extension.to_string()
+    } else {
+        format!("{:?}", extension)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(extension: &str, bytes: usize, tokens: usize) -> LanguageSample {
+        LanguageSample {
+            extension: extension.to_string(),
+            files: 1,
+            bytes,
+            tokens,
+        }
+    }
+
+    #[test]
+    fn test_replace_section_keeps_other_tables() {
+        let text = "header = \"x\"\n\n[token-ratios]\nrs = 3.0\n\n[defaults]\ncompress = true\n";
+        let replaced = replace_section(text, "[token-ratios]\nrs = 3.5\n");
+        assert_eq!(
+            replaced,
+            "header = \"x\"\n\n[token-ratios]\nrs = 3.5\n\n[defaults]\ncompress = true\n"
+        );
+
+        let appended = replace_section("header = \"x\"\n", "[token-ratios]\nrs = 3.5\n");
+        assert_eq!(appended, "header = \"x\"\n\n[token-ratios]\nrs = 3.5\n");
+    }
+
+    #[test]
+    fn test_write_ratios_skips_small_samples() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("flat.toml");
+        let samples = [sample("rs", 8000, 2000), sample("c++", 100, 25)];
+        assert_eq!(write_ratios(&path, &samples, "o200k_base").unwrap(), 1);
+
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("rs = 4.00\n"));
+        assert!(!text.contains("c++"));
+        assert!(crate::config_file::load(&path).is_ok());
+    }
+}
@@ -1,8 +1,15 @@
+use crate::condense::{condense_graphql, condense_markdown, condense_protobuf, condense_sql};
+use crate::encoding::strip_bom;
+use crate::filters::is_generated_stub;
+use crate::progress::CancelToken;
+use clap::ValueEnum;
+use serde::Deserialize;
 use std::path::Path;
+use std::time::{Duration, Instant};
 use tree_sitter::{Language, Parser};
 
 /// Languages supported for compression
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CompressLanguage {
     Rust,
     TypeScript,
@@ -17,6 +24,16 @@ pub enum CompressLanguage {
     Cpp,
     Ruby,
     Php,
+    /// CI pipeline YAML (GitHub Actions, GitLab CI, CircleCI, ...), condensed without tree-sitter
+    CiPipeline,
+    /// SQL schemas and migrations, condensed without tree-sitter
+    Sql,
+    /// Protocol Buffers definitions, condensed without tree-sitter
+    Protobuf,
+    /// GraphQL schemas and operations, condensed without tree-sitter
+    GraphQl,
+    /// Markdown, reduced to its heading outline; only with `--compress-markdown`
+    Markdown,
 }
 
 /// Map a file extension to a compressible language
@@ -35,20 +52,56 @@ pub fn language_for_extension(ext: &str) -> Option<CompressLanguage> {
         "cpp" | "cc" | "cxx" | "hpp" | "hh" | "hxx" => Some(CompressLanguage::Cpp),
         "rb" => Some(CompressLanguage::Ruby),
         "php" => Some(CompressLanguage::Php),
+        "sql" => Some(CompressLanguage::Sql),
+        "proto" => Some(CompressLanguage::Protobuf),
+        "graphql" | "gql" => Some(CompressLanguage::GraphQl),
+        "md" | "markdown" => Some(CompressLanguage::Markdown),
         _ => None,
     }
 }
 
-/// Detect language from a file path's extension
+/// Detect language from a file path's extension (or location, for CI pipelines)
 pub fn language_for_path(path: &Path) -> Option<CompressLanguage> {
+    if is_ci_pipeline(path) {
+        return Some(CompressLanguage::CiPipeline);
+    }
     path.extension()
         .and_then(|e| e.to_str())
         .and_then(language_for_extension)
 }
 
+/// Check if a path is a CI pipeline definition
+fn is_ci_pipeline(path: &Path) -> bool {
+    let file_name = match path.file_name() {
+        Some(name) => name.to_string_lossy().to_lowercase(),
+        None => return false,
+    };
+    let is_yaml = file_name.ends_with(".yml") || file_name.ends_with(".yaml");
+    if !is_yaml {
+        return false;
+    }
+    if matches!(
+        file_name.as_str(),
+        ".gitlab-ci.yml" | "azure-pipelines.yml" | "bitbucket-pipelines.yml"
+    ) {
+        return true;
+    }
+
+    let parent = path.parent().and_then(|p| p.file_name());
+    let grandparent = path
+        .parent()
+        .and_then(|p| p.parent())
+        .and_then(|p| p.file_name());
+    match (grandparent, parent) {
+        (Some(g), Some(p)) if g == ".github" && p == "workflows" => true,
+        (_, Some(p)) => p == ".circleci" && file_name.starts_with("config."),
+        _ => false,
+    }
+}
+
 /// Get the tree-sitter Language for a CompressLanguage
-fn tree_sitter_language(lang: CompressLanguage) -> Language {
-    match lang {
+fn tree_sitter_language(lang: CompressLanguage) -> Option<Language> {
+    let language = match lang {
         CompressLanguage::Rust => tree_sitter_rust::LANGUAGE.into(),
         CompressLanguage::TypeScript => tree_sitter_typescript::LANGUAGE_TYPESCRIPT.into(),
         CompressLanguage::Tsx => tree_sitter_typescript::LANGUAGE_TSX.into(),
@@ -62,7 +115,362 @@ fn tree_sitter_language(lang: CompressLanguage) -> Language {
         CompressLanguage::Cpp => tree_sitter_cpp::LANGUAGE.into(),
         CompressLanguage::Ruby => tree_sitter_ruby::LANGUAGE.into(),
         CompressLanguage::Php => tree_sitter_php::LANGUAGE_PHP.into(),
+        CompressLanguage::CiPipeline
+        | CompressLanguage::Sql
+        | CompressLanguage::Protobuf
+        | CompressLanguage::GraphQl
+        | CompressLanguage::Markdown => return None,
+    };
+    Some(language)
+}
+
+/// Identify the grammar behind a language, so cached compressions go stale when
+/// a grammar upgrade changes its shape. Built from the ABI version and table sizes,
+/// which nearly every grammar release changes.
+pub fn grammar_fingerprint(lang: CompressLanguage) -> String {
+    match tree_sitter_language(lang) {
+        Some(language) => format!(
+            "abi{}-k{}-s{}-f{}",
+            language.version(),
+            language.node_kind_count(),
+            language.parse_state_count(),
+            language.field_count()
+        ),
+        None => "builtin".to_string(),
+    }
+}
+
+/// Node kinds a compressor keeps verbatim or drops, adjusting its built-in choices
+/// without a rebuild (`[compress.rust] keep = ["macro_invocation"]` in `flat.toml`).
+///
+/// Rules apply wherever a compressor walks declarations: the top level and
+/// class, impl, trait, and namespace bodies. `drop` wins over `keep`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeRules {
+    #[serde(default)]
+    pub keep: Vec<String>,
+    #[serde(default)]
+    pub drop: Vec<String>,
+}
+
+impl NodeRules {
+    /// Handle `node` if a rule names its kind, returning false to leave it to
+    /// the compressor's own match arms
+    fn apply(
+        &self,
+        source: &str,
+        node: tree_sitter::Node,
+        output: &mut String,
+        indent: &str,
+    ) -> bool {
+        let kind = node.kind();
+        if self.drop.iter().any(|k| k == kind) {
+            return true;
+        }
+        if self.keep.iter().any(|k| k == kind) {
+            push_indented(output, indent, node_text(source, node));
+            return true;
+        }
+        false
+    }
+}
+
+/// How much of each file `--compress` keeps (`--compress-level`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, ValueEnum)]
+pub enum CompressLevel {
+    /// Declarations with bodies elided, plus imports and comments
+    #[default]
+    Signatures,
+    /// Public signatures only: no comments, imports, or private items
+    Minimal,
+    /// Signatures and their doc comments; imports and other comments dropped
+    Docs,
+    /// Signatures of public items, with imports and comments; private items
+    /// and the comments and attributes above them dropped
+    Public,
+}
+
+/// Import kinds across the grammars, dropped below the `signatures` level
+const IMPORT_KINDS: &[&str] = &[
+    "use_declaration",
+    "extern_crate_declaration",
+    "import_statement",
+    "import_from_statement",
+    "future_import_statement",
+    "import_declaration",
+    "using_directive",
+    "preproc_include",
+    "namespace_use_declaration",
+];
+
+/// What a compressor keeps of each declaration it walks: the `[compress.*]`
+/// node rules, then the compression level
+struct Policy<'a> {
+    rules: &'a NodeRules,
+    level: CompressLevel,
+    lang: CompressLanguage,
+    /// Whether the file is an ES module, so that top-level declarations
+    /// without `export` are private to it
+    exports: bool,
+}
+
+impl Policy<'_> {
+    /// Handle `node` if the rules or the level decide it, returning false to
+    /// leave it to the compressor's own match arms
+    fn apply(
+        &self,
+        source: &str,
+        node: tree_sitter::Node,
+        output: &mut String,
+        indent: &str,
+    ) -> bool {
+        if self.rules.apply(source, node, output, indent) {
+            return true;
+        }
+        let is_comment = node.kind().ends_with("comment") || is_docstring(source, node);
+        match self.level {
+            CompressLevel::Signatures => false,
+            CompressLevel::Minimal => {
+                is_comment || IMPORT_KINDS.contains(&node.kind()) || self.is_private(source, node)
+            }
+            CompressLevel::Docs => {
+                (is_comment && !self.is_doc_comment(source, node))
+                    || IMPORT_KINDS.contains(&node.kind())
+            }
+            CompressLevel::Public => {
+                self.is_private(source, node) || self.annotates_private(source, node)
+            }
+        }
+    }
+
+    /// A comment or attribute in the run directly above a private item, which
+    /// would be left describing nothing once the item is dropped
+    fn annotates_private(&self, source: &str, node: tree_sitter::Node) -> bool {
+        let annotates = |node: tree_sitter::Node| {
+            let text = node_text(source, node);
+            (node.kind().ends_with("comment") || node.kind() == "attribute_item")
+                && !text.starts_with("//!")
+                && !text.starts_with("/*!")
+        };
+        if !annotates(node) {
+            return false;
+        }
+        let mut current = node;
+        while let Some(next) = current.next_sibling() {
+            if next.start_position().row > current.end_position().row + 1 {
+                return false;
+            }
+            if !annotates(next) {
+                return self.is_private(source, next);
+            }
+            current = next;
+        }
+        false
+    }
+
+    /// Doc syntax (`///`, `/** */`, docstrings), or for languages whose docs
+    /// are plain comments, a comment run directly above a declaration
+    fn is_doc_comment(&self, source: &str, node: tree_sitter::Node) -> bool {
+        let text = node_text(source, node);
+        if ["///", "//!", "/**", "/*!"]
+            .iter()
+            .any(|p| text.starts_with(p))
+            || is_docstring(source, node)
+        {
+            return true;
+        }
+        use CompressLanguage::*;
+        if !matches!(self.lang, Go | Ruby | C | Cpp) {
+            return false;
+        }
+        let mut current = node;
+        while let Some(next) = current.next_sibling() {
+            if next.start_position().row > current.end_position().row + 1 {
+                return false;
+            }
+            if !next.kind().ends_with("comment") {
+                return true;
+            }
+            current = next;
+        }
+        false
     }
+
+    /// A declaration not visible outside its module, by each language's rules
+    fn is_private(&self, source: &str, node: tree_sitter::Node) -> bool {
+        let parent_kind = node.parent().map(|p| p.kind()).unwrap_or("");
+        let name_of = |node: tree_sitter::Node| {
+            node.child_by_field_name("name")
+                .map(|name| node_text(source, name))
+                .unwrap_or("")
+        };
+        let has_private_modifier = (0..node.child_count())
+            .filter_map(|i| node.child(i))
+            .filter(|c| c.kind().ends_with("modifier") || c.kind().ends_with("modifiers"))
+            .any(|c| {
+                node_text(source, c)
+                    .split_whitespace()
+                    .any(|w| w == "private")
+            });
+
+        use CompressLanguage::*;
+        match self.lang {
+            Rust => {
+                const ITEMS: &[&str] = &[
+                    "function_item",
+                    "struct_item",
+                    "enum_item",
+                    "trait_item",
+                    "type_item",
+                    "const_item",
+                    "static_item",
+                    "mod_item",
+                ];
+                // Trait items and trait impl members are as public as the trait
+                let in_trait = node.parent().and_then(|p| p.parent()).is_some_and(|owner| {
+                    owner.kind() == "trait_item"
+                        || owner.kind() == "impl_item"
+                            && owner.child_by_field_name("trait").is_some()
+                });
+                let is_pub = (0..node.child_count())
+                    .filter_map(|i| node.child(i))
+                    .any(|c| c.kind() == "visibility_modifier");
+                ITEMS.contains(&node.kind()) && !in_trait && !is_pub
+            }
+            TypeScript | Tsx | JavaScript | Jsx => {
+                const DECLARATIONS: &[&str] = &[
+                    "function_declaration",
+                    "generator_function_declaration",
+                    "class_declaration",
+                    "abstract_class_declaration",
+                    "lexical_declaration",
+                    "variable_declaration",
+                    "interface_declaration",
+                    "type_alias_declaration",
+                    "enum_declaration",
+                ];
+                let unexported =
+                    self.exports && parent_kind == "program" && DECLARATIONS.contains(&node.kind());
+                let private_name = name_of(node).starts_with('#');
+                unexported || private_name || has_private_modifier
+            }
+            Python => {
+                let definition = match node.kind() {
+                    "decorated_definition" => node.child_by_field_name("definition"),
+                    "function_definition" | "class_definition" => Some(node),
+                    _ => None,
+                };
+                definition.is_some_and(|d| {
+                    let name = name_of(d);
+                    name.starts_with('_') && !(name.starts_with("__") && name.ends_with("__"))
+                })
+            }
+            Go => {
+                let name = match node.kind() {
+                    "function_declaration" | "method_declaration" => name_of(node),
+                    "type_declaration" => node.named_child(0).map(name_of).unwrap_or(""),
+                    _ => "",
+                };
+                name.starts_with(|c: char| c.is_lowercase() || c == '_')
+            }
+            C | Cpp => {
+                let is_static = (0..node.child_count())
+                    .filter_map(|i| node.child(i))
+                    .any(|c| {
+                        c.kind() == "storage_class_specifier" && node_text(source, c) == "static"
+                    });
+                parent_kind == "translation_unit" && is_static
+            }
+            Java | CSharp | Php => has_private_modifier,
+            Ruby | CiPipeline | Sql | Protobuf | GraphQl | Markdown => false,
+        }
+    }
+}
+
+/// A Python docstring: a statement that is just a triple-quoted string
+fn is_docstring(source: &str, node: tree_sitter::Node) -> bool {
+    let text = node_text(source, node);
+    node.kind() == "expression_statement" && (text.starts_with("\"\"\"") || text.starts_with("'''"))
+}
+
+impl CompressLanguage {
+    /// Names of the tree-sitter languages, as used in `[compress.<name>]` config tables
+    pub const NAMES: &'static [&'static str] = &[
+        "rust",
+        "typescript",
+        "tsx",
+        "javascript",
+        "jsx",
+        "python",
+        "go",
+        "java",
+        "csharp",
+        "c",
+        "cpp",
+        "ruby",
+        "php",
+    ];
+
+    /// Language tag for a Markdown code fence
+    pub fn code_fence_tag(self) -> &'static str {
+        match self {
+            CompressLanguage::Rust => "rust",
+            CompressLanguage::TypeScript => "typescript",
+            CompressLanguage::Tsx => "tsx",
+            CompressLanguage::JavaScript => "javascript",
+            CompressLanguage::Jsx => "jsx",
+            CompressLanguage::Python => "python",
+            CompressLanguage::Go => "go",
+            CompressLanguage::Java => "java",
+            CompressLanguage::CSharp => "csharp",
+            CompressLanguage::C => "c",
+            CompressLanguage::Cpp => "cpp",
+            CompressLanguage::Ruby => "ruby",
+            CompressLanguage::Php => "php",
+            CompressLanguage::CiPipeline => "yaml",
+            CompressLanguage::Sql => "sql",
+            CompressLanguage::Protobuf => "protobuf",
+            CompressLanguage::GraphQl => "graphql",
+            CompressLanguage::Markdown => "markdown",
+        }
+    }
+
+    /// Look up a tree-sitter language by the name used in `[compress.<name>]` config tables
+    pub fn from_name(name: &str) -> Option<Self> {
+        let lang = match name {
+            "rust" => CompressLanguage::Rust,
+            "typescript" => CompressLanguage::TypeScript,
+            "tsx" => CompressLanguage::Tsx,
+            "javascript" => CompressLanguage::JavaScript,
+            "jsx" => CompressLanguage::Jsx,
+            "python" => CompressLanguage::Python,
+            "go" => CompressLanguage::Go,
+            "java" => CompressLanguage::Java,
+            "csharp" => CompressLanguage::CSharp,
+            "c" => CompressLanguage::C,
+            "cpp" => CompressLanguage::Cpp,
+            "ruby" => CompressLanguage::Ruby,
+            "php" => CompressLanguage::Php,
+            _ => return None,
+        };
+        Some(lang)
+    }
+}
+
+/// Load the language's grammar into a parser, as compression would
+pub fn load_grammar(lang: CompressLanguage) -> Result<(), String> {
+    let Some(language) = tree_sitter_language(lang) else {
+        return Ok(());
+    };
+    Parser::new()
+        .set_language(&language)
+        .map_err(|e| e.to_string())
+}
+
+/// Whether `kind` names a node in the language's grammar, to catch typos in rules
+pub fn is_node_kind(lang: CompressLanguage, kind: &str) -> bool {
+    tree_sitter_language(lang).is_some_and(|l| l.id_for_node_kind(kind, true) != 0)
 }
 
 /// Result of compressing a source file
@@ -74,31 +482,99 @@ pub enum CompressResult {
     Fallback(String, Option<String>),
 }
 
-/// Strip UTF-8 BOM if present
-fn strip_bom(source: &str) -> &str {
-    source.strip_prefix('\u{FEFF}').unwrap_or(source)
+/// Bounds on the tree-sitter work spent on one file, so a pathological input
+/// falls back to full content instead of stalling the run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseLimits {
+    /// Abandon a parse that runs longer than this (zero means no limit)
+    pub timeout: Duration,
+    /// Don't parse files larger than this many bytes
+    pub max_size: u64,
+}
+
+impl Default for ParseLimits {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(5),
+            max_size: 512 * 1024,
+        }
+    }
+}
+
+impl ParseLimits {
+    fn exceeded_by(&self, source: &str) -> Option<String> {
+        (source.len() as u64 > self.max_size).then(|| {
+            format!(
+                "file is larger than the {} byte compression limit (--max-compress-size)",
+                self.max_size
+            )
+        })
+    }
 }
 
 /// Compress a source file by extracting declarations and signatures.
 ///
 /// Returns compressed output or falls back to full content per the fallback rules:
 /// - Unsupported extension → full content
-/// - Parse error (NULL tree) → full content + warn
+/// - Larger than `ParseLimits::max_size` → full content + warn
+/// - Parse error or timeout (NULL tree) → full content + warn
 /// - ERROR nodes in parse tree → full content + warn
 /// - Empty compressed output → full content + warn
 /// - Compressed ≥ original → full content (no warning)
 /// - tree-sitter panic → full content + warn (catch_unwind)
 pub fn compress_source(source: &str, lang: CompressLanguage) -> CompressResult {
+    compress_source_with_rules(
+        source,
+        lang,
+        &NodeRules::default(),
+        CompressLevel::default(),
+        &ParseLimits::default(),
+        &CancelToken::default(),
+    )
+}
+
+/// [`compress_source`] with per-language keep/drop adjustments, compression
+/// level, and parse limits from config. A cancelled parse falls back to full
+/// content without a warning.
+pub fn compress_source_with_rules(
+    source: &str,
+    lang: CompressLanguage,
+    rules: &NodeRules,
+    level: CompressLevel,
+    limits: &ParseLimits,
+    cancel: &CancelToken,
+) -> CompressResult {
     let source = strip_bom(source);
 
     if source.is_empty() {
         return CompressResult::Compressed(String::new());
     }
 
+    // Languages condensed without tree-sitter need no parse limits
+    let condensed = match lang {
+        CompressLanguage::CiPipeline => Some(condense_ci_pipeline(source)),
+        CompressLanguage::Sql => Some(condense_sql(source)),
+        CompressLanguage::Protobuf => Some(condense_protobuf(source)),
+        CompressLanguage::GraphQl => Some(condense_graphql(source)),
+        CompressLanguage::Markdown => Some(condense_markdown(source)),
+        _ => None,
+    };
+    if let Some(condensed) = condensed {
+        // A file that is all declarations can come out no smaller
+        if condensed.len() >= source.len() {
+            return CompressResult::Fallback(source.to_string(), None);
+        }
+        return CompressResult::Compressed(condensed);
+    }
+
+    if let Some(reason) = limits.exceeded_by(source) {
+        return CompressResult::Fallback(source.to_string(), Some(reason));
+    }
+
     // Wrap tree-sitter calls in catch_unwind to prevent panics from crashing the process
     let source_owned = source.to_string();
     let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
-        compress_source_inner(&source_owned, lang)
+        compress_source_inner(&source_owned, lang, rules, level, limits.timeout, cancel)
     }));
 
     match result {
@@ -110,20 +586,55 @@ pub fn compress_source(source: &str, lang: CompressLanguage) -> CompressResult {
     }
 }
 
-/// Inner compression logic, separated so catch_unwind can wrap it
-fn compress_source_inner(source: &str, lang: CompressLanguage) -> CompressResult {
-    let ts_lang = tree_sitter_language(lang);
+/// Bytes handed to the parser at a time, between cancellation checks
+const PARSE_CHUNK: usize = 64 * 1024;
 
+/// Inner compression logic, separated so catch_unwind can wrap it
+fn compress_source_inner(
+    source: &str,
+    lang: CompressLanguage,
+    rules: &NodeRules,
+    level: CompressLevel,
+    timeout: Duration,
+    cancel: &CancelToken,
+) -> CompressResult {
     let mut parser = Parser::new();
-    if parser.set_language(&ts_lang).is_err() {
+    let language_set = tree_sitter_language(lang).is_some_and(|l| parser.set_language(&l).is_ok());
+    if !language_set {
         return CompressResult::Fallback(
             source.to_string(),
             Some("failed to set parser language".to_string()),
         );
     }
 
-    let tree = match parser.parse(source, None) {
+    let start = Instant::now();
+    parser.set_timeout_micros(timeout.as_micros().try_into().unwrap_or(u64::MAX));
+    // Fed in chunks so a cancelled run cuts the parse short at the next one
+    let read = &mut |offset: usize, _: tree_sitter::Point| {
+        if cancel.is_cancelled() || offset >= source.len() {
+            return "";
+        }
+        let mut end = (offset + PARSE_CHUNK).min(source.len());
+        while !source.is_char_boundary(end) {
+            end += 1;
+        }
+        &source[offset..end]
+    };
+    let tree = parser.parse_with(read, None);
+    if cancel.is_cancelled() {
+        return CompressResult::Fallback(source.to_string(), None);
+    }
+    let tree = match tree {
         Some(t) => t,
+        None if !timeout.is_zero() && start.elapsed() >= timeout => {
+            return CompressResult::Fallback(
+                source.to_string(),
+                Some(format!(
+                    "parse timed out after {:?} (--parse-timeout)",
+                    timeout
+                )),
+            );
+        }
         None => {
             return CompressResult::Fallback(
                 source.to_string(),
@@ -142,22 +653,42 @@ fn compress_source_inner(source: &str, lang: CompressLanguage) -> CompressResult
         );
     }
 
+    let rules = &Policy {
+        rules,
+        level,
+        lang,
+        exports: (0..root.named_child_count())
+            .filter_map(|i| root.named_child(i))
+            .any(|c| c.kind() == "export_statement"),
+    };
     let compressed = match lang {
-        CompressLanguage::Rust => compress_rust(source, root),
+        CompressLanguage::Rust => compress_rust(source, rules, root),
         CompressLanguage::TypeScript
         | CompressLanguage::Tsx
         | CompressLanguage::JavaScript
-        | CompressLanguage::Jsx => compress_typescript(source, root),
-        CompressLanguage::Python => compress_python(source, root),
-        CompressLanguage::Go => compress_go(source, root),
-        CompressLanguage::Java => compress_java(source, root),
-        CompressLanguage::CSharp => compress_csharp(source, root),
-        CompressLanguage::C => compress_c(source, root),
-        CompressLanguage::Cpp => compress_cpp(source, root),
-        CompressLanguage::Ruby => compress_ruby(source, root),
-        CompressLanguage::Php => compress_php(source, root),
+        | CompressLanguage::Jsx => compress_typescript(source, rules, root),
+        CompressLanguage::Python => compress_python(source, rules, root),
+        CompressLanguage::Go => compress_go(source, rules, root),
+        CompressLanguage::Java => compress_java(source, rules, root),
+        CompressLanguage::CSharp => compress_csharp(source, rules, root),
+        CompressLanguage::C => compress_c(source, rules, root),
+        CompressLanguage::Cpp => compress_cpp(source, rules, root),
+        CompressLanguage::Ruby => compress_ruby(source, rules, root),
+        CompressLanguage::Php => compress_php(source, rules, root),
+        CompressLanguage::CiPipeline => condense_ci_pipeline(source),
+        CompressLanguage::Sql => condense_sql(source),
+        CompressLanguage::Protobuf => condense_protobuf(source),
+        CompressLanguage::GraphQl => condense_graphql(source),
+        CompressLanguage::Markdown => condense_markdown(source),
     };
+    let compressed = elide_large_literals(compressed, source, root, lang);
 
+    // Minimal and public drop private items, so a file of nothing else is legitimately empty
+    if compressed.trim().is_empty()
+        && matches!(level, CompressLevel::Minimal | CompressLevel::Public)
+    {
+        return CompressResult::Compressed(String::new());
+    }
     if compressed.is_empty() {
         return CompressResult::Fallback(
             source.to_string(),
@@ -172,6 +703,296 @@ fn compress_source_inner(source: &str, lang: CompressLanguage) -> CompressResult
     CompressResult::Compressed(compressed)
 }
 
+/// Compress a file, reducing generated stubs (see `is_generated_stub`) to the
+/// names of the types they declare. Everything else goes through `compress_source`.
+pub fn compress_file(
+    path: &Path,
+    source: &str,
+    lang: CompressLanguage,
+    rules: &NodeRules,
+    level: CompressLevel,
+    limits: &ParseLimits,
+    cancel: &CancelToken,
+) -> CompressResult {
+    if is_generated_stub(path) && limits.exceeded_by(source).is_none() {
+        if let Some(outline) = outline_type_names(strip_bom(source), lang) {
+            return CompressResult::Compressed(outline);
+        }
+    }
+    compress_source_with_rules(source, lang, rules, level, limits, cancel)
+}
+
+/// Declaration node kinds that introduce a named type, with the keyword to show
+const TYPE_DECLARATIONS: &[(&str, &str)] = &[
+    ("class_definition", "class"),
+    ("class_declaration", "class"),
+    ("abstract_class_declaration", "class"),
+    ("class_specifier", "class"),
+    ("struct_item", "struct"),
+    ("struct_declaration", "struct"),
+    ("struct_specifier", "struct"),
+    ("record_declaration", "record"),
+    ("enum_item", "enum"),
+    ("enum_declaration", "enum"),
+    ("enum_specifier", "enum"),
+    ("interface_declaration", "interface"),
+    ("trait_item", "trait"),
+    ("type_alias_declaration", "type"),
+    ("type_item", "type"),
+    ("type_spec", "type"),
+];
+
+/// List the types declared in `source`, one `keyword Name` per line, nested
+/// types indented under their parent. Returns None if nothing could be found.
+fn outline_type_names(source: &str, lang: CompressLanguage) -> Option<String> {
+    let mut parser = Parser::new();
+    parser.set_language(&tree_sitter_language(lang)?).ok()?;
+    let tree = parser.parse(source, None)?;
+    let root = tree.root_node();
+    if has_error_nodes(root) {
+        return None;
+    }
+
+    let mut output = String::new();
+    collect_type_names(source, root, 0, &mut output);
+
+    // Older protobuf Python output binds messages as `Name = _reflection...`
+    if lang == CompressLanguage::Python {
+        let mut cursor = root.walk();
+        for child in root.children(&mut cursor) {
+            let Some(assignment) = child.named_child(0) else {
+                continue;
+            };
+            if child.kind() != "expression_statement" || assignment.kind() != "assignment" {
+                continue;
+            }
+            if let Some(left) = assignment.child_by_field_name("left") {
+                let name = node_text(source, left);
+                let is_type_like = name.starts_with(|c: char| c.is_ascii_uppercase())
+                    && name.chars().any(|c| c.is_ascii_lowercase());
+                if left.kind() == "identifier" && is_type_like {
+                    output.push_str(&format!("class {}\n", name));
+                }
+            }
+        }
+    }
+
+    if output.is_empty() {
+        None
+    } else {
+        Some(output)
+    }
+}
+
+fn collect_type_names(source: &str, node: tree_sitter::Node, depth: usize, output: &mut String) {
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        let keyword = TYPE_DECLARATIONS
+            .iter()
+            .find(|(kind, _)| *kind == child.kind())
+            .map(|(_, keyword)| *keyword);
+        // C/C++ specifiers are also used to reference a type; only definitions have a body
+        let is_definition =
+            !child.kind().ends_with("_specifier") || child.child_by_field_name("body").is_some();
+
+        match (keyword, child.child_by_field_name("name")) {
+            (Some(keyword), Some(name)) if is_definition => {
+                output.push_str(&"  ".repeat(depth));
+                output.push_str(&format!("{} {}\n", keyword, node_text(source, name)));
+                collect_type_names(source, child, depth + 1, output);
+            }
+            _ => collect_type_names(source, child, depth, output),
+        }
+    }
+}
+
+/// Keys whose values are shell scripts in CI pipeline definitions
+const CI_SCRIPT_KEYS: &[&str] = &["run", "script", "before_script", "after_script", "command"];
+
+/// Inline scripts up to this length are kept; they read like step names
+const CI_INLINE_SCRIPT_LIMIT: usize = 60;
+
+/// Condense a CI pipeline to its outline: jobs, triggers, and step names stay,
+/// script bodies become `...`. Line-based, so it works on any YAML dialect.
+fn condense_ci_pipeline(source: &str) -> String {
+    let mut output = String::new();
+    // Indentation of a script key whose body is being dropped
+    let mut skipping_under: Option<usize> = None;
+    // Indentation of a script key with an empty value: a list or a mapping may follow
+    let mut pending_list: Option<usize> = None;
+
+    for line in source.lines() {
+        let trimmed = line.trim_start();
+        let indent = line.len() - trimmed.len();
+        let is_list_item = trimmed.starts_with("- ");
+
+        if let Some(key_indent) = pending_list.take() {
+            if is_list_item && indent >= key_indent {
+                // `script:` followed by `- cmd` items: mark the key and drop the items
+                output.truncate(output.trim_end_matches('\n').len());
+                output.push_str(" ...\n");
+                skipping_under = Some(key_indent);
+            }
+        }
+
+        if let Some(key_indent) = skipping_under {
+            let continues = indent > key_indent || (indent == key_indent && is_list_item);
+            if trimmed.is_empty() || continues {
+                continue;
+            }
+            skipping_under = None;
+        }
+
+        let key_part = trimmed.strip_prefix("- ").unwrap_or(trimmed);
+        let script = key_part.split_once(':').and_then(|(key, value)| {
+            CI_SCRIPT_KEYS
+                .contains(&key.trim())
+                .then(|| (key.trim(), value.trim()))
+        });
+        let prefix = &line[..line.len() - key_part.len()];
+
+        match script {
+            Some((key, value)) if value.starts_with(['|', '>']) => {
+                output.push_str(&format!("{}{}: ...\n", prefix, key));
+                skipping_under = Some(indent);
+            }
+            Some((key, value)) if value.len() > CI_INLINE_SCRIPT_LIMIT => {
+                output.push_str(&format!("{}{}: ...\n", prefix, key));
+            }
+            Some((_, "")) => {
+                output.push_str(line);
+                output.push('\n');
+                pending_list = Some(indent);
+            }
+            _ => {
+                output.push_str(line);
+                output.push('\n');
+            }
+        }
+    }
+
+    output
+}
+
+/// String and array literals longer than this many bytes are elided from
+/// compressed output, wherever the compressor kept them
+const LITERAL_ELIDE_BYTES: usize = 1024;
+
+/// Node kinds of string and collection literals that can embed bulk data
+fn literal_kinds(lang: CompressLanguage) -> &'static [&'static str] {
+    match lang {
+        CompressLanguage::Rust => &["string_literal", "raw_string_literal", "array_expression"],
+        CompressLanguage::TypeScript
+        | CompressLanguage::Tsx
+        | CompressLanguage::JavaScript
+        | CompressLanguage::Jsx => &["string", "template_string", "array"],
+        CompressLanguage::Python => &["string", "concatenated_string", "list", "tuple"],
+        CompressLanguage::Go => &[
+            "interpreted_string_literal",
+            "raw_string_literal",
+            "literal_value",
+        ],
+        CompressLanguage::Java => &["string_literal", "text_block", "array_initializer"],
+        CompressLanguage::CSharp => &[
+            "string_literal",
+            "verbatim_string_literal",
+            "raw_string_literal",
+            "initializer_expression",
+        ],
+        CompressLanguage::C | CompressLanguage::Cpp => &[
+            "string_literal",
+            "raw_string_literal",
+            "concatenated_string",
+            "initializer_list",
+        ],
+        CompressLanguage::Ruby => &["string", "heredoc_body", "array"],
+        CompressLanguage::Php => &[
+            "string",
+            "encapsed_string",
+            "heredoc",
+            "array_creation_expression",
+        ],
+        CompressLanguage::CiPipeline
+        | CompressLanguage::Sql
+        | CompressLanguage::Protobuf
+        | CompressLanguage::GraphQl
+        | CompressLanguage::Markdown => &[],
+    }
+}
+
+/// Replace oversized literals (SQL strings, base64 blobs, lookup tables) that
+/// survived compression with `"…"` or `[…]`, noting how many bytes were dropped
+fn elide_large_literals(
+    compressed: String,
+    source: &str,
+    root: tree_sitter::Node,
+    lang: CompressLanguage,
+) -> String {
+    let mut literals = Vec::new();
+    collect_large_literals(source, root, literal_kinds(lang), &mut literals);
+    // Longest first, so a literal is replaced before any shorter one it contains
+    literals.sort_by_key(|(text, _)| std::cmp::Reverse(text.len()));
+    literals.dedup();
+    let mut output = compressed;
+    for (text, kind) in literals {
+        if output.contains(text) {
+            output = output.replace(text, &elided_literal(text, kind));
+        }
+    }
+    output
+}
+
+/// The outermost literals of `kinds` longer than [`LITERAL_ELIDE_BYTES`]
+fn collect_large_literals<'a>(
+    source: &'a str,
+    node: tree_sitter::Node,
+    kinds: &[&str],
+    out: &mut Vec<(&'a str, &'static str)>,
+) {
+    if node.byte_range().len() <= LITERAL_ELIDE_BYTES {
+        return;
+    }
+    if kinds.contains(&node.kind()) {
+        out.push((node_text(source, node), node.kind()));
+        return;
+    }
+    let mut cursor = node.walk();
+    for child in node.children(&mut cursor) {
+        collect_large_literals(source, child, kinds, out);
+    }
+}
+
+/// Collection literal kinds among [`literal_kinds`]; the rest are strings
+const COLLECTION_LITERALS: &[&str] = &[
+    "array_expression",
+    "array",
+    "list",
+    "tuple",
+    "literal_value",
+    "array_initializer",
+    "initializer_expression",
+    "initializer_list",
+    "array_creation_expression",
+];
+
+/// The literal with its contents replaced by the elided length, keeping its own
+/// delimiters: `"…"`, `r#"…"#`, `` `…` ``, `[…]`, `{…}`, `array(…)`
+fn elided_literal(text: &str, kind: &str) -> String {
+    let note = format!("… {} bytes", text.len());
+    let (open, close) = if COLLECTION_LITERALS.contains(&kind) {
+        (text.find(['[', '{', '(']), text.rfind([']', '}', ')']))
+    } else {
+        (text.find(['"', '\'', '`']), text.rfind(['"', '\'', '`']))
+    };
+    match (open, close) {
+        (Some(open), Some(close)) if open < close => {
+            format!("{}{}{}", &text[..=open], note, &text[close..])
+        }
+        // Heredoc bodies have no delimiters of their own
+        _ => note,
+    }
+}
+
 /// Recursively check if the parse tree contains any ERROR nodes
 fn has_error_nodes(node: tree_sitter::Node) -> bool {
     if node.is_error() {
@@ -228,22 +1049,25 @@ fn push_indented_block(output: &mut String, indent: &str, block: &str) {
 // Rust Compressor
 // ============================================================================
 
-fn compress_rust(source: &str, root: tree_sitter::Node) -> String {
+fn compress_rust(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "function_item" => {
                 output.push_str(&compress_rust_function(source, child));
                 output.push('\n');
             }
             "trait_item" => {
-                output.push_str(&compress_rust_trait(source, child));
+                output.push_str(&compress_rust_trait(source, rules, child));
                 output.push('\n');
             }
             "impl_item" => {
-                output.push_str(&compress_rust_impl(source, child));
+                output.push_str(&compress_rust_impl(source, rules, child));
                 output.push('\n');
             }
             "use_declaration"
@@ -274,7 +1098,7 @@ fn compress_rust_function(source: &str, node: tree_sitter::Node) -> String {
     compress_body(source, node, &["block"])
 }
 
-fn compress_rust_trait(source: &str, node: tree_sitter::Node) -> String {
+fn compress_rust_trait(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -285,6 +1109,9 @@ fn compress_rust_trait(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "function_item" => {
                         push_indented(&mut output, "    ", &compress_rust_function(source, item));
@@ -308,7 +1135,7 @@ fn compress_rust_trait(source: &str, node: tree_sitter::Node) -> String {
     node_text(source, node).to_string()
 }
 
-fn compress_rust_impl(source: &str, node: tree_sitter::Node) -> String {
+fn compress_rust_impl(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -319,6 +1146,9 @@ fn compress_rust_impl(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "function_item" => {
                         push_indented(&mut output, "    ", &compress_rust_function(source, item));
@@ -342,14 +1172,17 @@ fn compress_rust_impl(source: &str, node: tree_sitter::Node) -> String {
 // TypeScript/JavaScript Compressor
 // ============================================================================
 
-fn compress_typescript(source: &str, root: tree_sitter::Node) -> String {
+fn compress_typescript(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "export_statement" => {
-                output.push_str(&compress_ts_export(source, child));
+                output.push_str(&compress_ts_export(source, rules, child));
                 output.push('\n');
             }
             "function_declaration" => {
@@ -357,7 +1190,7 @@ fn compress_typescript(source: &str, root: tree_sitter::Node) -> String {
                 output.push('\n');
             }
             "class_declaration" => {
-                output.push_str(&compress_ts_class(source, child));
+                output.push_str(&compress_ts_class(source, rules, child));
                 output.push('\n');
             }
             "lexical_declaration" | "variable_declaration" => {
@@ -386,7 +1219,7 @@ fn compress_ts_function(source: &str, node: tree_sitter::Node) -> String {
     compress_body(source, node, &["statement_block"])
 }
 
-fn compress_ts_class(source: &str, node: tree_sitter::Node) -> String {
+fn compress_ts_class(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -397,6 +1230,9 @@ fn compress_ts_class(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "method_definition" | "public_field_definition" | "property_definition" => {
                         push_indented(
@@ -465,7 +1301,7 @@ fn compress_ts_var_inner(
     }
 }
 
-fn compress_ts_export(source: &str, node: tree_sitter::Node) -> String {
+fn compress_ts_export(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut cursor = node.walk();
     for inner in node.children(&mut cursor) {
         match inner.kind() {
@@ -484,7 +1320,7 @@ fn compress_ts_export(source: &str, node: tree_sitter::Node) -> String {
             }
             "class_declaration" => {
                 let prefix = &source[node.start_byte()..inner.start_byte()];
-                return format!("{}{}", prefix, compress_ts_class(source, inner));
+                return format!("{}{}", prefix, compress_ts_class(source, rules, inner));
             }
             _ => {}
         }
@@ -497,11 +1333,14 @@ fn compress_ts_export(source: &str, node: tree_sitter::Node) -> String {
 // Python Compressor
 // ============================================================================
 
-fn compress_python(source: &str, root: tree_sitter::Node) -> String {
+fn compress_python(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             // Imports
             "import_statement" | "import_from_statement" | "future_import_statement" => {
@@ -534,12 +1373,12 @@ fn compress_python(source: &str, root: tree_sitter::Node) -> String {
             }
             // Function definitions
             "function_definition" | "decorated_definition" => {
-                output.push_str(&compress_python_function(source, child));
+                output.push_str(&compress_python_function(source, rules, child));
                 output.push('\n');
             }
             // Class definitions
             "class_definition" => {
-                output.push_str(&compress_python_class(source, child));
+                output.push_str(&compress_python_class(source, rules, child));
                 output.push('\n');
             }
             // Global variable assignments at module level
@@ -558,7 +1397,7 @@ fn compress_python(source: &str, root: tree_sitter::Node) -> String {
     output.trim_end().to_string()
 }
 
-fn compress_python_function(source: &str, node: tree_sitter::Node) -> String {
+fn compress_python_function(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut cursor = node.walk();
 
     // Handle decorated functions
@@ -571,11 +1410,11 @@ fn compress_python_function(source: &str, node: tree_sitter::Node) -> String {
                     decorators.push('\n');
                 }
                 "function_definition" => {
-                    decorators.push_str(&compress_python_function_inner(source, child));
+                    decorators.push_str(&compress_python_function_inner(source, rules, child));
                     return decorators;
                 }
                 "class_definition" => {
-                    decorators.push_str(&compress_python_class(source, child));
+                    decorators.push_str(&compress_python_class(source, rules, child));
                     return decorators;
                 }
                 _ => {}
@@ -584,10 +1423,10 @@ fn compress_python_function(source: &str, node: tree_sitter::Node) -> String {
         return decorators;
     }
 
-    compress_python_function_inner(source, node)
+    compress_python_function_inner(source, rules, node)
 }
 
-fn compress_python_function_inner(source: &str, node: tree_sitter::Node) -> String {
+fn compress_python_function_inner(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut cursor = node.walk();
 
     for child in node.children(&mut cursor) {
@@ -598,7 +1437,8 @@ fn compress_python_function_inner(source: &str, node: tree_sitter::Node) -> Stri
             if let Some(block_child) = child.children(&mut block_cursor).next() {
                 if block_child.kind() == "expression_statement" {
                     let text = node_text(source, block_child);
-                    if text.starts_with("\"\"\"") || text.starts_with("'''") {
+                    let is_docstring = text.starts_with("\"\"\"") || text.starts_with("'''");
+                    if is_docstring && rules.level != CompressLevel::Minimal {
                         return format!("{}\n    {}\n    ...", sig, text);
                     }
                 }
@@ -610,7 +1450,7 @@ fn compress_python_function_inner(source: &str, node: tree_sitter::Node) -> Stri
     node_text(source, node).to_string()
 }
 
-fn compress_python_class(source: &str, node: tree_sitter::Node) -> String {
+fn compress_python_class(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -622,10 +1462,13 @@ fn compress_python_class(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "function_definition" | "decorated_definition" => {
                         // Indent the compressed function
-                        let compressed = compress_python_function(source, item);
+                        let compressed = compress_python_function(source, rules, item);
                         for line in compressed.lines() {
                             output.push_str("    ");
                             output.push_str(line);
@@ -664,11 +1507,14 @@ fn compress_python_class(source: &str, node: tree_sitter::Node) -> String {
 // Go Compressor
 // ============================================================================
 
-fn compress_go(source: &str, root: tree_sitter::Node) -> String {
+fn compress_go(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "function_declaration" | "method_declaration" => {
                 output.push_str(&compress_body(source, child, &["block"]));
@@ -690,18 +1536,21 @@ fn compress_go(source: &str, root: tree_sitter::Node) -> String {
 // Java Compressor
 // ============================================================================
 
-fn compress_java(source: &str, root: tree_sitter::Node) -> String {
+fn compress_java(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "class_declaration"
             | "interface_declaration"
             | "enum_declaration"
             | "record_declaration"
             | "annotation_type_declaration" => {
-                output.push_str(&compress_java_class(source, child));
+                output.push_str(&compress_java_class(source, rules, child));
                 output.push('\n');
             }
             "package_declaration" | "import_declaration" | "line_comment" | "block_comment" => {
@@ -715,7 +1564,7 @@ fn compress_java(source: &str, root: tree_sitter::Node) -> String {
     output.trim_end().to_string()
 }
 
-fn compress_java_class(source: &str, node: tree_sitter::Node) -> String {
+fn compress_java_class(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let body_kind = match node.kind() {
         "enum_declaration" => "enum_body",
         "interface_declaration" => "interface_body",
@@ -733,6 +1582,9 @@ fn compress_java_class(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "method_declaration" | "constructor_declaration" => {
                         push_indented(
@@ -752,6 +1604,9 @@ fn compress_java_class(source: &str, node: tree_sitter::Node) -> String {
                         // In Java enums, fields/methods are wrapped in this node
                         let mut decl_cursor = item.walk();
                         for decl in item.children(&mut decl_cursor) {
+                            if rules.apply(source, decl, &mut output, "    ") {
+                                continue;
+                            }
                             match decl.kind() {
                                 "method_declaration" | "constructor_declaration" => {
                                     push_indented(
@@ -781,7 +1636,7 @@ fn compress_java_class(source: &str, node: tree_sitter::Node) -> String {
                         push_indented_block(
                             &mut output,
                             "    ",
-                            &compress_java_class(source, item),
+                            &compress_java_class(source, rules, item),
                         );
                     }
                     _ => {}
@@ -799,14 +1654,17 @@ fn compress_java_class(source: &str, node: tree_sitter::Node) -> String {
 // C# Compressor
 // ============================================================================
 
-fn compress_csharp(source: &str, root: tree_sitter::Node) -> String {
+fn compress_csharp(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "namespace_declaration" | "file_scoped_namespace_declaration" => {
-                output.push_str(&compress_csharp_namespace(source, child));
+                output.push_str(&compress_csharp_namespace(source, rules, child));
                 output.push('\n');
             }
             "class_declaration"
@@ -814,7 +1672,7 @@ fn compress_csharp(source: &str, root: tree_sitter::Node) -> String {
             | "struct_declaration"
             | "enum_declaration"
             | "record_declaration" => {
-                output.push_str(&compress_csharp_class(source, child));
+                output.push_str(&compress_csharp_class(source, rules, child));
                 output.push('\n');
             }
             "using_directive" | "comment" => {
@@ -828,7 +1686,7 @@ fn compress_csharp(source: &str, root: tree_sitter::Node) -> String {
     output.trim_end().to_string()
 }
 
-fn compress_csharp_namespace(source: &str, node: tree_sitter::Node) -> String {
+fn compress_csharp_namespace(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -839,6 +1697,9 @@ fn compress_csharp_namespace(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "class_declaration"
                     | "interface_declaration"
@@ -848,7 +1709,7 @@ fn compress_csharp_namespace(source: &str, node: tree_sitter::Node) -> String {
                         push_indented_block(
                             &mut output,
                             "    ",
-                            &compress_csharp_class(source, item),
+                            &compress_csharp_class(source, rules, item),
                         );
                     }
                     "using_directive" | "comment" => {
@@ -865,7 +1726,7 @@ fn compress_csharp_namespace(source: &str, node: tree_sitter::Node) -> String {
     node_text(source, node).to_string()
 }
 
-fn compress_csharp_class(source: &str, node: tree_sitter::Node) -> String {
+fn compress_csharp_class(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -876,6 +1737,9 @@ fn compress_csharp_class(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "method_declaration" | "constructor_declaration" => {
                         push_indented(
@@ -905,7 +1769,7 @@ fn compress_csharp_class(source: &str, node: tree_sitter::Node) -> String {
                         push_indented_block(
                             &mut output,
                             "    ",
-                            &compress_csharp_class(source, item),
+                            &compress_csharp_class(source, rules, item),
                         );
                     }
                     _ => {}
@@ -923,21 +1787,28 @@ fn compress_csharp_class(source: &str, node: tree_sitter::Node) -> String {
 // C Compressor
 // ============================================================================
 
-fn compress_c(source: &str, root: tree_sitter::Node) -> String {
+fn compress_c(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "function_definition" => {
                 output.push_str(&compress_body(source, child, &["compound_statement"]));
                 output.push('\n');
             }
+            "preproc_ifdef" | "preproc_if" | "preproc_ifndef" => {
+                output.push_str(&compress_preproc_conditional(source, child, &|item| {
+                    (item.kind() == "function_definition")
+                        .then(|| compress_body(source, item, &["compound_statement"]))
+                }));
+                output.push('\n');
+            }
             "preproc_include"
             | "preproc_def"
-            | "preproc_ifdef"
-            | "preproc_if"
-            | "preproc_ifndef"
             | "preproc_function_def"
             | "preproc_call"
             | "comment"
@@ -956,41 +1827,79 @@ fn compress_c(source: &str, root: tree_sitter::Node) -> String {
     output.trim_end().to_string()
 }
 
+/// An `#if`/`#ifdef` block with its directives and everything else kept as
+/// written, except the items `compress` has a shorter form for, in every
+/// branch and nested conditional. Lines after an item's first are indented to
+/// its column so the branch still lines up.
+fn compress_preproc_conditional(
+    source: &str,
+    node: tree_sitter::Node,
+    compress: &dyn Fn(tree_sitter::Node) -> Option<String>,
+) -> String {
+    let mut output = String::new();
+    let mut written = node.start_byte();
+    let mut cursor = node.walk();
+
+    for child in node.children(&mut cursor) {
+        let compressed = match child.kind() {
+            "preproc_if" | "preproc_ifdef" | "preproc_ifndef" | "preproc_else" | "preproc_elif"
+            | "preproc_elifdef" => Some(compress_preproc_conditional(source, child, compress)),
+            _ => compress(child).map(|text| {
+                let indent = " ".repeat(child.start_position().column);
+                text.replace('\n', &format!("\n{}", indent))
+            }),
+        };
+        if let Some(text) = compressed {
+            output.push_str(&source[written..child.start_byte()]);
+            output.push_str(&text);
+            written = child.end_byte();
+        }
+    }
+    output.push_str(&source[written..node.end_byte()]);
+    output
+}
+
 // ============================================================================
 // C++ Compressor
 // ============================================================================
 
-fn compress_cpp(source: &str, root: tree_sitter::Node) -> String {
+fn compress_cpp(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "function_definition" => {
                 output.push_str(&compress_body(source, child, &["compound_statement"]));
                 output.push('\n');
             }
             "class_specifier" => {
-                output.push_str(&compress_cpp_class(source, child));
+                output.push_str(&compress_cpp_class(source, rules, child));
                 output.push('\n');
             }
             "namespace_definition" => {
-                output.push_str(&compress_cpp_namespace(source, child));
+                output.push_str(&compress_cpp_namespace(source, rules, child));
                 output.push('\n');
             }
             "template_declaration" => {
-                output.push_str(&compress_cpp_template(source, child));
+                output.push_str(&compress_cpp_template(source, rules, child));
                 output.push('\n');
             }
             "linkage_specification" => {
-                output.push_str(&compress_cpp_linkage(source, child));
+                output.push_str(&compress_cpp_linkage(source, rules, child));
+                output.push('\n');
+            }
+            "preproc_ifdef" | "preproc_if" | "preproc_ifndef" => {
+                output.push_str(&compress_preproc_conditional(source, child, &|item| {
+                    compress_cpp_item(source, rules, item)
+                }));
                 output.push('\n');
             }
             "preproc_include"
             | "preproc_def"
-            | "preproc_ifdef"
-            | "preproc_if"
-            | "preproc_ifndef"
             | "preproc_function_def"
             | "preproc_call"
             | "comment"
@@ -1011,7 +1920,19 @@ fn compress_cpp(source: &str, root: tree_sitter::Node) -> String {
     output.trim_end().to_string()
 }
 
-fn compress_cpp_class(source: &str, node: tree_sitter::Node) -> String {
+/// The compressed form of a C++ item found in a preprocessor conditional
+fn compress_cpp_item(source: &str, rules: &Policy, node: tree_sitter::Node) -> Option<String> {
+    match node.kind() {
+        "function_definition" => Some(compress_body(source, node, &["compound_statement"])),
+        "class_specifier" => Some(compress_cpp_class(source, rules, node)),
+        "namespace_definition" => Some(compress_cpp_namespace(source, rules, node)),
+        "template_declaration" => Some(compress_cpp_template(source, rules, node)),
+        "linkage_specification" => Some(compress_cpp_linkage(source, rules, node)),
+        _ => None,
+    }
+}
+
+fn compress_cpp_class(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -1022,6 +1943,9 @@ fn compress_cpp_class(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "function_definition" => {
                         push_indented(
@@ -1034,13 +1958,18 @@ fn compress_cpp_class(source: &str, node: tree_sitter::Node) -> String {
                         push_indented_block(
                             &mut output,
                             "    ",
-                            &compress_cpp_template(source, item),
+                            &compress_cpp_template(source, rules, item),
                         );
                     }
+                    "preproc_ifdef" | "preproc_if" | "preproc_ifndef" => {
+                        let conditional = compress_preproc_conditional(source, item, &|member| {
+                            compress_cpp_item(source, rules, member)
+                        });
+                        push_indented(&mut output, "    ", &conditional);
+                    }
                     "field_declaration" | "declaration" | "using_declaration"
                     | "alias_declaration" | "type_definition" | "access_specifier"
-                    | "friend_declaration" | "preproc_ifdef" | "preproc_if" | "preproc_ifndef"
-                    | "preproc_def" | "preproc_call" | "comment" => {
+                    | "friend_declaration" | "preproc_def" | "preproc_call" | "comment" => {
                         push_indented(&mut output, "    ", node_text(source, item));
                     }
                     _ => {}
@@ -1054,7 +1983,7 @@ fn compress_cpp_class(source: &str, node: tree_sitter::Node) -> String {
     node_text(source, node).to_string()
 }
 
-fn compress_cpp_namespace(source: &str, node: tree_sitter::Node) -> String {
+fn compress_cpp_namespace(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -1065,6 +1994,9 @@ fn compress_cpp_namespace(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "function_definition" => {
                         push_indented(
@@ -1074,26 +2006,35 @@ fn compress_cpp_namespace(source: &str, node: tree_sitter::Node) -> String {
                         );
                     }
                     "class_specifier" => {
-                        push_indented_block(&mut output, "    ", &compress_cpp_class(source, item));
+                        push_indented_block(
+                            &mut output,
+                            "    ",
+                            &compress_cpp_class(source, rules, item),
+                        );
                     }
                     "template_declaration" => {
                         push_indented_block(
                             &mut output,
                             "    ",
-                            &compress_cpp_template(source, item),
+                            &compress_cpp_template(source, rules, item),
                         );
                     }
                     "namespace_definition" => {
                         push_indented_block(
                             &mut output,
                             "    ",
-                            &compress_cpp_namespace(source, item),
+                            &compress_cpp_namespace(source, rules, item),
                         );
                     }
+                    "preproc_ifdef" | "preproc_if" | "preproc_ifndef" => {
+                        let conditional = compress_preproc_conditional(source, item, &|member| {
+                            compress_cpp_item(source, rules, member)
+                        });
+                        push_indented(&mut output, "    ", &conditional);
+                    }
                     "struct_specifier" | "enum_specifier" | "union_specifier" | "declaration"
                     | "type_definition" | "using_declaration" | "alias_declaration"
-                    | "preproc_ifdef" | "preproc_if" | "preproc_ifndef" | "preproc_def"
-                    | "preproc_call" | "comment" => {
+                    | "preproc_def" | "preproc_call" | "comment" => {
                         push_indented(&mut output, "    ", node_text(source, item));
                     }
                     _ => {}
@@ -1107,7 +2048,7 @@ fn compress_cpp_namespace(source: &str, node: tree_sitter::Node) -> String {
     node_text(source, node).to_string()
 }
 
-fn compress_cpp_template(source: &str, node: tree_sitter::Node) -> String {
+fn compress_cpp_template(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut cursor = node.walk();
     for child in node.children(&mut cursor) {
         let prefix = source[node.start_byte()..child.start_byte()].trim_end();
@@ -1120,7 +2061,7 @@ fn compress_cpp_template(source: &str, node: tree_sitter::Node) -> String {
                 );
             }
             "class_specifier" => {
-                return format!("{}\n{}", prefix, compress_cpp_class(source, child));
+                return format!("{}\n{}", prefix, compress_cpp_class(source, rules, child));
             }
             "declaration" => {
                 return format!("{}\n{}", prefix, node_text(source, child));
@@ -1131,7 +2072,7 @@ fn compress_cpp_template(source: &str, node: tree_sitter::Node) -> String {
     node_text(source, node).to_string()
 }
 
-fn compress_cpp_linkage(source: &str, node: tree_sitter::Node) -> String {
+fn compress_cpp_linkage(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -1142,6 +2083,9 @@ fn compress_cpp_linkage(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "function_definition" => {
                         push_indented(
@@ -1168,11 +2112,14 @@ fn compress_cpp_linkage(source: &str, node: tree_sitter::Node) -> String {
 // Ruby Compressor
 // ============================================================================
 
-fn compress_ruby(source: &str, root: tree_sitter::Node) -> String {
+fn compress_ruby(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "comment" => {
                 output.push_str(node_text(source, child));
@@ -1190,7 +2137,7 @@ fn compress_ruby(source: &str, root: tree_sitter::Node) -> String {
                 output.push('\n');
             }
             "class" | "module" => {
-                output.push_str(&compress_ruby_class(source, child));
+                output.push_str(&compress_ruby_class(source, rules, child));
                 output.push('\n');
             }
             "assignment" => {
@@ -1218,7 +2165,7 @@ fn compress_ruby_method(source: &str, node: tree_sitter::Node) -> String {
     node_text(source, node).to_string()
 }
 
-fn compress_ruby_class(source: &str, node: tree_sitter::Node) -> String {
+fn compress_ruby_class(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -1230,12 +2177,19 @@ fn compress_ruby_class(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "  ") {
+                    continue;
+                }
                 match item.kind() {
                     "method" | "singleton_method" => {
                         push_indented_block(&mut output, "  ", &compress_ruby_method(source, item));
                     }
                     "class" | "module" => {
-                        push_indented_block(&mut output, "  ", &compress_ruby_class(source, item));
+                        push_indented_block(
+                            &mut output,
+                            "  ",
+                            &compress_ruby_class(source, rules, item),
+                        );
                     }
                     "comment" => {
                         push_indented(&mut output, "  ", node_text(source, item));
@@ -1262,25 +2216,28 @@ fn compress_ruby_class(source: &str, node: tree_sitter::Node) -> String {
 // PHP Compressor
 // ============================================================================
 
-fn compress_php(source: &str, root: tree_sitter::Node) -> String {
+fn compress_php(source: &str, rules: &Policy, root: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = root.walk();
 
     for child in root.children(&mut cursor) {
+        if rules.apply(source, child, &mut output, "") {
+            continue;
+        }
         match child.kind() {
             "function_definition" => {
                 output.push_str(&compress_body(source, child, &["compound_statement"]));
                 output.push('\n');
             }
             "namespace_definition" => {
-                output.push_str(&compress_php_namespace(source, child));
+                output.push_str(&compress_php_namespace(source, rules, child));
                 output.push('\n');
             }
             "class_declaration"
             | "interface_declaration"
             | "trait_declaration"
             | "enum_declaration" => {
-                output.push_str(&compress_php_class(source, child));
+                output.push_str(&compress_php_class(source, rules, child));
                 output.push('\n');
             }
             "php_tag" | "namespace_use_declaration" | "const_declaration" | "comment" => {
@@ -1294,7 +2251,7 @@ fn compress_php(source: &str, root: tree_sitter::Node) -> String {
     output.trim_end().to_string()
 }
 
-fn compress_php_namespace(source: &str, node: tree_sitter::Node) -> String {
+fn compress_php_namespace(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -1305,12 +2262,19 @@ fn compress_php_namespace(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "class_declaration"
                     | "interface_declaration"
                     | "trait_declaration"
                     | "enum_declaration" => {
-                        push_indented_block(&mut output, "    ", &compress_php_class(source, item));
+                        push_indented_block(
+                            &mut output,
+                            "    ",
+                            &compress_php_class(source, rules, item),
+                        );
                     }
                     "function_definition" => {
                         push_indented(
@@ -1334,7 +2298,7 @@ fn compress_php_namespace(source: &str, node: tree_sitter::Node) -> String {
     node_text(source, node).to_string()
 }
 
-fn compress_php_class(source: &str, node: tree_sitter::Node) -> String {
+fn compress_php_class(source: &str, rules: &Policy, node: tree_sitter::Node) -> String {
     let mut output = String::new();
     let mut cursor = node.walk();
 
@@ -1345,6 +2309,9 @@ fn compress_php_class(source: &str, node: tree_sitter::Node) -> String {
 
             let mut inner_cursor = child.walk();
             for item in child.children(&mut inner_cursor) {
+                if rules.apply(source, item, &mut output, "    ") {
+                    continue;
+                }
                 match item.kind() {
                     "method_declaration" => {
                         push_indented(
@@ -1395,7 +2362,10 @@ mod tests {
         assert_eq!(language_for_extension("jsx"), Some(CompressLanguage::Jsx));
         assert_eq!(language_for_extension("py"), Some(CompressLanguage::Python));
         assert_eq!(language_for_extension("go"), Some(CompressLanguage::Go));
-        assert_eq!(language_for_extension("md"), None);
+        assert_eq!(
+            language_for_extension("md"),
+            Some(CompressLanguage::Markdown)
+        );
         assert_eq!(language_for_extension("toml"), None);
     }
 
@@ -1410,7 +2380,7 @@ mod tests {
             Some(CompressLanguage::TypeScript)
         );
         assert_eq!(language_for_path(Path::new("Makefile")), None);
-        assert_eq!(language_for_path(Path::new("README.md")), None);
+        assert_eq!(language_for_path(Path::new("NOTES.txt")), None);
     }
 
     // Rust compression tests
@@ -1978,6 +2948,43 @@ void cleanup(Node *head);
         }
     }
 
+    #[test]
+    fn test_compress_c_function_bodies_inside_conditionals() {
+        let source = r#"#ifdef _WIN32
+int open_port(const char *name) {
+    HANDLE handle = CreateFileA(name, GENERIC_READ, 0, NULL, OPEN_EXISTING, 0, NULL);
+    return handle != INVALID_HANDLE_VALUE;
+}
+#elif defined(__linux__)
+int open_port(const char *name) {
+    return open(name, O_RDWR | O_NOCTTY);
+}
+#else
+#define PORTS_UNSUPPORTED 1
+#endif"#;
+        match compress_source(source, CompressLanguage::C) {
+            CompressResult::Compressed(output) => {
+                assert!(output.contains("#ifdef _WIN32"), "{}", output);
+                assert!(output.contains("#elif defined(__linux__)"), "{}", output);
+                assert!(output.contains("#define PORTS_UNSUPPORTED 1"), "{}", output);
+                assert!(output.ends_with("#endif"), "{}", output);
+                assert_eq!(
+                    output
+                        .matches("int open_port(const char *name) { ... }")
+                        .count(),
+                    2,
+                    "{}",
+                    output
+                );
+                assert!(!output.contains("CreateFileA"), "{}", output);
+                assert!(!output.contains("O_NOCTTY"), "{}", output);
+            }
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+    }
+
     // C++ compression tests
     #[test]
     fn test_compress_cpp_class() {
@@ -2316,6 +3323,8 @@ private:
                     "Preprocessor endif should be preserved"
                 );
                 assert!(output.contains("std::string name_;"));
+                assert!(output.contains("void debugPrint() { ... }"), "{}", output);
+                assert!(!output.contains("std::cout"), "{}", output);
             }
             CompressResult::Fallback(_, reason) => {
                 panic!("Expected compression, got fallback: {:?}", reason)
@@ -2371,4 +3380,491 @@ private:
             }
         }
     }
+
+    #[test]
+    fn test_compress_file_outlines_generated_go_stub() {
+        let source = r#"package greeter
+
+type HelloRequest struct {
+	state protoimpl.MessageState
+	Name  string `protobuf:"bytes,1,opt,name=name,proto3" json:"name,omitempty"`
+}
+
+func (x *HelloRequest) GetName() string {
+	if x != nil {
+		return x.Name
+	}
+	return ""
+}
+
+type GreeterClient interface {
+	SayHello(ctx context.Context, in *HelloRequest) (*HelloReply, error)
+}
+"#;
+        match compress_file(
+            Path::new("greeter.pb.go"),
+            source,
+            CompressLanguage::Go,
+            &NodeRules::default(),
+            CompressLevel::default(),
+            &ParseLimits::default(),
+            &CancelToken::default(),
+        ) {
+            CompressResult::Compressed(output) => {
+                assert_eq!(output, "type HelloRequest\ntype GreeterClient\n");
+            }
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_file_outlines_generated_python_stub() {
+        let source = r#"DESCRIPTOR = _descriptor.FileDescriptor(name='greeter.proto')
+
+HelloRequest = _reflection.GeneratedProtocolMessageType('HelloRequest', (_message.Message,), {})
+
+class GreeterStub(object):
+    def __init__(self, channel):
+        self.SayHello = channel.unary_unary('/Greeter/SayHello')
+"#;
+        match compress_file(
+            Path::new("greeter_pb2.py"),
+            source,
+            CompressLanguage::Python,
+            &NodeRules::default(),
+            CompressLevel::default(),
+            &ParseLimits::default(),
+            &CancelToken::default(),
+        ) {
+            CompressResult::Compressed(output) => {
+                assert_eq!(output, "class GreeterStub\nclass HelloRequest\n");
+            }
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_limits_fall_back_to_full_content() {
+        let source = "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n";
+        let limits = ParseLimits {
+            max_size: 10,
+            ..Default::default()
+        };
+        match compress_source_with_rules(
+            source,
+            CompressLanguage::Rust,
+            &NodeRules::default(),
+            CompressLevel::default(),
+            &limits,
+            &CancelToken::default(),
+        ) {
+            CompressResult::Fallback(output, Some(reason)) => {
+                assert_eq!(output, source);
+                assert!(reason.contains("--max-compress-size"), "{}", reason);
+            }
+            other => panic!("Expected size fallback, got {:?}", other),
+        }
+
+        // Big enough that parsing can't finish within a microsecond
+        let source = "fn f() { let x = [1, 2, 3]; }\n".repeat(20_000);
+        let limits = ParseLimits {
+            timeout: Duration::from_micros(1),
+            max_size: u64::MAX,
+        };
+        match compress_source_with_rules(
+            &source,
+            CompressLanguage::Rust,
+            &NodeRules::default(),
+            CompressLevel::default(),
+            &limits,
+            &CancelToken::default(),
+        ) {
+            CompressResult::Fallback(output, Some(reason)) => {
+                assert_eq!(output, source);
+                assert!(reason.contains("timed out"), "{}", reason);
+            }
+            other => panic!("Expected timeout fallback, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_large_literals_elided() {
+        let blob = "QUJD".repeat(400);
+        let source = format!(
+            "const LOGO: &str = \"{}\";\nstatic TABLE: [u8; 600] = [{}];\nconst NAME: &str = \"flat\";\n\nfn main() {{\n    println!(\"hi\");\n}}\n",
+            blob,
+            "1, ".repeat(600)
+        );
+        match compress_source(&source, CompressLanguage::Rust) {
+            CompressResult::Compressed(output) => {
+                assert!(!output.contains("QUJD"), "{}", output);
+                assert!(
+                    output.contains("const LOGO: &str = \"… 1602 bytes\";"),
+                    "{}",
+                    output
+                );
+                assert!(
+                    output.contains("static TABLE: [u8; 600] = [… 1802 bytes];"),
+                    "{}",
+                    output
+                );
+                assert!(output.contains("const NAME: &str = \"flat\";"));
+            }
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+
+        let source = format!(
+            "export const QUERY = `{}`;\n\nexport function run() {{\n  return 1;\n}}\n",
+            "SELECT 1; ".repeat(200)
+        );
+        match compress_source(&source, CompressLanguage::TypeScript) {
+            CompressResult::Compressed(output) => {
+                assert!(
+                    output.starts_with("export const QUERY = `… 2002 bytes`;\n"),
+                    "{}",
+                    output
+                );
+            }
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+    }
+
+    #[test]
+    fn test_ci_pipeline_detection() {
+        assert_eq!(
+            language_for_path(Path::new(".github/workflows/ci.yml")),
+            Some(CompressLanguage::CiPipeline)
+        );
+        assert_eq!(
+            language_for_path(Path::new("repo/.gitlab-ci.yml")),
+            Some(CompressLanguage::CiPipeline)
+        );
+        assert_eq!(
+            language_for_path(Path::new(".circleci/config.yml")),
+            Some(CompressLanguage::CiPipeline)
+        );
+        assert_eq!(language_for_path(Path::new("config/app.yml")), None);
+        assert_eq!(language_for_path(Path::new(".github/dependabot.yml")), None);
+    }
+
+    #[test]
+    fn test_schema_language_detection() {
+        assert_eq!(
+            language_for_path(Path::new("migrations/001_init.SQL")),
+            Some(CompressLanguage::Sql)
+        );
+        assert_eq!(
+            language_for_path(Path::new("api/users.proto")),
+            Some(CompressLanguage::Protobuf)
+        );
+        assert_eq!(
+            language_for_path(Path::new("schema.gql")),
+            Some(CompressLanguage::GraphQl)
+        );
+        assert_eq!(CompressLanguage::GraphQl.code_fence_tag(), "graphql");
+    }
+
+    #[test]
+    fn test_compress_github_workflow() {
+        let source = r#"name: CI
+on:
+  push:
+    branches: [main]
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+      - name: Run tests
+        run: cargo test --all
+      - name: Publish
+        run: |
+          cargo login $TOKEN
+          cargo publish --allow-dirty
+      - name: Long one-liner
+        run: ./scripts/release.sh --channel stable --sign --upload --notify-everyone-on-slack
+"#;
+        let expected = r#"name: CI
+on:
+  push:
+    branches: [main]
+jobs:
+  test:
+    runs-on: ubuntu-latest
+    steps:
+      - uses: actions/checkout@v4
+      - name: Run tests
+        run: cargo test --all
+      - name: Publish
+        run: ...
+      - name: Long one-liner
+        run: ...
+"#;
+        match compress_source(source, CompressLanguage::CiPipeline) {
+            CompressResult::Compressed(output) => assert_eq!(output, expected),
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_gitlab_and_circleci_scripts() {
+        let source = r#"build:
+  stage: build
+  script:
+  - make deps
+  - make build
+  artifacts:
+    paths: [out/]
+jobs:
+  deploy:
+    steps:
+      - run:
+          name: Deploy
+          command: |
+            ./deploy.sh
+"#;
+        let expected = r#"build:
+  stage: build
+  script: ...
+  artifacts:
+    paths: [out/]
+jobs:
+  deploy:
+    steps:
+      - run:
+          name: Deploy
+          command: ...
+"#;
+        match compress_source(source, CompressLanguage::CiPipeline) {
+            CompressResult::Compressed(output) => assert_eq!(output, expected),
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+    }
+
+    #[test]
+    fn test_node_rules_keep_and_drop() {
+        let source = r#"// helper
+fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+impl Calc {
+    fn double(&self) -> i32 {
+        self.0 * 2
+    }
+}
+"#;
+        let rules = NodeRules {
+            keep: vec!["function_item".to_string()],
+            drop: vec!["line_comment".to_string()],
+        };
+        match compress_source_with_rules(
+            source,
+            CompressLanguage::Rust,
+            &rules,
+            CompressLevel::default(),
+            &ParseLimits::default(),
+            &CancelToken::default(),
+        ) {
+            CompressResult::Compressed(output) => {
+                assert!(!output.contains("// helper"));
+                assert!(output.contains("a + b"), "kept verbatim: {}", output);
+                assert!(
+                    output.contains("self.0 * 2"),
+                    "kept inside impl: {}",
+                    output
+                );
+            }
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+        assert!(is_node_kind(CompressLanguage::Rust, "macro_invocation"));
+        assert!(!is_node_kind(CompressLanguage::Rust, "macro_invokation"));
+    }
+
+    fn compress_at(source: &str, lang: CompressLanguage, level: CompressLevel) -> String {
+        match compress_source_with_rules(
+            source,
+            lang,
+            &NodeRules::default(),
+            level,
+            &ParseLimits::default(),
+            &CancelToken::default(),
+        ) {
+            CompressResult::Compressed(output) => output,
+            CompressResult::Fallback(_, reason) => {
+                panic!("Expected compression, got fallback: {:?}", reason)
+            }
+        }
+    }
+
+    #[test]
+    fn test_compress_level_minimal_keeps_public_signatures() {
+        let source = r#"use std::fmt;
+
+/// Adds numbers
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+// not public
+fn helper() -> i32 {
+    1
+}
+
+impl fmt::Display for Calc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        Ok(())
+    }
+}
+"#;
+        let output = compress_at(source, CompressLanguage::Rust, CompressLevel::Minimal);
+        assert!(output.contains("pub fn add(a: i32, b: i32) -> i32 { ... }"));
+        assert!(
+            output.contains("fn fmt("),
+            "trait impl members stay: {}",
+            output
+        );
+        for dropped in ["use std", "/// Adds", "// not public", "helper"] {
+            assert!(!output.contains(dropped), "{} kept: {}", dropped, output);
+        }
+
+        let source = "def public():\n    \"\"\"Doc.\"\"\"\n    return 1\n\ndef _private():\n    return 2\n\ndef __init__():\n    pass\n";
+        let output = compress_at(source, CompressLanguage::Python, CompressLevel::Minimal);
+        assert_eq!(output, "def public():\n    ...\ndef __init__():\n    ...");
+
+        let output = compress_at(
+            "fn helper() {}\n",
+            CompressLanguage::Rust,
+            CompressLevel::Minimal,
+        );
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn test_compress_level_public_drops_private_items_whole() {
+        let source = r#"//! Crate docs
+use std::fmt;
+
+/// Adds numbers
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+/// Only used here
+#[inline]
+fn helper() -> i32 {
+    1
+}
+
+pub struct Calc;
+
+impl Calc {
+    pub fn new() -> Self {
+        Calc
+    }
+
+    // internal
+    fn reset(&mut self) {}
+}
+"#;
+        let output = compress_at(source, CompressLanguage::Rust, CompressLevel::Public);
+        for kept in [
+            "//! Crate docs",
+            "use std::fmt;",
+            "/// Adds numbers",
+            "pub fn add(a: i32, b: i32) -> i32 { ... }",
+            "pub fn new() -> Self { ... }",
+        ] {
+            assert!(output.contains(kept), "{} dropped: {}", kept, output);
+        }
+        for dropped in [
+            "Only used here",
+            "#[inline]",
+            "helper",
+            "// internal",
+            "reset",
+        ] {
+            assert!(!output.contains(dropped), "{} kept: {}", dropped, output);
+        }
+
+        let source = "import { x } from './x';\n\nexport function shown(): number {\n  return x;\n}\n\n// hidden\nfunction hidden(): number {\n  return 1;\n}\n";
+        let output = compress_at(source, CompressLanguage::TypeScript, CompressLevel::Public);
+        assert!(output.contains("import { x } from './x';"), "{}", output);
+        assert!(
+            output.contains("export function shown(): number"),
+            "{}",
+            output
+        );
+        assert!(!output.contains("hidden"), "{}", output);
+
+        let source =
+            "package a\n\n// Hello says hi.\nfunc Hello() {\n\tbye()\n}\n\nfunc bye() {}\n";
+        let output = compress_at(source, CompressLanguage::Go, CompressLevel::Public);
+        assert!(
+            output.contains("// Hello says hi.\nfunc Hello() { ... }"),
+            "{}",
+            output
+        );
+        assert!(!output.contains("func bye"), "{}", output);
+    }
+
+    #[test]
+    fn test_compress_level_docs_keeps_only_doc_comments() {
+        let source = r#"//! Crate docs
+use std::fmt;
+
+/// Adds numbers
+pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+// helper
+fn helper() -> i32 {
+    1
+}
+"#;
+        let output = compress_at(source, CompressLanguage::Rust, CompressLevel::Docs);
+        assert!(output.contains("//! Crate docs"));
+        assert!(output.contains("/// Adds numbers"));
+        assert!(output.contains("fn helper() -> i32 { ... }"));
+        assert!(!output.contains("// helper"), "{}", output);
+        assert!(!output.contains("use std"), "{}", output);
+
+        let source =
+            "package a\n\n// Hello says hi.\nfunc Hello() {}\n\n// stray\n\nfunc bye() {}\n";
+        let output = compress_at(source, CompressLanguage::Go, CompressLevel::Docs);
+        assert!(output.contains("// Hello says hi."));
+        assert!(!output.contains("// stray"), "{}", output);
+    }
+
+    #[test]
+    fn test_cancelled_parse_falls_back_quietly() {
+        let cancel = CancelToken::default();
+        cancel.cancel();
+        match compress_source_with_rules(
+            "fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+            CompressLanguage::Rust,
+            &NodeRules::default(),
+            CompressLevel::default(),
+            &ParseLimits::default(),
+            &cancel,
+        ) {
+            CompressResult::Fallback(_, reason) => assert_eq!(reason, None),
+            other => panic!("Expected a quiet fallback, got {:?}", other),
+        }
+    }
 }
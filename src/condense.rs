@@ -0,0 +1,858 @@
+//! Condensers for languages compressed without tree-sitter: SQL, Protocol
+//! Buffers, and GraphQL keep their declarations (tables, messages, types) and
+//! drop comments, data, and bodies; Markdown keeps its heading outline.
+
+/// Statements that carry rows rather than schema
+const SQL_DATA_STATEMENTS: &[&str] = &[
+    "INSERT", "UPDATE", "DELETE", "COPY", "MERGE", "REPLACE", "UPSERT", "SELECT", "VALUES",
+];
+
+/// Condense SQL to its schema: DDL stays, runs of data statements become one
+/// `-- ...` line, and function, procedure, and trigger bodies become `...`.
+pub(crate) fn condense_sql(source: &str) -> String {
+    let mut output = String::new();
+    let mut dropped: Vec<String> = Vec::new();
+    let mut previous_multiline = false;
+
+    for statement in sql_statements(source) {
+        let keyword = first_word(&statement).to_ascii_uppercase();
+        if SQL_DATA_STATEMENTS.contains(&keyword.as_str()) {
+            dropped.push(keyword);
+            continue;
+        }
+        flush_dropped(&mut output, &mut dropped);
+
+        let statement = if is_sql_routine(&statement) {
+            elide_routine_body(&statement)
+        } else {
+            statement
+        };
+        let multiline = statement.contains('\n');
+        if !output.is_empty() && (multiline || previous_multiline) {
+            output.push('\n');
+        }
+        output.push_str(&statement);
+        output.push('\n');
+        previous_multiline = multiline;
+    }
+    flush_dropped(&mut output, &mut dropped);
+    output
+}
+
+/// Note a run of dropped data statements, by kind when they are all alike
+fn flush_dropped(output: &mut String, dropped: &mut Vec<String>) {
+    let Some(kind) = dropped.first() else {
+        return;
+    };
+    let kind = if dropped.iter().all(|k| k == kind) {
+        kind.as_str()
+    } else {
+        "data"
+    };
+    let plural = if dropped.len() == 1 { "" } else { "s" };
+    output.push_str(&format!(
+        "-- ... {} {} statement{}\n",
+        dropped.len(),
+        kind,
+        plural
+    ));
+    dropped.clear();
+}
+
+fn first_word(text: &str) -> &str {
+    let text = text.trim_start();
+    let end = text
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+        .unwrap_or(text.len());
+    &text[..end]
+}
+
+/// `CREATE [OR REPLACE] FUNCTION|PROCEDURE|TRIGGER ...`
+fn is_sql_routine(statement: &str) -> bool {
+    let words: Vec<String> = statement
+        .split_whitespace()
+        .take(6)
+        .map(|w| w.to_ascii_uppercase())
+        .collect();
+    words.first().is_some_and(|w| w == "CREATE")
+        && words
+            .iter()
+            .any(|w| matches!(w.as_str(), "FUNCTION" | "PROCEDURE" | "TRIGGER"))
+}
+
+/// A routine with its dollar-quoted or `BEGIN ... END` body replaced by `...`
+fn elide_routine_body(statement: &str) -> String {
+    if let Some((open, tag)) = (0..statement.len())
+        .filter(|&i| statement.is_char_boundary(i))
+        .find_map(|i| dollar_tag(&statement[i..]).map(|tag| (i, tag)))
+    {
+        let body = open + tag.len();
+        if let Some(close) = statement[body..].find(tag) {
+            return format!("{} ... {}", &statement[..body], &statement[body + close..]);
+        }
+    }
+    match find_word(statement, "BEGIN") {
+        Some(begin) => format!("{}BEGIN ... END;", &statement[..begin]),
+        None => statement.to_string(),
+    }
+}
+
+/// The `$tag$` opening a PostgreSQL dollar-quoted string at the start of `text`
+fn dollar_tag(text: &str) -> Option<&str> {
+    let rest = text.strip_prefix('$')?;
+    let len = rest.find(|c: char| !(c.is_ascii_alphabetic() || c == '_'))?;
+    rest[len..].starts_with('$').then(|| &text[..len + 2])
+}
+
+/// Byte offset of `word` (case-insensitive) standing on its own in `text`
+fn find_word(text: &str, word: &str) -> Option<usize> {
+    let upper = text.to_ascii_uppercase();
+    let bytes = upper.as_bytes();
+    let is_ident = |b: u8| b.is_ascii_alphanumeric() || b == b'_';
+    upper.match_indices(word).map(|(i, _)| i).find(|&i| {
+        let end = i + word.len();
+        (i == 0 || !is_ident(bytes[i - 1])) && (end == bytes.len() || !is_ident(bytes[end]))
+    })
+}
+
+/// Split SQL into statements with comments removed, honoring quotes,
+/// dollar quoting, MySQL `DELIMITER` lines, `BEGIN ... END` routine bodies,
+/// and the inline data of `COPY ... FROM stdin`
+fn sql_statements(source: &str) -> Vec<String> {
+    let bytes = source.as_bytes();
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut delimiter = ";".to_string();
+    let mut block_depth = 0usize;
+    let mut i = 0;
+
+    while i < source.len() {
+        let rest = &source[i..];
+        let line_start = i == 0 || bytes[i - 1] == b'\n';
+        if line_start && current.trim().is_empty() {
+            let line = rest.lines().next().unwrap_or("");
+            let mut words = line.split_whitespace();
+            if words
+                .next()
+                .is_some_and(|w| w.eq_ignore_ascii_case("DELIMITER"))
+            {
+                if let Some(new_delimiter) = words.next() {
+                    delimiter = new_delimiter.to_string();
+                }
+                i += line.len();
+                continue;
+            }
+        }
+
+        if block_depth == 0 && rest.starts_with(delimiter.as_str()) {
+            i += delimiter.len();
+            let statement = tidy_statement(&current);
+            current.clear();
+            if statement.is_empty() {
+                continue;
+            }
+            let upper = statement.to_ascii_uppercase();
+            if upper.starts_with("COPY") && upper.contains("FROM STDIN") {
+                // The rows follow up to a `\.` line
+                let data = &source[i..];
+                i += match data.find("\n\\.") {
+                    Some(end) => end + 3,
+                    None => data.len(),
+                };
+            }
+            statements.push(format!("{};", statement));
+            continue;
+        }
+
+        if rest.starts_with("--") {
+            i += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        }
+        if let Some(comment) = rest.strip_prefix("/*") {
+            i += comment.find("*/").map_or(rest.len(), |end| end + 4);
+            current.push(' ');
+            continue;
+        }
+        if let Some(tag) = dollar_tag(rest) {
+            let body = &rest[tag.len()..];
+            let len = body.find(tag).map_or(rest.len(), |end| tag.len() * 2 + end);
+            current.push_str(&rest[..len]);
+            i += len;
+            continue;
+        }
+        if let Some(quote) = rest
+            .chars()
+            .next()
+            .filter(|c| matches!(c, '\'' | '"' | '`'))
+        {
+            let len = quoted_len(rest, quote);
+            current.push_str(&rest[..len]);
+            i += len;
+            continue;
+        }
+
+        let ch = rest.chars().next().expect("non-empty");
+        let word_start = ch.is_ascii_alphabetic()
+            && (i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || bytes[i - 1] == b'_'));
+        if word_start {
+            let word = first_word(rest);
+            if word.eq_ignore_ascii_case("BEGIN") && is_sql_routine(&current) {
+                block_depth += 1;
+            } else if word.eq_ignore_ascii_case("END") && block_depth > 0 {
+                let after = rest[word.len()..].trim_start();
+                if after.starts_with(';') || after.starts_with(delimiter.as_str()) {
+                    block_depth -= 1;
+                }
+            }
+            current.push_str(word);
+            i += word.len();
+            continue;
+        }
+
+        current.push(ch);
+        i += ch.len_utf8();
+    }
+
+    let statement = tidy_statement(&current);
+    if !statement.is_empty() {
+        statements.push(statement);
+    }
+    statements
+}
+
+/// Length of the quoted string at the start of `text`, through its closing quote
+fn quoted_len(text: &str, quote: char) -> usize {
+    let mut escaped = false;
+    for (i, c) in text.char_indices().skip(1) {
+        if escaped {
+            escaped = false;
+        } else if c == '\\' {
+            escaped = true;
+        } else if c == quote {
+            return i + 1;
+        }
+    }
+    text.len()
+}
+
+/// A statement without blank lines, trailing spaces, or leading indentation
+fn tidy_statement(text: &str) -> String {
+    let lines: Vec<&str> = text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let mut tidy = lines.join("\n");
+    let indent = tidy.len() - tidy.trim_start().len();
+    tidy.drain(..indent);
+    tidy
+}
+
+/// Block declarations whose contents are schema, and are condensed in turn
+const PROTO_BLOCKS: &[&str] = &["message", "enum", "service", "oneof", "extend"];
+
+/// Widest line a block with a single statement is kept on
+const PROTO_ONE_LINE_WIDTH: usize = 80;
+
+/// Condense a `.proto` file to its declarations: messages, enums, and
+/// services with their fields and rpc signatures. Comments, nested options,
+/// and rpc option bodies are dropped.
+pub(crate) fn condense_protobuf(source: &str) -> String {
+    let text = strip_comments(source, "//", Some(("/*", "*/")));
+    let mut output = String::new();
+    condense_proto_block(&text, 0, &mut output);
+    output
+}
+
+fn condense_proto_block(text: &str, depth: usize, output: &mut String) {
+    let indent = "  ".repeat(depth);
+    for (head, body) in proto_items(text) {
+        let head = head.split_whitespace().collect::<Vec<_>>().join(" ");
+        let keyword = first_word(&head);
+        match body {
+            _ if depth > 0 && keyword == "option" => {}
+            Some(_) if keyword == "rpc" => output.push_str(&format!("{}{};\n", indent, head)),
+            Some(body) if PROTO_BLOCKS.contains(&keyword) => {
+                if depth == 0 && !output.is_empty() {
+                    output.push('\n');
+                }
+                let mut inner = String::new();
+                condense_proto_block(body, depth + 1, &mut inner);
+                // `message Empty {}` and `message Id { string id = 1; }` stay on one line
+                let one_line = match inner.trim() {
+                    "" => format!("{}{} {{}}", indent, head),
+                    item => format!("{}{} {{ {} }}", indent, head, item),
+                };
+                if inner.lines().count() <= 1 && one_line.len() <= PROTO_ONE_LINE_WIDTH {
+                    output.push_str(&one_line);
+                    output.push('\n');
+                } else {
+                    output.push_str(&format!("{}{} {{\n", indent, head));
+                    output.push_str(&inner);
+                    output.push_str(&format!("{}}}\n", indent));
+                }
+            }
+            Some(body) => {
+                let body = body.split_whitespace().collect::<Vec<_>>().join(" ");
+                output.push_str(&format!("{}{} {{ {} }};\n", indent, head, body));
+            }
+            None => output.push_str(&format!("{}{};\n", indent, head)),
+        }
+    }
+}
+
+/// Split protobuf text into `head;` statements and `head { body }` blocks
+fn proto_items(text: &str) -> Vec<(&str, Option<&str>)> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut nesting = 0usize; // parentheses and brackets
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let ch = rest.chars().next().expect("non-empty");
+        match ch {
+            '"' | '\'' => {
+                i += quoted_len(rest, ch);
+                continue;
+            }
+            '(' | '[' => nesting += 1,
+            ')' | ']' => nesting = nesting.saturating_sub(1),
+            ';' if nesting == 0 => {
+                let head = text[start..i].trim();
+                if !head.is_empty() {
+                    items.push((head, None));
+                }
+                start = i + 1;
+            }
+            '{' => {
+                let close = i + matching_brace(rest);
+                if nesting == 0 {
+                    let body = &text[i + 1..close.saturating_sub(1).max(i + 1)];
+                    items.push((text[start..i].trim(), Some(body)));
+                    start = close;
+                    // A `;` after a block closes nothing
+                    if text[start..].trim_start().starts_with(';') {
+                        start += text[start..].find(';').expect("checked") + 1;
+                    }
+                    i = start;
+                } else {
+                    i = close;
+                }
+                continue;
+            }
+            _ => {}
+        }
+        i += ch.len_utf8();
+    }
+    let head = text[start..].trim();
+    if !head.is_empty() {
+        items.push((head, None));
+    }
+    items
+}
+
+/// Length of the `{ ... }` block at the start of `text`, through its closing brace
+fn matching_brace(text: &str) -> usize {
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let ch = rest.chars().next().expect("non-empty");
+        match ch {
+            '"' | '\'' => {
+                i += quoted_len(rest, ch);
+                continue;
+            }
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += ch.len_utf8();
+    }
+    text.len()
+}
+
+/// `source` without comments; strings are left alone
+fn strip_comments(source: &str, line: &str, block: Option<(&str, &str)>) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut i = 0;
+    while i < source.len() {
+        let rest = &source[i..];
+        if rest.starts_with(line) {
+            i += rest.find('\n').unwrap_or(rest.len());
+            continue;
+        }
+        if let Some((open, close)) = block.filter(|(open, _)| rest.starts_with(open)) {
+            i += rest[open.len()..]
+                .find(close)
+                .map_or(rest.len(), |end| open.len() + end + close.len());
+            output.push(' ');
+            continue;
+        }
+        let ch = rest.chars().next().expect("non-empty");
+        let len = if ch == '"' || ch == '\'' {
+            quoted_len(rest, ch)
+        } else {
+            ch.len_utf8()
+        };
+        output.push_str(&rest[..len]);
+        i += len;
+    }
+    output
+}
+
+/// Definitions whose selection sets are dropped
+const GRAPHQL_OPERATIONS: &[&str] = &["query", "mutation", "subscription", "fragment"];
+
+/// Keywords that open a top-level GraphQL definition
+const GRAPHQL_DEFINITIONS: &[&str] = &[
+    "type",
+    "interface",
+    "input",
+    "enum",
+    "union",
+    "scalar",
+    "directive",
+    "schema",
+    "extend",
+    "query",
+    "mutation",
+    "subscription",
+    "fragment",
+];
+
+/// Condense GraphQL to its schema: type definitions keep their fields,
+/// operations and fragments keep their signatures with `{ ... }` for the
+/// selection set. Comments and descriptions are dropped.
+pub(crate) fn condense_graphql(source: &str) -> String {
+    let text = strip_graphql_noise(source);
+    let mut output = String::new();
+    let mut segment = 0;
+    // Where the open top-level definition's body starts, if it is dropped
+    let mut elided_at: Option<usize> = None;
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < text.len() {
+        let rest = &text[i..];
+        let ch = rest.chars().next().expect("non-empty");
+        match ch {
+            '"' => {
+                i += quoted_len(rest, ch);
+                continue;
+            }
+            '{' => {
+                if depth == 0 {
+                    let keyword = text[segment..i]
+                        .lines()
+                        .map(first_word)
+                        .rfind(|word| GRAPHQL_DEFINITIONS.contains(word));
+                    let is_operation = keyword.is_none_or(|k| GRAPHQL_OPERATIONS.contains(&k));
+                    elided_at = is_operation.then_some(i);
+                }
+                depth += 1;
+            }
+            '}' => {
+                depth = depth.saturating_sub(1);
+                if depth == 0 {
+                    match elided_at.take() {
+                        Some(open) => {
+                            output.push_str(&text[segment..open]);
+                            output.push_str("{ ... }");
+                        }
+                        None => output.push_str(&text[segment..=i]),
+                    }
+                    segment = i + 1;
+                }
+            }
+            _ => {}
+        }
+        i += ch.len_utf8();
+    }
+    output.push_str(&text[segment..]);
+    tidy_lines(&output)
+}
+
+/// GraphQL without `#` comments or descriptions: block strings, and strings
+/// that open a line. Lines left empty are removed.
+fn strip_graphql_noise(source: &str) -> String {
+    let mut output = String::with_capacity(source.len());
+    let mut i = 0;
+    while i < source.len() {
+        let rest = &source[i..];
+        let line_so_far = output.rsplit('\n').next().unwrap_or("");
+        let opens_line = line_so_far.trim().is_empty();
+        let removed = if rest.starts_with('#') {
+            rest.find('\n').unwrap_or(rest.len())
+        } else if let Some(block) = rest.strip_prefix("\"\"\"") {
+            block.find("\"\"\"").map_or(rest.len(), |end| end + 6)
+        } else if rest.starts_with('"') {
+            let len = quoted_len(rest, '"');
+            if !opens_line {
+                output.push_str(&rest[..len]);
+                i += len;
+                continue;
+            }
+            len
+        } else {
+            let ch = rest.chars().next().expect("non-empty");
+            output.push(ch);
+            i += ch.len_utf8();
+            continue;
+        };
+        i += removed;
+        let line_rest = source[i..].split_inclusive('\n').next().unwrap_or("");
+        if opens_line && line_rest.trim().is_empty() {
+            output.truncate(output.len() - line_so_far.len());
+            i += line_rest.len();
+        }
+    }
+    output
+}
+
+/// Condense Markdown to its outline: every heading with the first paragraph
+/// under it, and code blocks as their fences around `...`. Front matter stays;
+/// later paragraphs and thematic breaks are dropped.
+pub(crate) fn condense_markdown(source: &str) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let mut blocks = Blocks::default();
+    let mut i = 0;
+
+    if lines.first().is_some_and(|line| line.trim_end() == "---") {
+        if let Some(end) = lines[1..].iter().position(|line| line.trim_end() == "---") {
+            blocks.start();
+            for line in &lines[..end + 2] {
+                blocks.push(line);
+            }
+            i = end + 2;
+        }
+    }
+
+    // Whether the open section's first paragraph has been seen, and whether
+    // the paragraph being read (if any) is kept
+    let mut seen_paragraph = false;
+    let mut paragraph: Option<bool> = None;
+    while i < lines.len() {
+        let line = lines[i];
+        let trimmed = line.trim();
+
+        if let Some(fence) = code_fence(trimmed) {
+            paragraph = None;
+            blocks.start();
+            blocks.push(line);
+            let indent = &line[..line.len() - line.trim_start().len()];
+            let close = lines[i + 1..]
+                .iter()
+                .position(|l| {
+                    let l = l.trim();
+                    l.starts_with(fence) && l.chars().all(|c| c == fence.as_bytes()[0] as char)
+                })
+                .map(|offset| i + 1 + offset);
+            let body_end = close.unwrap_or(lines.len());
+            if body_end > i + 1 {
+                blocks.push(&format!("{}...", indent));
+            }
+            if let Some(close) = close {
+                blocks.push(lines[close]);
+            }
+            i = body_end + 1;
+            continue;
+        }
+
+        if trimmed.is_empty() {
+            paragraph = None;
+        } else if is_atx_heading(trimmed) {
+            blocks.start();
+            blocks.push(line);
+            (seen_paragraph, paragraph) = (false, None);
+        } else if paragraph.is_none()
+            && lines
+                .get(i + 1)
+                .is_some_and(|next| is_setext_underline(next))
+        {
+            blocks.start();
+            blocks.push(line);
+            blocks.push(lines[i + 1]);
+            (seen_paragraph, paragraph) = (false, None);
+            i += 1;
+        } else if paragraph.is_some() || !is_thematic_break(trimmed) {
+            let keep = *paragraph.get_or_insert_with(|| {
+                let first = !seen_paragraph;
+                seen_paragraph = true;
+                if first {
+                    blocks.start();
+                }
+                first
+            });
+            if keep {
+                blocks.push(line);
+            }
+        }
+        i += 1;
+    }
+    blocks.output
+}
+
+/// Output assembled from blocks of lines, one blank line between blocks
+#[derive(Default)]
+struct Blocks {
+    output: String,
+    /// Whether the next line pushed opens a new block
+    fresh: bool,
+}
+
+impl Blocks {
+    fn start(&mut self) {
+        self.fresh = true;
+    }
+
+    fn push(&mut self, line: &str) {
+        if self.fresh && !self.output.is_empty() {
+            self.output.push('\n');
+        }
+        self.fresh = false;
+        self.output.push_str(line.trim_end());
+        self.output.push('\n');
+    }
+}
+
+/// The fence (three or more backticks or tildes) opening a fenced code block
+fn code_fence(line: &str) -> Option<&str> {
+    let marker = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let len = line.find(|c| c != marker).unwrap_or(line.len());
+    (len >= 3).then(|| &line[..len])
+}
+
+/// `# Title` through `###### Title`
+fn is_atx_heading(line: &str) -> bool {
+    let level = line.find(|c| c != '#').unwrap_or(line.len());
+    (1..=6).contains(&level)
+        && line[level..]
+            .chars()
+            .next()
+            .is_none_or(|c| c == ' ' || c == '\t')
+}
+
+/// The `===` or `---` line under a setext heading
+fn is_setext_underline(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && (line.chars().all(|c| c == '=') || line.chars().all(|c| c == '-'))
+}
+
+/// `---`, `***`, or `___`, spaces allowed between
+fn is_thematic_break(line: &str) -> bool {
+    let marks: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|&mark| marks.chars().all(|c| c == mark))
+}
+
+/// Trailing spaces trimmed and runs of blank lines collapsed to one
+fn tidy_lines(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut blank = false;
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() {
+            blank = !output.is_empty();
+            continue;
+        }
+        if blank {
+            output.push('\n');
+            blank = false;
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condense_sql_keeps_ddl_and_drops_data() {
+        let source = r#"-- Users
+CREATE TABLE users (
+    id SERIAL PRIMARY KEY, -- surrogate key
+    email TEXT NOT NULL
+);
+CREATE INDEX users_email ON users (email);
+INSERT INTO users (email) VALUES ('a;b@example.com');
+INSERT INTO users (email) VALUES ('c@example.com');
+COPY users (id, email) FROM stdin;
+1	x@example.com
+\.
+CREATE FUNCTION touch() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at = now();
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql;
+"#;
+        let expected = r#"CREATE TABLE users (
+    id SERIAL PRIMARY KEY,
+    email TEXT NOT NULL
+);
+
+CREATE INDEX users_email ON users (email);
+-- ... 3 data statements
+CREATE FUNCTION touch() RETURNS trigger AS $$ ... $$ LANGUAGE plpgsql;
+"#;
+        assert_eq!(condense_sql(source), expected);
+    }
+
+    #[test]
+    fn test_condense_sql_routine_with_begin_end() {
+        let source = "DELIMITER //\nCREATE PROCEDURE bump(IN n INT)\nBEGIN\n  UPDATE counters SET value = value + n;\n  IF n > 10 THEN\n    DELETE FROM counters;\n  END IF;\nEND //\nDELIMITER ;\nCREATE TRIGGER t AFTER INSERT ON a BEGIN UPDATE b SET x = 1; END;\n";
+        assert_eq!(
+            condense_sql(source),
+            "CREATE PROCEDURE bump(IN n INT)\nBEGIN ... END;\n\nCREATE TRIGGER t AFTER INSERT ON a BEGIN ... END;\n"
+        );
+    }
+
+    #[test]
+    fn test_condense_protobuf() {
+        let source = r#"syntax = "proto3";
+// The user service
+package users.v1;
+
+option go_package = "example.com/users";
+
+/* A user */
+message User {
+  option deprecated = true;
+  string id = 1; // opaque
+  string email = 2 [(validate.rules).string = {email: true}];
+  oneof contact {
+    string phone = 3;
+  }
+}
+
+service Users {
+  rpc GetUser(GetUserRequest) returns (User) {
+    option (google.api.http) = { get: "/v1/users/{id}" };
+  }
+  rpc ListUsers(ListUsersRequest) returns (stream User);
+}
+"#;
+        let expected = r#"syntax = "proto3";
+package users.v1;
+option go_package = "example.com/users";
+
+message User {
+  string id = 1;
+  string email = 2 [(validate.rules).string = {email: true}];
+  oneof contact { string phone = 3; }
+}
+
+service Users {
+  rpc GetUser(GetUserRequest) returns (User);
+  rpc ListUsers(ListUsersRequest) returns (stream User);
+}
+"#;
+        assert_eq!(condense_protobuf(source), expected);
+    }
+
+    #[test]
+    fn test_condense_markdown_outline() {
+        let source = r#"---
+title: Guide
+---
+
+# Guide
+
+Flat turns a tree into one file.
+It has two modes.
+
+Second paragraph, dropped.
+
+---
+
+## Install
+
+```sh
+cargo install flat
+cargo install flat --locked
+```
+
+After the fence.
+
+Usage
+-----
+
+- a list is a paragraph
+- kept whole
+
+Dropped too.
+"#;
+        let expected = r#"---
+title: Guide
+---
+
+# Guide
+
+Flat turns a tree into one file.
+It has two modes.
+
+## Install
+
+```sh
+...
+```
+
+After the fence.
+
+Usage
+-----
+
+- a list is a paragraph
+- kept whole
+"#;
+        assert_eq!(condense_markdown(source), expected);
+    }
+
+    #[test]
+    fn test_condense_graphql() {
+        let source = r#"# Schema
+"""
+A person
+"""
+type User implements Node {
+  "The id"
+  id: ID!
+  posts(first: Int = 10, after: String = "start"): [Post!]!
+}
+
+scalar Date
+
+query GetUser($id: ID!) {
+  user(id: $id) {
+    id
+    posts { title }
+  }
+}
+
+fragment UserFields on User {
+  id
+}
+"#;
+        let expected = r#"type User implements Node {
+  id: ID!
+  posts(first: Int = 10, after: String = "start"): [Post!]!
+}
+
+scalar Date
+
+query GetUser($id: ID!) { ... }
+
+fragment UserFields on User { ... }
+"#;
+        assert_eq!(condense_graphql(source), expected);
+    }
+}
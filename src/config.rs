@@ -1,42 +1,360 @@
-use globset::GlobMatcher;
-use std::path::PathBuf;
+use crate::compress::{language_for_path, CompressLanguage, CompressLevel, NodeRules, ParseLimits};
+use crate::filters::is_secret_file;
+use crate::format::{number_lines, DEFAULT_LINE_NUMBER_SEPARATOR};
+use crate::git::LineChanges;
+use crate::lock::DEFAULT_LOCK_TIMEOUT;
+use crate::output::{Layout, Order, OutputFormat, SummaryPlacement};
+use crate::overrides::{OverrideAction, Overrides};
+use crate::profiles::{Profile, Profiles};
+use crate::project::ProjectType;
+use crate::since::{ChangeSource, Since};
+use crate::tokens::{PerLanguage, TokenEstimator, TokenRatios, Tokenizer};
+use anyhow::{bail, Result};
+use globset::{Glob, GlobMatcher};
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
+/// Pseudo-extension that stands for files without one (`Makefile`, `LICENSE`)
+/// in `--include` and `--exclude`
+pub const NO_EXTENSION: &str = "_noext";
+
+/// Stands for the part number in an `-o` path under `--split-tokens`, which then
+/// writes one file per part (`-o context-{n}.xml`)
+pub const PART_PLACEHOLDER: &str = "{n}";
+
+/// What `--no-extension` does with files that have no extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum NoExtension {
+    /// Keep them even when `--include` names only real extensions
+    Include,
+    /// Drop them
+    Exclude,
+}
+
+/// A slice of the token budget: a percentage of `--tokens` or a fixed token count
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BudgetShare {
+    Percent(f64),
+    Tokens(usize),
+}
+
+impl BudgetShare {
+    /// Resolve to a token count within `budget`
+    pub fn resolve(self, budget: usize) -> usize {
+        match self {
+            BudgetShare::Percent(pct) => (budget as f64 * pct / 100.0) as usize,
+            BudgetShare::Tokens(tokens) => tokens.min(budget),
+        }
+    }
+}
+
+impl std::fmt::Display for BudgetShare {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BudgetShare::Percent(pct) => write!(f, "{}%", pct),
+            BudgetShare::Tokens(tokens) => write!(f, "{}", tokens),
+        }
+    }
+}
+
+/// What `--diff` resolved to in the repository
+#[derive(Debug, Clone, Default)]
+pub struct DiffSelection {
+    /// Each changed file, with its line changes (None for binary files)
+    pub changes: HashMap<PathBuf, Option<LineChanges>>,
+    /// Unchanged files that a changed file imports, included compressed for context
+    pub context: HashSet<PathBuf>,
+}
+
+/// A 1-based, inclusive range of lines, selected with `path:100-400`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRange {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl LineRange {
+    /// The lines of `content` in this range, with their line endings
+    pub fn slice(self, content: &str) -> &str {
+        let mut ends = content.split_inclusive('\n').scan(0, |offset, line| {
+            *offset += line.len();
+            Some(*offset)
+        });
+        let start = match self.start {
+            1 => 0,
+            n => ends.nth(n - 2).unwrap_or(content.len()),
+        };
+        let end = ends.take(self.end + 1 - self.start).last().unwrap_or(start);
+        &content[start..end]
+    }
+}
+
+impl std::fmt::Display for LineRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl serde::Serialize for LineRange {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
 
 #[derive(Debug, Clone)]
 pub struct Config {
     pub path: PathBuf,
+    /// The directories and files under `path` to walk, when several were given
+    /// (`flat backend/src frontend/src`); empty walks all of `path`
+    pub roots: Vec<PathBuf>,
     pub include_extensions: Option<Vec<String>>,
     pub exclude_extensions: Option<Vec<String>>,
     pub match_patterns: Option<Vec<GlobMatcher>>,
+    pub not_match_patterns: Option<Vec<GlobMatcher>>,
+    /// Paths dropped outright, whatever else selects them (`--exclude-match`)
+    pub exclude_match_patterns: Option<Vec<GlobMatcher>>,
+    /// Files written as only a range of their lines (`path:100-400`)
+    pub line_ranges: Vec<(GlobMatcher, LineRange)>,
+    /// Detected ecosystem; its curated exclusions go in `project_exclusions`
+    pub project_type: Option<ProjectType>,
+    pub project_exclusions: Option<Vec<GlobMatcher>>,
     pub output_file: Option<PathBuf>,
+    /// Every name a templated `-o` gives files in `output_file`'s directory,
+    /// earlier runs' outputs among them
+    pub output_names: Option<GlobMatcher>,
     pub dry_run: bool,
+    /// Write only the annotated directory tree and the summary (`--outline-only`)
+    pub outline_only: bool,
     pub stats_only: bool,
     pub gitignore_path: Option<PathBuf>,
+    /// Honor `.gitignore`, `.git/info/exclude`, and the global git excludes;
+    /// off under `--no-gitignore`
+    pub use_gitignore: bool,
+    /// Walk into hidden files and directories (`--hidden`); `.git` is still skipped
+    pub include_hidden: bool,
+    /// Skip well-known build/dependency directories when the root has no `.gitignore`
+    pub default_excludes: bool,
+    /// Walk into symlinked files and directories instead of writing stubs (`--follow-symlinks`)
+    pub follow_symlinks: bool,
+    /// Descend at most this many directory levels below the root (`--max-depth`)
+    pub max_depth: Option<usize>,
+    /// Directories never descended into (`--prune`): a name matches at any
+    /// depth, a path with a `/` matches relative to the root
+    pub prune: Vec<String>,
     pub max_file_size: u64,
     pub compress: bool,
+    /// How much of each compressed file to keep (`--compress-level`)
+    pub compress_level: CompressLevel,
+    /// Also compress Markdown, to its heading outline (`--compress-markdown`)
+    pub compress_markdown: bool,
     pub full_match_patterns: Option<Vec<GlobMatcher>>,
+    pub no_compress_extensions: Option<Vec<String>>,
     pub token_budget: Option<usize>,
+    /// Set aside from `token_budget` for the prompt and the answer before any
+    /// file is allocated (`--reserve-tokens`)
+    pub reserve_tokens: Option<BudgetShare>,
+    /// How tokens are counted against `token_budget` and in the output
+    pub tokenizer: Tokenizer,
+    /// Bytes per token by extension from `[token-ratios]`, which stand in for
+    /// the heuristic's two ratios
+    pub token_ratios: TokenRatios,
+    pub tests_budget: Option<BudgetShare>,
+    pub docs_budget: Option<BudgetShare>,
+    /// Caps within the budget on the files under each directory, relative to
+    /// `path` (`--quota`)
+    pub directory_quotas: Vec<(PathBuf, BudgetShare)>,
+    pub follow_doc_links: bool,
+    /// Under a token budget, rank docs and design docs below tests, for pure coding tasks
+    pub demote_docs: bool,
+    /// Under a token budget, write the start of a file that doesn't fit instead
+    /// of leaving it out (`--truncate`)
+    pub truncate: bool,
+    /// Files matching a glob score at least its score (`--priority-boost GLOB=SCORE`)
+    pub priority_boosts: Vec<(GlobMatcher, u32)>,
+    /// Files matching a glob score at most its score (`--priority-drop GLOB=SCORE`)
+    pub priority_drops: Vec<(GlobMatcher, u32)>,
+    /// Under a token budget, prioritize this file and the files its imports name
+    pub related: Option<PathBuf>,
+    /// Under a token budget, prioritize the files reachable from this one through
+    /// imports, nearest first (`--entry`)
+    pub entry: Option<PathBuf>,
+    /// Files from outside the walk written under a name of their own, as if they
+    /// were in the scanned directory (`--attach NAME=PATH`)
+    pub attachments: Vec<(String, PathBuf)>,
+    pub format: OutputFormat,
+    /// Group files into labeled role sections instead of one path-sorted stream
+    pub layout: Layout,
+    /// Where the summary goes (`--summary`)
+    pub summary: SummaryPlacement,
+    /// Write order (`--order`); None is by path, or by priority under `--tokens`
+    pub order: Option<Order>,
+    /// Cut the output into numbered parts of at most this many tokens (`--split-tokens`)
+    pub split_tokens: Option<usize>,
+    /// Write each file's role and priority score as attributes (`--annotate`)
+    pub annotate: bool,
+    /// Include secret files with their values masked instead of skipping them (`--redact`)
+    pub redact: bool,
+    /// Write minified files whole instead of outlining or truncating them (`--keep-minified`)
+    pub keep_minified: bool,
+    /// Prefix each written line with its number and this separator (`--line-numbers`)
+    pub line_numbers: Option<String>,
+    /// Write file paths absolute instead of relative to `path` (`--absolute-paths`)
+    pub absolute_paths: bool,
+    /// Read CRLF line endings as LF, before tokens are counted (`--normalize-newlines`)
+    pub normalize_newlines: bool,
+    pub header: Option<String>,
+    pub footer: Option<String>,
+    /// Flatten exactly these files (`--files-from`) instead of walking `path`
+    pub files_from: Option<Vec<PathBuf>>,
+    /// Where `files_from` was read, `-` for stdin
+    pub files_from_path: Option<PathBuf>,
+    /// Flatten only the files changed relative to this git ref (`--diff`)
+    pub diff_base: Option<String>,
+    /// Flatten only the files staged in the index, as staged (`--staged`)
+    pub staged: bool,
+    /// `diff_base` or `staged` resolved against the repository; the walker fills this in
+    pub diff: Option<DiffSelection>,
+    /// Flatten only the files changed within an age or since a git revision (`--since`)
+    pub since: Option<Since>,
+    /// `since` resolved against the clock or the repository; the walker fills this in
+    pub recent: Option<ChangeSource>,
+    /// Tag each file with its most frequent committers (`--owners`)
+    pub owners: bool,
+    /// `owners` resolved against the history: `alice,bob` per file; the walker fills this in
+    pub file_owners: Option<HashMap<PathBuf, String>>,
+    /// Tag each file with the tokens, bytes, and SHA-256 of its content (`--file-meta`)
+    pub file_meta: bool,
+    pub overlay_file: Option<PathBuf>,
+    /// Per-file decisions from `flat.overrides`, applied after the built-in rules
+    pub overrides: Option<Overrides>,
+    /// `flat:full` and `flat:compress` pragmas of the files to write, which
+    /// `flat.overrides` rules outrank; the walker fills this in
+    pub pragmas: HashMap<PathBuf, OverrideAction>,
+    /// `.flat.toml` profiles of the directories under `path`; the walker fills this in
+    pub profiles: Profiles,
+    /// Per-language node kinds to keep verbatim or drop when compressing
+    pub compress_rules: HashMap<CompressLanguage, NodeRules>,
+    /// Size and time bounds on parsing a file for compression
+    pub parse_limits: ParseLimits,
+    /// Cache compressed output under this directory across runs (`--cache`)
+    pub cache_dir: Option<PathBuf>,
+    /// How long to wait on other flat processes holding the output or cache lock
+    pub lock_timeout: Duration,
+    /// Also write the selected files' raw contents to this `.zip`/`.tar` archive
+    pub export_selection: Option<PathBuf>,
+    /// Also write the output, manifest, tree, and config to this bundle (`--bundle`)
+    pub bundle: Option<PathBuf>,
+    /// Also write every file's decision and the summary as JSON to this file (`--manifest`)
+    pub manifest: Option<PathBuf>,
+    pub verbose: bool,
 }
 
 impl Default for Config {
     fn default() -> Self {
         Self {
             path: PathBuf::from("."),
+            roots: Vec::new(),
             include_extensions: None,
             exclude_extensions: None,
             match_patterns: None,
+            not_match_patterns: None,
+            exclude_match_patterns: None,
+            line_ranges: Vec::new(),
+            project_type: None,
+            project_exclusions: None,
             output_file: None,
+            output_names: None,
             dry_run: false,
+            outline_only: false,
             stats_only: false,
             gitignore_path: None,
+            use_gitignore: true,
+            include_hidden: false,
+            default_excludes: true,
+            follow_symlinks: false,
+            max_depth: None,
+            prune: Vec::new(),
             max_file_size: 1024 * 1024, // 1MB
             compress: false,
+            compress_level: CompressLevel::default(),
+            compress_markdown: false,
             full_match_patterns: None,
+            no_compress_extensions: None,
             token_budget: None,
+            reserve_tokens: None,
+            tokenizer: Tokenizer::default(),
+            token_ratios: TokenRatios::new(),
+            tests_budget: None,
+            docs_budget: None,
+            directory_quotas: Vec::new(),
+            follow_doc_links: false,
+            truncate: false,
+            demote_docs: false,
+            priority_boosts: Vec::new(),
+            priority_drops: Vec::new(),
+            related: None,
+            entry: None,
+            attachments: Vec::new(),
+            format: OutputFormat::Xml,
+            layout: Layout::Flat,
+            summary: SummaryPlacement::default(),
+            order: None,
+            split_tokens: None,
+            annotate: false,
+            redact: false,
+            keep_minified: false,
+            line_numbers: None,
+            absolute_paths: false,
+            normalize_newlines: false,
+            header: None,
+            footer: None,
+            files_from: None,
+            files_from_path: None,
+            diff_base: None,
+            staged: false,
+            diff: None,
+            since: None,
+            recent: None,
+            owners: false,
+            file_owners: None,
+            file_meta: false,
+            overlay_file: None,
+            overrides: None,
+            pragmas: HashMap::new(),
+            profiles: Profiles::default(),
+            compress_rules: HashMap::new(),
+            parse_limits: ParseLimits::default(),
+            cache_dir: None,
+            lock_timeout: DEFAULT_LOCK_TIMEOUT,
+            export_selection: None,
+            bundle: None,
+            manifest: None,
+            verbose: false,
         }
     }
 }
 
 impl Config {
+    /// Fold `--no-extension` into the extension lists as [`NO_EXTENSION`]
+    pub fn set_no_extension(&mut self, mode: NoExtension) {
+        let (add_to, remove_from) = match mode {
+            NoExtension::Include => (&mut self.include_extensions, &mut self.exclude_extensions),
+            NoExtension::Exclude => (&mut self.exclude_extensions, &mut self.include_extensions),
+        };
+        if let Some(list) = remove_from {
+            list.retain(|e| !e.eq_ignore_ascii_case(NO_EXTENSION));
+        }
+        // Without an include list everything is already included
+        if mode == NoExtension::Exclude || add_to.is_some() {
+            add_to
+                .get_or_insert_with(Vec::new)
+                .push(NO_EXTENSION.to_string());
+        }
+    }
+
+    /// Whether `ext` passes `--include`/`--exclude`; pass [`NO_EXTENSION`] for
+    /// files without an extension
     pub fn should_include_extension(&self, ext: &str) -> bool {
         // If include list is specified, extension must be in it
         if let Some(ref include) = self.include_extensions {
@@ -58,12 +376,557 @@ impl Config {
     /// Check if a file name matches any of the configured glob patterns.
     /// Returns true if no patterns are set or if the name matches at least one pattern.
     pub fn should_include_by_match(&self, file_name: &str) -> bool {
+        self.should_include_path_by_match(Path::new(file_name))
+    }
+
+    /// Evaluate a path against the match rules in order: it must match at least
+    /// one allow pattern (when any are set), and then no deny pattern.
+    ///
+    /// Patterns containing a `/` are matched against the path relative to the
+    /// scanned root; all other patterns are matched against the file name.
+    pub fn should_include_path_by_match(&self, path: &Path) -> bool {
+        let allowed = match &self.match_patterns {
+            Some(patterns) => patterns.iter().any(|m| self.glob_matches(m, path)),
+            None => true,
+        };
+        if !allowed {
+            return false;
+        }
+
+        match &self.not_match_patterns {
+            Some(patterns) => !patterns.iter().any(|m| self.glob_matches(m, path)),
+            None => true,
+        }
+    }
+
+    /// Check if a path matches an `--exclude-match` glob, matched like `--match`
+    pub fn is_excluded_by_match(&self, path: &Path) -> bool {
+        self.exclude_match_patterns
+            .iter()
+            .flatten()
+            .any(|m| self.glob_matches(m, path))
+    }
+
+    /// Check if a file is dropped by the detected project type's curated exclusions.
+    /// A file named by an explicit `--match` pattern is always kept.
+    pub fn is_project_default_excluded(&self, path: &Path) -> bool {
+        let Some(exclusions) = &self.project_exclusions else {
+            return false;
+        };
+        if !exclusions.iter().any(|m| self.glob_matches(m, path)) {
+            return false;
+        }
         match &self.match_patterns {
-            Some(patterns) => patterns.iter().any(|m| m.is_match(file_name)),
+            Some(patterns) => !patterns.iter().any(|m| self.glob_matches(m, path)),
             None => true,
         }
     }
 
+    /// The line range selected for `path`, if any
+    pub fn line_range_for(&self, path: &Path) -> Option<LineRange> {
+        self.line_ranges
+            .iter()
+            .find(|(matcher, _)| self.glob_matches(matcher, path))
+            .map(|(_, range)| *range)
+    }
+
+    /// Lines added and removed in `path` since the `--diff` base
+    pub fn line_changes_for(&self, path: &Path) -> Option<LineChanges> {
+        self.diff.as_ref()?.changes.get(path).copied().flatten()
+    }
+
+    /// `path`'s top committers under `--owners`, as `alice,bob`
+    pub fn owners_for(&self, path: &Path) -> Option<&str> {
+        self.file_owners.as_ref()?.get(path).map(String::as_str)
+    }
+
+    /// Tokens `--reserve-tokens` takes out of `--tokens`
+    pub fn reserved_tokens(&self) -> usize {
+        match (self.token_budget, self.reserve_tokens) {
+            (Some(budget), Some(reserve)) => reserve.resolve(budget),
+            _ => 0,
+        }
+    }
+
+    /// What the files are allocated: `--tokens` less the reservation
+    pub fn allocation_budget(&self) -> Option<usize> {
+        self.token_budget
+            .map(|budget| budget - self.reserved_tokens())
+    }
+
+    fn glob_matches(&self, matcher: &GlobMatcher, path: &Path) -> bool {
+        if matcher.glob().glob().contains('/') {
+            let relative = path.strip_prefix(&self.path).unwrap_or(path);
+            matcher.is_match(relative)
+        } else {
+            match path.file_name() {
+                Some(name) => matcher.is_match(name),
+                None => false,
+            }
+        }
+    }
+
+    /// Check for contradictory settings before any work is done.
+    ///
+    /// Combinations that can't do what was asked are errors; settings that are
+    /// merely ignored in this combination come back as warnings for the caller
+    /// to print.
+    pub fn validate(&self) -> Result<Vec<String>> {
+        if self.stats_only && self.output_file.is_some() {
+            bail!("--stats prints its summary to stderr and writes no output, so -o would only create an empty file");
+        }
+        if self.files_from.is_some() && self.diff_base.is_some() {
+            bail!("--files-from and --diff both pick the files to flatten; use one");
+        }
+        if self.staged && (self.files_from.is_some() || self.diff_base.is_some()) {
+            bail!("--staged picks the files to flatten, as do --files-from and --diff; use one");
+        }
+        if self.max_depth == Some(0) {
+            bail!("--max-depth 0 would walk nothing; 1 keeps just the files at the root");
+        }
+        if let (Some(budget), Some(reserve)) = (self.token_budget, self.reserve_tokens) {
+            if budget > 0 && reserve.resolve(budget) >= budget {
+                bail!(
+                    "--reserve-tokens {} leaves nothing of --tokens {} for the files",
+                    reserve,
+                    budget
+                );
+            }
+        }
+        if self.staged && self.overlay_file.is_some() {
+            bail!("--staged reads contents from the git index, so --overlay would be ignored");
+        }
+        if !self.tokenizer.is_available() {
+            bail!("--tokenizer needs the `tiktoken` cargo feature, which this build of flat was compiled without");
+        }
+        if self.outline_only && (self.dry_run || self.stats_only) {
+            bail!("--outline-only is its own output mode; drop --dry-run and --stats");
+        }
+        if self.stats_only && self.bundle.is_some() {
+            bail!("--stats writes no output, so --bundle would hold an empty context");
+        }
+        if self.split_tokens.is_some() {
+            if matches!(self.format, OutputFormat::Json | OutputFormat::Html) {
+                bail!("--split-tokens needs --format xml, markdown, jsonl-events, or messages; a JSON or HTML document can't be cut into parts");
+            }
+            if self.format == OutputFormat::Messages
+                && self
+                    .output_file
+                    .as_ref()
+                    .is_some_and(|path| path.to_string_lossy().contains(PART_PLACEHOLDER))
+            {
+                bail!("--format messages writes one array with a message per part; drop {{n}} from -o");
+            }
+            if self.summary == SummaryPlacement::First && self.format != OutputFormat::Messages {
+                bail!("--summary first would land in the first part only; use --summary last or none with --split-tokens");
+            }
+            if self.layout == Layout::Sections {
+                bail!("--split-tokens and --layout sections both group the files; use one");
+            }
+        }
+
+        let mut warnings = Vec::new();
+        let mut warn = |applies: bool, message: &str| {
+            if applies {
+                warnings.push(message.to_string());
+            }
+        };
+        warn(
+            self.stats_only && self.dry_run,
+            "--dry-run has no effect with --stats",
+        );
+        warn(
+            self.split_tokens.is_some() && (self.dry_run || self.stats_only || self.outline_only),
+            "--split-tokens has no effect on a listing, --stats, or --outline-only, which write no content",
+        );
+        warn(
+            self.split_tokens.is_none()
+                && self
+                    .output_file
+                    .as_ref()
+                    .is_some_and(|path| path.to_string_lossy().contains(PART_PLACEHOLDER)),
+            "{n} in -o is only replaced under --split-tokens",
+        );
+        warn(
+            self.outline_only && (self.compress || self.token_budget.is_some()),
+            "--compress and --tokens have no effect with --outline-only, which writes no content",
+        );
+        warn(
+            self.full_match_patterns.is_some() && !self.compress,
+            "--full-match has no effect without --compress",
+        );
+        warn(
+            self.compress_level != CompressLevel::default() && !self.compress,
+            "--compress-level has no effect without --compress",
+        );
+        warn(
+            self.summary != SummaryPlacement::default()
+                && matches!(self.format, OutputFormat::Json | OutputFormat::Html),
+            "--summary has no effect with --format json or html, which keep the summary in its own place",
+        );
+        warn(
+            self.compress_markdown && !self.compress,
+            "--compress-markdown has no effect without --compress",
+        );
+        warn(
+            self.no_compress_extensions.is_some() && !self.compress,
+            "--no-compress-ext has no effect without --compress",
+        );
+        warn(
+            self.parse_limits != ParseLimits::default() && !self.compress,
+            "--parse-timeout and --max-compress-size have no effect without --compress",
+        );
+        warn(
+            (self.tests_budget.is_some() || self.docs_budget.is_some())
+                && self.token_budget.is_none(),
+            "--tests-budget and --docs-budget have no effect without --tokens",
+        );
+        warn(
+            !self.directory_quotas.is_empty() && self.token_budget.is_none(),
+            "--quota has no effect without --tokens",
+        );
+        warn(
+            self.reserve_tokens.is_some() && self.token_budget.is_none(),
+            "--reserve-tokens has no effect without --tokens",
+        );
+        warn(
+            self.follow_doc_links && self.token_budget.is_none(),
+            "--follow-doc-links has no effect without --tokens",
+        );
+        warn(
+            self.truncate && self.token_budget.is_none(),
+            "--truncate has no effect without --tokens",
+        );
+        warn(
+            self.demote_docs && self.token_budget.is_none() && self.order != Some(Order::Priority),
+            "--demote-docs has no effect without --tokens or --order priority",
+        );
+        warn(
+            !(self.priority_boosts.is_empty() && self.priority_drops.is_empty())
+                && self.token_budget.is_none()
+                && self.order != Some(Order::Priority),
+            "--priority-boost and --priority-drop have no effect without --tokens or --order priority",
+        );
+        warn(
+            self.related.is_some() && self.token_budget.is_none(),
+            "--related has no effect without --tokens",
+        );
+        warn(
+            self.entry.is_some() && self.token_budget.is_none(),
+            "--entry has no effect without --tokens",
+        );
+        warn(
+            self.token_budget == Some(0),
+            "--tokens 0 leaves no room for any file; only the summary will be written",
+        );
+        Ok(warnings)
+    }
+
+    /// Render the CLI invocation that reproduces this selection and output.
+    ///
+    /// Values are normalized (patterns split out, sizes in bytes), and
+    /// settings that don't change the content — `-o`, `-v` — are left out.
+    /// A `--files-from -` list read from stdin is noted, since it isn't repeated.
+    /// `flat.toml` snippets are picked up again on re-run, so they aren't repeated.
+    pub fn to_command_line(&self) -> String {
+        let defaults = Config::default();
+        let mut args = vec!["flat".to_string()];
+        // `path:START-END` selections keep their lines
+        let range_of = |glob: &str| {
+            self.line_ranges
+                .iter()
+                .find(|(matcher, _)| matcher.glob().glob() == glob)
+                .map(|(_, range)| *range)
+        };
+        if self.roots.is_empty() {
+            let mut root = self.path.to_string_lossy().into_owned();
+            let file_name = self.path.file_name().filter(|_| self.path.is_file());
+            if let Some(range) =
+                file_name.and_then(|name| range_of(&globset::escape(&name.to_string_lossy())))
+            {
+                root = format!("{}:{}", root, range);
+            }
+            args.push(shell_quote(&root));
+        }
+        for root in &self.roots {
+            let spelled: PathBuf = root
+                .components()
+                .filter(|c| !matches!(c, Component::CurDir))
+                .collect();
+            args.push(shell_quote(&spelled.to_string_lossy()));
+        }
+
+        let mut push_list = |flag: &str, values: &Option<Vec<String>>| {
+            if let Some(values) = values {
+                args.push(format!("--{}", flag));
+                args.push(shell_quote(&values.join(",")));
+            }
+        };
+        push_list("include", &self.include_extensions);
+        push_list("exclude", &self.exclude_extensions);
+        push_list("no-compress-ext", &self.no_compress_extensions);
+
+        let globs = [
+            ("match", &self.match_patterns),
+            ("not-match", &self.not_match_patterns),
+            ("exclude-match", &self.exclude_match_patterns),
+            ("full-match", &self.full_match_patterns),
+        ];
+        for (flag, patterns) in globs {
+            for matcher in patterns.iter().flatten() {
+                let glob = matcher.glob().glob();
+                args.push(format!("--{}", flag));
+                match range_of(glob).filter(|_| flag == "match") {
+                    Some(range) => args.push(shell_quote(&format!("{}:{}", glob, range))),
+                    None => args.push(shell_quote(glob)),
+                }
+            }
+        }
+
+        let from_stdin = self.files_from_path.as_deref() == Some(Path::new("-"));
+        if let Some(path) = &self.files_from_path {
+            args.push("--files-from".to_string());
+            args.push(shell_quote(&path.to_string_lossy()));
+        }
+        if let Some(base) = &self.diff_base {
+            args.push("--diff".to_string());
+            args.push(shell_quote(base));
+        }
+        if self.staged {
+            args.push("--staged".to_string());
+        }
+        if let Some(since) = &self.since {
+            args.push("--since".to_string());
+            args.push(shell_quote(&since.to_arg()));
+        }
+        if let Some(path) = &self.gitignore_path {
+            args.push("--gitignore".to_string());
+            args.push(shell_quote(&path.to_string_lossy()));
+        }
+        if !self.use_gitignore {
+            args.push("--no-gitignore".to_string());
+        }
+        if self.include_hidden {
+            args.push("--hidden".to_string());
+        }
+        if !self.default_excludes {
+            args.push("--no-default-excludes".to_string());
+        }
+        if self.follow_symlinks {
+            args.push("--follow-symlinks".to_string());
+        }
+        if let Some(depth) = self.max_depth {
+            args.push(format!("--max-depth {}", depth));
+        }
+        if !self.prune.is_empty() {
+            args.push("--prune".to_string());
+            args.push(shell_quote(&self.prune.join(",")));
+        }
+        if self.project_type.is_some() && self.project_exclusions.is_none() {
+            args.push("--no-project-defaults".to_string());
+        }
+        if self.max_file_size != defaults.max_file_size {
+            args.push(format!("--max-size {}", self.max_file_size));
+        }
+        if self.compress {
+            args.push("--compress".to_string());
+        }
+        if self.compress_level != defaults.compress_level {
+            if let Some(value) = clap::ValueEnum::to_possible_value(&self.compress_level) {
+                args.push(format!("--compress-level {}", value.get_name()));
+            }
+        }
+        if self.compress_markdown {
+            args.push("--compress-markdown".to_string());
+        }
+        if self.parse_limits.timeout != defaults.parse_limits.timeout {
+            args.push(format!(
+                "--parse-timeout {}",
+                self.parse_limits.timeout.as_secs_f64()
+            ));
+        }
+        if self.parse_limits.max_size != defaults.parse_limits.max_size {
+            args.push(format!(
+                "--max-compress-size {}",
+                self.parse_limits.max_size
+            ));
+        }
+        if let Some(budget) = self.token_budget {
+            args.push(format!("--tokens {}", budget));
+        }
+        if let Some(reserve) = self.reserve_tokens {
+            args.push(format!("--reserve-tokens {}", reserve));
+        }
+        if self.tokenizer != defaults.tokenizer {
+            if let Some(value) = clap::ValueEnum::to_possible_value(&self.tokenizer) {
+                args.push(format!("--tokenizer {}", value.get_name()));
+            }
+        }
+        if let Some(share) = self.tests_budget {
+            args.push(format!("--tests-budget {}", share));
+        }
+        if let Some(share) = self.docs_budget {
+            args.push(format!("--docs-budget {}", share));
+        }
+        if !self.directory_quotas.is_empty() {
+            let quotas: Vec<String> = self
+                .directory_quotas
+                .iter()
+                .map(|(dir, share)| format!("{}/={}", dir.display(), share))
+                .collect();
+            args.push("--quota".to_string());
+            args.push(shell_quote(&quotas.join(",")));
+        }
+        if self.follow_doc_links {
+            args.push("--follow-doc-links".to_string());
+        }
+        if self.demote_docs {
+            args.push("--demote-docs".to_string());
+        }
+        if self.truncate {
+            args.push("--truncate".to_string());
+        }
+        let rules = [
+            ("priority-boost", &self.priority_boosts),
+            ("priority-drop", &self.priority_drops),
+        ];
+        for (flag, rules) in rules {
+            for (matcher, score) in rules {
+                args.push(format!("--{}", flag));
+                args.push(shell_quote(&format!("{}={}", matcher.glob().glob(), score)));
+            }
+        }
+        if let Some(path) = &self.related {
+            args.push("--related".to_string());
+            args.push(shell_quote(&path.to_string_lossy()));
+        }
+        if let Some(path) = &self.entry {
+            args.push("--entry".to_string());
+            args.push(shell_quote(&path.to_string_lossy()));
+        }
+        for (name, path) in &self.attachments {
+            args.push("--attach".to_string());
+            args.push(shell_quote(&format!("{}={}", name, path.display())));
+        }
+        if let Some(path) = &self.overlay_file {
+            args.push("--overlay".to_string());
+            args.push(shell_quote(&path.to_string_lossy()));
+        }
+        if self.cache_dir.is_some() {
+            args.push("--cache".to_string());
+        }
+        if self.dry_run {
+            args.push("--dry-run".to_string());
+        }
+        if self.stats_only {
+            args.push("--stats".to_string());
+        }
+        if self.outline_only {
+            args.push("--outline-only".to_string());
+        }
+        if self.format != defaults.format {
+            if let Some(value) = clap::ValueEnum::to_possible_value(&self.format) {
+                args.push(format!("--format {}", value.get_name()));
+            }
+        }
+        if self.layout != defaults.layout {
+            if let Some(value) = clap::ValueEnum::to_possible_value(&self.layout) {
+                args.push(format!("--layout {}", value.get_name()));
+            }
+        }
+        if self.summary != defaults.summary {
+            if let Some(value) = clap::ValueEnum::to_possible_value(&self.summary) {
+                args.push(format!("--summary {}", value.get_name()));
+            }
+        }
+        if let Some(limit) = self.split_tokens {
+            args.push(format!("--split-tokens {}", limit));
+        }
+        if let Some(order) = self.order {
+            if let Some(value) = clap::ValueEnum::to_possible_value(&order) {
+                args.push(format!("--order {}", value.get_name()));
+            }
+        }
+        if self.annotate {
+            args.push("--annotate".to_string());
+        }
+        if self.owners {
+            args.push("--owners".to_string());
+        }
+        if self.file_meta {
+            args.push("--file-meta".to_string());
+        }
+        if self.redact {
+            args.push("--redact".to_string());
+        }
+        if self.keep_minified {
+            args.push("--keep-minified".to_string());
+        }
+        if let Some(separator) = &self.line_numbers {
+            args.push("--line-numbers".to_string());
+            if separator != DEFAULT_LINE_NUMBER_SEPARATOR {
+                args.push("--line-number-separator".to_string());
+                args.push(shell_quote(separator));
+            }
+        }
+        if self.absolute_paths {
+            args.push("--absolute-paths".to_string());
+        }
+        if self.normalize_newlines {
+            args.push("--normalize-newlines".to_string());
+        }
+
+        let command = args.join(" ");
+        if from_stdin {
+            // The list itself isn't kept, so the command alone can't replay the run
+            return format!(
+                "{}  # reads the --files-from list on stdin, not saved",
+                command
+            );
+        }
+        command
+    }
+
+    /// The command that flattens just `files`, the ones a token budget left
+    /// out, with this run's other settings. It compresses, so as much as
+    /// possible fits a second message of the same budget.
+    pub fn follow_up_command(&self, files: &[String]) -> String {
+        let matchers = files
+            .iter()
+            .filter_map(|file| {
+                let path = Path::new(file);
+                let relative = path.strip_prefix(&self.path).unwrap_or(path);
+                let glob = globset::escape(&relative.to_string_lossy());
+                Glob::new(&glob).ok().map(|glob| glob.compile_matcher())
+            })
+            .collect();
+        Config {
+            match_patterns: Some(matchers),
+            compress: true,
+            files_from: None,
+            files_from_path: None,
+            diff_base: None,
+            staged: false,
+            since: None,
+            related: None,
+            entry: None,
+            // The quotas are what left some of these files out
+            directory_quotas: Vec::new(),
+            // A listing wouldn't fetch the files' content
+            dry_run: false,
+            stats_only: false,
+            outline_only: false,
+            ..self.clone()
+        }
+        .to_command_line()
+    }
+
+    /// Whether the built-in build/dependency directory exclusions are in effect:
+    /// enabled, and no `.gitignore` at the root is in use to say otherwise
+    pub fn applies_default_excludes(&self) -> bool {
+        self.default_excludes && !(self.use_gitignore && self.path.join(".gitignore").is_file())
+    }
+
     /// Check if a file should always get full content (skip compression).
     /// Returns true if --full-match patterns are set and the file name matches.
     pub fn is_full_match(&self, file_name: &str) -> bool {
@@ -72,6 +935,310 @@ impl Config {
             None => false,
         }
     }
+
+    /// Whether a file is written with its values masked: a secret file under `--redact`
+    pub fn redacts(&self, path: &Path) -> bool {
+        self.redact && is_secret_file(path)
+    }
+
+    /// A file's priority `score` after `--priority-boost` and `--priority-drop`:
+    /// raised to the highest matching boost, then capped at the lowest matching
+    /// drop, so a drop wins over a boost
+    pub fn adjust_priority(&self, path: &Path, score: u32) -> u32 {
+        let boosted = self
+            .priority_boosts
+            .iter()
+            .filter(|(glob, _)| self.glob_matches(glob, path))
+            .fold(score, |score, &(_, boost)| score.max(boost));
+        self.priority_drops
+            .iter()
+            .filter(|(glob, _)| self.glob_matches(glob, path))
+            .fold(boosted, |score, &(_, drop)| score.min(drop))
+    }
+
+    /// Tokens `content` costs once written for `path`, line numbers included
+    pub fn emitted_tokens(&self, path: &Path, content: &str) -> usize {
+        let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+        match &self.line_numbers {
+            Some(separator) => {
+                let first = self.line_range_for(path).map_or(1, |lines| lines.start);
+                let numbered = number_lines(content, first, separator);
+                self.token_estimator().estimate(&numbered, ext)
+            }
+            None => self.token_estimator().estimate(content, ext),
+        }
+    }
+
+    /// The scanned directory: `path`, or its directory when it is a file
+    fn scan_dir(&self) -> &Path {
+        if self.path.is_file() {
+            self.path.parent().unwrap_or(Path::new(""))
+        } else {
+            &self.path
+        }
+    }
+
+    /// Where the `--attach` artifact `name` is placed among the scanned files
+    pub fn attachment_path(&self, name: &str) -> PathBuf {
+        self.scan_dir().join(name)
+    }
+
+    /// Whether `path` is an `--attach` artifact's place among the scanned files
+    pub fn is_attachment(&self, path: &Path) -> bool {
+        self.attachments
+            .iter()
+            .any(|(name, _)| self.attachment_path(name) == path)
+    }
+
+    /// Whether `--prune` names the directory `dir`
+    pub fn is_pruned(&self, dir: &Path) -> bool {
+        if self.prune.is_empty() {
+            return false;
+        }
+        let relative = dir.strip_prefix(&self.path).unwrap_or(dir);
+        self.prune.iter().any(|pattern| {
+            let pattern = pattern.trim_end_matches('/');
+            if pattern.contains('/') {
+                relative == Path::new(pattern)
+            } else {
+                dir.file_name().is_some_and(|name| name == pattern)
+            }
+        })
+    }
+
+    /// How many levels below the root being walked `path` sits: 1 for an
+    /// entry of the root itself
+    pub fn walk_depth(&self, path: &Path) -> usize {
+        let root = self
+            .roots
+            .iter()
+            .find(|root| path.starts_with(root))
+            .unwrap_or(&self.path);
+        path.strip_prefix(root)
+            .map_or(0, |relative| relative.components().count())
+    }
+
+    /// Whether `dir` holds or leads to one of the `roots` being walked
+    pub fn walks(&self, dir: &Path) -> bool {
+        self.roots.is_empty()
+            || self
+                .roots
+                .iter()
+                .any(|root| dir.starts_with(root) || root.starts_with(dir))
+    }
+
+    /// How `path` is written in the output: relative to the scanned directory
+    /// (the file's own directory when a single file is scanned), or absolute
+    /// under `--absolute-paths`. Paths outside the scan stay as they are.
+    /// Components are always separated by `/`.
+    pub fn display_path(&self, path: &Path) -> String {
+        if self.absolute_paths {
+            return slashed(&std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()));
+        }
+        slashed(
+            path.strip_prefix(self.scan_dir())
+                .ok()
+                .filter(|relative| !relative.as_os_str().is_empty())
+                .unwrap_or(path),
+        )
+    }
+
+    /// `tokenizer`, with the `[token-ratios]` in place of the heuristic's
+    pub fn token_estimator(&self) -> PerLanguage<'_> {
+        PerLanguage {
+            ratios: &self.token_ratios,
+            fallback: self.tokenizer,
+        }
+    }
+
+    /// Whether a file that looks minified is shortened: unless `--keep-minified`,
+    /// a line range, a `--full-match`, or a `full` override asks for it as is
+    pub fn shortens_minified(&self, path: &Path) -> bool {
+        !self.keep_minified
+            && self.line_range_for(path).is_none()
+            && !self.keeps_full_content(path)
+            && !matches!(self.override_for(path), Some(OverrideAction::Full))
+    }
+
+    /// The `flat.overrides` decision for a file, if any rule matches it, or
+    /// else the one its own pragma asks for
+    pub fn override_for(&self, path: &Path) -> Option<OverrideAction> {
+        self.overrides
+            .as_ref()
+            .and_then(|overrides| overrides.action_for(|glob| self.glob_matches(glob, path)))
+            .or_else(|| self.pragmas.get(path).copied())
+    }
+
+    /// Whether a file's content should go through the compressor: `--compress`
+    /// minus the full-content exceptions, plus `--diff` context files, with
+    /// `flat.overrides` having the last word.
+    pub fn should_compress(&self, path: &Path) -> bool {
+        // A line range asks for those exact lines
+        if self.line_range_for(path).is_some() {
+            return false;
+        }
+        match self.override_for(path) {
+            Some(OverrideAction::Compress) => true,
+            Some(OverrideAction::Full | OverrideAction::Skip) => false,
+            // Context for a diff only needs its signatures
+            None if self.diff.as_ref().is_some_and(|d| d.context.contains(path)) => true,
+            None => {
+                // Markdown is prose, so only an opt-in compresses it by default
+                let by_default = self.compress
+                    && (self.compress_markdown
+                        || language_for_path(path) != Some(CompressLanguage::Markdown));
+                let compress = self.profile_for(path).and_then(|p| p.compress);
+                compress.unwrap_or(by_default) && !self.keeps_full_content(path)
+            }
+        }
+    }
+
+    /// The `.flat.toml` profile in force for `path`, if any
+    pub fn profile_for(&self, path: &Path) -> Option<&Profile> {
+        self.profiles.for_file(path)
+    }
+
+    /// The size limit for `path`: `--max-size`, or its profile's if lower
+    pub fn max_file_size_for(&self, path: &Path) -> u64 {
+        match self.profile_for(path).and_then(|p| p.max_size) {
+            Some(limit) => limit.min(self.max_file_size),
+            None => self.max_file_size,
+        }
+    }
+
+    /// The largest `path` that is read at all. A file flat compresses is held
+    /// to its limit once compressed, so it is read up to the parse limit
+    /// (`--max-compress-size`) if that is higher.
+    pub fn read_size_limit(&self, path: &Path) -> u64 {
+        let limit = self.max_file_size_for(path);
+        if self.should_compress(path) && language_for_path(path).is_some() {
+            limit.max(self.parse_limits.max_size)
+        } else {
+            limit
+        }
+    }
+
+    /// Check if a file bypasses compression, by `--full-match` glob or `--no-compress-ext`.
+    pub fn keeps_full_content(&self, path: &Path) -> bool {
+        let file_name = path
+            .file_name()
+            .map(|f| f.to_string_lossy())
+            .unwrap_or_default();
+        if self.is_full_match(&file_name)
+            || self.profile_for(path).is_some_and(|p| p.keeps_full(path))
+        {
+            return true;
+        }
+
+        match (&self.no_compress_extensions, path.extension()) {
+            (Some(exts), Some(ext)) => {
+                let ext = ext.to_string_lossy();
+                exts.iter().any(|e| e.eq_ignore_ascii_case(&ext))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Paths from a `--files-from` list, one per line, relative ones resolved
+/// against `root` the way the walker would name them
+pub fn parse_file_list(text: &str, root: &Path) -> Vec<PathBuf> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let path = Path::new(line);
+            if path.is_absolute() {
+                return path.to_path_buf();
+            }
+            // `./src/a.rs` and `src/a.rs` are the same file
+            let relative: PathBuf = path
+                .components()
+                .filter(|c| !matches!(c, Component::CurDir))
+                .collect();
+            root.join(relative)
+        })
+        .collect()
+}
+
+/// The directory to scan for several paths given at once, and the paths to walk
+/// under it: their nearest common directory, so each file's output path starts
+/// with the root it came from. A path inside another given path is dropped, as
+/// the walk of the outer one reaches it. Paths outside the working directory
+/// (`../shared`) or absolute ones are resolved to absolute paths first.
+pub fn resolve_roots(paths: &[PathBuf]) -> Result<(PathBuf, Vec<PathBuf>)> {
+    let outside = paths.iter().any(|path| {
+        path.is_absolute()
+            || path
+                .components()
+                .any(|c| matches!(c, Component::ParentDir | Component::Prefix(_)))
+    });
+    let mut normalized = Vec::new();
+    for path in paths {
+        if !path.exists() {
+            bail!("No such file or directory: {}", path.display());
+        }
+        let path: PathBuf = if outside {
+            std::fs::canonicalize(path)?
+        } else {
+            path.components()
+                .filter(|c| !matches!(c, Component::CurDir))
+                .collect()
+        };
+        normalized.push(path);
+    }
+    normalized.sort();
+    let mut roots: Vec<PathBuf> = Vec::new();
+    for path in normalized {
+        if !roots.iter().any(|root| path.starts_with(root)) {
+            roots.push(path);
+        }
+    }
+
+    let mut common: Vec<Component> = roots[0].components().collect();
+    for root in &roots[1..] {
+        let shared = common
+            .iter()
+            .zip(root.components())
+            .take_while(|(a, b)| **a == *b)
+            .count();
+        common.truncate(shared);
+    }
+    let mut base: PathBuf = common.iter().collect();
+    if roots.len() == 1 {
+        return Ok((base, Vec::new()));
+    }
+    if base.as_os_str().is_empty() {
+        base = PathBuf::from(".");
+    }
+    let roots = roots
+        .iter()
+        .map(|root| base.join(root.strip_prefix(&base).unwrap_or(root)))
+        .collect();
+    Ok((base, roots))
+}
+
+/// `path` with `/` between its components on every platform, so output written
+/// on Windows names files the way it does everywhere else
+fn slashed(path: &Path) -> String {
+    let text = path.display().to_string();
+    match std::path::MAIN_SEPARATOR {
+        '/' => text,
+        separator => text.replace(separator, "/"),
+    }
+}
+
+/// Single-quote an argument when a POSIX shell would otherwise split or expand it
+fn shell_quote(arg: &str) -> String {
+    let is_plain = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./,:=@+".contains(c));
+    if is_plain {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
 }
 
 #[cfg(test)]
@@ -79,6 +1246,50 @@ mod tests {
     use super::*;
     use globset::Glob;
 
+    #[test]
+    fn test_parse_file_list() {
+        let list = "src/main.rs\n\n  ./README.md \n/abs/lib.rs\n";
+        assert_eq!(
+            parse_file_list(list, Path::new(".")),
+            vec![
+                PathBuf::from("./src/main.rs"),
+                PathBuf::from("./README.md"),
+                PathBuf::from("/abs/lib.rs"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_roots_walks_from_the_common_directory() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let root = dir.path().canonicalize().unwrap();
+        for sub in ["app/backend/src", "app/frontend/src", "app/shared"] {
+            std::fs::create_dir_all(root.join(sub)).unwrap();
+        }
+        let paths = [
+            root.join("app/frontend/src"),
+            root.join("app/backend/src"),
+            root.join("app/shared"),
+            root.join("app/backend/src/../src"),
+        ];
+        let (base, roots) = resolve_roots(&paths).unwrap();
+        assert_eq!(base, root.join("app"));
+        assert_eq!(
+            roots,
+            vec![
+                root.join("app/backend/src"),
+                root.join("app/frontend/src"),
+                root.join("app/shared"),
+            ]
+        );
+
+        let (base, roots) = resolve_roots(&[root.join("app"), root.join("app/shared")]).unwrap();
+        assert_eq!(base, root.join("app"));
+        assert!(roots.is_empty());
+
+        assert!(resolve_roots(&[root.join("app"), root.join("missing")]).is_err());
+    }
+
     #[test]
     fn test_include_only() {
         let config = Config {
@@ -91,6 +1302,25 @@ mod tests {
         assert!(!config.should_include_extension("json"));
     }
 
+    #[test]
+    fn test_no_extension_pseudo_extension() {
+        let mut config = Config {
+            include_extensions: Some(vec!["rs".to_string()]),
+            ..Default::default()
+        };
+        assert!(!config.should_include_extension(NO_EXTENSION));
+        config.set_no_extension(NoExtension::Include);
+        assert!(config.should_include_extension(NO_EXTENSION));
+        assert!(!config.should_include_extension("toml"));
+
+        let mut config = Config::default();
+        config.set_no_extension(NoExtension::Include);
+        assert_eq!(config.include_extensions, None);
+        config.set_no_extension(NoExtension::Exclude);
+        assert!(!config.should_include_extension(NO_EXTENSION));
+        assert!(config.should_include_extension("rs"));
+    }
+
     #[test]
     fn test_exclude_only() {
         let config = Config {
@@ -149,4 +1379,261 @@ mod tests {
         assert!(config.should_include_by_match("button.spec.js"));
         assert!(!config.should_include_by_match("main.go"));
     }
+
+    #[test]
+    fn test_not_match_only() {
+        let config = Config {
+            not_match_patterns: Some(vec![Glob::new("*.lock").unwrap().compile_matcher()]),
+            ..Default::default()
+        };
+
+        assert!(config.should_include_by_match("main.rs"));
+        assert!(!config.should_include_by_match("Cargo.lock"));
+    }
+
+    #[test]
+    fn test_not_match_wins_over_match() {
+        let config = Config {
+            path: PathBuf::from("/project"),
+            match_patterns: Some(vec![Glob::new("*.rs").unwrap().compile_matcher()]),
+            not_match_patterns: Some(vec![Glob::new("**/generated/**")
+                .unwrap()
+                .compile_matcher()]),
+            ..Default::default()
+        };
+
+        assert!(config.should_include_path_by_match(Path::new("/project/src/lib.rs")));
+        assert!(!config.should_include_path_by_match(Path::new("/project/src/generated/api.rs")));
+        assert!(!config.should_include_path_by_match(Path::new("/project/src/main.go")));
+    }
+
+    #[test]
+    fn test_project_default_exclusions_yield_to_match() {
+        let lockfile = Glob::new("Cargo.lock").unwrap().compile_matcher();
+        let mut config = Config {
+            project_exclusions: Some(vec![lockfile.clone()]),
+            ..Default::default()
+        };
+        assert!(config.is_project_default_excluded(Path::new("./Cargo.lock")));
+        assert!(!config.is_project_default_excluded(Path::new("./Cargo.toml")));
+
+        config.match_patterns = Some(vec![lockfile]);
+        assert!(!config.is_project_default_excluded(Path::new("./Cargo.lock")));
+    }
+
+    #[test]
+    fn test_keeps_full_content_by_extension() {
+        let config = Config {
+            no_compress_extensions: Some(vec!["sql".to_string(), "md".to_string()]),
+            full_match_patterns: Some(vec![Glob::new("main.rs").unwrap().compile_matcher()]),
+            ..Default::default()
+        };
+
+        assert!(config.keeps_full_content(Path::new("db/schema.SQL")));
+        assert!(config.keeps_full_content(Path::new("src/main.rs")));
+        assert!(!config.keeps_full_content(Path::new("src/lib.rs")));
+        assert!(!config.keeps_full_content(Path::new("Makefile")));
+    }
+
+    #[test]
+    fn test_adjust_priority_boosts_then_drops() {
+        let rule = |glob: &str, score| (Glob::new(glob).unwrap().compile_matcher(), score);
+        let config = Config {
+            path: PathBuf::from("/project"),
+            priority_boosts: vec![rule("src/core/**", 95), rule("*.rs", 40)],
+            priority_drops: vec![rule("src/core/generated/**", 10)],
+            ..Default::default()
+        };
+
+        let score = |path: &str, base| config.adjust_priority(Path::new(path), base);
+        assert_eq!(score("/project/src/core/engine.rs", 50), 95);
+        assert_eq!(
+            score("/project/src/util.rs", 50),
+            50,
+            "a boost never lowers"
+        );
+        assert_eq!(score("/project/src/util.rs", 30), 40);
+        assert_eq!(score("/project/src/core/generated/api.rs", 50), 10);
+        assert_eq!(score("/project/README.md", 100), 100);
+    }
+
+    #[test]
+    fn test_to_command_line_normalizes_options() {
+        let config = Config {
+            path: PathBuf::from("my project"),
+            include_extensions: Some(vec!["rs".to_string(), "toml".to_string()]),
+            not_match_patterns: Some(vec![Glob::new("*_test.rs").unwrap().compile_matcher()]),
+            output_file: Some(PathBuf::from("out.xml")),
+            max_file_size: 10 * 1024 * 1024,
+            compress: true,
+            token_budget: Some(8000),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            config.to_command_line(),
+            "flat 'my project' --include rs,toml --not-match '*_test.rs' \
+             --max-size 10485760 --compress --tokens 8000"
+        );
+        assert_eq!(Config::default().to_command_line(), "flat .");
+    }
+
+    #[test]
+    fn test_to_command_line_keeps_the_file_list() {
+        let listed = Config {
+            files_from: Some(vec![PathBuf::from("src/main.rs")]),
+            files_from_path: Some(PathBuf::from("list.txt")),
+            ..Default::default()
+        };
+        assert_eq!(listed.to_command_line(), "flat . --files-from list.txt");
+
+        let piped = Config {
+            files_from_path: Some(PathBuf::from("-")),
+            ..listed
+        };
+        assert_eq!(
+            piped.to_command_line(),
+            "flat . --files-from -  # reads the --files-from list on stdin, not saved"
+        );
+    }
+
+    #[test]
+    fn test_to_command_line_keeps_line_ranges() {
+        let range = LineRange { start: 1, end: 2 };
+        let matcher = || Glob::new("src/main.rs").unwrap().compile_matcher();
+        let matched = Config {
+            match_patterns: Some(vec![matcher()]),
+            line_ranges: vec![(matcher(), range)],
+            ..Default::default()
+        };
+        assert_eq!(matched.to_command_line(), "flat . --match src/main.rs:1-2");
+
+        let dir = tempfile::TempDir::new().unwrap();
+        let file = dir.path().join("main.rs");
+        std::fs::write(&file, "fn main() {}\n").unwrap();
+        let rooted = Config {
+            path: file.clone(),
+            line_ranges: vec![(Glob::new("main.rs").unwrap().compile_matcher(), range)],
+            ..Default::default()
+        };
+        assert_eq!(
+            rooted.to_command_line(),
+            format!("flat {}", shell_quote(&format!("{}:1-2", file.display())))
+        );
+    }
+
+    #[test]
+    fn test_follow_up_command_matches_only_the_excluded_files() {
+        let config = Config {
+            path: PathBuf::from("proj"),
+            match_patterns: Some(vec![Glob::new("*.rs").unwrap().compile_matcher()]),
+            token_budget: Some(8000),
+            diff_base: Some("main".to_string()),
+            ..Default::default()
+        };
+        let files = ["proj/src/a.rs".to_string(), "proj/src/[b].rs".to_string()];
+        assert_eq!(
+            config.follow_up_command(&files),
+            "flat proj --match src/a.rs --match 'src/[[]b[]].rs' --compress --tokens 8000"
+        );
+    }
+
+    #[test]
+    fn test_follow_up_command_drops_directory_quotas() {
+        let config = Config {
+            token_budget: Some(200),
+            directory_quotas: vec![
+                (PathBuf::from("src"), BudgetShare::Percent(10.0)),
+                (PathBuf::from("docs"), BudgetShare::Percent(50.0)),
+            ],
+            ..Default::default()
+        };
+        assert!(config.to_command_line().contains("--quota"));
+        assert_eq!(
+            config.follow_up_command(&["src/main.rs".to_string()]),
+            "flat . --match src/main.rs --compress --tokens 200"
+        );
+    }
+
+    #[test]
+    fn test_follow_up_command_writes_content_after_a_dry_run() {
+        let config = Config {
+            token_budget: Some(20),
+            dry_run: true,
+            ..Default::default()
+        };
+        let command = config.follow_up_command(&["src/main.rs".to_string()]);
+        assert_eq!(command, "flat . --match src/main.rs --compress --tokens 20");
+        assert!(!command.contains("--dry-run"));
+
+        let stats_only = Config {
+            stats_only: true,
+            ..config
+        };
+        assert!(!stats_only
+            .follow_up_command(&["src/main.rs".to_string()])
+            .contains("--stats"));
+    }
+
+    #[test]
+    fn test_reserve_tokens_comes_out_of_the_budget() {
+        let config = Config {
+            token_budget: Some(128_000),
+            reserve_tokens: Some(BudgetShare::Tokens(8_000)),
+            ..Config::default()
+        };
+        assert_eq!(config.reserved_tokens(), 8_000);
+        assert_eq!(config.allocation_budget(), Some(120_000));
+        assert!(config
+            .to_command_line()
+            .ends_with("--tokens 128000 --reserve-tokens 8000"));
+
+        let config = Config {
+            reserve_tokens: Some(BudgetShare::Percent(100.0)),
+            ..config
+        };
+        assert!(config.validate().is_err());
+        assert_eq!(Config::default().allocation_budget(), None);
+    }
+
+    #[test]
+    fn test_validate_clean_config() {
+        assert!(Config::default().validate().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_validate_rejects_stats_with_output_file() {
+        let config = Config {
+            stats_only: true,
+            output_file: Some(PathBuf::from("out.xml")),
+            ..Config::default()
+        };
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_warns_about_ignored_flags() {
+        let config = Config {
+            full_match_patterns: Some(vec![Glob::new("main.rs").unwrap().compile_matcher()]),
+            follow_doc_links: true,
+            token_budget: None,
+            ..Config::default()
+        };
+        let warnings = config.validate().unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("--full-match"));
+        assert!(warnings[1].contains("--follow-doc-links"));
+
+        let config = Config {
+            compress: true,
+            dry_run: true,
+            token_budget: Some(0),
+            ..Config::default()
+        };
+        let warnings = config.validate().unwrap();
+        assert_eq!(
+            warnings,
+            vec!["--tokens 0 leaves no room for any file; only the summary will be written"]
+        );
+    }
 }
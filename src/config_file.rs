@@ -0,0 +1,206 @@
+use crate::compress::NodeRules;
+use crate::output::{Layout, Order, OutputFormat};
+use crate::parse::{parse_binary_number, parse_decimal_number};
+use crate::tokens::TokenRatios;
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use serde::de::{self, Deserializer};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the project config file looked up at the scanned root
+pub const CONFIG_FILE_NAME: &str = "flat.toml";
+
+/// Settings read from a `flat.toml` file.
+///
+/// Every field is optional; CLI flags take precedence over file values.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FileConfig {
+    /// Text written before any file content (supports `{{date}}`, `{{git_sha}}`, `{{path}}`)
+    pub header: Option<String>,
+    /// Text written after the summary (same placeholders as `header`)
+    pub footer: Option<String>,
+    /// Per-language compressor adjustments, e.g. `[compress.rust] keep = ["macro_invocation"]`
+    #[serde(default)]
+    pub compress: BTreeMap<String, NodeRules>,
+    /// Shared defaults for command-line flags, e.g. `[defaults] include = ["rs"]`
+    #[serde(default)]
+    pub defaults: Defaults,
+    /// Bytes per token by file extension, e.g. `[token-ratios] rs = 3.4`, as
+    /// written by `flat calibrate`
+    #[serde(default, rename = "token-ratios", deserialize_with = "token_ratios")]
+    pub token_ratios: TokenRatios,
+}
+
+/// The `[defaults]` table: values for flags the command line leaves unset.
+///
+/// Keys are the flag names; a flag given on the command line replaces the file
+/// value entirely (an `--include` list is not merged with the file's list).
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Defaults {
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    /// Globs as for `--match`, `!` negating
+    #[serde(rename = "match")]
+    pub match_patterns: Option<Vec<String>>,
+    pub full_match: Option<Vec<String>>,
+    /// Globs as for `--exclude-match`
+    pub exclude_match: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "binary_number")]
+    pub max_size: Option<u64>,
+    pub compress: Option<bool>,
+    #[serde(default, deserialize_with = "decimal_number")]
+    pub tokens: Option<usize>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub format: Option<OutputFormat>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub layout: Option<Layout>,
+    #[serde(default, deserialize_with = "value_enum")]
+    pub order: Option<Order>,
+    /// `GLOB=SCORE` rules, as for `--priority-boost`
+    pub priority_boost: Option<Vec<String>>,
+    /// `GLOB=SCORE` rules, as for `--priority-drop`
+    pub priority_drop: Option<Vec<String>>,
+}
+
+/// A count written as a TOML integer or as a string with a suffix (`"10M"`)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Count {
+    Plain(u64),
+    Suffixed(String),
+}
+
+pub(crate) fn binary_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u64>, D::Error> {
+    match Count::deserialize(deserializer)? {
+        Count::Plain(n) => Ok(Some(n)),
+        Count::Suffixed(s) => parse_binary_number(&s).map(Some).map_err(de::Error::custom),
+    }
+}
+
+fn decimal_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<usize>, D::Error> {
+    match Count::deserialize(deserializer)? {
+        Count::Plain(n) => usize::try_from(n).map(Some).map_err(de::Error::custom),
+        Count::Suffixed(s) => parse_decimal_number(&s)
+            .map(Some)
+            .map_err(de::Error::custom),
+    }
+}
+
+/// A flag value spelled as on the command line (`format = "jsonl-events"`)
+fn value_enum<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: ValueEnum,
+{
+    let name = String::deserialize(deserializer)?;
+    T::from_str(&name, false)
+        .map(Some)
+        .map_err(de::Error::custom)
+}
+
+/// Bytes-per-token ratios keyed by lowercased extension; each must be positive
+fn token_ratios<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TokenRatios, D::Error> {
+    let ratios = BTreeMap::<String, f64>::deserialize(deserializer)?;
+    ratios
+        .into_iter()
+        .map(|(ext, ratio)| {
+            if ratio.is_finite() && ratio > 0.0 {
+                Ok((ext.trim_start_matches('.').to_ascii_lowercase(), ratio))
+            } else {
+                Err(de::Error::custom(format!(
+                    "token ratio for '{}' must be a positive number of bytes per token",
+                    ext
+                )))
+            }
+        })
+        .collect()
+}
+
+/// Find the config file for a scan rooted at `root`, if one exists
+pub fn discover(root: &Path) -> Option<PathBuf> {
+    let dir = if root.is_file() { root.parent()? } else { root };
+    let candidate = dir.join(CONFIG_FILE_NAME);
+    candidate.is_file().then_some(candidate)
+}
+
+/// Read and parse a config file
+pub fn load(path: &Path) -> Result<FileConfig> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+    parse(&text).with_context(|| format!("Invalid config file: {}", path.display()))
+}
+
+fn parse(text: &str) -> Result<FileConfig> {
+    Ok(toml::from_str(text)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_header_footer() {
+        let config = parse("header = \"Repo at {{git_sha}}\"\nfooter = \"end\"\n").unwrap();
+        assert_eq!(config.header.as_deref(), Some("Repo at {{git_sha}}"));
+        assert_eq!(config.footer.as_deref(), Some("end"));
+    }
+
+    #[test]
+    fn test_parse_empty() {
+        let config = parse("").unwrap();
+        assert!(config.header.is_none());
+        assert!(config.footer.is_none());
+    }
+
+    #[test]
+    fn test_parse_compress_rules() {
+        let config =
+            parse("[compress.rust]\nkeep = [\"macro_invocation\"]\ndrop = [\"line_comment\"]\n")
+                .unwrap();
+        let rules = &config.compress["rust"];
+        assert_eq!(rules.keep, vec!["macro_invocation"]);
+        assert_eq!(rules.drop, vec!["line_comment"]);
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let config = parse(
+            "[defaults]\ninclude = [\"rs\", \"toml\"]\nmatch = [\"!*.snap\"]\n\
+             max-size = \"10M\"\ncompress = true\ntokens = 8000\nformat = \"markdown\"\n",
+        )
+        .unwrap();
+        let defaults = config.defaults;
+        assert_eq!(defaults.include.unwrap(), vec!["rs", "toml"]);
+        assert_eq!(defaults.match_patterns.unwrap(), vec!["!*.snap"]);
+        assert_eq!(defaults.max_size, Some(10 * 1024 * 1024));
+        assert_eq!(defaults.compress, Some(true));
+        assert_eq!(defaults.tokens, Some(8000));
+        assert_eq!(defaults.format, Some(OutputFormat::Markdown));
+        assert!(defaults.layout.is_none());
+
+        assert!(parse("[defaults]\nformat = \"yaml\"\n").is_err());
+        assert!(parse("[defaults]\nmax-size = \"lots\"\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_token_ratios() {
+        let config = parse("[token-ratios]\nrs = 3.4\n\".MD\" = 4\n").unwrap();
+        assert_eq!(config.token_ratios["rs"], 3.4);
+        assert_eq!(config.token_ratios["md"], 4.0);
+
+        assert!(parse("[token-ratios]\nrs = 0\n").is_err());
+        assert!(parse("[token-ratios]\nrs = -2.5\n").is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_keys() {
+        assert!(parse("heder = \"typo\"").is_err());
+    }
+}
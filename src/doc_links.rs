@@ -0,0 +1,126 @@
+use std::collections::HashSet;
+use std::path::{Component, Path, PathBuf};
+
+/// Extract local link targets from Markdown: inline links `[text](target)`,
+/// reference definitions `[id]: target`, and path-like code spans `` `src/lib.rs` ``.
+///
+/// URLs, in-page anchors, and titles are dropped; `#fragment` suffixes are stripped.
+pub fn extract_link_targets(markdown: &str) -> Vec<String> {
+    let mut targets = Vec::new();
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+
+        // Reference definition: [id]: target "title"
+        if trimmed.starts_with('[') {
+            if let Some((_, rest)) = trimmed.split_once("]:") {
+                push_target(&mut targets, rest);
+                continue;
+            }
+        }
+
+        // Inline links and images: ...](target "title")
+        let mut rest = line;
+        while let Some(start) = rest.find("](") {
+            let after = &rest[start + 2..];
+            let Some(end) = after.find(')') else {
+                break;
+            };
+            push_target(&mut targets, &after[..end]);
+            rest = &after[end..];
+        }
+
+        // Code spans that look like file paths
+        for (i, span) in line.split('`').enumerate() {
+            if i % 2 == 1 && looks_like_path(span) {
+                push_target(&mut targets, span);
+            }
+        }
+    }
+
+    targets
+}
+
+fn push_target(targets: &mut Vec<String>, raw: &str) {
+    let target = raw.split_whitespace().next().unwrap_or("");
+    let target = target.trim_start_matches('<').trim_end_matches('>');
+    let target = target.split('#').next().unwrap_or("");
+    if target.is_empty() || target.contains("://") || target.starts_with("mailto:") {
+        return;
+    }
+    targets.push(target.to_string());
+}
+
+fn looks_like_path(span: &str) -> bool {
+    !span.is_empty()
+        && !span.contains(char::is_whitespace)
+        && span.contains('/')
+        && Path::new(span).extension().is_some()
+}
+
+/// Resolve link targets found in `doc` to candidate paths: relative to the
+/// document's directory first, then to the scanned root.
+pub fn resolve_targets(doc: &Path, root: &Path, targets: &[String]) -> HashSet<PathBuf> {
+    let doc_dir = doc.parent().unwrap_or(Path::new(""));
+    let mut resolved = HashSet::new();
+    for target in targets {
+        let relative = target.trim_start_matches('/');
+        resolved.insert(normalize(&doc_dir.join(relative)));
+        resolved.insert(normalize(&root.join(relative)));
+    }
+    resolved
+}
+
+/// Lexically normalize a path: drop `.` components and fold `..` into its parent
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push("..");
+                }
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_link_targets() {
+        let markdown = "\
+See [the walker](src/walker.rs#L10) and ![diagram](docs/arch.png \"Arch\").
+Hosted at [GitHub](https://github.com/zkoranges/flat), jump to [usage](#usage).
+Scoring lives in `src/priority.rs`; run `cargo test`.
+
+[config]: ./src/config.rs
+";
+        assert_eq!(
+            extract_link_targets(markdown),
+            vec![
+                "src/walker.rs",
+                "docs/arch.png",
+                "src/priority.rs",
+                "./src/config.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_targets_relative_to_doc_and_root() {
+        let resolved = resolve_targets(
+            Path::new("project/docs/guide.md"),
+            Path::new("project"),
+            &["../src/lib.rs".to_string(), "src/main.rs".to_string()],
+        );
+        assert!(resolved.contains(Path::new("project/src/lib.rs")));
+        assert!(resolved.contains(Path::new("project/src/main.rs")));
+        assert!(resolved.contains(Path::new("project/docs/src/main.rs")));
+    }
+}
@@ -0,0 +1,180 @@
+//! `flat doctor`: check each piece flat depends on and report what works, for
+//! when a run hangs or crashes on one machine and not another.
+
+use crate::cache;
+use crate::compress::{grammar_fingerprint, load_grammar, CompressLanguage};
+use crate::config::Config;
+use crate::git;
+use crate::tokens::Tokenizer;
+use crate::walker::walk_and_flatten;
+use anyhow::{Context, Result};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Outcome of one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Works, with a limitation worth knowing about
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Status::Ok => " ok ",
+            Status::Warn => "warn",
+            Status::Fail => "FAIL",
+        };
+        f.write_str(label)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub detail: String,
+}
+
+impl Check {
+    fn new(name: impl Into<String>, status: Status, detail: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            status,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}] {:<20} {}", self.status, self.name, self.detail)
+    }
+}
+
+/// Run every check, in report order
+pub fn run() -> Vec<Check> {
+    let mut checks = Vec::new();
+    for name in CompressLanguage::NAMES {
+        let lang = CompressLanguage::from_name(name).expect("listed language");
+        checks.push(match load_grammar(lang) {
+            Ok(()) => Check::new(
+                format!("grammar {}", name),
+                Status::Ok,
+                grammar_fingerprint(lang),
+            ),
+            Err(e) => Check::new(format!("grammar {}", name), Status::Fail, e),
+        });
+    }
+
+    checks.push(match git::version() {
+        Some(version) => Check::new("git", Status::Ok, version),
+        None => Check::new(
+            "git",
+            Status::Warn,
+            "git not found; {git_sha} expands to \"unknown\"",
+        ),
+    });
+    checks.push(Check::new(
+        "clipboard",
+        Status::Ok,
+        "not used; flat writes to stdout or -o, pipe it to your clipboard tool",
+    ));
+    checks.push(if Tokenizer::Cl100kBase.is_available() {
+        Check::new(
+            "tokenizer",
+            Status::Ok,
+            "heuristic, cl100k_base, o200k_base",
+        )
+    } else {
+        Check::new(
+            "tokenizer",
+            Status::Warn,
+            "heuristic only; built without the `tiktoken` feature",
+        )
+    });
+    checks.push(check_cache());
+    checks.push(match end_to_end() {
+        Ok(detail) => Check::new("end-to-end", Status::Ok, detail),
+        Err(e) => Check::new("end-to-end", Status::Fail, format!("{:#}", e)),
+    });
+    checks
+}
+
+fn check_cache() -> Check {
+    let Some(root) = cache::default_dir() else {
+        return Check::new(
+            "cache",
+            Status::Warn,
+            "no cache directory: set FLAT_CACHE_DIR, XDG_CACHE_HOME, or HOME",
+        );
+    };
+    let probe = root.join(format!(".doctor{}", std::process::id()));
+    let writable = fs::create_dir_all(&root).and_then(|_| fs::write(&probe, b"ok"));
+    let _ = fs::remove_file(&probe);
+    match writable {
+        Ok(()) => Check::new("cache", Status::Ok, root.display().to_string()),
+        Err(e) => Check::new(
+            "cache",
+            Status::Warn,
+            format!(
+                "{} is not writable ({}); --cache won't work",
+                root.display(),
+                e
+            ),
+        ),
+    }
+}
+
+/// Flatten a two-file project in a scratch directory and check the output
+fn end_to_end() -> Result<String> {
+    let dir = std::env::temp_dir().join(format!("flat-doctor-{}", std::process::id()));
+    let result = flatten_sample(&dir);
+    let _ = fs::remove_dir_all(&dir);
+    result
+}
+
+fn flatten_sample(dir: &Path) -> Result<String> {
+    fs::create_dir_all(dir.join("src"))
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+    fs::write(
+        dir.join("src/main.rs"),
+        "fn main() {\n    println!(\"hello\");\n}\n",
+    )?;
+    fs::write(dir.join("README.md"), "# Sample\n")?;
+
+    let output_file = dir.join("out.xml");
+    let config = Config {
+        path: dir.to_path_buf(),
+        compress: true,
+        output_file: Some(output_file.clone()),
+        ..Default::default()
+    };
+    let stats = walk_and_flatten(&config)?;
+    let output = fs::read_to_string(&output_file)?;
+    anyhow::ensure!(
+        stats.included_files == 2 && output.contains("fn main() { ... }"),
+        "unexpected output:\n{}",
+        output
+    );
+    Ok(format!(
+        "flattened {} files, compressed {}",
+        stats.included_files, stats.compressed_files
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_checks_pass_in_the_build_environment() {
+        let checks = run();
+        let failed: Vec<_> = checks.iter().filter(|c| c.status == Status::Fail).collect();
+        assert!(failed.is_empty(), "{:?}", failed);
+        assert!(checks.iter().any(|c| c.name == "end-to-end"));
+    }
+}
@@ -0,0 +1,251 @@
+//! Reading files as text. A byte order mark says how a file is encoded: UTF-8
+//! files lose theirs, and UTF-16 and UTF-32 files (common from Windows tools and
+//! PowerShell) are decoded to UTF-8, so neither the mark nor the encoding reaches
+//! the output, the token estimates, or the compressors. Without a mark, UTF-16
+//! is recognized by its null bytes, and text that isn't UTF-8 is read as
+//! windows-1252, the superset of Latin-1 that legacy Western files are in.
+
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, WINDOWS_1252};
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// An encoding announced by a byte order mark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bom {
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    Utf32Le,
+    Utf32Be,
+}
+
+impl Bom {
+    /// The mark at the start of `bytes`, if any
+    pub fn detect(bytes: &[u8]) -> Option<Bom> {
+        // UTF-32LE's mark begins with UTF-16LE's, so it is checked first
+        match bytes {
+            [0xFF, 0xFE, 0x00, 0x00, ..] => Some(Bom::Utf32Le),
+            [0x00, 0x00, 0xFE, 0xFF, ..] => Some(Bom::Utf32Be),
+            [0xEF, 0xBB, 0xBF, ..] => Some(Bom::Utf8),
+            [0xFF, 0xFE, ..] => Some(Bom::Utf16Le),
+            [0xFE, 0xFF, ..] => Some(Bom::Utf16Be),
+            _ => None,
+        }
+    }
+
+    /// Length of the mark in bytes
+    pub fn byte_len(self) -> usize {
+        match self {
+            Bom::Utf8 => 3,
+            Bom::Utf16Le | Bom::Utf16Be => 2,
+            Bom::Utf32Le | Bom::Utf32Be => 4,
+        }
+    }
+
+    /// Whether the encoding is a wide one, whose ASCII text is full of null bytes
+    pub fn is_wide(self) -> bool {
+        self != Bom::Utf8
+    }
+
+    /// The encoding's name, as the summary and errors spell it
+    pub fn name(self) -> &'static str {
+        match self {
+            Bom::Utf8 => "UTF-8",
+            Bom::Utf16Le => "UTF-16LE",
+            Bom::Utf16Be => "UTF-16BE",
+            Bom::Utf32Le => "UTF-32LE",
+            Bom::Utf32Be => "UTF-32BE",
+        }
+    }
+}
+
+/// Bytes sampled to tell UTF-16 without a byte order mark from other text
+const SNIFF_BYTES: usize = 8192;
+
+/// UTF-16 without a byte order mark, recognized by its null bytes: the high
+/// byte of every ASCII character. `bytes` is the start of a file.
+pub fn sniff_utf16(bytes: &[u8]) -> Option<Bom> {
+    let sample = &bytes[..bytes.len().min(SNIFF_BYTES) & !1];
+    let units = sample.len() / 2;
+    if units < 2 {
+        return None;
+    }
+    let nulls_at = |parity: usize| {
+        sample
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let (even, odd) = (nulls_at(0), nulls_at(1));
+    // Mostly ASCII in one byte of each unit, and no null in the other
+    if even == 0 && odd * 2 >= units {
+        Some(Bom::Utf16Le)
+    } else if odd == 0 && even * 2 >= units {
+        Some(Bom::Utf16Be)
+    } else {
+        None
+    }
+}
+
+/// Read `path` as text, decoding it by its byte order mark and dropping the mark
+pub fn read_text(path: &Path) -> io::Result<String> {
+    decode(fs::read(path)?)
+}
+
+/// [`read_text`], also naming the encoding the text was transcoded from, if it
+/// wasn't UTF-8
+pub fn read_transcoded(path: &Path) -> io::Result<(String, Option<&'static str>)> {
+    transcode(fs::read(path)?)
+}
+
+/// Decode `bytes` by their byte order mark, or when there is none as UTF-16 if
+/// they look it, UTF-8 if they are, and windows-1252 otherwise. Content that
+/// isn't valid in its encoding, or that comes out with control characters
+/// text doesn't have, is an `InvalidData` error, as with `fs::read_to_string`.
+pub fn decode(bytes: Vec<u8>) -> io::Result<String> {
+    transcode(bytes).map(|(text, _)| text)
+}
+
+/// [`decode`], also naming the encoding the text was transcoded from, if it
+/// wasn't UTF-8
+pub fn transcode(bytes: Vec<u8>) -> io::Result<(String, Option<&'static str>)> {
+    let (bom, body) = match Bom::detect(&bytes) {
+        Some(bom) => (bom, &bytes[bom.byte_len()..]),
+        None => match sniff_utf16(&bytes) {
+            Some(bom) => (bom, &bytes[..]),
+            None => return decode_unmarked(bytes),
+        },
+    };
+    let decoded = match bom {
+        Bom::Utf8 => std::str::from_utf8(body).ok().map(str::to_string),
+        Bom::Utf16Le => decode_with(UTF_16LE, body),
+        Bom::Utf16Be => decode_with(UTF_16BE, body),
+        Bom::Utf32Le => decode_utf32(body, u32::from_le_bytes),
+        Bom::Utf32Be => decode_utf32(body, u32::from_be_bytes),
+    };
+    let text = decoded.ok_or_else(|| invalid_data(bom.name()))?;
+    Ok((text, (bom != Bom::Utf8).then(|| bom.name())))
+}
+
+/// UTF-8, or failing that windows-1252, which maps every byte to a character;
+/// only text without stray control characters passes for it
+fn decode_unmarked(bytes: Vec<u8>) -> io::Result<(String, Option<&'static str>)> {
+    let bytes = match String::from_utf8(bytes) {
+        Ok(text) => return Ok((text, None)),
+        Err(e) => e.into_bytes(),
+    };
+    let text = decode_with(WINDOWS_1252, &bytes)
+        .filter(|text| !text.chars().any(is_stray_control))
+        .ok_or_else(|| invalid_data("UTF-8 or windows-1252"))?;
+    Ok((text, Some(WINDOWS_1252.name())))
+}
+
+fn decode_with(encoding: &'static Encoding, bytes: &[u8]) -> Option<String> {
+    encoding
+        .decode_without_bom_handling_and_without_replacement(bytes)
+        .map(|text| text.into_owned())
+}
+
+/// A control character that doesn't belong in text, as opposed to tabs, line
+/// breaks, form feeds, and escapes for terminal colors
+fn is_stray_control(c: char) -> bool {
+    c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b')
+}
+
+/// [`decode`], replacing what can't be decoded with U+FFFD instead of failing
+pub fn decode_lossy(bytes: &[u8]) -> String {
+    match decode(bytes.to_vec()) {
+        Ok(text) => text,
+        Err(_) => {
+            let body = &bytes[Bom::detect(bytes)
+                .filter(|b| !b.is_wide())
+                .map_or(0, Bom::byte_len)..];
+            String::from_utf8_lossy(body).into_owned()
+        }
+    }
+}
+
+/// `text` without a leading byte order mark, for text that is already decoded
+pub fn strip_bom(text: &str) -> &str {
+    text.strip_prefix('\u{FEFF}').unwrap_or(text)
+}
+
+fn decode_utf32(body: &[u8], unit: fn([u8; 4]) -> u32) -> Option<String> {
+    if !body.len().is_multiple_of(4) {
+        return None;
+    }
+    body.chunks_exact(4)
+        .map(|quad| char::from_u32(unit([quad[0], quad[1], quad[2], quad[3]])))
+        .collect()
+}
+
+fn invalid_data(encoding: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("stream did not contain valid {}", encoding),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utf16(text: &str, bom: [u8; 2], unit: fn(u16) -> [u8; 2]) -> Vec<u8> {
+        let mut bytes = bom.to_vec();
+        bytes.extend(text.encode_utf16().flat_map(unit));
+        bytes
+    }
+
+    #[test]
+    fn test_decode_by_byte_order_mark() {
+        let text = "fn main() {} // é 🦀\n";
+        assert_eq!(decode(text.as_bytes().to_vec()).unwrap(), text);
+        let utf8 = [&[0xEF, 0xBB, 0xBF], text.as_bytes()].concat();
+        assert_eq!(decode(utf8).unwrap(), text);
+        let le = utf16(text, [0xFF, 0xFE], u16::to_le_bytes);
+        assert_eq!(decode(le).unwrap(), text);
+        let be = utf16(text, [0xFE, 0xFF], u16::to_be_bytes);
+        assert_eq!(decode(be).unwrap(), text);
+        let mut utf32 = vec![0xFF, 0xFE, 0x00, 0x00];
+        utf32.extend(text.chars().flat_map(|c| (c as u32).to_le_bytes()));
+        assert_eq!(decode(utf32).unwrap(), text);
+    }
+
+    #[test]
+    fn test_invalid_content_is_an_error() {
+        // An unpaired surrogate, and an odd byte count
+        assert!(decode(vec![0xFF, 0xFE, 0x00, 0xD8]).is_err());
+        assert!(decode(vec![0xFF, 0xFE, b'a']).is_err());
+        assert!(decode(vec![0xEF, 0xBB, 0xBF, 0xC3]).is_err());
+        assert_eq!(decode_lossy(&[0xEF, 0xBB, 0xBF, b'a', 0xC3]), "a\u{FFFD}");
+    }
+
+    #[test]
+    fn test_transcode_without_a_mark() {
+        let text = "fn main() {} // é\n";
+        assert_eq!(
+            transcode(text.as_bytes().to_vec()).unwrap(),
+            (text.to_string(), None)
+        );
+
+        let le: Vec<u8> = text.encode_utf16().flat_map(u16::to_le_bytes).collect();
+        assert_eq!(sniff_utf16(&le), Some(Bom::Utf16Le));
+        assert_eq!(transcode(le).unwrap(), (text.to_string(), Some("UTF-16LE")));
+        let be: Vec<u8> = text.encode_utf16().flat_map(u16::to_be_bytes).collect();
+        assert_eq!(transcode(be).unwrap(), (text.to_string(), Some("UTF-16BE")));
+
+        // Latin-1: "café" with é as the single byte 0xE9
+        let latin1 = b"# caf\xE9\r\n".to_vec();
+        assert_eq!(
+            transcode(latin1).unwrap(),
+            ("# café\r\n".to_string(), Some("windows-1252"))
+        );
+
+        // Not UTF-8, and control characters no text has
+        assert!(decode(b"\x01\x02\xFF\x03".to_vec()).is_err());
+        assert_eq!(sniff_utf16(b"plain ASCII"), None);
+    }
+}
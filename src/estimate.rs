@@ -0,0 +1,152 @@
+//! `--estimate-compress`: run the allocator with and without `--compress` and
+//! compare what each would write, to show what compression buys on this project.
+
+use crate::config::Config;
+use crate::output::Statistics;
+use crate::report::{Decision, FileReport};
+use crate::walker::{analyze, analyze_with};
+use crate::warnings::Warnings;
+use anyhow::Result;
+use std::fmt;
+
+/// What one allocation run would write
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Allocation {
+    pub included: usize,
+    pub compressed: usize,
+    pub excluded: usize,
+    /// Estimated tokens of the content written, compressed where it was
+    pub tokens: usize,
+}
+
+impl Allocation {
+    fn from_reports(reports: &[FileReport]) -> Self {
+        let mut allocation = Allocation::default();
+        for report in reports {
+            match report.decision {
+                Decision::Full => {
+                    allocation.included += 1;
+                    allocation.tokens += report.tokens_full;
+                }
+                Decision::Truncated => {
+                    allocation.included += 1;
+                    allocation.tokens += report.tokens_written();
+                }
+                Decision::Compressed => {
+                    allocation.included += 1;
+                    allocation.compressed += 1;
+                    allocation.tokens += report.tokens_written();
+                }
+                Decision::Excluded => allocation.excluded += 1,
+                Decision::Skipped => {}
+            }
+        }
+        allocation
+    }
+}
+
+/// The same selection allocated without and with compression
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompressEstimate {
+    pub budget: Option<usize>,
+    pub full: Allocation,
+    pub compressed: Allocation,
+}
+
+/// Allocate `config`'s selection twice, ignoring its own `--compress` setting.
+/// Only the compressed pass prints warnings, since it sees every file the
+/// other pass does plus any compression failures.
+pub fn estimate_compress(config: &Config) -> Result<CompressEstimate> {
+    let with_compress = |compress: bool| Config {
+        compress,
+        ..config.clone()
+    };
+    let compressed = analyze(&with_compress(true))?;
+    let full = analyze_with(&with_compress(false), Warnings::silent())?;
+    Ok(CompressEstimate {
+        budget: config.allocation_budget(),
+        full: Allocation::from_reports(&full),
+        compressed: Allocation::from_reports(&compressed),
+    })
+}
+
+impl fmt::Display for CompressEstimate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let rows = [
+            (
+                "Files included",
+                self.full.included.to_string(),
+                self.compressed.included.to_string(),
+            ),
+            (
+                "  compressed",
+                self.full.compressed.to_string(),
+                self.compressed.compressed.to_string(),
+            ),
+            (
+                "Excluded by budget",
+                self.full.excluded.to_string(),
+                self.compressed.excluded.to_string(),
+            ),
+            (
+                "Tokens used",
+                Statistics::format_tokens(self.full.tokens),
+                Statistics::format_tokens(self.compressed.tokens),
+            ),
+        ];
+        writeln!(
+            f,
+            "{:<20}{:>20}{:>20}",
+            "", "without --compress", "with --compress"
+        )?;
+        for (label, full, compressed) in rows {
+            writeln!(f, "{:<20}{:>20}{:>20}", label, full, compressed)?;
+        }
+        match self.budget {
+            Some(budget) => writeln!(f, "Budget: {} tokens", Statistics::format_tokens(budget)),
+            None => writeln!(f, "No --tokens budget; every file is included either way"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn report(
+        decision: Decision,
+        tokens_full: usize,
+        tokens_compressed: Option<usize>,
+    ) -> FileReport {
+        FileReport {
+            path: PathBuf::from("a.rs"),
+            score: 0,
+            role: crate::priority::Role::Source,
+            bytes: tokens_full * 3,
+            tokens_full,
+            tokens_compressed,
+            decision,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn test_allocation_counts_written_tokens() {
+        let reports = [
+            report(Decision::Full, 100, None),
+            report(Decision::Compressed, 300, Some(40)),
+            report(Decision::Excluded, 900, None),
+            report(Decision::Skipped, 50, None),
+        ];
+        assert_eq!(
+            Allocation::from_reports(&reports),
+            Allocation {
+                included: 2,
+                compressed: 1,
+                excluded: 1,
+                tokens: 140,
+            }
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+
+/// Archive formats for `--export-selection`, picked by file extension
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    Zip,
+    Tar,
+}
+
+impl ArchiveFormat {
+    fn from_path(path: &Path) -> Result<Self> {
+        let ext = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "zip" => Ok(ArchiveFormat::Zip),
+            "tar" => Ok(ArchiveFormat::Tar),
+            _ => bail!(
+                "Unsupported archive format for {} (use .zip or .tar)",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Write the selected files, with their raw contents, into an archive at `archive`.
+///
+/// Entry names are the file paths relative to `root`, so unpacking the archive
+/// recreates a trimmed copy of the tree.
+pub fn export_selection(archive: &Path, root: &Path, files: &[(PathBuf, String)]) -> Result<()> {
+    let format = ArchiveFormat::from_path(archive)?;
+    let file = fs::File::create(archive)
+        .with_context(|| format!("Failed to create archive: {}", archive.display()))?;
+
+    match format {
+        ArchiveFormat::Zip => {
+            let mut zip = zip::ZipWriter::new(file);
+            let options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Deflated);
+            for (path, content) in files {
+                zip.start_file(entry_name(path, root), options)?;
+                zip.write_all(content.as_bytes())?;
+            }
+            zip.finish()?;
+        }
+        ArchiveFormat::Tar => {
+            let mut tar = tar::Builder::new(file);
+            for (path, content) in files {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(content.len() as u64);
+                header.set_mode(0o644);
+                tar.append_data(&mut header, entry_name(path, root), content.as_bytes())?;
+            }
+            tar.finish()?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Archive entry name: relative to `root`, with `/` separators and no leading `./`
+pub(crate) fn entry_name(path: &Path, root: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .filter(|c| !matches!(c, Component::CurDir | Component::RootDir))
+        .map(|c| c.as_os_str().to_string_lossy().into_owned())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_entry_name_is_relative() {
+        assert_eq!(
+            entry_name(Path::new("./project/src/main.rs"), Path::new("./project")),
+            "src/main.rs"
+        );
+        assert_eq!(
+            entry_name(Path::new("./src/lib.rs"), Path::new(".")),
+            "src/lib.rs"
+        );
+    }
+
+    #[test]
+    fn test_export_tar_roundtrip() {
+        let dir = TempDir::new().unwrap();
+        let archive = dir.path().join("selection.tar");
+        let files = vec![(
+            PathBuf::from("root/src/main.rs"),
+            "fn main() {}\n".to_string(),
+        )];
+        export_selection(&archive, Path::new("root"), &files).unwrap();
+
+        let mut reader = tar::Archive::new(fs::File::open(&archive).unwrap());
+        let names: Vec<String> = reader
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().to_string_lossy().into_owned())
+            .collect();
+        assert_eq!(names, vec!["src/main.rs"]);
+    }
+
+    #[test]
+    fn test_export_rejects_unknown_format() {
+        let err = export_selection(Path::new("out.rar"), Path::new("."), &[]).unwrap_err();
+        assert!(err.to_string().contains("use .zip or .tar"));
+    }
+}
@@ -1,3 +1,4 @@
+use crate::encoding::{sniff_utf16, Bom};
 use std::fs::File;
 use std::io::Read;
 use std::path::Path;
@@ -27,31 +28,125 @@ const BINARY_EXTENSIONS: &[&str] = &[
     "pdf", "doc", "docx", "xls", "xlsx", "ppt", "pptx",
 ];
 
-#[derive(Debug, Clone, PartialEq)]
+/// File name suffixes of code generated from protobuf/gRPC and similar schemas
+const GENERATED_SUFFIXES: &[&str] = &[
+    "_pb2.py",
+    "_pb2.pyi",
+    "_pb2_grpc.py",
+    ".pb.go",
+    ".pb.cc",
+    ".pb.h",
+    "_pb.js",
+    "_pb.d.ts",
+    "_grpc_pb.js",
+    "_grpc_pb.d.ts",
+    ".generated.ts",
+    ".generated.js",
+    ".generated.cs",
+];
+
+/// Build output and dependency directories skipped when the tree has no `.gitignore`
+const DEFAULT_EXCLUDED_DIRS: &[&str] = &[
+    "node_modules",
+    "bower_components",
+    "target",
+    "dist",
+    "build",
+    ".venv",
+    "venv",
+    "__pycache__",
+    ".tox",
+    ".mypy_cache",
+    ".pytest_cache",
+    ".gradle",
+    ".next",
+];
+
+/// Why a file was left out before its content mattered. Every reason is
+/// written as its [`code`](SkipReason::code): in the summary, in JSON stats and
+/// events, and by `--show-skipped`. Codes are stable; tooling can match on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SkipReason {
+    /// Named like a credential file (`.env`, `*.pem`, `credentials.json`)
     Secret,
-    Binary,
+    /// A binary file type by extension (`.png`, `.exe`)
+    BinaryExtension,
+    /// Null bytes in the first 8KB
+    BinaryContent,
+    /// Over `--max-size`
     TooLarge,
-    Extension,
-    Match,
+    /// Ruled out by `--include` or `--exclude`
+    ExtensionFilter,
+    /// Ruled out by `--match` or `--not-match`
+    GlobFilter,
+    /// Matched by `--exclude-match`
+    GlobExclude,
+    ProjectDefault,
+    /// A `skip` rule in `flat.overrides`
+    Override,
     Gitignore,
     ReadError,
+    /// One of flat's own artifacts: the output file, a bundle, the cache
+    OwnOutput,
+    /// Reached through a followed symlink, but already included by another path
+    Duplicate,
+    /// Unchanged within `--since`
+    NotRecent,
+    /// A `flat:skip` pragma in the file
+    Pragma,
+    /// Not text in its byte order mark's encoding, UTF-8, or windows-1252
+    Undecodable,
 }
 
-impl std::fmt::Display for SkipReason {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl SkipReason {
+    /// Every reason, in declaration order
+    pub const ALL: [SkipReason; 16] = [
+        SkipReason::Secret,
+        SkipReason::BinaryExtension,
+        SkipReason::BinaryContent,
+        SkipReason::TooLarge,
+        SkipReason::ExtensionFilter,
+        SkipReason::GlobFilter,
+        SkipReason::GlobExclude,
+        SkipReason::ProjectDefault,
+        SkipReason::Override,
+        SkipReason::Gitignore,
+        SkipReason::ReadError,
+        SkipReason::OwnOutput,
+        SkipReason::Duplicate,
+        SkipReason::NotRecent,
+        SkipReason::Pragma,
+        SkipReason::Undecodable,
+    ];
+
+    pub fn code(self) -> &'static str {
         match self {
-            SkipReason::Secret => write!(f, "secret"),
-            SkipReason::Binary => write!(f, "binary"),
-            SkipReason::TooLarge => write!(f, "too large"),
-            SkipReason::Extension => write!(f, "extension"),
-            SkipReason::Match => write!(f, "no match"),
-            SkipReason::Gitignore => write!(f, "gitignore"),
-            SkipReason::ReadError => write!(f, "read error"),
+            SkipReason::Secret => "secret",
+            SkipReason::BinaryExtension => "binary-ext",
+            SkipReason::BinaryContent => "binary-content",
+            SkipReason::TooLarge => "too-large",
+            SkipReason::ExtensionFilter => "ext-filter",
+            SkipReason::GlobFilter => "glob-filter",
+            SkipReason::GlobExclude => "glob-exclude",
+            SkipReason::ProjectDefault => "project-default",
+            SkipReason::Override => "override",
+            SkipReason::Gitignore => "gitignore",
+            SkipReason::ReadError => "read-error",
+            SkipReason::OwnOutput => "own-output",
+            SkipReason::Duplicate => "duplicate",
+            SkipReason::NotRecent => "not-recent",
+            SkipReason::Pragma => "pragma",
+            SkipReason::Undecodable => "undecodable",
         }
     }
 }
 
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
 /// Check if a filename matches secret patterns
 pub fn is_secret_file(path: &Path) -> bool {
     let file_name = match path.file_name() {
@@ -90,6 +185,22 @@ pub fn is_binary_extension(path: &Path) -> bool {
     false
 }
 
+/// Check if a file name marks it as generated from a schema (e.g. `*_pb2.py`, `*.pb.go`)
+pub fn is_generated_stub(path: &Path) -> bool {
+    match path.file_name() {
+        Some(name) => {
+            let name = name.to_string_lossy().to_lowercase();
+            GENERATED_SUFFIXES.iter().any(|s| name.ends_with(s))
+        }
+        None => false,
+    }
+}
+
+/// Check if a directory name is a well-known build or dependency directory
+pub fn is_default_excluded_dir(name: &str) -> bool {
+    DEFAULT_EXCLUDED_DIRS.contains(&name)
+}
+
 /// Check if a file is binary by reading its content
 /// Returns true if the file appears to be binary (contains null bytes in first 8KB)
 pub fn is_binary_content(path: &Path) -> bool {
@@ -101,13 +212,55 @@ pub fn is_binary_content(path: &Path) -> bool {
     let mut buffer = vec![0; 8192];
     match file.read(&mut buffer) {
         Ok(n) => {
-            // Check for null bytes in the read portion
+            // UTF-16 and UTF-32 text is full of null bytes; its mark, or where
+            // they fall, says it's text
+            if Bom::detect(&buffer[..n]).is_some_and(Bom::is_wide)
+                || sniff_utf16(&buffer[..n]).is_some()
+            {
+                return false;
+            }
             buffer[..n].contains(&0)
         }
         Err(_) => false,
     }
 }
 
+/// Files at least this big whose lines average at least [`MINIFIED_LINE_BYTES`]
+/// are treated as minified
+const MINIFIED_MIN_BYTES: usize = 16 * 1024;
+
+/// Average line length above which a file reads as minified JSON or a bundle
+const MINIFIED_LINE_BYTES: usize = 1000;
+
+/// Only the start of a file is sampled for minification
+const MINIFIED_SAMPLE_BYTES: usize = 64 * 1024;
+
+/// Check if content looks minified: big, with almost no line breaks (a bundle,
+/// a one-line JSON dump). Only the first 64KB is looked at.
+pub fn is_minified(content: &[u8]) -> bool {
+    let sample = &content[..content.len().min(MINIFIED_SAMPLE_BYTES)];
+    if sample.len() < MINIFIED_MIN_BYTES {
+        return false;
+    }
+    let lines = sample.iter().filter(|&&b| b == b'\n').count() + 1;
+    sample.len() / lines >= MINIFIED_LINE_BYTES
+}
+
+/// Check if a file looks minified by reading its first 64KB, like [`is_minified`]
+pub fn is_minified_content(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let mut sample = Vec::with_capacity(MINIFIED_SAMPLE_BYTES);
+    match file
+        .take(MINIFIED_SAMPLE_BYTES as u64)
+        .read_to_end(&mut sample)
+    {
+        Ok(_) => is_minified(&sample),
+        Err(_) => false,
+    }
+}
+
 /// Check if a file exceeds the size limit
 pub fn exceeds_size_limit(path: &Path, max_size: u64) -> bool {
     match std::fs::metadata(path) {
@@ -120,6 +273,17 @@ pub fn exceeds_size_limit(path: &Path, max_size: u64) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_skip_reason_codes_are_distinct_kebab_case() {
+        let codes: std::collections::HashSet<_> =
+            SkipReason::ALL.iter().map(|r| r.code()).collect();
+        assert_eq!(codes.len(), SkipReason::ALL.len());
+        for code in codes {
+            assert!(code.chars().all(|c| c.is_ascii_lowercase() || c == '-'));
+        }
+        assert_eq!(SkipReason::BinaryContent.to_string(), "binary-content");
+    }
+
     #[test]
     fn test_secret_file_detection() {
         assert!(is_secret_file(Path::new(".env")));
@@ -148,4 +312,27 @@ mod tests {
         assert!(!is_binary_extension(Path::new("config.toml")));
         assert!(!is_binary_extension(Path::new("README.md")));
     }
+
+    #[test]
+    fn test_minified_detection() {
+        let bundle = format!("!function(){{{}}}();\n", "var a=1;".repeat(4000));
+        assert!(is_minified(bundle.as_bytes()));
+
+        let source = "fn main() {\n    println!(\"hi\");\n}\n".repeat(1000);
+        assert!(!is_minified(source.as_bytes()));
+        assert!(!is_minified(b"{\"small\":true}"), "too small to matter");
+    }
+
+    #[test]
+    fn test_generated_stub_detection() {
+        assert!(is_generated_stub(Path::new("api/greeter_pb2.py")));
+        assert!(is_generated_stub(Path::new("api/greeter_pb2_grpc.py")));
+        assert!(is_generated_stub(Path::new("api/greeter.pb.go")));
+        assert!(is_generated_stub(Path::new("api/greeter_grpc.pb.go")));
+        assert!(is_generated_stub(Path::new("src/client.generated.ts")));
+
+        assert!(!is_generated_stub(Path::new("api/greeter.proto")));
+        assert!(!is_generated_stub(Path::new("src/client.ts")));
+        assert!(!is_generated_stub(Path::new("pb.go")));
+    }
 }
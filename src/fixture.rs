@@ -0,0 +1,239 @@
+//! `flat gen-fixture`: write a synthetic polyglot project of a chosen size, for
+//! benchmarking and stress-testing the walker and the budget allocator.
+//!
+//! Output is deterministic: the same spec always writes the same tree.
+
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How big a project to generate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixtureSpec {
+    /// Source files written for each language
+    pub files_per_language: usize,
+    /// Directory levels below each language's directory
+    pub depth: usize,
+    /// Approximate lines per source file
+    pub lines_per_file: usize,
+}
+
+impl Default for FixtureSpec {
+    fn default() -> Self {
+        Self {
+            files_per_language: 10,
+            depth: 2,
+            lines_per_file: 100,
+        }
+    }
+}
+
+/// What [`generate`] wrote
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FixtureSummary {
+    pub files: usize,
+    pub bytes: u64,
+}
+
+/// A language's directory and extension, the lines wrapping each file, and one
+/// function (`{i}` is replaced by its index, `{F}` by its capitalized name)
+struct Template {
+    dir: &'static str,
+    ext: &'static str,
+    open: &'static str,
+    function: &'static str,
+    close: &'static str,
+}
+
+const TEMPLATES: &[Template] = &[
+    Template {
+        dir: "rust",
+        ext: "rs",
+        open: "use std::collections::HashMap;\n\n",
+        function: "/// Computes value {i}\npub fn f{i}(x: i32, cache: &mut HashMap<i32, i32>) -> i32 {\n    let y = x + {i};\n    cache.insert(x, y);\n    y * 2\n}\n\n",
+        close: "",
+    },
+    Template {
+        dir: "typescript",
+        ext: "ts",
+        open: "import { readFileSync } from \"fs\";\n\n",
+        function: "/** Computes value {i} */\nexport function f{i}(x: number): number {\n  const y = x + {i};\n  return y * 2;\n}\n\n",
+        close: "",
+    },
+    Template {
+        dir: "javascript",
+        ext: "js",
+        open: "const path = require(\"path\");\n\n",
+        function: "function f{i}(x) {\n  const y = x + {i};\n  return y * 2;\n}\n\n",
+        close: "",
+    },
+    Template {
+        dir: "python",
+        ext: "py",
+        open: "import os\n\n",
+        function: "def f{i}(x):\n    \"\"\"Computes value {i}.\"\"\"\n    y = x + {i}\n    return y * 2\n\n",
+        close: "",
+    },
+    Template {
+        dir: "go",
+        ext: "go",
+        open: "package gen\n\nimport \"fmt\"\n\n",
+        function: "// {F} computes value {i}.\nfunc {F}(x int) int {\n\ty := x + {i}\n\tfmt.Println(y)\n\treturn y * 2\n}\n\n",
+        close: "",
+    },
+    Template {
+        dir: "java",
+        ext: "java",
+        open: "import java.util.List;\n\npublic class Gen {\n",
+        function: "    public static int f{i}(int x) {\n        int y = x + {i};\n        return y * 2;\n    }\n\n",
+        close: "}\n",
+    },
+    Template {
+        dir: "csharp",
+        ext: "cs",
+        open: "using System;\n\npublic static class Gen\n{\n",
+        function: "    public static int F{i}(int x)\n    {\n        var y = x + {i};\n        return y * 2;\n    }\n\n",
+        close: "}\n",
+    },
+    Template {
+        dir: "c",
+        ext: "c",
+        open: "#include <stdio.h>\n\n",
+        function: "int f{i}(int x) {\n    int y = x + {i};\n    return y * 2;\n}\n\n",
+        close: "",
+    },
+    Template {
+        dir: "cpp",
+        ext: "cpp",
+        open: "#include <vector>\n\nnamespace gen {\n\n",
+        function: "int f{i}(const std::vector<int>& xs) {\n    int y = static_cast<int>(xs.size()) + {i};\n    return y * 2;\n}\n\n",
+        close: "}\n",
+    },
+    Template {
+        dir: "ruby",
+        ext: "rb",
+        open: "require \"json\"\n\nmodule Gen\n",
+        function: "  def self.f{i}(x)\n    y = x + {i}\n    y * 2\n  end\n\n",
+        close: "end\n",
+    },
+    Template {
+        dir: "php",
+        ext: "php",
+        open: "<?php\n\nnamespace Gen;\n\n",
+        function: "function f{i}($x) {\n    $y = $x + {i};\n    return $y * 2;\n}\n\n",
+        close: "",
+    },
+];
+
+/// Write a project per `spec` into `dir`, which must be empty or not exist yet
+pub fn generate(dir: &Path, spec: &FixtureSpec) -> Result<FixtureSummary> {
+    if fs::read_dir(dir).is_ok_and(|mut entries| entries.next().is_some()) {
+        bail!("{} is not empty", dir.display());
+    }
+    let mut summary = FixtureSummary::default();
+    let mut write = |relative: PathBuf, content: String| -> Result<()> {
+        let path = dir.join(relative);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        fs::write(&path, &content)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        summary.files += 1;
+        summary.bytes += content.len() as u64;
+        Ok(())
+    };
+
+    write(
+        PathBuf::from("README.md"),
+        format!(
+            "# Generated fixture\n\n{} files per language, {} levels deep. See [the design](docs/design.md).\n",
+            spec.files_per_language, spec.depth
+        ),
+    )?;
+    write(
+        PathBuf::from("docs/design.md"),
+        "# Design\n\nEvery module exports numbered functions.\n".to_string(),
+    )?;
+
+    for template in TEMPLATES {
+        for n in 0..spec.files_per_language {
+            write(
+                module_path(template, n, spec.depth),
+                source_file(template, n, spec),
+            )?;
+        }
+        // One test per language, so the allocator sees every role
+        let test = PathBuf::from("tests")
+            .join(template.dir)
+            .join(format!("gen_test.{}", template.ext));
+        write(test, source_file(template, 0, spec))?;
+    }
+    Ok(summary)
+}
+
+/// `src/<language>/d<k>/.../mod<n>.<ext>`, spreading files across a binary tree
+/// of directories `depth` levels deep
+fn module_path(template: &Template, n: usize, depth: usize) -> PathBuf {
+    let mut path = PathBuf::from("src").join(template.dir);
+    for level in 0..depth {
+        path.push(format!("d{}", (n >> level) & 1));
+    }
+    path.join(format!("mod{}.{}", n, template.ext))
+}
+
+fn source_file(template: &Template, n: usize, spec: &FixtureSpec) -> String {
+    let function_lines = template.function.lines().count().max(1);
+    let functions = (spec.lines_per_file / function_lines).max(1);
+    let mut content = template.open.to_string();
+    for f in 0..functions {
+        let i = (n * functions + f).to_string();
+        content.push_str(
+            &template
+                .function
+                .replace("{F}", &format!("F{}", i))
+                .replace("{i}", &i),
+        );
+    }
+    content.push_str(template.close);
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::{compress_source, language_for_path, CompressResult};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_generated_sources_compress_cleanly() {
+        let dir = TempDir::new().unwrap();
+        let spec = FixtureSpec {
+            files_per_language: 3,
+            depth: 2,
+            lines_per_file: 40,
+        };
+        let summary = generate(dir.path(), &spec).unwrap();
+        assert_eq!(summary.files, 2 + TEMPLATES.len() * 4);
+        assert!(dir.path().join("src/rust/d1/d0/mod1.rs").is_file());
+
+        for template in TEMPLATES {
+            let path = dir.path().join(module_path(template, 2, spec.depth));
+            let source = fs::read_to_string(&path).unwrap();
+            let lang = language_for_path(&path).unwrap();
+            match compress_source(&source, lang) {
+                CompressResult::Compressed(output) => {
+                    assert!(output.len() < source.len(), "{}", path.display())
+                }
+                CompressResult::Fallback(_, reason) => {
+                    panic!("{} fell back: {:?}", path.display(), reason)
+                }
+            }
+        }
+
+        assert!(
+            generate(dir.path(), &spec).is_err(),
+            "refuses a non-empty dir"
+        );
+    }
+}
@@ -0,0 +1,175 @@
+//! The pieces flat's XML output is made of, for tools that add fragments of
+//! their own to a flat document — a synthetic file holding runtime logs, a stub
+//! for something they left out — and need them to read exactly like flat's.
+//!
+//! Each function returns the text [`OutputWriter`](crate::output::OutputWriter)
+//! writes for the same input, trailing blank line included, so fragments can be
+//! concatenated in any order ahead of the [`summary`].
+
+use crate::filters::is_generated_stub;
+use crate::output::Statistics;
+use std::path::Path;
+
+pub use crate::output::{FileAttrs, FileMode};
+
+/// Closes a file's content, ending the element
+pub const FILE_CLOSE: &str = "</file>\n\n";
+
+/// Escape `&`, `<`, `>`, and both quotes, for use in attribute values
+pub fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Last line of a file cut short under `--truncate`, after the content kept
+pub const TRUNCATION_MARKER: &str = "<!-- truncated -->";
+
+/// What separates a line's number from its text under `--line-numbers`, unless
+/// `--line-number-separator` says otherwise
+pub const DEFAULT_LINE_NUMBER_SEPARATOR: &str = ": ";
+
+/// `content` with each line prefixed by its number, counting from `first`, and
+/// `separator`. Numbers are right-aligned to the widest one, so the text
+/// stays in one column.
+pub fn number_lines(content: &str, first: usize, separator: &str) -> String {
+    let count = content.split_inclusive('\n').count();
+    let width = (first + count.saturating_sub(1)).to_string().len();
+    let mut numbered = String::with_capacity(content.len() + count * (width + separator.len()));
+    for (i, line) in content.split_inclusive('\n').enumerate() {
+        numbered.push_str(&format!("{:>width$}{}", first + i, separator));
+        numbered.push_str(line);
+    }
+    numbered
+}
+
+/// `<file path="..." ...>` line opening a file's content, with the attributes
+/// described on [`FileAttrs`]. Paths of generated stubs (`*_pb2.py`, `*.pb.go`)
+/// also get `generated="true"`, as flat marks them.
+pub fn file_open_tag(path: &str, attrs: FileAttrs) -> String {
+    let mut tag = format!("<file path=\"{}\"", escape_xml(path));
+    if let Some(mode) = attrs.mode {
+        tag.push_str(&format!(" mode=\"{}\"", mode.as_str()));
+    }
+    if attrs.truncated {
+        tag.push_str(" truncated=\"true\"");
+    }
+    if let Some(lines) = attrs.lines {
+        tag.push_str(&format!(" lines=\"{}\"", lines));
+    }
+    if let Some(role) = attrs.role {
+        tag.push_str(&format!(" role=\"{}\"", role.as_str()));
+    }
+    if let Some(score) = attrs.score {
+        tag.push_str(&format!(" score=\"{}\"", score));
+    }
+    if let (Some(added), Some(removed)) = (attrs.added, attrs.removed) {
+        tag.push_str(&format!(" added=\"{}\" removed=\"{}\"", added, removed));
+    }
+    if let Some(owners) = attrs.owners {
+        tag.push_str(&format!(" owners=\"{}\"", escape_xml(owners)));
+    }
+    if let Some(tokens) = attrs.tokens {
+        tag.push_str(&format!(" tokens=\"{}\"", tokens));
+    }
+    if let Some(bytes) = attrs.bytes {
+        tag.push_str(&format!(" bytes=\"{}\"", bytes));
+    }
+    if let Some(sha256) = attrs.sha256 {
+        tag.push_str(&format!(" sha256=\"{}\"", sha256));
+    }
+    // Derivable from their schema, which is the better thing to read
+    if is_generated_stub(Path::new(path)) {
+        tag.push_str(" generated=\"true\"");
+    }
+    tag.push_str(">\n");
+    tag
+}
+
+/// A whole file element. Content is written verbatim, as flat writes it, and
+/// gets a final newline if it lacks one.
+pub fn file_element(path: &str, content: &str, attrs: FileAttrs) -> String {
+    let mut element = file_open_tag(path, attrs);
+    element.push_str(content);
+    if !content.ends_with('\n') {
+        element.push('\n');
+    }
+    element.push_str(FILE_CLOSE);
+    element
+}
+
+/// Self-closing stub for a file that was left out, e.g. `reason` "budget"
+pub fn excluded_stub(path: &str, reason: &str) -> String {
+    format!(
+        "<file path=\"{}\" excluded-reason=\"{}\"/>\n\n",
+        escape_xml(path),
+        escape_xml(reason)
+    )
+}
+
+/// Self-closing stub for a symlink that wasn't followed
+pub fn symlink_stub(path: &str, target: &str) -> String {
+    format!(
+        "<file path=\"{}\" symlink-to=\"{}\"/>\n\n",
+        escape_xml(path),
+        escape_xml(target)
+    )
+}
+
+/// The `<summary>` block ending a document
+pub fn summary(stats: &Statistics) -> String {
+    stats.format_summary() + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::output::OutputWriter;
+
+    #[test]
+    fn test_escape_xml() {
+        assert_eq!(escape_xml("hello"), "hello");
+        assert_eq!(escape_xml("<tag>"), "&lt;tag&gt;");
+        assert_eq!(escape_xml("a & b"), "a &amp; b");
+        assert_eq!(escape_xml("\"quoted\""), "&quot;quoted&quot;");
+    }
+
+    #[test]
+    fn test_number_lines_aligns_numbers() {
+        let content: String = (1..=10).map(|i| format!("line {}\n", i)).collect();
+        let numbered = number_lines(&content, 1, ": ");
+        assert!(numbered.starts_with(" 1: line 1\n 2: line 2\n"));
+        assert!(numbered.ends_with("10: line 10\n"));
+        assert_eq!(number_lines("a\nb", 99, "\t"), " 99\ta\n100\tb");
+        assert_eq!(number_lines("", 1, ": "), "");
+    }
+
+    #[test]
+    fn test_fragments_match_the_writer() {
+        let attrs = FileAttrs::with_mode(Some(FileMode::Full)).with_truncated(true);
+        let mut stats = Statistics::new();
+        stats.add_included(Some("log"));
+
+        let mut buf = Vec::new();
+        {
+            let mut writer = OutputWriter::new(Box::new(&mut buf));
+            writer
+                .write_file_content_with_attrs("logs/<run>.log", "started\nfailed", attrs)
+                .unwrap();
+            writer.write_excluded("big.rs", "budget").unwrap();
+            writer.write_symlink("link.rs", "../a.rs").unwrap();
+            writer.write_summary(&stats).unwrap();
+        }
+
+        let fragments = file_element("logs/<run>.log", "started\nfailed", attrs)
+            + &excluded_stub("big.rs", "budget")
+            + &symlink_stub("link.rs", "../a.rs")
+            + &summary(&stats);
+        assert_eq!(String::from_utf8(buf).unwrap(), fragments);
+        assert!(fragments.starts_with(
+            "<file path=\"logs/&lt;run&gt;.log\" mode=\"full\" truncated=\"true\">\nstarted\nfailed\n</file>\n\n"
+        ));
+    }
+}
@@ -0,0 +1,334 @@
+use crate::encoding::decode_lossy;
+use anyhow::{bail, Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::thread;
+
+/// Run a git command in `dir`, returning trimmed stdout on success.
+///
+/// Returns None when git is not installed, `dir` is not inside a work tree,
+/// or the command fails for any other reason.
+fn git_output(dir: &Path, args: &[&str]) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let stdout = String::from_utf8(output.stdout).ok()?;
+    Some(stdout.trim().to_string())
+}
+
+/// Run a git command in `dir` for output flat can't do without, turning a
+/// failure into an error that carries git's own message
+fn git_required(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).context("git printed a path that isn't UTF-8")
+}
+
+/// Lines added and removed in one file, as `git diff --numstat` counts them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineChanges {
+    pub added: usize,
+    pub removed: usize,
+}
+
+/// Files under `dir` that differ from `base` (`--diff`): committed, staged, and
+/// unstaged changes, plus untracked files that aren't ignored. Deleted files
+/// are left out, since there's nothing to flatten.
+///
+/// Each comes with its line changes, None for binary files; every line of an
+/// untracked file counts as added.
+pub fn changed_files(dir: &Path, base: &str) -> Result<Vec<(PathBuf, Option<LineChanges>)>> {
+    let numstat = git_required(
+        dir,
+        &[
+            "diff",
+            "--numstat",
+            "-z",
+            "--relative",
+            "--diff-filter=d",
+            base,
+            "--",
+        ],
+    )?;
+    let untracked = git_required(dir, &["ls-files", "-z", "--others", "--exclude-standard"])?;
+
+    let mut files: Vec<_> = parse_numstat(&numstat)
+        .into_iter()
+        .map(|(path, changes)| (dir.join(path), changes))
+        .collect();
+    for path in untracked.split('\0').filter(|path| !path.is_empty()) {
+        let path = dir.join(path);
+        let added = fs::read_to_string(&path)
+            .ok()
+            .map(|text| text.lines().count());
+        files.push((path, added.map(|added| LineChanges { added, removed: 0 })));
+    }
+    Ok(files)
+}
+
+/// Files under `dir` staged in the index (`--staged`), with their line changes
+/// against HEAD. Deleted files are left out. Before the first commit, every
+/// staged file is new.
+pub fn staged_files(dir: &Path) -> Result<Vec<(PathBuf, Option<LineChanges>)>> {
+    let numstat = git_required(
+        dir,
+        &[
+            "diff",
+            "--cached",
+            "--numstat",
+            "-z",
+            "--relative",
+            "--diff-filter=d",
+            "--",
+        ],
+    )?;
+    Ok(parse_numstat(&numstat)
+        .into_iter()
+        .map(|(path, changes)| (dir.join(path), changes))
+        .collect())
+}
+
+/// The index's version of each of `paths` under `dir`, read in one
+/// `git cat-file --batch` run. Paths not in the index are left out. Content
+/// that isn't UTF-8 is converted lossily; null bytes survive, so binary files
+/// are still recognized as binary.
+pub fn index_contents(dir: &Path, paths: &[PathBuf]) -> Result<Vec<(PathBuf, String)>> {
+    let mut requests = String::new();
+    let mut requested = Vec::new();
+    for path in paths {
+        let relative = path.strip_prefix(dir).unwrap_or(path).to_string_lossy();
+        // One object name per line, so a path with a newline can't be asked for
+        if !relative.contains('\n') {
+            requests.push_str(&format!(":./{}\n", relative));
+            requested.push(path.clone());
+        }
+    }
+
+    let mut child = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["cat-file", "--batch"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to run git")?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    // Written from another thread, so a full stdout pipe can't stall both sides
+    let writer = thread::spawn(move || stdin.write_all(requests.as_bytes()));
+    let output = child.wait_with_output().context("Failed to run git")?;
+    writer
+        .join()
+        .expect("stdin writer panicked")
+        .context("Failed to write to git cat-file")?;
+    if !output.status.success() {
+        bail!(
+            "git cat-file --batch failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let blobs = parse_batch(&output.stdout)?;
+    Ok(requested
+        .into_iter()
+        .zip(blobs)
+        .filter_map(|(path, blob)| Some((path, decode_lossy(blob?))))
+        .collect())
+}
+
+/// Parse `git cat-file --batch` output: for each request, `SHA TYPE SIZE\n`
+/// then SIZE bytes and a newline, or `NAME missing\n`
+fn parse_batch(mut output: &[u8]) -> Result<Vec<Option<&[u8]>>> {
+    let mut blobs = Vec::new();
+    while let Some(end) = output.iter().position(|&b| b == b'\n') {
+        let header = String::from_utf8_lossy(&output[..end]);
+        output = &output[end + 1..];
+        if header.ends_with(" missing") || header.ends_with(" ambiguous") {
+            blobs.push(None);
+            continue;
+        }
+        let size: usize = header
+            .rsplit(' ')
+            .next()
+            .and_then(|size| size.parse().ok())
+            .with_context(|| format!("Unexpected git cat-file header: {}", header))?;
+        if output.len() < size {
+            bail!("git cat-file output ended early");
+        }
+        blobs.push(Some(&output[..size]));
+        output = output.get(size + 1..).unwrap_or_default();
+    }
+    Ok(blobs)
+}
+
+/// Parse `git diff --numstat -z` records: `ADDED\tREMOVED\tPATH\0`, or
+/// `ADDED\tREMOVED\t\0OLD\0NEW\0` for a rename. Binary files count `-`.
+fn parse_numstat(text: &str) -> Vec<(&str, Option<LineChanges>)> {
+    let mut fields = text.split('\0');
+    let mut files = Vec::new();
+    while let Some(record) = fields.next() {
+        let mut parts = record.splitn(3, '\t');
+        let (Some(added), Some(removed), Some(path)) = (parts.next(), parts.next(), parts.next())
+        else {
+            continue;
+        };
+        let path = match path {
+            // A rename: the old path, then the new one
+            "" => fields.nth(1).unwrap_or_default(),
+            path => path,
+        };
+        let changes = match (added.parse(), removed.parse()) {
+            (Ok(added), Ok(removed)) => Some(LineChanges { added, removed }),
+            _ => None,
+        };
+        if !path.is_empty() {
+            files.push((path, changes));
+        }
+    }
+    files
+}
+
+/// The `max` most frequent committers of each file under `dir` (`--owners`),
+/// joined with commas, e.g. `alice,bob`.
+///
+/// One `git log` pass over the history counts the non-merge commits each
+/// author made to each path, which is much faster than blaming file by file.
+/// Ties go to the alphabetically first name.
+pub fn owners(dir: &Path, max: usize) -> Result<HashMap<PathBuf, String>> {
+    let log = git_required(
+        dir,
+        &[
+            "-c",
+            "core.quotePath=false",
+            "log",
+            "--no-merges",
+            "--no-renames",
+            "--relative",
+            "--name-only",
+            "--format=%x01%aN",
+            "--",
+            ".",
+        ],
+    )?;
+    Ok(parse_owners(&log, max)
+        .into_iter()
+        .map(|(path, owners)| (dir.join(path), owners))
+        .collect())
+}
+
+/// Parse `git log --name-only --format=%x01%aN`: each commit is `\x01AUTHOR`
+/// followed by the paths it touched, one per line
+fn parse_owners(log: &str, max: usize) -> HashMap<&str, String> {
+    let mut commits: HashMap<&str, HashMap<&str, usize>> = HashMap::new();
+    for commit in log.split('\x01') {
+        let mut lines = commit.lines();
+        let Some(author) = lines.next().map(str::trim).filter(|a| !a.is_empty()) else {
+            continue;
+        };
+        for path in lines.filter(|line| !line.is_empty()) {
+            *commits.entry(path).or_default().entry(author).or_default() += 1;
+        }
+    }
+    commits
+        .into_iter()
+        .map(|(path, authors)| {
+            let mut authors: Vec<_> = authors.into_iter().collect();
+            authors.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then(a.cmp(b)));
+            let top: Vec<_> = authors.into_iter().take(max).map(|(a, _)| a).collect();
+            (path, top.join(","))
+        })
+        .collect()
+}
+
+/// `git --version` output, or None when git can't be run
+pub fn version() -> Option<String> {
+    let output = Command::new("git").arg("--version").output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Abbreviated SHA of the commit checked out in the repository containing `dir`
+pub fn head_sha(dir: &Path) -> Option<String> {
+    git_output(dir, &["rev-parse", "--short", "HEAD"]).filter(|sha| !sha.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_batch_reads_blobs_and_missing_objects() {
+        let output = b"d670460b blob 6\nhello\n\n:./gone.rs missing\n8b137891 blob 0\n\n";
+        assert_eq!(
+            parse_batch(output).unwrap(),
+            vec![Some(&b"hello\n"[..]), None, Some(&b""[..])]
+        );
+        assert!(parse_batch(b"d670460b blob 60\nshort\n").is_err());
+    }
+
+    #[test]
+    fn test_head_sha_missing_directory() {
+        assert_eq!(head_sha(Path::new("/nonexistent/flat/dir")), None);
+    }
+
+    #[test]
+    fn test_parse_numstat_handles_renames_and_binaries() {
+        let text = "12\t3\tsrc/a.rs\0-\t-\tlogo.png\x001\t1\t\0old.rs\0new.rs\0";
+        assert_eq!(
+            parse_numstat(text),
+            vec![
+                (
+                    "src/a.rs",
+                    Some(LineChanges {
+                        added: 12,
+                        removed: 3
+                    })
+                ),
+                ("logo.png", None),
+                (
+                    "new.rs",
+                    Some(LineChanges {
+                        added: 1,
+                        removed: 1
+                    })
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_owners_ranks_by_commit_count() {
+        let log = "\x01Bob\n\nsrc/a.rs\nsrc/b.rs\n\x01Alice\n\nsrc/a.rs\n\x01Alice\n\nsrc/a.rs\n\x01Carol\n\nsrc/a.rs\n";
+        let owners = parse_owners(log, 2);
+        assert_eq!(owners["src/a.rs"], "Alice,Bob");
+        assert_eq!(owners["src/b.rs"], "Bob");
+    }
+
+    #[test]
+    fn test_changed_files_outside_a_repository() {
+        let err = changed_files(Path::new("/nonexistent/flat/dir"), "main").unwrap_err();
+        assert!(err.to_string().starts_with("git diff"), "{}", err);
+    }
+}
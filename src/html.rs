@@ -0,0 +1,250 @@
+//! Pieces of the standalone `--format html` report.
+//!
+//! The page streams like the other formats: the preamble goes out before the
+//! first file, each file is a collapsible section, and the summary closes the
+//! page with the stats dashboard and a file tree linking back to each section.
+
+use crate::output::Statistics;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+const HIGHLIGHT_JS: &str = "https://cdnjs.cloudflare.com/ajax/libs/highlight.js/11.9.0";
+
+/// Document head and the opening of the content column
+pub fn preamble() -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>flat report</title>
+<link rel="stylesheet" href="{HIGHLIGHT_JS}/styles/github.min.css">
+<script src="{HIGHLIGHT_JS}/highlight.min.js"></script>
+<style>
+body {{ display: flex; margin: 0; font-family: system-ui, sans-serif; }}
+nav {{ order: -1; width: 22rem; flex-shrink: 0; height: 100vh; overflow: auto; position: sticky; top: 0; padding: 1rem; border-right: 1px solid #ddd; box-sizing: border-box; }}
+nav ul {{ list-style: none; padding-left: 1rem; margin: 0; }}
+main {{ flex: 1; min-width: 0; padding: 1rem; }}
+details.file {{ border: 1px solid #ddd; border-radius: 4px; margin-bottom: .5rem; }}
+details.file > summary {{ cursor: pointer; padding: .4rem .6rem; font-family: monospace; background: #f6f8fa; }}
+.mode, .annotation, .subtotal {{ color: #666; font-size: .85em; margin-left: .5rem; }}
+pre {{ margin: 0; overflow: auto; }}
+table.stats td {{ padding: .2rem .8rem .2rem 0; }}
+</style>
+</head>
+<body>
+<main>
+"#
+    )
+}
+
+/// One collapsible file section; `id` is the anchor the tree links to
+pub fn file_section(id: usize, path: &str, mode: Option<&str>, content: &str) -> String {
+    format!(
+        "{}{}{}",
+        file_section_open(id, path, mode),
+        escape_html(content),
+        FILE_SECTION_CLOSE
+    )
+}
+
+/// [`file_section`] up to the start of the content, for streamed files
+pub fn file_section_open(id: usize, path: &str, mode: Option<&str>) -> String {
+    let language = Path::new(path)
+        .extension()
+        .map(|e| e.to_string_lossy())
+        .unwrap_or("plaintext".into());
+    let mode = mode
+        .map(|m| format!(r#"<span class="mode">{}</span>"#, escape_html(m)))
+        .unwrap_or_default();
+    format!(
+        "<details class=\"file\" id=\"f-{id}\" open>\n<summary>{}{mode}</summary>\n<pre><code class=\"language-{}\">",
+        escape_html(path),
+        escape_html(&language),
+    )
+}
+
+/// Streamed content, escaped chunk by chunk
+pub fn file_section_chunk(content: &str) -> String {
+    escape_html(content)
+}
+
+pub const FILE_SECTION_CLOSE: &str = "</code></pre>\n</details>\n";
+
+/// A dry-run listing line
+pub fn listing(path: &str, annotation: Option<&str>) -> String {
+    let annotation = annotation
+        .map(|a| format!(r#"<span class="annotation">{}</span>"#, escape_html(a)))
+        .unwrap_or_default();
+    format!(
+        "<div class=\"listing\"><code>{}</code>{annotation}</div>\n",
+        escape_html(path)
+    )
+}
+
+/// Heading for a `--layout sections` section
+pub fn section_heading(name: &str) -> String {
+    format!("<h2 class=\"section\">{}</h2>\n", escape_html(name))
+}
+
+/// Closing subtotal for a `--layout sections` section
+pub fn section_subtotal(files: usize, tokens: &str) -> String {
+    format!(
+        "<p class=\"subtotal\">{} files, ~{} tokens</p>\n",
+        files,
+        escape_html(tokens)
+    )
+}
+
+/// A header or footer snippet
+pub fn snippet(text: &str) -> String {
+    format!(
+        "<section class=\"snippet\"><pre>{}</pre></section>\n",
+        escape_html(text)
+    )
+}
+
+/// Stats dashboard, the file tree, and the highlighter kick-off
+pub fn summary(stats: &Statistics, paths: &[String]) -> String {
+    let mut html =
+        String::from("<section class=\"stats\">\n<h2>Summary</h2>\n<table class=\"stats\">\n");
+    let mut row = |label: &str, value: String| {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td>{}</td></tr>",
+            label,
+            escape_html(&value)
+        );
+    };
+    row("Total files", stats.total_files.to_string());
+    row("Included", stats.included_files.to_string());
+    if stats.coverage.text_files > 0 {
+        row("Coverage", stats.coverage.describe());
+    }
+    if stats.compressed_files > 0 {
+        row("Compressed", stats.compressed_files.to_string());
+    }
+    if stats.redactions > 0 {
+        row("Redacted values", stats.redactions.to_string());
+    }
+    if stats.minified_files > 0 {
+        row("Minified (shortened)", stats.minified_files.to_string());
+    }
+    if stats.transcoded_files > 0 {
+        row("Transcoded to UTF-8", stats.transcoded_files.to_string());
+    }
+    if let Some(separator) = &stats.line_numbers {
+        row("Line numbers", format!("on (separator {:?})", separator));
+    }
+    for (reason, count) in &stats.skipped_by_reason {
+        row(
+            &format!("Skipped ({})", escape_html(reason)),
+            count.to_string(),
+        );
+    }
+    if let Some(budget) = stats.token_budget {
+        row(
+            "Token budget",
+            format!("{} / {} used", stats.tokens_used, budget),
+        );
+        if stats.reserved_tokens > 0 {
+            row("Reserved", stats.reserved_tokens.to_string());
+        }
+        row(
+            "Excluded by budget",
+            stats.excluded_by_budget.len().to_string(),
+        );
+        if stats.truncated_files > 0 {
+            row("Truncated to fit", stats.truncated_files.to_string());
+        }
+    }
+    row("Output size", format!("{} bytes", stats.output_size));
+    if let Some(command) = &stats.command {
+        row("Re-run", command.clone());
+    }
+    html.push_str("</table>\n</section>\n</main>\n");
+
+    html.push_str("<nav>\n<h3>Files</h3>\n");
+    let mut tree = TreeNode::default();
+    for (id, path) in paths.iter().enumerate() {
+        tree.insert(path.split('/').collect::<Vec<_>>().as_slice(), id);
+    }
+    tree.render(&mut html);
+    html.push_str("</nav>\n<script>hljs.highlightAll();</script>\n");
+    html
+}
+
+/// Directory tree of the written files, keyed by path component
+#[derive(Default)]
+struct TreeNode {
+    dirs: BTreeMap<String, TreeNode>,
+    files: BTreeMap<String, usize>,
+}
+
+impl TreeNode {
+    fn insert(&mut self, components: &[&str], id: usize) {
+        match components {
+            [] => {}
+            [file] => {
+                self.files.insert(file.to_string(), id);
+            }
+            [dir, rest @ ..] => self
+                .dirs
+                .entry(dir.to_string())
+                .or_default()
+                .insert(rest, id),
+        }
+    }
+
+    fn render(&self, html: &mut String) {
+        html.push_str("<ul>\n");
+        for (name, node) in &self.dirs {
+            let _ = writeln!(
+                html,
+                "<li><details open><summary>{}/</summary>",
+                escape_html(name)
+            );
+            node.render(html);
+            html.push_str("</details></li>\n");
+        }
+        for (name, id) in &self.files {
+            let _ = writeln!(
+                html,
+                "<li><a href=\"#f-{id}\">{}</a></li>",
+                escape_html(name)
+            );
+        }
+        html.push_str("</ul>\n");
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_section_escapes_content() {
+        let html = file_section(3, "src/a.rs", Some("compressed"), "if a < b && c {}");
+        assert!(html.contains("id=\"f-3\""));
+        assert!(html.contains("class=\"language-rs\""));
+        assert!(html.contains("if a &lt; b &amp;&amp; c {}"));
+    }
+
+    #[test]
+    fn test_summary_renders_tree() {
+        let paths = vec!["src/main.rs".to_string(), "src/util/io.rs".to_string()];
+        let html = summary(&Statistics::new(), &paths);
+        assert!(html.contains("<summary>src/</summary>"));
+        assert!(html.contains("<summary>util/</summary>"));
+        assert!(html.contains("<a href=\"#f-0\">main.rs</a>"));
+        assert!(html.contains("<a href=\"#f-1\">io.rs</a>"));
+    }
+}
@@ -1,11 +1,46 @@
+pub mod bundle;
+pub mod cache;
+pub mod calibrate;
 pub mod compress;
+mod condense;
 pub mod config;
+pub mod config_file;
+pub mod doc_links;
+pub mod doctor;
+pub mod encoding;
+pub mod estimate;
+pub mod export;
 pub mod filters;
+pub mod fixture;
+pub mod format;
+pub mod git;
+mod html;
+pub mod lock;
+pub mod manifest;
+mod markdown;
+mod messages;
+pub mod minified;
 pub mod output;
+pub mod overrides;
 pub mod parse;
 pub mod priority;
+pub mod profiles;
+pub mod progress;
+pub mod project;
+pub mod redact;
+pub mod related;
+pub mod report;
+pub mod schema;
+pub mod since;
+mod source;
+pub mod template;
 pub mod tokens;
+mod tree;
 pub mod walker;
+pub mod warnings;
+pub mod watch;
 
 pub use config::Config;
-pub use walker::walk_and_flatten;
+pub use progress::FlattenHandle;
+pub use report::{Decision, FileReport};
+pub use walker::{analyze, flatten_entries, walk_and_flatten};
@@ -0,0 +1,156 @@
+//! Cross-process locking and atomic output, for when several flat instances
+//! (watch mode, editor integrations) share an output file or cache.
+
+use anyhow::{bail, Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How long to wait for another flat process to release a lock by default
+pub const DEFAULT_LOCK_TIMEOUT: Duration = Duration::from_secs(10);
+
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Whether other holders may share the lock
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LockMode {
+    Shared,
+    Exclusive,
+}
+
+/// Lock `path` (created if missing), waiting up to `timeout` for other processes.
+/// The lock is held until the returned file is dropped.
+pub fn acquire(path: &Path, mode: LockMode, timeout: Duration) -> Result<File> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .with_context(|| format!("Failed to open lock file: {}", path.display()))?;
+
+    let started = Instant::now();
+    loop {
+        let attempt = match mode {
+            LockMode::Shared => file.try_lock_shared(),
+            LockMode::Exclusive => file.try_lock(),
+        };
+        match attempt {
+            Ok(()) => return Ok(file),
+            Err(TryLockError::WouldBlock) if started.elapsed() < timeout => {
+                thread::sleep(POLL_INTERVAL)
+            }
+            Err(TryLockError::WouldBlock) => bail!(
+                "Timed out after {:.1}s waiting for {} (another flat process is using it; see --lock-timeout)",
+                timeout.as_secs_f64(),
+                path.display()
+            ),
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to lock {}", path.display()))
+            }
+        }
+    }
+}
+
+/// An output file that appears all at once: content goes to a hidden temp file
+/// beside the destination and is renamed over it by [`AtomicFile::commit`].
+/// A lock file in the system temp directory, named after the destination,
+/// serializes processes writing the same destination without cluttering it.
+pub struct AtomicFile {
+    tmp: PathBuf,
+    dest: PathBuf,
+    _lock: File,
+    committed: bool,
+}
+
+impl AtomicFile {
+    /// Lock `dest` and create its temp file, returning the writer for it
+    pub fn create(dest: &Path, timeout: Duration) -> Result<(Self, File)> {
+        let lock = acquire(&lock_path(dest), LockMode::Exclusive, timeout)?;
+        let tmp = temp_path(dest);
+        let file = File::create(&tmp)
+            .with_context(|| format!("Failed to create output file: {}", dest.display()))?;
+        let atomic = Self {
+            tmp,
+            dest: dest.to_path_buf(),
+            _lock: lock,
+            committed: false,
+        };
+        Ok((atomic, file))
+    }
+
+    /// Move the finished output into place
+    pub fn commit(mut self) -> Result<()> {
+        fs::rename(&self.tmp, &self.dest)
+            .with_context(|| format!("Failed to write output file: {}", self.dest.display()))?;
+        self.committed = true;
+        Ok(())
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        // A run that failed part-way leaves the previous output untouched
+        if !self.committed {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+/// `dir/.name.tmp<pid>` for `dir/name`; hidden, so a rescan of `dir` skips it
+fn temp_path(dest: &Path) -> PathBuf {
+    let name = dest
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    dest.with_file_name(format!(".{}.tmp{}", name, std::process::id()))
+}
+
+/// Shared lock file for a destination, keyed by its absolute path
+fn lock_path(dest: &Path) -> PathBuf {
+    let absolute = std::path::absolute(dest).unwrap_or_else(|_| dest.to_path_buf());
+    let mut hasher = DefaultHasher::new();
+    absolute.hash(&mut hasher);
+    std::env::temp_dir().join(format!("flat-{:016x}.lock", hasher.finish()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_exclusive_lock_times_out_while_held() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("lock");
+        let _held = acquire(&path, LockMode::Exclusive, Duration::ZERO).unwrap();
+
+        let err = acquire(&path, LockMode::Shared, Duration::from_millis(100)).unwrap_err();
+        assert!(err.to_string().contains("Timed out"));
+    }
+
+    #[test]
+    fn test_atomic_file_replaces_only_on_commit() {
+        let dir = TempDir::new().unwrap();
+        let dest = dir.path().join("out.xml");
+        fs::write(&dest, "old").unwrap();
+
+        let (atomic, mut file) = AtomicFile::create(&dest, DEFAULT_LOCK_TIMEOUT).unwrap();
+        std::io::Write::write_all(&mut file, b"new").unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "old");
+        drop(file);
+        atomic.commit().unwrap();
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+
+        let (abandoned, _file) = AtomicFile::create(&dest, DEFAULT_LOCK_TIMEOUT).unwrap();
+        let err = AtomicFile::create(&dest, Duration::from_millis(100))
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("Timed out"));
+        drop(abandoned);
+        assert_eq!(fs::read_to_string(&dest).unwrap(), "new");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+}
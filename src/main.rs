@@ -1,9 +1,34 @@
-use anyhow::{bail, Result};
-use clap::Parser;
-use flat::parse::{parse_binary_number, parse_decimal_number};
-use flat::{walk_and_flatten, Config};
-use globset::Glob;
-use std::path::PathBuf;
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use flat::cache;
+use flat::calibrate::{self, MIN_SAMPLE_BYTES};
+use flat::compress::{is_node_kind, CompressLanguage, CompressLevel, NodeRules, ParseLimits};
+use flat::config::{parse_file_list, resolve_roots, BudgetShare, NoExtension};
+use flat::config_file;
+use flat::doctor;
+use flat::estimate::estimate_compress;
+use flat::fixture::{self, FixtureSpec};
+use flat::format::DEFAULT_LINE_NUMBER_SEPARATOR;
+use flat::lock::{LockMode, DEFAULT_LOCK_TIMEOUT};
+use flat::output::{Layout, Order, OutputFormat, Statistics, SummaryPlacement};
+use flat::overrides;
+use flat::parse::{
+    parse_attachment, parse_binary_number, parse_budget_share, parse_decimal_number,
+    parse_directory_quota, parse_model, parse_seconds, split_line_range,
+};
+use flat::priority::parse_priority_rule;
+use flat::project;
+use flat::schema;
+use flat::since::Since;
+use flat::template::{expand_path, has_path_placeholders, path_glob, TemplateVars};
+use flat::tokens::{Model, Tokenizer, DEFAULT_HEADROOM};
+use flat::{walk_and_flatten, Config, Decision};
+use globset::{Glob, GlobMatcher};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{ErrorKind, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Parser, Debug)]
 #[command(name = "flat")]
@@ -21,7 +46,8 @@ Examples:
   flat --compress --tokens 8k            Fit into a token budget (8k = 8,000 tokens)
   flat --compress --full-match 'main.rs'  Keep main.rs full, compress the rest
   flat --stats                          Preview file count and size
-  flat --dry-run                        List files without content")]
+  flat --dry-run                        List files without content
+  flat cache stats                      Show compression cache usage (see --cache)")]
 #[command(after_help = "\
 Compression (--compress) extracts signatures and strips function/method bodies, \
 reducing token usage by 30-60%. Supported languages: Rust, TypeScript, JavaScript, \
@@ -31,12 +57,18 @@ Combine --compress with --tokens to fit a codebase into a context window. \
 High-priority files (README, entry points, configs) are included first; \
 low-priority files (tests, fixtures) are excluded first.
 
-Exit codes: 0 = success, 3 = no files matched")]
+Exit codes: 0 = success (including a reader that stops early, like `| head`), \
+3 = no files matched")]
 struct Cli {
     /// Directory to process
     #[arg(default_value = ".", value_name = "DIR")]
     path: PathBuf,
 
+    /// More directories or files to flatten with DIR, in one output whose paths
+    /// start from their common directory
+    #[arg(value_name = "DIR")]
+    more_paths: Vec<PathBuf>,
+
     /// Include only these extensions [e.g. --include rs,toml,md]
     #[arg(long, value_delimiter = ',', value_name = "EXT")]
     include: Option<Vec<String>>,
@@ -45,14 +77,30 @@ struct Cli {
     #[arg(long, value_delimiter = ',', value_name = "EXT")]
     exclude: Option<Vec<String>>,
 
-    /// Only files matching a glob pattern [e.g. --match '*_test.go']
+    /// Include or exclude files without an extension (also: `_noext` in --include/--exclude)
+    #[arg(long, value_enum, value_name = "MODE")]
+    no_extension: Option<NoExtension>,
+
+    /// Only files matching a glob pattern; prefix with '!' to negate [e.g. --match '*_test.go']
     #[arg(long, alias = "regex", value_name = "GLOB")]
     r#match: Option<Vec<String>>,
 
-    /// Write output to a file instead of stdout
+    /// Skip files matching a glob pattern [e.g. --not-match '**/generated/**']
+    #[arg(long, value_name = "GLOB")]
+    not_match: Option<Vec<String>>,
+
+    /// Skip paths matching these globs, even ones --match selects [e.g. --exclude-match 'migrations/**,**/*.generated.ts']
+    #[arg(long, value_delimiter = ',', value_name = "GLOB")]
+    exclude_match: Option<Vec<String>>,
+
+    /// Write output to a file instead of stdout ({date}, {time}, {timestamp}, {git_sha} expand)
     #[arg(short, long, value_name = "FILE")]
     output: Option<PathBuf>,
 
+    /// Keep running and rewrite the output file whenever the sources change
+    #[arg(long, requires = "output")]
+    watch: bool,
+
     /// List files that would be included, without content
     #[arg(long)]
     dry_run: bool,
@@ -61,78 +109,632 @@ struct Cli {
     #[arg(long)]
     stats: bool,
 
+    /// List each skipped file on stderr with its reason code (e.g. binary-ext, too-large)
+    #[arg(long)]
+    show_skipped: bool,
+
+    /// Write only the directory tree, with estimated tokens per file, and the summary
+    #[arg(long, conflicts_with_all = ["dry_run", "stats"])]
+    outline_only: bool,
+
     /// Path to a custom .gitignore file
     #[arg(long, value_name = "FILE")]
     gitignore: Option<PathBuf>,
 
-    /// Maximum file size in bytes (supports k/M/G suffixes, e.g., 10M)
-    #[arg(long, default_value = "1048576", value_parser = parse_binary_number, value_name = "BYTES")]
-    max_size: u64,
+    /// Don't skip files matched by .gitignore, .git/info/exclude, or global git excludes (.flatignore still applies)
+    #[arg(long)]
+    no_gitignore: bool,
+
+    /// Include hidden files and directories (dotfiles); .git is still skipped
+    #[arg(long)]
+    hidden: bool,
+
+    /// Include node_modules/, target/, dist/, .venv/, ... even when there is no .gitignore
+    #[arg(long)]
+    no_default_excludes: bool,
+
+    /// Flatten what symlinks point to instead of writing stubs; loops and files already reached are skipped
+    #[arg(long)]
+    follow_symlinks: bool,
+
+    /// Descend at most N directory levels (1 = only the files at the root)
+    #[arg(long, value_name = "N")]
+    max_depth: Option<usize>,
+
+    /// Never descend into these directories, ignored or not: names (`node_modules`) or root-relative paths (`web/dist`)
+    #[arg(long, value_delimiter = ',', value_name = "DIRS")]
+    prune: Vec<String>,
+
+    /// Don't apply the detected project type's curated exclusions (lockfiles, bundles, ...)
+    #[arg(long)]
+    no_project_defaults: bool,
+
+    /// Include secret files (.env, credentials.json, keys) with their values masked instead of skipping them
+    #[arg(long)]
+    redact: bool,
+
+    /// Write minified files (one-line JSON, bundles) whole instead of outlining or truncating them
+    #[arg(long)]
+    keep_minified: bool,
+
+    /// Prefix each line of file content with its line number, counted after compression
+    #[arg(long)]
+    line_numbers: bool,
+
+    /// What follows each line number under --line-numbers [default: ": "]
+    #[arg(long, requires = "line_numbers", value_name = "SEP")]
+    line_number_separator: Option<String>,
+
+    /// Write absolute file paths instead of paths relative to the scanned directory
+    #[arg(long)]
+    absolute_paths: bool,
+
+    /// Convert CRLF line endings to LF before counting tokens and writing
+    #[arg(long)]
+    normalize_newlines: bool,
+
+    /// Maximum file size in bytes, after compression for files --compress compresses (supports k/M/G suffixes, e.g., 10M) [default: 1048576]
+    #[arg(long, value_parser = parse_binary_number, value_name = "BYTES")]
+    max_size: Option<u64>,
 
     /// Extract signatures and strip function bodies (Rust, TS, JS, Python, Go)
     #[arg(long)]
     compress: bool,
 
+    /// Don't compress, even when flat.toml sets `compress = true` under [defaults]
+    #[arg(long, conflicts_with = "compress")]
+    no_compress: bool,
+
+    /// How much --compress keeps: signatures (bodies elided), minimal (public
+    /// signatures only, no comments or imports), docs (signatures with doc comments),
+    /// or public (public signatures with their comments and imports)
+    #[arg(long, value_enum, default_value_t = CompressLevel::Signatures, value_name = "LEVEL")]
+    compress_level: CompressLevel,
+
+    /// Compress to public items only, dropping private ones whole (same as
+    /// --compress --compress-level public)
+    #[arg(long, conflicts_with_all = ["compress_level", "no_compress"])]
+    public_only: bool,
+
+    /// Also compress Markdown to its outline: headings, the first paragraph under each, and code fences
+    #[arg(long)]
+    compress_markdown: bool,
+
     /// Keep full content for files matching these globs (use with --compress)
     #[arg(long, value_delimiter = ',', value_name = "GLOB")]
     full_match: Option<Vec<String>>,
 
+    /// Never compress files with these extensions (comma-separated, use with --compress)
+    #[arg(long, value_delimiter = ',', value_name = "EXTS")]
+    no_compress_ext: Option<Vec<String>>,
+
+    /// Give up parsing a file for compression after this many seconds and keep it in full (0 = no limit)
+    #[arg(long, default_value = "5", value_parser = parse_seconds, value_name = "SECS")]
+    parse_timeout: Duration,
+
+    /// Keep files larger than this in full instead of parsing them for compression (k/M/G suffixes)
+    #[arg(long, default_value = "512k", value_parser = parse_binary_number, value_name = "BYTES")]
+    max_compress_size: u64,
+
     /// Cap output to an estimated token budget (supports k/M/G suffixes, e.g., 10k)
     #[arg(long, value_parser = parse_decimal_number, value_name = "N")]
     tokens: Option<usize>,
+
+    /// Hold back part of --tokens for the prompt and the answer (percentage like 10% or a token count)
+    #[arg(long, value_parser = parse_budget_share, value_name = "SHARE")]
+    reserve_tokens: Option<BudgetShare>,
+
+    /// Set --tokens to MODEL's context window less --headroom, and count with its tokenizer (e.g. claude-sonnet, gpt-4o)
+    #[arg(long, value_parser = parse_model, value_name = "MODEL", conflicts_with = "tokens")]
+    model: Option<Model>,
+
+    /// Share of the --model context left for the prompt and the answer (percentage like 20% or a token count)
+    #[arg(long, value_parser = parse_budget_share, value_name = "SHARE", requires = "model")]
+    headroom: Option<BudgetShare>,
+
+    /// How to count tokens: a byte-length estimate, or an exact BPE encoding [default: heuristic, or --model's]
+    #[arg(long, value_enum, value_name = "TOKENIZER")]
+    tokenizer: Option<Tokenizer>,
+
+    /// Cap tests and fixtures within --tokens (percentage like 10% or a token count)
+    #[arg(long, value_parser = parse_budget_share, value_name = "SHARE")]
+    tests_budget: Option<BudgetShare>,
+
+    /// Cap documentation within --tokens (percentage like 15% or a token count)
+    #[arg(long, value_parser = parse_budget_share, value_name = "SHARE")]
+    docs_budget: Option<BudgetShare>,
+
+    /// Cap the files under DIR within --tokens, e.g. src/=60%,tests/=10% (percentage or token count)
+    #[arg(long, value_delimiter = ',', value_parser = parse_directory_quota, value_name = "DIR=SHARE")]
+    quota: Vec<(PathBuf, BudgetShare)>,
+
+    /// Under --tokens, prioritize files that the README and docs link to
+    #[arg(long)]
+    follow_doc_links: bool,
+
+    /// Under --tokens, rank docs and design docs below tests (for pure coding tasks)
+    #[arg(long)]
+    demote_docs: bool,
+
+    /// Under --tokens, write the start of a file that doesn't fit, marked mode="truncated", instead of leaving it out
+    #[arg(long)]
+    truncate: bool,
+
+    /// Write the file at PATH as NAME alongside the scanned files, e.g. a test log or
+    /// stack trace, scored 97 under --tokens (repeatable)
+    #[arg(long, value_parser = parse_attachment, value_name = "NAME=PATH")]
+    attach: Vec<(String, PathBuf)>,
+
+    /// Raise files matching GLOB to a priority score of at least SCORE (0-100), e.g. 'src/core/**=95' (repeatable)
+    #[arg(long, value_name = "GLOB=SCORE")]
+    priority_boost: Vec<String>,
+
+    /// Lower files matching GLOB to a priority score of at most SCORE (0-100), e.g. 'examples/**=10' (repeatable)
+    #[arg(long, value_name = "GLOB=SCORE")]
+    priority_drop: Vec<String>,
+
+    /// Under --tokens, prioritize FILE and the files named on its import lines
+    #[arg(long, value_name = "FILE")]
+    related: Option<PathBuf>,
+
+    /// Under --tokens, prioritize the files FILE reaches through its imports, nearest first
+    #[arg(long, value_name = "FILE")]
+    entry: Option<PathBuf>,
+
+    /// Flatten only the paths listed in FILE, one per line, instead of walking (`-` reads stdin)
+    #[arg(long, value_name = "FILE")]
+    files_from: Option<PathBuf>,
+
+    /// Flatten only the files changed relative to a git ref (e.g. `main`), plus untracked files
+    #[arg(long, value_name = "REF", conflicts_with = "files_from")]
+    diff: Option<String>,
+
+    /// Flatten only the files staged for commit, with their contents from the index
+    #[arg(long, conflicts_with_all = ["files_from", "diff"])]
+    staged: bool,
+
+    /// Flatten only files modified within an age (`7d`, `12h`) or changed since a git revision (`HEAD~5`)
+    #[arg(long, value_name = "AGE|REV", value_parser = Since::parse)]
+    since: Option<Since>,
+
+    /// JSON object mapping paths to contents that override files on disk (e.g. unsaved buffers)
+    #[arg(long, value_name = "FILE")]
+    overlay: Option<PathBuf>,
+
+    /// Also archive the selected files, unmodified, to a .zip or .tar (paths preserved)
+    #[arg(long, value_name = "ARCHIVE")]
+    export_selection: Option<PathBuf>,
+
+    /// Also write a zip bundle of the output, a JSON manifest, the file tree, and the config used
+    #[arg(long, value_name = "FILE")]
+    bundle: Option<PathBuf>,
+
+    /// Also write a JSON manifest of every file's decision, score, and tokens, plus the summary
+    #[arg(long, value_name = "FILE")]
+    manifest: Option<PathBuf>,
+
+    /// Seconds to wait for other flat processes writing the same output or cache
+    #[arg(long, value_parser = parse_seconds, value_name = "SECS")]
+    lock_timeout: Option<Duration>,
+
+    /// Reuse compressed output across runs from the cache (manage with `flat cache clear|stats`)
+    #[arg(long)]
+    cache: bool,
+
+    /// Print every warning instead of summarizing repeats
+    #[arg(short, long)]
+    verbose: bool,
+
+    /// Output format [default: xml]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    format: Option<OutputFormat>,
+
+    /// Group output into labeled sections (project metadata, configuration, source, tests, docs) [default: flat]
+    #[arg(long, value_enum, value_name = "LAYOUT")]
+    layout: Option<Layout>,
+
+    /// Put the summary before the files, after them, or leave it out [default: last]
+    #[arg(long, value_enum, value_name = "WHERE")]
+    summary: Option<SummaryPlacement>,
+
+    /// Cut the output into parts of at most N tokens, never splitting a file (-o out-{n}.xml writes one file per part)
+    #[arg(long, value_parser = parse_decimal_number, value_name = "N")]
+    split_tokens: Option<usize>,
+
+    /// Write files by path, highest priority first (README, entry points, configs), smallest first (size, tokens), or newest first (mtime) [default: path, or priority under --tokens]
+    #[arg(long, visible_alias = "sort", value_enum, value_name = "ORDER")]
+    order: Option<Order>,
+
+    /// Add each file's role (e.g. test, config) and priority score to its output attributes
+    #[arg(long)]
+    annotate: bool,
+
+    /// Add each file's top 1-2 committers from git history to its output attributes
+    #[arg(long)]
+    owners: bool,
+
+    /// Add the tokens, bytes, and SHA-256 of each file's written content to its attributes
+    #[arg(long)]
+    file_meta: bool,
+
+    /// Print the JSON Schema of flat's machine-readable output and exit
+    #[arg(long)]
+    schema: bool,
+
+    /// Compare files included and tokens used with and without --compress, and exit
+    #[arg(long, conflicts_with_all = ["output", "dry_run", "stats", "outline_only", "bundle", "manifest"])]
+    estimate_compress: bool,
+}
+
+/// `flat cache <command>`: manage the `--cache` compression cache
+#[derive(Parser, Debug)]
+#[command(name = "flat cache", bin_name = "flat cache")]
+struct CacheCli {
+    #[command(subcommand)]
+    command: CacheCommand,
+
+    /// Seconds to wait for flat runs that are using the cache
+    #[arg(long, global = true, value_parser = parse_seconds, value_name = "SECS")]
+    lock_timeout: Option<Duration>,
+}
+
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Remove all cached entries, including those from other flat versions
+    Clear,
+    /// Show cached entries, counting those from other flat versions as stale
+    Stats,
+}
+
+/// `flat doctor`: check grammars, integrations, and a sample run
+#[derive(Parser, Debug)]
+#[command(name = "flat doctor", bin_name = "flat doctor")]
+struct DoctorCli {}
+
+/// `flat calibrate`: measure bytes per token by language against a BPE tokenizer
+#[derive(Parser, Debug)]
+#[command(name = "flat calibrate", bin_name = "flat calibrate")]
+struct CalibrateCli {
+    /// Sample project to measure
+    #[arg(default_value = ".")]
+    dir: PathBuf,
+
+    /// Reference tokenizer to measure against
+    #[arg(long, value_enum, default_value = "o200k_base")]
+    tokenizer: Tokenizer,
+
+    /// Save the ratios to the [token-ratios] table of DIR/flat.toml, where the
+    /// heuristic tokenizer then uses them
+    #[arg(long)]
+    write: bool,
+}
+
+/// `flat gen-fixture`: write a synthetic polyglot project for benchmarks and stress tests
+#[derive(Parser, Debug)]
+#[command(name = "flat gen-fixture", bin_name = "flat gen-fixture")]
+struct GenFixtureCli {
+    /// Directory to write into, which must be empty [default: a new temp directory]
+    dir: Option<PathBuf>,
+
+    /// Source files per language
+    #[arg(long, default_value_t = FixtureSpec::default().files_per_language, value_name = "N")]
+    files: usize,
+
+    /// Directory levels below each language's directory
+    #[arg(long, default_value_t = FixtureSpec::default().depth, value_name = "N")]
+    depth: usize,
+
+    /// Approximate lines per source file
+    #[arg(long, default_value_t = FixtureSpec::default().lines_per_file, value_name = "N")]
+    lines: usize,
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
-
-    let match_patterns = match cli.r#match {
-        Some(patterns) => {
-            let mut compiled = Vec::new();
-            for pattern in &patterns {
-                match Glob::new(pattern) {
-                    Ok(glob) => compiled.push(glob.compile_matcher()),
-                    Err(e) => bail!("Invalid match pattern '{}': {}", pattern, e),
-                }
+    match run() {
+        // The reader (`head`, a pager) quit early; there's no one left to write for
+        Err(err) if is_broken_pipe(&err) => Ok(()),
+        result => result,
+    }
+}
+
+/// Whether `err` comes from writing to a pipe whose reading end was closed
+fn is_broken_pipe(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<std::io::Error>()
+            .is_some_and(|e| e.kind() == ErrorKind::BrokenPipe)
+    })
+}
+
+fn run() -> Result<()> {
+    // `flat cache ...`, `flat doctor`, `flat gen-fixture`, and `flat calibrate` are
+    // the subcommands; a directory named `cache` or `doctor` is `./cache` or `./doctor`
+    let subcommand = std::env::args_os().nth(1);
+    let subcommand_args = || std::env::args_os().skip(1);
+    match subcommand.as_ref().and_then(|arg| arg.to_str()) {
+        Some("cache") => return run_cache_command(CacheCli::parse_from(subcommand_args())),
+        Some("doctor") => {
+            DoctorCli::parse_from(subcommand_args());
+            return run_doctor();
+        }
+        Some("gen-fixture") => {
+            return run_gen_fixture(GenFixtureCli::parse_from(subcommand_args()))
+        }
+        Some("calibrate") => return run_calibrate(CalibrateCli::parse_from(subcommand_args())),
+        _ => {}
+    }
+
+    let mut cli = Cli::parse();
+    if cli.schema {
+        let schema = serde_json::to_string_pretty(&schema::document())?;
+        writeln!(std::io::stdout(), "{}", schema)?;
+        return Ok(());
+    }
+
+    // `flat src/big.rs:100-400` flattens just those lines of one file
+    let mut line_ranges = Vec::new();
+    if !cli.path.exists() {
+        if let Some((file, range)) = cli.path.to_str().and_then(split_line_range) {
+            let file = PathBuf::from(file);
+            if file.is_file() {
+                let name = file.file_name().unwrap_or_default().to_string_lossy();
+                line_ranges.push((range_matcher(&globset::escape(&name))?, range));
+                cli.path = file;
             }
-            Some(compiled)
         }
-        None => None,
+    }
+
+    // `flat backend/src frontend/src` scans their common directory, walking only those
+    let mut roots = Vec::new();
+    if !cli.more_paths.is_empty() {
+        let paths: Vec<PathBuf> = std::iter::once(cli.path.clone())
+            .chain(cli.more_paths.iter().cloned())
+            .collect();
+        (cli.path, roots) = resolve_roots(&paths)?;
+    }
+
+    let file_config = match config_file::discover(&cli.path) {
+        Some(path) => config_file::load(&path)?,
+        None => Default::default(),
     };
+    // Flags given on the command line win over `[defaults]` in flat.toml
+    let defaults = file_config.defaults;
+    let (match_list, not_match_list) = if cli.r#match.is_none() && cli.not_match.is_none() {
+        (defaults.match_patterns, None)
+    } else {
+        (cli.r#match, cli.not_match)
+    };
+    let format = cli.format.or(defaults.format).unwrap_or_default();
 
-    let full_match_patterns = match cli.full_match {
-        Some(patterns) => {
-            if !cli.compress {
-                eprintln!("Warning: --full-match has no effect without --compress");
-            }
-            let mut compiled = Vec::new();
-            for pattern in &patterns {
-                match Glob::new(pattern) {
-                    Ok(glob) => compiled.push(glob.compile_matcher()),
-                    Err(e) => bail!("Invalid full-match pattern '{}': {}", pattern, e),
+    // `--match '!glob'` is shorthand for `--not-match 'glob'`
+    let mut allow = Vec::new();
+    let mut deny = not_match_list.unwrap_or_default();
+    for pattern in match_list.unwrap_or_default() {
+        match pattern.strip_prefix('!') {
+            Some(negated) => deny.push(negated.to_string()),
+            None => match split_line_range(&pattern) {
+                // `--match 'src/big.rs:100-400'` selects the file and its lines
+                Some((glob, range)) => {
+                    line_ranges.push((range_matcher(glob)?, range));
+                    allow.push(glob.to_string());
                 }
-            }
-            Some(compiled)
+                None => allow.push(pattern),
+            },
         }
+    }
+
+    let match_patterns = compile_globs(&allow, "match")?;
+    let not_match_patterns = compile_globs(&deny, "not-match")?;
+
+    let exclude_match_patterns = match cli.exclude_match.or(defaults.exclude_match) {
+        Some(patterns) => compile_globs(&patterns, "exclude-match")?,
+        None => None,
+    };
+    let full_match_patterns = match cli.full_match.or(defaults.full_match) {
+        Some(patterns) => compile_globs(&patterns, "full-match")?,
         None => None,
     };
 
-    let config = Config {
+    let priority_boosts = priority_rules(cli.priority_boost, defaults.priority_boost, "boost")?;
+    let priority_drops = priority_rules(cli.priority_drop, defaults.priority_drop, "drop")?;
+
+    // Curated per-ecosystem exclusions; compile_globs only fails on our own patterns
+    let project_type = project::detect(&cli.path);
+    let project_exclusions = match project_type {
+        Some(project) if !cli.no_project_defaults => {
+            let globs: Vec<String> = project
+                .default_exclusions()
+                .into_iter()
+                .map(String::from)
+                .collect();
+            compile_globs(&globs, "project default")?
+        }
+        _ => None,
+    };
+
+    let overrides = match overrides::discover(&cli.path) {
+        Some(path) => Some(overrides::load(&path)?),
+        None => None,
+    };
+
+    let compress_rules = resolve_compress_rules(file_config.compress)?;
+
+    let files_from = match &cli.files_from {
+        Some(list) => Some(parse_file_list(&read_file_list(list)?, &cli.path)),
+        None => None,
+    };
+
+    let (output_file, output_names) = match cli.output {
+        Some(path) => {
+            let (file, names) = resolve_output_path(path, format, &cli.path)?;
+            (Some(file), names)
+        }
+        None => (None, None),
+    };
+
+    // --model stands in for --tokens, which conflicts with it
+    let model_budget = cli
+        .model
+        .map(|model| model.budget(cli.headroom.unwrap_or(DEFAULT_HEADROOM)));
+
+    let mut config = Config {
         path: cli.path,
-        include_extensions: cli.include,
-        exclude_extensions: cli.exclude,
+        roots,
+        include_extensions: cli.include.or(defaults.include),
+        exclude_extensions: cli.exclude.or(defaults.exclude),
         match_patterns,
-        output_file: cli.output,
+        not_match_patterns,
+        exclude_match_patterns,
+        line_ranges,
+        project_type,
+        project_exclusions,
+        output_file,
+        output_names,
         dry_run: cli.dry_run,
+        outline_only: cli.outline_only,
         stats_only: cli.stats,
         gitignore_path: cli.gitignore,
-        max_file_size: cli.max_size,
-        compress: cli.compress,
+        use_gitignore: !cli.no_gitignore,
+        include_hidden: cli.hidden,
+        default_excludes: !cli.no_default_excludes,
+        follow_symlinks: cli.follow_symlinks,
+        max_depth: cli.max_depth,
+        prune: cli.prune,
+        max_file_size: cli
+            .max_size
+            .or(defaults.max_size)
+            .unwrap_or(Config::default().max_file_size),
+        compress: !cli.no_compress
+            && (cli.compress || cli.public_only || defaults.compress == Some(true)),
         full_match_patterns,
-        token_budget: cli.tokens,
+        no_compress_extensions: cli.no_compress_ext,
+        parse_limits: ParseLimits {
+            timeout: cli.parse_timeout,
+            max_size: cli.max_compress_size,
+        },
+        token_budget: cli.tokens.or(model_budget).or(defaults.tokens),
+        reserve_tokens: cli.reserve_tokens,
+        compress_level: if cli.public_only {
+            CompressLevel::Public
+        } else {
+            cli.compress_level
+        },
+        compress_markdown: cli.compress_markdown,
+        // A model's own BPE, when this build has it
+        tokenizer: cli
+            .tokenizer
+            .or(cli
+                .model
+                .map(|model| model.tokenizer)
+                .filter(|t| t.is_available()))
+            .unwrap_or_default(),
+        tests_budget: cli.tests_budget,
+        docs_budget: cli.docs_budget,
+        directory_quotas: cli.quota,
+        follow_doc_links: cli.follow_doc_links,
+        demote_docs: cli.demote_docs,
+        truncate: cli.truncate,
+        priority_boosts,
+        priority_drops,
+        related: cli.related,
+        entry: cli.entry,
+        format,
+        layout: cli.layout.or(defaults.layout).unwrap_or_default(),
+        summary: cli.summary.unwrap_or_default(),
+        order: cli.order.or(defaults.order),
+        split_tokens: cli.split_tokens,
+        annotate: cli.annotate,
+        redact: cli.redact,
+        keep_minified: cli.keep_minified,
+        line_numbers: cli.line_numbers.then(|| {
+            cli.line_number_separator
+                .unwrap_or_else(|| DEFAULT_LINE_NUMBER_SEPARATOR.to_string())
+        }),
+        absolute_paths: cli.absolute_paths,
+        normalize_newlines: cli.normalize_newlines,
+        compress_rules,
+        token_ratios: file_config.token_ratios,
+        header: file_config.header,
+        footer: file_config.footer,
+        files_from,
+        files_from_path: cli.files_from,
+        diff_base: cli.diff,
+        staged: cli.staged,
+        diff: None,
+        since: cli.since,
+        recent: None,
+        owners: cli.owners,
+        file_owners: None,
+        file_meta: cli.file_meta,
+        attachments: cli.attach,
+        overlay_file: cli.overlay,
+        overrides,
+        pragmas: Default::default(),
+        profiles: Default::default(),
+        lock_timeout: cli.lock_timeout.unwrap_or(DEFAULT_LOCK_TIMEOUT),
+        cache_dir: if cli.cache {
+            cache::default_dir()
+        } else {
+            None
+        },
+        export_selection: cli.export_selection,
+        bundle: cli.bundle,
+        manifest: cli.manifest,
+        verbose: cli.verbose,
     };
+    if let Some(mode) = cli.no_extension {
+        config.set_no_extension(mode);
+    }
+
+    // The estimate runs with compression too, so compression flags aren't idle
+    if cli.estimate_compress {
+        config.compress = true;
+    }
+    for warning in config.validate()? {
+        eprintln!("Warning: {}", warning);
+    }
+
+    if cli.estimate_compress {
+        let estimate = estimate_compress(&config)?;
+        write!(std::io::stdout(), "{}", estimate)?;
+        return Ok(());
+    }
+
+    if cli.watch {
+        let output = config.output_file.clone().unwrap_or_default();
+        return flat::watch::watch(&config, |run| match run {
+            Ok(stats) => eprintln!(
+                "Wrote {} ({} files)",
+                output.display(),
+                stats.included_files
+            ),
+            Err(e) => eprintln!("Error: {:#}", e),
+        });
+    }
 
     let stats = walk_and_flatten(&config)?;
 
+    if cli.show_skipped {
+        let skipped = stats
+            .file_reports
+            .iter()
+            .filter(|report| report.decision == Decision::Skipped);
+        for report in skipped {
+            let reason = report.reason.as_deref().unwrap_or_default();
+            eprintln!("{}\t{}", reason, report.path.display());
+        }
+    }
+
+    // A ready-made second run for what didn't fit, unless it would be a wall of paths
+    let excluded = &stats.excluded_by_budget;
+    if !excluded.is_empty() && excluded.len() <= MAX_FOLLOW_UP_FILES {
+        eprintln!(
+            "To flatten the {} files left out by the budget:\n  {}",
+            excluded.len(),
+            config.follow_up_command(excluded)
+        );
+    }
+
     // Exit with error if no files appear in the output
     let output_files = if stats.token_budget.is_some() {
         stats
@@ -148,3 +750,261 @@ fn main() -> Result<()> {
 
     Ok(())
 }
+
+/// Most budget exclusions a follow-up command is suggested for
+const MAX_FOLLOW_UP_FILES: usize = 50;
+
+/// Compile a list of glob patterns, returning None when the list is empty
+fn compile_globs(patterns: &[String], flag: &str) -> Result<Option<Vec<GlobMatcher>>> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut compiled = Vec::new();
+    for pattern in patterns {
+        match Glob::new(pattern) {
+            Ok(glob) => compiled.push(glob.compile_matcher()),
+            Err(e) => bail!("Invalid {} pattern '{}': {}", flag, pattern, e),
+        }
+    }
+    Ok(Some(compiled))
+}
+
+/// Parse `--priority-boost` or `--priority-drop` rules, the command line's
+/// replacing flat.toml's when given
+fn priority_rules(
+    cli: Vec<String>,
+    defaults: Option<Vec<String>>,
+    kind: &str,
+) -> Result<Vec<(GlobMatcher, u32)>> {
+    let rules = if cli.is_empty() {
+        defaults.unwrap_or_default()
+    } else {
+        cli
+    };
+    rules
+        .iter()
+        .map(|rule| {
+            parse_priority_rule(rule)
+                .with_context(|| format!("Invalid --priority-{} rule '{}'", kind, rule))
+        })
+        .collect()
+}
+
+/// Matcher for the file a line range applies to
+fn range_matcher(glob: &str) -> Result<GlobMatcher> {
+    Glob::new(glob)
+        .map(|g| g.compile_matcher())
+        .with_context(|| format!("Invalid match pattern '{}'", glob))
+}
+
+/// Read a `--files-from` list from a file, or stdin for `-`
+fn read_file_list(path: &Path) -> Result<String> {
+    if path == Path::new("-") {
+        let mut text = String::new();
+        std::io::stdin()
+            .read_to_string(&mut text)
+            .context("Failed to read file list from stdin")?;
+        return Ok(text);
+    }
+    fs::read_to_string(path)
+        .with_context(|| format!("Failed to read file list: {}", path.display()))
+}
+
+/// Key `[compress.<language>]` tables by language, warning about node kinds the
+/// grammar doesn't have (most likely typos)
+fn resolve_compress_rules(
+    tables: BTreeMap<String, NodeRules>,
+) -> Result<HashMap<CompressLanguage, NodeRules>> {
+    let mut rules = HashMap::new();
+    for (name, table) in tables {
+        let Some(lang) = CompressLanguage::from_name(&name) else {
+            bail!(
+                "Unknown language in [compress.{}] of flat.toml (expected one of: {})",
+                name,
+                CompressLanguage::NAMES.join(", ")
+            );
+        };
+        for kind in table.keep.iter().chain(&table.drop) {
+            if !is_node_kind(lang, kind) {
+                eprintln!(
+                    "Warning: [compress.{}] names '{}', which is not a {} node kind",
+                    name, kind, name
+                );
+            }
+        }
+        rules.insert(lang, table);
+    }
+    Ok(rules)
+}
+
+/// Default file name when `-o` points at a directory
+const DEFAULT_OUTPUT_NAME: &str = "flat-{git_sha}-{date}";
+
+/// Expand placeholders in `-o`, and pick a file name inside it when it names a
+/// directory (an existing one, or any path ending in a separator). Along with
+/// the path comes a matcher for every name its template gives, when the file
+/// name has placeholders, so earlier runs' outputs are recognized too.
+fn resolve_output_path(
+    path: PathBuf,
+    format: OutputFormat,
+    root: &Path,
+) -> Result<(PathBuf, Option<GlobMatcher>)> {
+    // `-o 'context-{git_sha}-{date}.txt'` keeps repeated exports apart
+    let template = path.to_string_lossy();
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    let path = if has_path_placeholders(&template) {
+        PathBuf::from(expand_path(&template, &TemplateVars::for_root(root)))
+    } else {
+        path
+    };
+
+    let names_directory = path.is_dir() || path.to_string_lossy().ends_with(['/', '\\']);
+    if !names_directory {
+        return Ok((path, name.and_then(|name| output_names(&name))));
+    }
+
+    fs::create_dir_all(&path)
+        .with_context(|| format!("Failed to create output directory: {}", path.display()))?;
+    let name = format!("{}.{}", DEFAULT_OUTPUT_NAME, format.file_extension());
+    let file = path.join(expand_path(&name, &TemplateVars::for_root(root)));
+    Ok((file, output_names(&name)))
+}
+
+/// Every file name `template` expands to, if it has placeholders at all
+fn output_names(template: &str) -> Option<GlobMatcher> {
+    if !has_path_placeholders(template) {
+        return None;
+    }
+    let glob = Glob::new(&path_glob(template)).ok()?;
+    Some(glob.compile_matcher())
+}
+
+fn run_doctor() -> Result<()> {
+    let checks = doctor::run();
+    let mut out = std::io::stdout().lock();
+    for check in &checks {
+        writeln!(out, "{}", check)?;
+    }
+    let failed = checks
+        .iter()
+        .filter(|c| c.status == doctor::Status::Fail)
+        .count();
+    if failed > 0 {
+        bail!("{} of {} checks failed", failed, checks.len());
+    }
+    Ok(())
+}
+
+fn run_gen_fixture(cli: GenFixtureCli) -> Result<()> {
+    let dir = cli.dir.unwrap_or_else(|| {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.subsec_nanos());
+        std::env::temp_dir().join(format!("flat-fixture-{}-{}", std::process::id(), nanos))
+    });
+    let spec = FixtureSpec {
+        files_per_language: cli.files,
+        depth: cli.depth,
+        lines_per_file: cli.lines,
+    };
+    let summary = fixture::generate(&dir, &spec)?;
+    writeln!(
+        std::io::stdout(),
+        "Wrote {} files ({}) to {}",
+        summary.files,
+        Statistics::format_bytes(summary.bytes as usize),
+        dir.display()
+    )?;
+    Ok(())
+}
+
+fn run_calibrate(cli: CalibrateCli) -> Result<()> {
+    if cli.tokenizer == Tokenizer::Heuristic {
+        bail!("flat calibrate measures against a BPE tokenizer; pick cl100k_base or o200k_base");
+    }
+    if !cli.tokenizer.is_available() {
+        bail!("--tokenizer needs the `tiktoken` cargo feature, which this build of flat was compiled without");
+    }
+    if !cli.dir.is_dir() {
+        bail!("Not a directory: {}", cli.dir.display());
+    }
+    let config = Config {
+        path: cli.dir.clone(),
+        ..Config::default()
+    };
+    let samples = calibrate::calibrate(&config, &cli.tokenizer)?;
+    let name = clap::ValueEnum::to_possible_value(&cli.tokenizer)
+        .map_or_else(String::new, |value| value.get_name().to_string());
+
+    let mut out = std::io::stdout().lock();
+    writeln!(out, "Bytes per token with {}:", name)?;
+    for sample in &samples {
+        writeln!(
+            out,
+            "  {:<10} {:>6.2}  ({} files, {}){}",
+            sample.extension,
+            sample.bytes_per_token(),
+            sample.files,
+            Statistics::format_bytes(sample.bytes),
+            if sample.is_reliable() {
+                ""
+            } else {
+                "  too little text to keep"
+            }
+        )?;
+    }
+    if cli.write {
+        let path = cli.dir.join(config_file::CONFIG_FILE_NAME);
+        let written = calibrate::write_ratios(&path, &samples, &name)?;
+        writeln!(out, "Wrote {} ratios to {}", written, path.display())?;
+    } else {
+        writeln!(
+            out,
+            "Languages with at least {} of text can be saved with --write",
+            Statistics::format_bytes(MIN_SAMPLE_BYTES)
+        )?;
+    }
+    Ok(())
+}
+
+fn run_cache_command(cli: CacheCli) -> Result<()> {
+    let Some(root) = cache::default_dir() else {
+        bail!("No cache directory: set FLAT_CACHE_DIR, XDG_CACHE_HOME, or HOME");
+    };
+    let mut out = std::io::stdout().lock();
+    match cli.command {
+        CacheCommand::Clear => {
+            let timeout = cli.lock_timeout.unwrap_or(DEFAULT_LOCK_TIMEOUT);
+            let _lock = cache::lock(&root, LockMode::Exclusive, timeout)?;
+            let removed = cache::clear(&root)?;
+            writeln!(
+                out,
+                "Removed {} entries ({}) from {}",
+                removed.entries + removed.stale_entries,
+                Statistics::format_bytes((removed.bytes + removed.stale_bytes) as usize),
+                root.display()
+            )?;
+        }
+        CacheCommand::Stats => {
+            let stats = cache::stats(&root)?;
+            writeln!(out, "Cache: {}", root.display())?;
+            writeln!(
+                out,
+                "Entries: {} ({})",
+                stats.entries,
+                Statistics::format_bytes(stats.bytes as usize)
+            )?;
+            if stats.stale_entries > 0 {
+                writeln!(
+                    out,
+                    "Stale: {} ({}) from other flat versions, reclaim with `flat cache clear`",
+                    stats.stale_entries,
+                    Statistics::format_bytes(stats.stale_bytes as usize)
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
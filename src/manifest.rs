@@ -0,0 +1,38 @@
+//! `--manifest`: a JSON sidecar listing what flat decided for every file it
+//! saw, so CI can check what went into a context (no secrets, a size trend)
+//! without parsing the output itself.
+
+use crate::config::Config;
+use crate::export::entry_name;
+use crate::output::Statistics;
+use crate::schema::SCHEMA_VERSION;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+/// Every file's decision (path, decision, reason, score, tokens) plus the run
+/// summary. The `--bundle` manifest is this with the bundle's own fields added.
+pub fn manifest(config: &Config, stats: &Statistics) -> Result<Value> {
+    // Reports as serialized for `analyze`, with paths relative to the root
+    let mut files = Vec::new();
+    for report in &stats.file_reports {
+        let mut file = serde_json::to_value(report)?;
+        file["path"] = entry_name(&report.path, &config.path).into();
+        files.push(file);
+    }
+    Ok(json!({
+        "flat_schema": SCHEMA_VERSION,
+        "flat_version": env!("CARGO_PKG_VERSION"),
+        "format": clap::ValueEnum::to_possible_value(&config.format).map(|v| v.get_name().to_string()),
+        "files": files,
+        "summary": stats,
+    }))
+}
+
+/// Write the manifest of a finished run to `path`
+pub fn write_manifest(path: &Path, config: &Config, stats: &Statistics) -> Result<()> {
+    let mut text = serde_json::to_string_pretty(&manifest(config, stats)?)?;
+    text.push('\n');
+    fs::write(path, text).with_context(|| format!("Failed to write manifest: {}", path.display()))
+}
@@ -0,0 +1,120 @@
+//! Pieces of `--format markdown`: each file is a `## path` heading followed by
+//! a fenced code block tagged with its language, which chat UIs render well.
+
+use crate::compress::language_for_path;
+use crate::output::Statistics;
+use std::path::Path;
+
+/// `## path` heading, with a parenthesized label such as `(compressed)`
+pub fn file_heading(path: &str, label: Option<&str>) -> String {
+    match label {
+        Some(label) => format!("## {} ({})\n\n", path, label),
+        None => format!("## {}\n\n", path),
+    }
+}
+
+/// A code fence longer than any backtick run in the content, so the content
+/// can't close it early
+pub fn fence(longest_backtick_run: usize) -> String {
+    "`".repeat(longest_backtick_run.max(2) + 1)
+}
+
+/// Longest run of consecutive backticks in `text`, continuing a run of
+/// `carry` backticks that ended the previous chunk. Returns the longest run
+/// and the run still open at the end of `text`.
+pub fn backtick_runs(text: &str, carry: usize) -> (usize, usize) {
+    let (mut longest, mut run) = (carry, carry);
+    for c in text.chars() {
+        if c == '`' {
+            run += 1;
+            longest = longest.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    (longest, run)
+}
+
+/// Opening fence line tagged with the file's language
+pub fn code_block_open(path: &str, fence: &str) -> String {
+    format!("{}{}\n", fence, language_tag(path))
+}
+
+/// Closing fence, after content that may not end with a newline
+pub fn code_block_close(fence: &str, ends_with_newline: bool) -> String {
+    let newline = if ends_with_newline { "" } else { "\n" };
+    format!("{}{}\n\n", newline, fence)
+}
+
+/// Info-string language for a fenced block: the compression language when flat
+/// knows one, otherwise the file extension
+fn language_tag(path: &str) -> String {
+    let path = Path::new(path);
+    if let Some(lang) = language_for_path(path) {
+        return lang.code_fence_tag().to_string();
+    }
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) => ext.to_lowercase(),
+        None => String::new(),
+    }
+}
+
+/// A dry-run listing line
+pub fn listing(path: &str, annotation: Option<&str>) -> String {
+    match annotation {
+        Some(a) => format!("- `{}` {}\n", path, a),
+        None => format!("- `{}`\n", path),
+    }
+}
+
+/// The `--outline-only` tree as a text block under its own heading
+pub fn tree(tree: &str) -> String {
+    format!("## Tree\n\n```text\n{}```\n\n", tree)
+}
+
+/// Heading for a `--layout sections` section
+pub fn section_heading(name: &str) -> String {
+    format!("# {}\n\n", name)
+}
+
+/// Heading for a `--split-tokens` part
+pub fn part_heading(index: usize) -> String {
+    format!("# Part {}\n\n", index)
+}
+
+/// Closing subtotal for a `--layout sections` section or a `--split-tokens` part
+pub fn section_subtotal(files: usize, tokens: &str) -> String {
+    format!("_{} files, ~{} tokens_\n\n", files, tokens)
+}
+
+/// The summary block, as plain text under its own heading
+pub fn summary(stats: &Statistics) -> String {
+    let text = stats.format_summary();
+    let body = text
+        .trim_start_matches("<summary>\n")
+        .trim_end_matches("</summary>\n");
+    format!("## Summary\n\n```text\n{}```\n", body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fence_outgrows_backticks_in_content() {
+        let (longest, open) = backtick_runs("a ``` b ``", 0);
+        assert_eq!((longest, open), (3, 2));
+        assert_eq!(backtick_runs("``c", open).0, 4);
+        assert_eq!(fence(0), "```");
+        assert_eq!(fence(3), "````");
+    }
+
+    #[test]
+    fn test_language_tag() {
+        assert_eq!(language_tag("src/main.rs"), "rust");
+        assert_eq!(language_tag("app/view.tsx"), "tsx");
+        assert_eq!(language_tag("Cargo.toml"), "toml");
+        assert_eq!(language_tag("README.md"), "markdown");
+        assert_eq!(language_tag("Makefile"), "");
+    }
+}
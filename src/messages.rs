@@ -0,0 +1,124 @@
+//! Pieces of `--format messages`: a JSON array of chat messages to send to an
+//! LLM API as is. A system message carries the header, the file tree, and the
+//! summary; the files follow as XML in user messages, one per `--split-tokens`
+//! part.
+
+use crate::format::escape_xml;
+use crate::tree;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Message<'a> {
+    role: &'static str,
+    content: &'a str,
+}
+
+/// The conversation as it is written, held until the summary is known
+#[derive(Debug, Default)]
+pub struct Conversation {
+    header: String,
+    tree: Option<String>,
+    summary: String,
+    user: Vec<String>,
+}
+
+impl Conversation {
+    /// Append to the open user message, opening the first if there is none
+    pub fn push_str(&mut self, text: &str) {
+        match self.user.last_mut() {
+            Some(message) => message.push_str(text),
+            None => self.user.push(text.to_string()),
+        }
+    }
+
+    /// Start the next user message, unless the open one is still empty
+    pub fn next_message(&mut self) {
+        if self.user.last().is_none_or(|message| !message.is_empty()) {
+            self.user.push(String::new());
+        }
+    }
+
+    pub fn set_header(&mut self, text: &str) {
+        self.header = text.to_string();
+        if !self.header.ends_with('\n') {
+            self.header.push('\n');
+        }
+        self.header.push('\n');
+    }
+
+    /// The `--outline-only` tree, in place of the one drawn from the written files
+    pub fn set_tree(&mut self, tree: &str) {
+        self.tree = Some(tree.to_string());
+    }
+
+    pub fn set_summary(&mut self, summary: String) {
+        self.summary = summary;
+    }
+
+    /// The JSON array, with a tree of `paths` (the files written) unless one was set
+    pub fn to_json(&self, paths: &[String]) -> serde_json::Result<String> {
+        let tree = match &self.tree {
+            Some(tree) => tree.clone(),
+            None => {
+                let files: Vec<(String, Option<String>)> =
+                    paths.iter().map(|path| (path.clone(), None)).collect();
+                tree::render(&files)
+            }
+        };
+        let mut system = self.header.clone();
+        if !tree.is_empty() {
+            system.push_str(&format!("<tree>\n{}</tree>\n\n", escape_xml(&tree)));
+        }
+        system.push_str(&self.summary);
+
+        let messages: Vec<Message> = std::iter::once(Message {
+            role: "system",
+            content: &system,
+        })
+        .chain(
+            self.user
+                .iter()
+                .filter(|message| !message.is_empty())
+                .map(|message| Message {
+                    role: "user",
+                    content: message,
+                }),
+        )
+        .collect();
+        let mut json = serde_json::to_string_pretty(&messages)?;
+        json.push('\n');
+        Ok(json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversation_splits_user_messages() {
+        let mut conversation = Conversation::default();
+        conversation.set_header("Review this");
+        conversation.next_message();
+        conversation.push_str("<file path=\"src/a.rs\">\n</file>\n");
+        conversation.next_message();
+        conversation.push_str("<file path=\"src/b.rs\">\n</file>\n");
+        conversation.set_summary("<summary>\n</summary>\n".to_string());
+
+        let paths = ["src/a.rs".to_string(), "src/b.rs".to_string()];
+        let json: serde_json::Value =
+            serde_json::from_str(&conversation.to_json(&paths).unwrap()).unwrap();
+        let messages = json.as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["role"], "system");
+        let system = messages[0]["content"].as_str().unwrap();
+        assert!(system.starts_with("Review this\n\n<tree>\n"), "{}", system);
+        assert!(system.contains("a.rs"), "{}", system);
+        assert!(system.ends_with("</summary>\n"), "{}", system);
+        assert_eq!(messages[1]["role"], "user");
+        assert!(messages[2]["content"]
+            .as_str()
+            .unwrap()
+            .contains("src/b.rs"));
+    }
+}
@@ -0,0 +1,133 @@
+//! Minified files (one-line JSON dumps, bundled JavaScript) are shortened
+//! instead of written whole: a single such file can use up a whole token
+//! budget while telling the model almost nothing. JSON keeps its shape as an
+//! outline; anything else keeps only its start.
+
+use serde_json::{Map, Value};
+
+/// Bytes kept from the start of a minified file that isn't JSON
+pub const KEEP_BYTES: usize = 2048;
+
+/// Array items kept in a JSON outline; the rest are counted
+const OUTLINE_ITEMS: usize = 1;
+
+/// Object keys kept in a JSON outline; the rest are counted
+const OUTLINE_KEYS: usize = 32;
+
+/// Characters kept of each string in a JSON outline
+const OUTLINE_STRING_CHARS: usize = 64;
+
+/// Nesting below this depth is elided in a JSON outline
+const OUTLINE_DEPTH: usize = 8;
+
+/// How a minified file was shortened
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shortening {
+    /// JSON reduced to its structure: keys, one item per array, clipped strings
+    Outlined,
+    /// Cut after its first [`KEEP_BYTES`] bytes
+    Truncated,
+}
+
+impl Shortening {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Shortening::Outlined => "outlined",
+            Shortening::Truncated => "truncated",
+        }
+    }
+}
+
+/// Shorten minified `content`: an indented outline if it parses as JSON,
+/// otherwise its first [`KEEP_BYTES`] bytes, cut at a character boundary
+pub fn shorten(content: &str) -> (String, Shortening) {
+    if let Ok(value) = serde_json::from_str::<Value>(content) {
+        let outline = outline(value, 0);
+        if let Ok(text) = serde_json::to_string_pretty(&outline) {
+            return (text + "\n", Shortening::Outlined);
+        }
+    }
+    let mut end = KEEP_BYTES.min(content.len());
+    while !content.is_char_boundary(end) {
+        end -= 1;
+    }
+    (content[..end].to_string(), Shortening::Truncated)
+}
+
+/// `value` with long arrays, objects, and strings elided; what was left out
+/// is counted in place, so the outline is still valid JSON
+fn outline(value: Value, depth: usize) -> Value {
+    match value {
+        Value::Array(items) if depth >= OUTLINE_DEPTH && !items.is_empty() => {
+            Value::String(format!("[{} items]", items.len()))
+        }
+        Value::Object(map) if depth >= OUTLINE_DEPTH && !map.is_empty() => {
+            Value::String(format!("{{{} keys}}", map.len()))
+        }
+        Value::Array(items) => {
+            let total = items.len();
+            let mut kept: Vec<Value> = items
+                .into_iter()
+                .take(OUTLINE_ITEMS)
+                .map(|item| outline(item, depth + 1))
+                .collect();
+            if total > OUTLINE_ITEMS {
+                kept.push(Value::String(format!(
+                    "... {} more items",
+                    total - OUTLINE_ITEMS
+                )));
+            }
+            Value::Array(kept)
+        }
+        Value::Object(map) => {
+            let total = map.len();
+            let mut kept: Map<String, Value> = map
+                .into_iter()
+                .take(OUTLINE_KEYS)
+                .map(|(key, value)| (key, outline(value, depth + 1)))
+                .collect();
+            if total > OUTLINE_KEYS {
+                kept.insert(
+                    "...".to_string(),
+                    Value::String(format!("{} more keys", total - OUTLINE_KEYS)),
+                );
+            }
+            Value::Object(kept)
+        }
+        Value::String(s) if s.chars().count() > OUTLINE_STRING_CHARS => {
+            let clipped: String = s.chars().take(OUTLINE_STRING_CHARS).collect();
+            Value::String(clipped + "...")
+        }
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_is_outlined_to_its_shape() {
+        let rows: Vec<String> = (0..500)
+            .map(|i| format!("{{\"id\":{},\"name\":\"{}\"}}", i, "x".repeat(100)))
+            .collect();
+        let json = format!("{{\"version\":2,\"rows\":[{}]}}", rows.join(","));
+        let (outline, how) = shorten(&json);
+        assert_eq!(how, Shortening::Outlined);
+        let value: Value = serde_json::from_str(&outline).unwrap();
+        assert_eq!(value["version"], 2);
+        assert_eq!(value["rows"][0]["id"], 0);
+        assert_eq!(value["rows"][0]["name"], format!("{}...", "x".repeat(64)));
+        assert_eq!(value["rows"][1], "... 499 more items");
+        assert!(outline.len() < 300);
+    }
+
+    #[test]
+    fn test_other_content_is_truncated_at_a_char_boundary() {
+        let bundle = format!("a{}", "é".repeat(KEEP_BYTES));
+        let (kept, how) = shorten(&bundle);
+        assert_eq!(how, Shortening::Truncated);
+        assert_eq!(kept.len(), KEEP_BYTES - 1);
+        assert!(bundle.starts_with(&kept));
+    }
+}
@@ -1,18 +1,393 @@
-use crate::filters::SkipReason;
-use std::collections::HashMap;
+use crate::config::LineRange;
+use crate::filters::{is_generated_stub, SkipReason};
+use crate::format::{self, escape_xml};
+use crate::git::LineChanges;
+use crate::html;
+use crate::markdown;
+use crate::messages::Conversation;
+use crate::priority::Role;
+use crate::progress::ProgressTracker;
+use crate::report::{Decision, FileReport};
+use crate::schema::{self, Event, JsonFile};
+use crate::tokens::{PerLanguage, TokenEstimator, TokenRatios, Tokenizer};
+use crate::warnings::Warnings;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
 use std::io::Write;
+use std::path::Path;
 
-#[derive(Debug, Default)]
+/// Output format for flattened content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// `<file>` tags with a trailing `<summary>` block
+    #[default]
+    Xml,
+    /// One JSON event per line, emitted as each stage happens
+    JsonlEvents,
+    /// A single JSON document: a `files` array and a `summary` object
+    Json,
+    /// A `## path` heading and a language-tagged fenced code block per file
+    Markdown,
+    /// Standalone HTML report with a file tree and stats, for human review
+    Html,
+    /// A JSON array of chat messages for an LLM API: a system message with the
+    /// tree and summary, then the files as XML in user messages
+    Messages,
+}
+
+/// How files are grouped in the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Layout {
+    /// One stream sorted by path
+    #[default]
+    Flat,
+    /// Labeled sections by role (project metadata, configuration, source, tests, docs),
+    /// each closed with a file and token subtotal
+    Sections,
+}
+
+/// Why a file that passed the filters was left out of the output by the token budget
+pub const BUDGET_EXCLUSION: &str = "budget";
+
+/// Every reason [`OutputWriter::write_excluded`] is given, which `file-skipped`
+/// events carry alongside the [`SkipReason`] codes
+pub const EXCLUSION_REASONS: &[&str] = &[BUDGET_EXCLUSION];
+
+/// Where the summary goes (`--summary`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum SummaryPlacement {
+    /// Right after the header, ahead of the files
+    First,
+    /// After the files
+    #[default]
+    Last,
+    /// Left out
+    None,
+}
+
+/// The order files are written in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Order {
+    /// Sorted by path
+    Path,
+    /// Highest priority first (README, entry points, configs, ...), then by path
+    Priority,
+    /// Smallest file first
+    Size,
+    /// Fewest estimated tokens first
+    Tokens,
+    /// Most recently modified first
+    Mtime,
+}
+
+/// Whether a file was written in full, compressed, or cut short
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FileMode {
+    Full,
+    Compressed,
+    /// Only the start of the file, cut to fit the token budget (`--truncate`)
+    Truncated,
+    /// Markdown compressed to its heading outline (`--compress-markdown`)
+    Outline,
+}
+
+impl FileMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FileMode::Full => "full",
+            FileMode::Compressed => "compressed",
+            FileMode::Truncated => "truncated",
+            FileMode::Outline => "outline",
+        }
+    }
+}
+
+/// Per-file attributes, spelled the same way by every format and every run mode:
+///
+/// - `mode="full|compressed|truncated|outline"`: present whenever flat chose how much of the file to
+///   write, i.e. under `--compress` or `--tokens`
+/// - `truncated="true"`: only part of the file's content was written
+/// - `lines="100-400"`: the content is just these lines, selected with `path:100-400`
+/// - `role="test" score="30"`: flat's classification and priority score, under `--annotate`
+/// - `added="12" removed="3"`: lines changed since the `--diff` base
+/// - `owners="alice,bob"`: the file's most frequent committers, under `--owners`
+/// - `tokens="123" bytes="456" sha256="..."`: the size and hash of the content
+///   as written, under `--file-meta` (JSON always has `bytes` and `tokens`)
+/// - `excluded-reason="..."`: on the stub of a file left out of the output
+///   (see [`OutputWriter::write_excluded`])
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileAttrs<'a> {
+    pub mode: Option<FileMode>,
+    pub truncated: bool,
+    pub lines: Option<LineRange>,
+    pub role: Option<Role>,
+    pub score: Option<u32>,
+    pub added: Option<usize>,
+    pub removed: Option<usize>,
+    pub owners: Option<&'a str>,
+    pub tokens: Option<usize>,
+    pub bytes: Option<usize>,
+    pub sha256: Option<&'a str>,
+}
+
+impl<'a> FileAttrs<'a> {
+    pub fn with_mode(mode: Option<FileMode>) -> Self {
+        Self {
+            mode,
+            ..Default::default()
+        }
+    }
+
+    pub fn with_lines(self, lines: Option<LineRange>) -> Self {
+        Self { lines, ..self }
+    }
+
+    /// Role and score, when `--annotate` asked for them
+    pub fn with_annotation(self, annotation: Option<(Role, u32)>) -> Self {
+        Self {
+            role: annotation.map(|(role, _)| role),
+            score: annotation.map(|(_, score)| score),
+            ..self
+        }
+    }
+
+    /// Lines added and removed, for a file changed since the `--diff` base
+    pub fn with_changes(self, changes: Option<LineChanges>) -> Self {
+        Self {
+            added: changes.map(|c| c.added),
+            removed: changes.map(|c| c.removed),
+            ..self
+        }
+    }
+
+    /// Mark the content as only part of the file
+    pub fn with_truncated(self, truncated: bool) -> Self {
+        Self { truncated, ..self }
+    }
+
+    /// Top committers, when `--owners` asked for them
+    pub fn with_owners(self, owners: Option<&'a str>) -> Self {
+        Self { owners, ..self }
+    }
+
+    /// How much of the file was written, spelled as on its tag, for a
+    /// `--dry-run` line: `mode=full truncated=true`
+    pub fn listing_label(&self) -> String {
+        let mut parts = Vec::new();
+        if let Some(mode) = self.mode {
+            parts.push(format!("mode={}", mode.as_str()));
+        }
+        if self.truncated {
+            parts.push("truncated=true".to_string());
+        }
+        parts.join(" ")
+    }
+
+    /// Short human-readable label, for formats without attributes (HTML)
+    fn label(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if let Some(mode) = self.mode {
+            parts.push(mode.as_str().to_string());
+        }
+        if self.truncated {
+            parts.push("truncated".to_string());
+        }
+        if let Some(lines) = self.lines {
+            parts.push(format!("lines {}", lines));
+        }
+        if let Some(role) = self.role {
+            parts.push(role.as_str().to_string());
+        }
+        if let Some(score) = self.score {
+            parts.push(format!("score {}", score));
+        }
+        if let (Some(added), Some(removed)) = (self.added, self.removed) {
+            parts.push(format!("+{} -{}", added, removed));
+        }
+        if let Some(owners) = self.owners {
+            parts.push(format!("owners {}", owners));
+        }
+        if let Some(tokens) = self.tokens {
+            parts.push(format!("{} tokens", tokens));
+        }
+        if let Some(bytes) = self.bytes {
+            parts.push(format!("{} bytes", bytes));
+        }
+        if let Some(sha256) = self.sha256 {
+            parts.push(format!("sha256 {}", sha256));
+        }
+        (!parts.is_empty()).then(|| parts.join(", "))
+    }
+}
+
+impl OutputFormat {
+    /// File extension used when `-o` names a directory and flat picks the file name
+    pub fn file_extension(self) -> &'static str {
+        match self {
+            OutputFormat::Xml => "xml",
+            OutputFormat::JsonlEvents => "jsonl",
+            OutputFormat::Json => "json",
+            OutputFormat::Markdown => "md",
+            OutputFormat::Html => "html",
+            OutputFormat::Messages => "json",
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
 pub struct Statistics {
     pub total_files: usize,
     pub included_files: usize,
-    pub skipped_by_reason: HashMap<String, usize>,
-    pub included_by_extension: HashMap<String, usize>,
+    pub skipped_by_reason: BTreeMap<String, usize>,
+    pub included_by_extension: BTreeMap<String, usize>,
     pub output_size: usize,
     pub compressed_files: usize,
+    /// Values masked in secret files under `--redact`
+    pub redactions: usize,
+    /// Minified files outlined or truncated instead of written whole
+    pub minified_files: usize,
+    /// Files written from UTF-16, windows-1252, or another encoding than UTF-8
+    pub transcoded_files: usize,
+    /// The separator after each line's number, when lines were numbered
+    pub line_numbers: Option<String>,
+    /// What the files were allocated, after `reserved_tokens`
     pub token_budget: Option<usize>,
+    /// Held back from `--tokens` by `--reserve-tokens`
+    pub reserved_tokens: usize,
     pub tokens_used: usize,
     pub excluded_by_budget: Vec<String>,
+    /// Files cut short to fit the token budget under `--truncate`
+    pub truncated_files: usize,
+    pub doc_link_boosts: usize,
+    pub related_boosts: usize,
+    /// Files scored up under `--entry` for being reachable from it
+    pub entry_boosts: usize,
+    pub symlinks: usize,
+    pub project: Option<String>,
+    pub command: Option<String>,
+    pub directories: BTreeMap<String, DirectoryOutcome>,
+    /// Directories the walker skipped without descending into them
+    pub pruned_dirs: Vec<PrunedDir>,
+    /// Estimated output size per top-level directory (`.` for files at the
+    /// root), tallied under `--stats`
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub directory_sizes: BTreeMap<String, DirectorySize>,
+    /// Output offset where each `--split-tokens` part begins
+    #[serde(skip)]
+    pub part_offsets: Vec<usize>,
+    /// How much of the scanned text the output covers, tallied from `file_reports`
+    pub coverage: Coverage,
+    /// Per-file decisions, in the order they were made (see [`Statistics::add_file_report`])
+    #[serde(skip)]
+    pub file_reports: Vec<FileReport>,
+    #[serde(skip)]
+    pub warnings: Warnings,
+    #[serde(skip)]
+    pub progress: ProgressTracker,
+}
+
+/// How representative the output is of the scanned text: every file that
+/// was read or could have been, except binaries and flat's own files
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Coverage {
+    pub text_files: usize,
+    pub included_files: usize,
+    /// Full size of every text file, and of the included ones
+    pub text_bytes: usize,
+    pub included_bytes: usize,
+    /// Estimated tokens of every text file in full, and of what was written
+    pub text_tokens: usize,
+    pub included_tokens: usize,
+}
+
+impl Coverage {
+    fn add(&mut self, report: &FileReport) {
+        let not_text = [
+            SkipReason::BinaryExtension,
+            SkipReason::BinaryContent,
+            SkipReason::OwnOutput,
+            SkipReason::Duplicate,
+        ];
+        if report.decision == Decision::Skipped
+            && not_text
+                .iter()
+                .any(|reason| report.reason.as_deref() == Some(reason.code()))
+        {
+            return;
+        }
+        self.text_files += 1;
+        self.text_bytes += report.bytes;
+        self.text_tokens += report.tokens_full;
+        if matches!(
+            report.decision,
+            Decision::Full | Decision::Compressed | Decision::Truncated
+        ) {
+            self.included_files += 1;
+            self.included_bytes += report.bytes;
+            self.included_tokens += report.tokens_written();
+        }
+    }
+
+    /// "Included 212/340 text files (62%), 48% of text bytes, 71% of estimated tokens"
+    pub fn describe(&self) -> String {
+        format!(
+            "Included {}/{} text files ({}%), {}% of text bytes, {}% of estimated tokens",
+            self.included_files,
+            self.text_files,
+            percent(self.included_files, self.text_files),
+            percent(self.included_bytes, self.text_bytes),
+            percent(self.included_tokens, self.text_tokens)
+        )
+    }
+}
+
+/// `part` as a whole percentage of `whole`, rounded down so only everything is 100%
+fn percent(part: usize, whole: usize) -> usize {
+    if whole == 0 {
+        return 100;
+    }
+    (part as u128 * 100 / whole as u128) as usize
+}
+
+/// Aggregated decisions for one top-level directory of the scanned root
+#[derive(Debug, Default, Serialize)]
+pub struct DirectoryOutcome {
+    pub included: usize,
+    pub excluded_by_reason: BTreeMap<String, usize>,
+}
+
+/// What one top-level directory contributes to the output
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct DirectorySize {
+    pub files: usize,
+    pub bytes: usize,
+    pub tokens: usize,
+}
+
+/// A directory the walker never entered (default exclude, gitignore, or hidden)
+#[derive(Debug, Clone, Serialize)]
+pub struct PrunedDir {
+    /// Relative to the scanned root
+    pub path: String,
+    pub reason: String,
+    /// Files inside, counted up to a limit
+    pub files: usize,
+    /// The count stopped at the limit; the directory holds at least `files`
+    pub files_capped: bool,
+}
+
+impl DirectoryOutcome {
+    /// The most common exclusion reason, ties broken alphabetically
+    fn main_reason(&self) -> Option<&str> {
+        self.excluded_by_reason
+            .iter()
+            .max_by(|(a_reason, a_count), (b_reason, b_count)| {
+                a_count.cmp(b_count).then_with(|| b_reason.cmp(a_reason))
+            })
+            .map(|(reason, _)| reason.as_str())
+    }
 }
 
 impl Statistics {
@@ -27,6 +402,20 @@ impl Statistics {
         *self.included_by_extension.entry(ext).or_insert(0) += 1;
     }
 
+    /// Take back [`Self::add_included`], for a file that passed the filters
+    /// but turned out not to be text
+    pub fn remove_included(&mut self, extension: Option<&str>) {
+        self.total_files -= 1;
+        self.included_files -= 1;
+        let ext = extension.unwrap_or("no extension");
+        if let Some(count) = self.included_by_extension.get_mut(ext) {
+            *count -= 1;
+            if *count == 0 {
+                self.included_by_extension.remove(ext);
+            }
+        }
+    }
+
     pub fn add_file_size_estimate(&mut self, file_size: u64, path_length: usize) {
         // Estimate XML overhead:
         // - Opening tag: <file path="..."> + newline = ~15 + path_length bytes
@@ -44,14 +433,116 @@ impl Statistics {
         self.total_files += 1;
         *self
             .skipped_by_reason
+            .entry(reason.code().to_string())
+            .or_insert(0) += 1;
+    }
+
+    pub fn add_directory_included(&mut self, dir: &str) {
+        self.directories
+            .entry(dir.to_string())
+            .or_default()
+            .included += 1;
+    }
+
+    pub fn remove_directory_included(&mut self, dir: &str) {
+        if let Some(outcome) = self.directories.get_mut(dir) {
+            outcome.included -= 1;
+        }
+    }
+
+    pub fn add_directory_excluded(&mut self, dir: &str, reason: &str) {
+        *self
+            .directories
+            .entry(dir.to_string())
+            .or_default()
+            .excluded_by_reason
             .entry(reason.to_string())
             .or_insert(0) += 1;
     }
 
+    pub fn add_directory_size(&mut self, dir: &str, bytes: usize, tokens: usize) {
+        let size = self.directory_sizes.entry(dir.to_string()).or_default();
+        size.files += 1;
+        size.bytes += bytes;
+        size.tokens += tokens;
+    }
+
+    /// Move a file that passed filtering out of its directory's included count
+    pub fn add_directory_budget_exclusion(&mut self, dir: &str) {
+        if let Some(outcome) = self.directories.get_mut(dir) {
+            outcome.included = outcome.included.saturating_sub(1);
+        }
+        self.add_directory_excluded(dir, "budget");
+    }
+
+    fn format_directories(&self) -> String {
+        self.directories
+            .iter()
+            .map(|(dir, outcome)| {
+                if outcome.included > 0 {
+                    format!("{} ✓ ({} files)", dir, outcome.included)
+                } else {
+                    format!("{} ✗ {}", dir, outcome.main_reason().unwrap_or("empty"))
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+    }
+
+    /// `node_modules 10,000+ files, target 312 files, ...`, largest first
+    fn format_pruned(&self) -> String {
+        const SHOWN: usize = 3;
+        let mut dirs: Vec<_> = self.pruned_dirs.iter().collect();
+        dirs.sort_by(|a, b| b.files.cmp(&a.files).then_with(|| a.path.cmp(&b.path)));
+        let mut parts: Vec<String> = dirs
+            .iter()
+            .take(SHOWN)
+            .map(|dir| {
+                let plus = if dir.files_capped { "+" } else { "" };
+                format!(
+                    "{} {}{} files",
+                    dir.path,
+                    Self::format_tokens(dir.files),
+                    plus
+                )
+            })
+            .collect();
+        if dirs.len() > SHOWN {
+            parts.push(format!("{} more", dirs.len() - SHOWN));
+        }
+        parts.join(", ")
+    }
+
+    /// One aligned line per directory, most tokens first
+    fn format_directory_sizes(&self) -> String {
+        let mut dirs: Vec<_> = self.directory_sizes.iter().collect();
+        dirs.sort_by(|(a_dir, a), (b_dir, b)| {
+            b.tokens.cmp(&a.tokens).then_with(|| a_dir.cmp(b_dir))
+        });
+        let width = dirs.iter().map(|(dir, _)| dir.len()).max().unwrap_or(0);
+        dirs.iter()
+            .map(|(dir, size)| {
+                format!(
+                    "  {:<width$}  {:>5} files  {:>10}  ~{} tokens\n",
+                    dir,
+                    size.files,
+                    Self::format_bytes(size.bytes),
+                    Self::format_tokens(size.tokens),
+                )
+            })
+            .collect()
+    }
+
     pub fn add_output_bytes(&mut self, bytes: usize) {
         self.output_size += bytes;
     }
 
+    /// Record the decision made for one file, and count it toward the coverage
+    pub fn add_file_report(&mut self, report: FileReport) {
+        self.coverage.add(&report);
+        self.file_reports.push(report);
+    }
+
     pub fn total_skipped(&self) -> usize {
         self.skipped_by_reason.values().sum()
     }
@@ -61,7 +552,7 @@ impl Statistics {
         self.output_size / 4
     }
 
-    fn format_bytes(bytes: usize) -> String {
+    pub fn format_bytes(bytes: usize) -> String {
         const KB: usize = 1024;
         const MB: usize = KB * 1024;
 
@@ -74,7 +565,7 @@ impl Statistics {
         }
     }
 
-    fn format_tokens(tokens: usize) -> String {
+    pub fn format_tokens(tokens: usize) -> String {
         if tokens >= 10_000 {
             // Use k suffix for 10k and above
             if tokens >= 1_000_000 {
@@ -129,10 +620,36 @@ impl Statistics {
 
         summary.push('\n');
 
+        if self.coverage.text_files > 0 {
+            summary.push_str(&format!("Coverage: {}\n", self.coverage.describe()));
+        }
+
         if self.compressed_files > 0 {
             summary.push_str(&format!("Compressed: {} files\n", self.compressed_files));
         }
 
+        if self.redactions > 0 {
+            summary.push_str(&format!("Redacted: {} values\n", self.redactions));
+        }
+
+        if self.minified_files > 0 {
+            summary.push_str(&format!(
+                "Minified: {} files shortened\n",
+                self.minified_files
+            ));
+        }
+
+        if self.transcoded_files > 0 {
+            summary.push_str(&format!(
+                "Transcoded: {} files to UTF-8\n",
+                self.transcoded_files
+            ));
+        }
+
+        if let Some(separator) = &self.line_numbers {
+            summary.push_str(&format!("Line numbers: on (separator {:?})\n", separator));
+        }
+
         if self.total_skipped() > 0 {
             summary.push_str(&format!("Skipped: {}", self.total_skipped()));
 
@@ -151,6 +668,39 @@ impl Statistics {
             summary.push('\n');
         }
 
+        if let Some(project) = &self.project {
+            summary.push_str(&format!("Project: {}\n", project));
+        }
+
+        if self.symlinks > 0 {
+            summary.push_str(&format!("Symlinks: {} (not followed)\n", self.symlinks));
+        }
+
+        if !self.directories.is_empty() {
+            summary.push_str(&format!("Directories: {}\n", self.format_directories()));
+        }
+
+        if !self.directory_sizes.is_empty() {
+            summary.push_str("By directory:\n");
+            summary.push_str(&self.format_directory_sizes());
+        }
+
+        if !self.pruned_dirs.is_empty() {
+            let files: usize = self.pruned_dirs.iter().map(|d| d.files).sum();
+            let plus = if self.pruned_dirs.iter().any(|d| d.files_capped) {
+                "+"
+            } else {
+                ""
+            };
+            summary.push_str(&format!(
+                "Pruned: {} directories, {}{} files not scanned ({})\n",
+                self.pruned_dirs.len(),
+                Self::format_tokens(files),
+                plus,
+                self.format_pruned()
+            ));
+        }
+
         // Add token budget info
         if let Some(budget) = self.token_budget {
             summary.push_str(&format!(
@@ -158,12 +708,43 @@ impl Statistics {
                 Self::format_tokens(self.tokens_used),
                 Self::format_tokens(budget)
             ));
+            if self.reserved_tokens > 0 {
+                summary.push_str(&format!(
+                    "Reserved: {} of {} for the prompt and answer\n",
+                    Self::format_tokens(self.reserved_tokens),
+                    Self::format_tokens(budget + self.reserved_tokens)
+                ));
+            }
             if !self.excluded_by_budget.is_empty() {
                 summary.push_str(&format!(
                     "Excluded by budget: {} files\n",
                     self.excluded_by_budget.len()
                 ));
             }
+            if self.truncated_files > 0 {
+                summary.push_str(&format!(
+                    "Truncated to fit: {} files\n",
+                    self.truncated_files
+                ));
+            }
+            if self.doc_link_boosts > 0 {
+                summary.push_str(&format!(
+                    "Boosted by doc links: {} files\n",
+                    self.doc_link_boosts
+                ));
+            }
+            if self.related_boosts > 0 {
+                summary.push_str(&format!(
+                    "Boosted as related: {} files\n",
+                    self.related_boosts
+                ));
+            }
+            if self.entry_boosts > 0 {
+                summary.push_str(&format!(
+                    "Reachable from entry: {} files\n",
+                    self.entry_boosts
+                ));
+            }
         }
 
         // Add output size (skip token estimate when budget is active to avoid confusion)
@@ -182,86 +763,892 @@ impl Statistics {
             }
         }
 
+        if let Some(command) = &self.command {
+            summary.push_str(&format!("Re-run: {}\n", command));
+        }
+
         summary.push_str("</summary>\n");
         summary
     }
 }
 
-pub struct OutputWriter {
-    writer: Box<dyn Write>,
+/// How far a `--format json` document has been written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JsonState {
+    NotStarted,
+    /// The top-level object is open; `files` may still be to come
+    Open,
+    /// Inside the `files` array, with at least one entry written
+    InFiles,
+    /// `files` was written and closed
+    FilesClosed,
+    Finished,
+}
+
+/// The `--layout sections` section currently being written, with its running subtotal
+struct OpenSection {
+    name: &'static str,
+    files: usize,
+    tokens: usize,
+}
+
+/// `--split-tokens` progress: the part being written and where each part began
+struct Split {
+    limit: usize,
+    /// Files and tokens in the open part, if one is open
+    open: Option<(usize, usize)>,
+    /// Output offset of each part's start marker
+    offsets: Vec<usize>,
+}
+
+pub struct OutputWriter<'a> {
+    writer: Box<dyn Write + 'a>,
     bytes_written: usize,
+    format: OutputFormat,
+    /// Paths of file sections written so far (the HTML report's and the messages' tree)
+    written_paths: Vec<String>,
+    /// `--format messages` output, written out whole by `finish`
+    conversation: Conversation,
+    section: Option<OpenSection>,
+    json: JsonState,
+    tokenizer: Tokenizer,
+    token_ratios: TokenRatios,
+    split: Option<Split>,
+    /// Separator after each line's number, when lines are numbered
+    line_numbers: Option<String>,
+    summary: SummaryPlacement,
+    /// Under `--summary first`, the output so far, held until the summary can
+    /// go in after its first `header_end` bytes
+    held: Option<Vec<u8>>,
+    header_end: usize,
+    /// Tag each file with its tokens, bytes, and SHA-256 (`--file-meta`)
+    file_meta: bool,
 }
 
-impl OutputWriter {
-    pub fn new(writer: Box<dyn Write>) -> Self {
+/// Lowercase hex SHA-256 of `chunks`, taken in order
+fn sha256_hex<S: AsRef<str>>(
+    chunks: impl IntoIterator<Item = std::io::Result<S>>,
+) -> std::io::Result<String> {
+    let mut hasher = Sha256::new();
+    for chunk in chunks {
+        hasher.update(chunk?.as_ref().as_bytes());
+    }
+    Ok(hasher
+        .finalize()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect())
+}
+
+impl<'a> OutputWriter<'a> {
+    pub fn new(writer: Box<dyn Write + 'a>) -> Self {
+        Self::with_format(writer, OutputFormat::Xml)
+    }
+
+    pub fn with_format(writer: Box<dyn Write + 'a>, format: OutputFormat) -> Self {
         Self {
             writer,
             bytes_written: 0,
+            format,
+            written_paths: Vec::new(),
+            conversation: Conversation::default(),
+            section: None,
+            json: JsonState::NotStarted,
+            tokenizer: Tokenizer::default(),
+            token_ratios: TokenRatios::new(),
+            split: None,
+            line_numbers: None,
+            summary: SummaryPlacement::default(),
+            held: None,
+            header_end: 0,
+            file_meta: false,
         }
     }
 
+    /// Group files into numbered parts of at most `limit` tokens each (`--split-tokens`).
+    /// A file is never split, so one bigger than `limit` gets a part to itself.
+    pub fn with_split(mut self, limit: Option<usize>) -> Self {
+        self.split = limit.map(|limit| Split {
+            limit,
+            open: None,
+            offsets: Vec::new(),
+        });
+        self
+    }
+
+    /// Output offset where each `--split-tokens` part begins, in order
+    pub fn part_offsets(&self) -> &[usize] {
+        self.split.as_ref().map_or(&[], |split| &split.offsets)
+    }
+
+    /// Count the per-file and per-section tokens reported in the output with `tokenizer`
+    pub fn with_tokenizer(mut self, tokenizer: Tokenizer) -> Self {
+        self.tokenizer = tokenizer;
+        self
+    }
+
+    /// Count with these bytes-per-token ratios by extension where the tokenizer
+    /// is the heuristic (`[token-ratios]`)
+    pub fn with_token_ratios(mut self, ratios: TokenRatios) -> Self {
+        self.token_ratios = ratios;
+        self
+    }
+
+    /// Prefix each line of file content with its number and `separator`
+    /// (`--line-numbers`). Numbers count from the start of a file's line range,
+    /// or from 1, through the text as written, compressed or not.
+    pub fn with_line_numbers(mut self, separator: Option<String>) -> Self {
+        self.line_numbers = separator;
+        self
+    }
+
+    /// Put the summary ahead of the files, or leave it out. Ahead of the files,
+    /// the output is held in memory until the summary is known; JSON and HTML
+    /// keep theirs where it is, and messages always have it up front.
+    pub fn with_summary(mut self, summary: SummaryPlacement) -> Self {
+        self.summary = summary;
+        let holds = matches!(
+            self.format,
+            OutputFormat::Xml | OutputFormat::Markdown | OutputFormat::JsonlEvents
+        );
+        self.held = (summary == SummaryPlacement::First && holds).then(Vec::new);
+        self
+    }
+
+    /// Tag each file with the tokens, bytes, and SHA-256 of its content as
+    /// written (`--file-meta`), so a tool can budget with it and tell when it
+    /// went stale
+    pub fn with_file_meta(mut self, file_meta: bool) -> Self {
+        self.file_meta = file_meta;
+        self
+    }
+
     pub fn bytes_written(&self) -> usize {
         self.bytes_written
     }
 
+    /// Write an HTML fragment, preceded by the page preamble if this is the first one
+    fn write_html(&mut self, fragment: &str) -> std::io::Result<()> {
+        if self.bytes_written == 0 {
+            let preamble = html::preamble();
+            self.writer.write_all(preamble.as_bytes())?;
+            self.bytes_written += preamble.len();
+        }
+        self.writer.write_all(fragment.as_bytes())?;
+        self.bytes_written += fragment.len();
+        Ok(())
+    }
+
+    fn write_event(&mut self, event: &Event) -> std::io::Result<()> {
+        let mut line = schema::to_json(event)?;
+        line.push('\n');
+        self.put(line.as_bytes())?;
+        self.bytes_written += line.len();
+        Ok(())
+    }
+
+    /// Write to the destination, or hold it for `--summary first`
+    fn put(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match &mut self.held {
+            Some(held) => {
+                held.extend_from_slice(bytes);
+                Ok(())
+            }
+            None => self.writer.write_all(bytes),
+        }
+    }
+
+    /// Write out what was held for a summary that never came
+    fn release_held(&mut self) -> std::io::Result<()> {
+        match self.held.take() {
+            Some(held) => self.writer.write_all(&held),
+            None => Ok(()),
+        }
+    }
+
+    /// Open the `--format json` document if this is its first output
+    fn open_json(&mut self) -> std::io::Result<()> {
+        if self.json == JsonState::NotStarted {
+            self.write_raw(&format!("{{\"flat_schema\":{}", schema::SCHEMA_VERSION))?;
+            self.json = JsonState::Open;
+        }
+        Ok(())
+    }
+
+    /// Append one entry to the `files` array of the `--format json` document
+    fn write_json_file(&mut self, file: &JsonFile) -> std::io::Result<()> {
+        let entry = serde_json::to_string(file)?;
+        self.open_json_file_entry()?;
+        self.write_raw(&entry)
+    }
+
+    /// Write what precedes a `files` entry: the array opening or a separator
+    fn open_json_file_entry(&mut self) -> std::io::Result<()> {
+        self.open_json()?;
+        match self.json {
+            JsonState::InFiles => self.write_raw(","),
+            _ => {
+                self.json = JsonState::InFiles;
+                self.write_raw(",\"files\":[")
+            }
+        }
+    }
+
+    /// Write a top-level `"key": value` of the `--format json` document. Every
+    /// key but `header` comes after `files`, so `files` is closed (or written
+    /// empty) first.
+    fn write_json_field(&mut self, key: &str, value: &impl Serialize) -> std::io::Result<()> {
+        self.open_json()?;
+        if key != "header" {
+            self.close_json_files()?;
+        }
+        let value = serde_json::to_string(value)?;
+        self.write_raw(&format!(",\"{}\":{}", key, value))
+    }
+
+    fn close_json_files(&mut self) -> std::io::Result<()> {
+        match self.json {
+            JsonState::Open => self.write_raw(",\"files\":[]")?,
+            JsonState::InFiles => self.write_raw("]")?,
+            _ => return Ok(()),
+        }
+        self.json = JsonState::FilesClosed;
+        Ok(())
+    }
+
+    /// Complete the output: close the `--format json` document, or write out
+    /// the `--format messages` array
+    pub fn finish(&mut self) -> std::io::Result<()> {
+        self.release_held()?;
+        if self.format == OutputFormat::Messages {
+            let json = self.conversation.to_json(&self.written_paths)?;
+            return self.writer.write_all(json.as_bytes());
+        }
+        if self.format != OutputFormat::Json || self.json == JsonState::Finished {
+            return Ok(());
+        }
+        self.open_json()?;
+        self.close_json_files()?;
+        self.json = JsonState::Finished;
+        self.write_raw("}\n")
+    }
+
+    /// Make room for a file of `tokens` in the open part, starting the next
+    /// part when it would overflow
+    fn enter_part(&mut self, tokens: usize) -> std::io::Result<()> {
+        let Some(split) = &mut self.split else {
+            return Ok(());
+        };
+        if let Some((files, part_tokens)) = &mut split.open {
+            if *files == 0 || *part_tokens + tokens <= split.limit {
+                *files += 1;
+                *part_tokens += tokens;
+                return Ok(());
+            }
+        }
+        self.close_part()?;
+        let split = self.split.as_mut().expect("split output");
+        split.offsets.push(self.bytes_written);
+        split.open = Some((1, tokens));
+        let index = split.offsets.len();
+        match self.format {
+            OutputFormat::Xml => self.write_raw(&format!("<part index=\"{}\">\n\n", index)),
+            // Each part is a user message of its own
+            OutputFormat::Messages => {
+                self.conversation.next_message();
+                Ok(())
+            }
+            OutputFormat::JsonlEvents => self.write_event(&Event::PartStart { index }),
+            OutputFormat::Markdown => self.write_raw(&markdown::part_heading(index)),
+            // Rejected by Config::validate: a document can't be cut into parts
+            OutputFormat::Json | OutputFormat::Html => Ok(()),
+        }
+    }
+
+    /// Close the open `--split-tokens` part, if any, with its file and token count
+    fn close_part(&mut self) -> std::io::Result<()> {
+        let Some(split) = &mut self.split else {
+            return Ok(());
+        };
+        let Some((files, tokens)) = split.open.take() else {
+            return Ok(());
+        };
+        let index = split.offsets.len();
+        match self.format {
+            OutputFormat::Xml => self.write_raw(&format!(
+                "<subtotal files=\"{}\" tokens=\"{}\"/>\n</part>\n\n",
+                files, tokens
+            )),
+            OutputFormat::JsonlEvents => self.write_event(&Event::PartEnd {
+                index,
+                files,
+                tokens,
+            }),
+            OutputFormat::Markdown => self.write_raw(&markdown::section_subtotal(
+                files,
+                &Statistics::format_tokens(tokens),
+            )),
+            OutputFormat::Json | OutputFormat::Html | OutputFormat::Messages => Ok(()),
+        }
+    }
+
+    fn section_name(&self) -> Option<&'static str> {
+        self.section.as_ref().map(|s| s.name)
+    }
+
+    /// Report a file rejected by a filter (event formats only)
+    pub fn write_skipped(&mut self, path: &str, reason: &str) -> std::io::Result<()> {
+        match self.format {
+            OutputFormat::Xml
+            | OutputFormat::Html
+            | OutputFormat::Json
+            | OutputFormat::Markdown
+            | OutputFormat::Messages => Ok(()),
+            OutputFormat::JsonlEvents => self.write_event(&Event::FileSkipped { path, reason }),
+        }
+    }
+
+    /// Write a stub for a file that passed the filters but was left out, e.g. by
+    /// the token budget, so readers know it exists. `reason` is one of
+    /// [`EXCLUSION_REASONS`].
+    pub fn write_excluded(&mut self, path: &str, reason: &str) -> std::io::Result<()> {
+        match self.format {
+            OutputFormat::Xml | OutputFormat::Messages => {
+                self.write_raw(&format::excluded_stub(path, reason))
+            }
+            OutputFormat::JsonlEvents => self.write_event(&Event::FileSkipped { path, reason }),
+            OutputFormat::Json => self.write_json_file(&JsonFile {
+                path,
+                excluded_reason: Some(reason),
+                ..Default::default()
+            }),
+            OutputFormat::Markdown => self.write_raw(&format!(
+                "{}_Excluded: {}_\n\n",
+                markdown::file_heading(path, None),
+                reason
+            )),
+            OutputFormat::Html => {
+                let annotation = format!("excluded: {}", reason);
+                self.write_html(&html::listing(path, Some(&annotation)))
+            }
+        }
+    }
+
+    /// Report a successful compression (event formats only)
+    pub fn write_compressed(
+        &mut self,
+        path: &str,
+        original_bytes: usize,
+        compressed_bytes: usize,
+    ) -> std::io::Result<()> {
+        match self.format {
+            OutputFormat::Xml
+            | OutputFormat::Html
+            | OutputFormat::Json
+            | OutputFormat::Markdown
+            | OutputFormat::Messages => Ok(()),
+            OutputFormat::JsonlEvents => self.write_event(&Event::FileCompressed {
+                path,
+                original_bytes,
+                compressed_bytes,
+            }),
+        }
+    }
+
     pub fn write_file_content(&mut self, path: &str, content: &str) -> std::io::Result<()> {
-        self.write_file_content_with_mode(path, content, None)
+        self.write_file_content_with_attrs(path, content, FileAttrs::default())
     }
 
-    pub fn write_file_content_with_mode(
+    pub fn write_file_content_with_attrs(
         &mut self,
         path: &str,
         content: &str,
-        mode: Option<&str>,
+        attrs: FileAttrs,
     ) -> std::io::Result<()> {
-        let escaped_path = escape_xml(path);
-        let opening_tag = match mode {
-            Some(m) => format!("<file path=\"{}\" mode=\"{}\">\n", escaped_path, m),
-            None => format!("<file path=\"{}\">\n", escaped_path),
+        let numbered = self.line_numbers.as_ref().map(|separator| {
+            let first = attrs.lines.map_or(1, |lines| lines.start);
+            format::number_lines(content, first, separator)
+        });
+        let content = numbered.as_deref().unwrap_or(content);
+        let generated = is_generated_stub(Path::new(path));
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("");
+        let estimator = PerLanguage {
+            ratios: &self.token_ratios,
+            fallback: self.tokenizer,
         };
-        self.writer.write_all(opening_tag.as_bytes())?;
-        self.bytes_written += opening_tag.len();
+        let tokens = estimator.estimate(content, ext);
+        self.enter_part(tokens)?;
+        if let Some(section) = &mut self.section {
+            section.files += 1;
+            section.tokens += tokens;
+        }
+        let sha256 = if self.file_meta {
+            Some(sha256_hex([Ok(content)])?)
+        } else {
+            None
+        };
+        let attrs = self.with_meta(attrs, tokens, content.len(), sha256.as_deref());
+
+        if self.format == OutputFormat::JsonlEvents {
+            return self.write_event(&Event::FileIncluded {
+                path,
+                mode: attrs.mode,
+                truncated: attrs.truncated,
+                lines: attrs.lines,
+                role: attrs.role,
+                score: attrs.score,
+                added: attrs.added,
+                removed: attrs.removed,
+                owners: attrs.owners,
+                generated,
+                bytes: content.len(),
+                tokens,
+                sha256: attrs.sha256,
+                content,
+            });
+        }
+        if self.format == OutputFormat::Json {
+            return self.write_json_file(&JsonFile {
+                path,
+                section: self.section_name(),
+                mode: attrs.mode,
+                truncated: attrs.truncated,
+                lines: attrs.lines,
+                role: attrs.role,
+                score: attrs.score,
+                added: attrs.added,
+                removed: attrs.removed,
+                owners: attrs.owners,
+                generated,
+                bytes: Some(content.len()),
+                tokens: Some(tokens),
+                sha256: attrs.sha256,
+                content: Some(content),
+                ..Default::default()
+            });
+        }
+        if self.format == OutputFormat::Markdown {
+            let fence = markdown::fence(markdown::backtick_runs(content, 0).0);
+            let label = attrs.label();
+            let block = markdown::file_heading(path, label.as_deref())
+                + &markdown::code_block_open(path, &fence)
+                + content
+                + &markdown::code_block_close(&fence, content.ends_with('\n'));
+            return self.write_raw(&block);
+        }
+        if self.format == OutputFormat::Html {
+            let label = attrs.label();
+            let section =
+                html::file_section(self.written_paths.len(), path, label.as_deref(), content);
+            self.written_paths.push(path.to_string());
+            return self.write_html(&section);
+        }
+        if self.format == OutputFormat::Messages {
+            self.written_paths.push(path.to_string());
+        }
+
+        self.write_raw(&format::file_element(path, content, attrs))
+    }
 
-        self.writer.write_all(content.as_bytes())?;
-        self.bytes_written += content.len();
+    /// Write a file's content as it is read, in UTF-8 chunks, so a large file is
+    /// never held in memory whole. `len` is its size in bytes, which is all the
+    /// heuristic and `[token-ratios]` need; a BPE tokenizer counts the chunks
+    /// one by one, so a token across a chunk boundary may count twice. The
+    /// output is otherwise the same as [`Self::write_file_content_with_attrs`]
+    /// would produce. `open_chunks` may be called more than once: for the BPE
+    /// count, the `--file-meta` hash, and the Markdown scan for backticks.
+    pub fn write_file_stream<I>(
+        &mut self,
+        path: &str,
+        len: usize,
+        attrs: FileAttrs,
+        open_chunks: impl Fn() -> std::io::Result<I>,
+    ) -> std::io::Result<()>
+    where
+        I: Iterator<Item = std::io::Result<String>>,
+    {
+        let generated = is_generated_stub(Path::new(path));
+        let ext = Path::new(path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .unwrap_or("");
+        let estimator = PerLanguage {
+            ratios: &self.token_ratios,
+            fallback: self.tokenizer,
+        };
+        let tokens = match estimator.estimate_len(len, ext) {
+            Some(tokens) => tokens,
+            None => open_chunks()?.try_fold(0, |tokens, chunk| {
+                chunk.map(|chunk| tokens + estimator.estimate(&chunk, ext))
+            })?,
+        };
+        self.enter_part(tokens)?;
+        if let Some(section) = &mut self.section {
+            section.files += 1;
+            section.tokens += tokens;
+        }
+        // The tag comes before the content, so the hash takes a pass of its own
+        let sha256 = if self.file_meta {
+            Some(sha256_hex(open_chunks()?)?)
+        } else {
+            None
+        };
+        let attrs = self.with_meta(attrs, tokens, len, sha256.as_deref());
 
-        if !content.ends_with('\n') {
-            self.writer.write_all(b"\n")?;
-            self.bytes_written += 1;
+        let (open, close) = match self.format {
+            OutputFormat::Xml => (format::file_open_tag(path, attrs), String::new()),
+            OutputFormat::Messages => {
+                self.written_paths.push(path.to_string());
+                (format::file_open_tag(path, attrs), String::new())
+            }
+            OutputFormat::JsonlEvents => {
+                // The event with empty content, cut open where the content goes
+                let mut event = schema::to_json(&Event::FileIncluded {
+                    path,
+                    mode: attrs.mode,
+                    truncated: attrs.truncated,
+                    lines: attrs.lines,
+                    role: attrs.role,
+                    score: attrs.score,
+                    added: attrs.added,
+                    removed: attrs.removed,
+                    owners: attrs.owners,
+                    generated,
+                    bytes: len,
+                    tokens,
+                    sha256: attrs.sha256,
+                    content: "",
+                })?;
+                event.truncate(event.len() - "\"}".len());
+                (event, "\"}\n".to_string())
+            }
+            OutputFormat::Json => {
+                let mut entry = serde_json::to_string(&JsonFile {
+                    path,
+                    section: self.section_name(),
+                    mode: attrs.mode,
+                    truncated: attrs.truncated,
+                    lines: attrs.lines,
+                    role: attrs.role,
+                    score: attrs.score,
+                    added: attrs.added,
+                    removed: attrs.removed,
+                    owners: attrs.owners,
+                    generated,
+                    bytes: Some(len),
+                    tokens: Some(tokens),
+                    sha256: attrs.sha256,
+                    content: Some(""),
+                    ..Default::default()
+                })?;
+                entry.truncate(entry.len() - "\"}".len());
+                self.open_json_file_entry()?;
+                (entry, "\"}".to_string())
+            }
+            OutputFormat::Html => {
+                let label = attrs.label();
+                let open =
+                    html::file_section_open(self.written_paths.len(), path, label.as_deref());
+                self.written_paths.push(path.to_string());
+                (open, html::FILE_SECTION_CLOSE.to_string())
+            }
+            OutputFormat::Markdown => {
+                let (mut longest, mut run) = (0, 0);
+                for chunk in open_chunks()? {
+                    (longest, run) = markdown::backtick_runs(&chunk?, run);
+                }
+                let fence = markdown::fence(longest);
+                let label = attrs.label();
+                let open = markdown::file_heading(path, label.as_deref())
+                    + &markdown::code_block_open(path, &fence);
+                (open, fence)
+            }
+        };
+        if self.format == OutputFormat::Html {
+            self.write_html(&open)?;
+        } else {
+            self.write_raw(&open)?;
         }
 
-        self.writer.write_all(b"</file>\n\n")?;
-        self.bytes_written += 9; // "</file>\n\n"
+        let mut ends_with_newline = true;
+        for chunk in open_chunks()? {
+            let chunk = chunk?;
+            if let Some(last) = chunk.chars().last() {
+                ends_with_newline = last == '\n';
+            }
+            match self.format {
+                OutputFormat::Xml | OutputFormat::Markdown | OutputFormat::Messages => {
+                    self.write_raw(&chunk)?
+                }
+                OutputFormat::JsonlEvents | OutputFormat::Json => {
+                    let escaped = serde_json::to_string(chunk.as_str())?;
+                    self.write_raw(&escaped[1..escaped.len() - 1])?;
+                }
+                OutputFormat::Html => self.write_raw(&html::file_section_chunk(&chunk))?,
+            }
+        }
 
-        Ok(())
+        if matches!(self.format, OutputFormat::Xml | OutputFormat::Messages) {
+            if !ends_with_newline || len == 0 {
+                self.write_raw("\n")?;
+            }
+            self.write_raw(format::FILE_CLOSE)
+        } else if self.format == OutputFormat::Markdown {
+            let newline = ends_with_newline && len > 0;
+            self.write_raw(&markdown::code_block_close(&close, newline))
+        } else {
+            self.write_raw(&close)
+        }
+    }
+
+    /// `attrs` with the content's size and hash under `--file-meta`
+    fn with_meta<'m>(
+        &self,
+        attrs: FileAttrs<'m>,
+        tokens: usize,
+        bytes: usize,
+        sha256: Option<&'m str>,
+    ) -> FileAttrs<'m> {
+        if !self.file_meta {
+            return attrs;
+        }
+        FileAttrs {
+            tokens: Some(tokens),
+            bytes: Some(bytes),
+            sha256,
+            ..attrs
+        }
+    }
+
+    /// Write a stub for an unfollowed symlink, naming its target
+    pub fn write_symlink(&mut self, path: &str, target: &str) -> std::io::Result<()> {
+        if self.format == OutputFormat::JsonlEvents {
+            return self.write_event(&Event::FileSymlink { path, target });
+        }
+        if self.format == OutputFormat::Json {
+            return self.write_json_file(&JsonFile {
+                path,
+                symlink_to: Some(target),
+                ..Default::default()
+            });
+        }
+        if self.format == OutputFormat::Html {
+            let annotation = format!("symlink to {}", target);
+            return self.write_html(&html::listing(path, Some(&annotation)));
+        }
+        if self.format == OutputFormat::Markdown {
+            return self.write_raw(&format!(
+                "{}Symlink to `{}`\n\n",
+                markdown::file_heading(path, None),
+                target
+            ));
+        }
+
+        self.write_raw(&format::symlink_stub(path, target))
     }
 
     pub fn write_summary(&mut self, stats: &Statistics) -> std::io::Result<()> {
-        let summary = stats.format_summary();
-        self.writer.write_all(summary.as_bytes())?;
-        self.bytes_written += summary.len();
+        self.close_part()?;
+        match self.summary {
+            SummaryPlacement::None => Ok(()),
+            SummaryPlacement::First => match self.held.take() {
+                Some(held) => {
+                    self.writer.write_all(&held[..self.header_end])?;
+                    self.write_summary_block(stats)?;
+                    self.writer.write_all(&held[self.header_end..])
+                }
+                None => self.write_summary_block(stats),
+            },
+            SummaryPlacement::Last => self.write_summary_block(stats),
+        }
+    }
+
+    fn write_summary_block(&mut self, stats: &Statistics) -> std::io::Result<()> {
+        if self.format == OutputFormat::JsonlEvents {
+            return self.write_event(&Event::Summary { stats });
+        }
+        if self.format == OutputFormat::Json {
+            return self.write_json_field("summary", stats);
+        }
+        if self.format == OutputFormat::Html {
+            let summary = html::summary(stats, &self.written_paths);
+            return self.write_html(&summary);
+        }
+        if self.format == OutputFormat::Markdown {
+            return self.write_raw(&markdown::summary(stats));
+        }
+        if self.format == OutputFormat::Messages {
+            self.conversation.set_summary(format::summary(stats));
+            return Ok(());
+        }
 
-        self.writer.write_all(b"\n")?;
-        self.bytes_written += 1;
+        self.write_raw(&format::summary(stats))
+    }
 
+    /// Write a configured header snippet before any file content
+    pub fn write_header(&mut self, text: &str) -> std::io::Result<()> {
+        match self.format {
+            OutputFormat::Xml | OutputFormat::Markdown => self.write_snippet(text)?,
+            OutputFormat::JsonlEvents => self.write_event(&Event::Header { text })?,
+            OutputFormat::Json => self.write_json_field("header", &text)?,
+            OutputFormat::Html => self.write_html(&html::snippet(text))?,
+            OutputFormat::Messages => self.conversation.set_header(text),
+        }
+        // A summary put first goes in after the header
+        self.header_end = self.held.as_ref().map_or(0, Vec::len);
         Ok(())
     }
 
-    pub fn write_file_path(&mut self, path: &str) -> std::io::Result<()> {
-        let line = format!("{}\n", path);
-        self.writer.write_all(line.as_bytes())?;
-        self.bytes_written += line.len();
+    /// Write a configured footer snippet after the summary
+    pub fn write_footer(&mut self, text: &str) -> std::io::Result<()> {
+        match self.format {
+            OutputFormat::Xml | OutputFormat::Markdown | OutputFormat::Messages => {
+                self.write_snippet(text)
+            }
+            OutputFormat::JsonlEvents => self.write_event(&Event::Footer { text }),
+            OutputFormat::Json => self.write_json_field("footer", &text),
+            OutputFormat::Html => self.write_html(&html::snippet(text)),
+        }
+    }
+
+    /// Write the `--outline-only` directory tree
+    pub fn write_tree(&mut self, tree: &str) -> std::io::Result<()> {
+        match self.format {
+            OutputFormat::Xml => {
+                self.write_raw(&format!("<tree>\n{}</tree>\n\n", escape_xml(tree)))
+            }
+            OutputFormat::JsonlEvents => self.write_event(&Event::Tree { text: tree }),
+            OutputFormat::Json => self.write_json_field("tree", &tree),
+            OutputFormat::Markdown => self.write_raw(&markdown::tree(tree)),
+            OutputFormat::Html => self.write_html(&html::snippet(tree)),
+            OutputFormat::Messages => {
+                self.conversation.set_tree(tree);
+                Ok(())
+            }
+        }
+    }
+
+    fn write_snippet(&mut self, text: &str) -> std::io::Result<()> {
+        self.write_raw(text)?;
+        self.write_raw(if text.ends_with('\n') { "\n" } else { "\n\n" })
+    }
+
+    /// Start section `name` unless it is already open, closing the previous one
+    pub fn enter_section(&mut self, name: &'static str) -> std::io::Result<()> {
+        if self.section.as_ref().is_some_and(|s| s.name == name) {
+            return Ok(());
+        }
+        self.close_section()?;
+        match self.format {
+            OutputFormat::Xml | OutputFormat::Messages => {
+                self.write_raw(&format!("<section name=\"{}\">\n\n", name))?
+            }
+            OutputFormat::JsonlEvents => self.write_event(&Event::SectionStart { name })?,
+            // Each file names its section instead
+            OutputFormat::Json => {}
+            OutputFormat::Markdown => self.write_raw(&markdown::section_heading(name))?,
+            OutputFormat::Html => self.write_html(&html::section_heading(name))?,
+        }
+        self.section = Some(OpenSection {
+            name,
+            files: 0,
+            tokens: 0,
+        });
         Ok(())
     }
-}
 
-/// Escape XML special characters in strings
-fn escape_xml(s: &str) -> String {
-    s.replace('&', "&amp;")
-        .replace('<', "&lt;")
-        .replace('>', "&gt;")
-        .replace('"', "&quot;")
-        .replace('\'', "&apos;")
+    /// Close the open section, if any, with its subtotal
+    pub fn close_section(&mut self) -> std::io::Result<()> {
+        let Some(OpenSection {
+            name,
+            files,
+            tokens,
+        }) = self.section.take()
+        else {
+            return Ok(());
+        };
+        match self.format {
+            OutputFormat::Xml | OutputFormat::Messages => {
+                // Dry-run listings carry no content, so no token count either
+                let tokens = if tokens > 0 {
+                    format!(" tokens=\"{}\"", tokens)
+                } else {
+                    String::new()
+                };
+                self.write_raw(&format!(
+                    "<subtotal files=\"{}\"{}/>\n</section>\n\n",
+                    files, tokens
+                ))
+            }
+            OutputFormat::JsonlEvents => self.write_event(&Event::SectionEnd {
+                name,
+                files,
+                tokens,
+            }),
+            OutputFormat::Json => Ok(()),
+            OutputFormat::Markdown => self.write_raw(&markdown::section_subtotal(
+                files,
+                &Statistics::format_tokens(tokens),
+            )),
+            OutputFormat::Html => self.write_html(&html::section_subtotal(
+                files,
+                &Statistics::format_tokens(tokens),
+            )),
+        }
+    }
+
+    fn write_raw(&mut self, text: &str) -> std::io::Result<()> {
+        if self.format == OutputFormat::Messages {
+            self.conversation.push_str(text);
+            self.bytes_written += text.len();
+            return Ok(());
+        }
+        self.put(text.as_bytes())?;
+        self.bytes_written += text.len();
+        Ok(())
+    }
+
+    pub fn write_file_path(&mut self, path: &str) -> std::io::Result<()> {
+        self.write_file_listing(path, None)
+    }
+
+    /// Write a dry-run listing line, optionally annotated (e.g. `mode=compressed`)
+    pub fn write_file_listing(
+        &mut self,
+        path: &str,
+        annotation: Option<&str>,
+    ) -> std::io::Result<()> {
+        if let Some(section) = &mut self.section {
+            section.files += 1;
+        }
+
+        if self.format == OutputFormat::JsonlEvents {
+            return self.write_event(&Event::FileListed { path, annotation });
+        }
+        if self.format == OutputFormat::Json {
+            return self.write_json_file(&JsonFile {
+                path,
+                section: self.section_name(),
+                annotation,
+                ..Default::default()
+            });
+        }
+        if self.format == OutputFormat::Html {
+            return self.write_html(&html::listing(path, annotation));
+        }
+        if self.format == OutputFormat::Markdown {
+            return self.write_raw(&markdown::listing(path, annotation));
+        }
+
+        let line = match annotation {
+            Some(a) => format!("{} {}\n", path, a),
+            None => format!("{}\n", path),
+        };
+        self.write_raw(&line)
+    }
 }
 
 #[cfg(test)]
@@ -273,22 +1660,132 @@ mod tests {
         let mut stats = Statistics::new();
         stats.add_included(Some("rs"));
         stats.add_included(Some("toml"));
-        stats.add_skipped(SkipReason::Binary);
+        stats.add_skipped(SkipReason::BinaryExtension);
         stats.add_skipped(SkipReason::Secret);
-        stats.add_skipped(SkipReason::Binary);
+        stats.add_skipped(SkipReason::BinaryContent);
+        stats.add_skipped(SkipReason::BinaryExtension);
 
-        assert_eq!(stats.total_files, 5);
+        assert_eq!(stats.total_files, 6);
         assert_eq!(stats.included_files, 2);
-        assert_eq!(stats.total_skipped(), 3);
+        assert_eq!(stats.total_skipped(), 4);
+        assert!(stats
+            .format_summary()
+            .contains("Skipped: 4 (2 binary-ext, 1 binary-content, 1 secret)\n"));
         assert_eq!(stats.included_by_extension.get("rs"), Some(&1));
         assert_eq!(stats.included_by_extension.get("toml"), Some(&1));
     }
 
     #[test]
-    fn test_escape_xml() {
-        assert_eq!(escape_xml("hello"), "hello");
-        assert_eq!(escape_xml("<tag>"), "&lt;tag&gt;");
-        assert_eq!(escape_xml("a & b"), "a &amp; b");
-        assert_eq!(escape_xml("\"quoted\""), "&quot;quoted&quot;");
+    fn test_directory_map_in_summary() {
+        let mut stats = Statistics::new();
+        stats.add_directory_included("src");
+        stats.add_directory_included("src");
+        stats.add_directory_included("docs");
+        stats.add_directory_budget_exclusion("docs");
+        stats.add_directory_excluded("assets", "binary");
+
+        let summary = stats.format_summary();
+        assert!(
+            summary.contains("Directories: assets ✗ binary | docs ✗ budget | src ✓ (2 files)\n")
+        );
+    }
+
+    #[test]
+    fn test_summary_first_goes_in_after_the_header() {
+        let write = |summary: SummaryPlacement| {
+            let mut buf = Vec::new();
+            {
+                let mut writer = OutputWriter::new(Box::new(&mut buf)).with_summary(summary);
+                writer.write_header("Review this").unwrap();
+                writer.write_file_content("a.rs", "fn a() {}\n").unwrap();
+                writer.write_summary(&Statistics::new()).unwrap();
+                writer.write_footer("Thanks").unwrap();
+                writer.finish().unwrap();
+            }
+            String::from_utf8(buf).unwrap()
+        };
+
+        let first = write(SummaryPlacement::First);
+        let at = |text: &str| first.find(text).unwrap();
+        assert!(at("Review this") < at("<summary>"), "{}", first);
+        assert!(at("</summary>") < at("<file path=\"a.rs\">"), "{}", first);
+        assert!(first.ends_with("Thanks\n\n"), "{}", first);
+
+        let last = write(SummaryPlacement::Last);
+        assert!(last.find("</file>").unwrap() < last.find("<summary>").unwrap());
+        assert!(!write(SummaryPlacement::None).contains("<summary>"));
+    }
+
+    #[test]
+    fn test_file_attrs_vocabulary() {
+        let mut buf = Vec::new();
+        {
+            let mut writer = OutputWriter::new(Box::new(&mut buf));
+            let attrs = FileAttrs {
+                mode: Some(FileMode::Full),
+                truncated: true,
+                ..Default::default()
+            };
+            writer
+                .write_file_content_with_attrs("a.rs", "fn a() {}", attrs)
+                .unwrap();
+            writer.write_excluded("b.rs", "budget").unwrap();
+        }
+        let xml = String::from_utf8(buf).unwrap();
+        assert!(xml.contains("<file path=\"a.rs\" mode=\"full\" truncated=\"true\">"));
+        assert!(xml.contains("<file path=\"b.rs\" excluded-reason=\"budget\"/>"));
+    }
+
+    #[test]
+    fn test_streamed_file_matches_whole_file_in_every_format() {
+        let content = "line <1>\n\"quoted\" é";
+        for format in [
+            OutputFormat::Xml,
+            OutputFormat::JsonlEvents,
+            OutputFormat::Json,
+            OutputFormat::Html,
+            OutputFormat::Markdown,
+        ] {
+            let (mut whole, mut streamed) = (Vec::new(), Vec::new());
+            let attrs = FileAttrs::with_mode(Some(FileMode::Full));
+            OutputWriter::with_format(Box::new(&mut whole), format)
+                .write_file_content_with_attrs("a.rs", content, attrs)
+                .unwrap();
+            let chunks = || {
+                Ok(["line <1>\n\"quo", "ted\" é"]
+                    .map(|c| Ok(c.to_string()))
+                    .into_iter())
+            };
+            OutputWriter::with_format(Box::new(&mut streamed), format)
+                .write_file_stream("a.rs", content.len(), attrs, chunks)
+                .unwrap();
+            assert_eq!(
+                String::from_utf8(streamed).unwrap(),
+                String::from_utf8(whole).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_file_meta_hashes_the_content_as_written() {
+        const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
+        let (mut whole, mut streamed) = (Vec::new(), Vec::new());
+        OutputWriter::new(Box::new(&mut whole))
+            .with_file_meta(true)
+            .write_file_content("a.txt", "abc")
+            .unwrap();
+        let chunks = || Ok(["a", "bc"].map(|c| Ok(c.to_string())).into_iter());
+        OutputWriter::new(Box::new(&mut streamed))
+            .with_file_meta(true)
+            .write_file_stream("a.txt", 3, FileAttrs::default(), chunks)
+            .unwrap();
+        let xml = String::from_utf8(whole).unwrap();
+        assert!(xml.starts_with("<file path=\"a.txt\" tokens=\""), "{}", xml);
+        assert!(
+            xml.contains(&format!(" bytes=\"3\" sha256=\"{}\">\n", ABC_SHA256)),
+            "{}",
+            xml
+        );
+        assert_eq!(String::from_utf8(streamed).unwrap(), xml);
     }
 }
@@ -0,0 +1,148 @@
+use anyhow::{bail, Context, Result};
+use globset::{Glob, GlobMatcher};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the per-file decision overrides file looked up at the scanned root
+pub const OVERRIDES_FILE_NAME: &str = "flat.overrides";
+
+/// What to do with a file, overriding the decision flat would otherwise make
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverrideAction {
+    /// Compress, even without `--compress` or when `--full-match` would keep it full
+    Compress,
+    /// Never compress
+    Full,
+    /// Leave out entirely
+    Skip,
+}
+
+/// Glob → decision rules from a `flat.overrides` file, in file order.
+///
+/// ```toml
+/// 'src/legacy/**' = "compress"
+/// 'docs/adr/**' = "full"
+/// '**/*.snap' = "skip"
+/// ```
+///
+/// When several globs match a file, the last one wins, as in `.gitignore`.
+#[derive(Debug, Clone, Default)]
+pub struct Overrides {
+    rules: Vec<(GlobMatcher, OverrideAction)>,
+}
+
+impl Overrides {
+    /// The decision of the last rule whose glob matches, using `matches` to test each glob
+    pub fn action_for(&self, matches: impl Fn(&GlobMatcher) -> bool) -> Option<OverrideAction> {
+        self.rules
+            .iter()
+            .rev()
+            .find(|(glob, _)| matches(glob))
+            .map(|(_, action)| *action)
+    }
+}
+
+/// How many lines from the top of a file a `flat:` pragma may sit
+pub const PRAGMA_LINES: usize = 20;
+
+/// Comment openers a pragma may follow
+const COMMENT_MARKERS: &[&str] = &["//", "/*", "<!--", "#", "--", ";", "%", "(*", "{-", "*"];
+
+/// The decision a file asks for itself with a comment near its top:
+/// `// flat:full`, `# flat:skip`, `<!-- flat:compress -->`. The directive has to
+/// open the comment, so prose that mentions one isn't taken for it.
+pub fn pragma(text: &str) -> Option<OverrideAction> {
+    text.lines().take(PRAGMA_LINES).find_map(|line| {
+        let line = line.trim_start();
+        let comment = COMMENT_MARKERS
+            .iter()
+            .find_map(|marker| line.strip_prefix(marker))?;
+        let directive = comment.trim_start().strip_prefix("flat:")?;
+        let end = directive
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '_')
+            .unwrap_or(directive.len());
+        match &directive[..end] {
+            "compress" => Some(OverrideAction::Compress),
+            "full" => Some(OverrideAction::Full),
+            "skip" => Some(OverrideAction::Skip),
+            _ => None,
+        }
+    })
+}
+
+/// Find the overrides file for a scan rooted at `root`, if one exists
+pub fn discover(root: &Path) -> Option<PathBuf> {
+    let dir = if root.is_file() { root.parent()? } else { root };
+    let candidate = dir.join(OVERRIDES_FILE_NAME);
+    candidate.is_file().then_some(candidate)
+}
+
+/// Read and parse an overrides file
+pub fn load(path: &Path) -> Result<Overrides> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read overrides file: {}", path.display()))?;
+    parse(&text).with_context(|| format!("Invalid overrides file: {}", path.display()))
+}
+
+fn parse(text: &str) -> Result<Overrides> {
+    let table: toml::Table = toml::from_str(text)?;
+    let mut rules = Vec::new();
+    for (pattern, value) in table {
+        let action = match value.as_str() {
+            Some("compress") => OverrideAction::Compress,
+            Some("full") => OverrideAction::Full,
+            Some("skip") => OverrideAction::Skip,
+            _ => bail!(
+                "'{}' must be \"compress\", \"full\", or \"skip\", got {}",
+                pattern,
+                value
+            ),
+        };
+        let glob = Glob::new(&pattern)
+            .with_context(|| format!("Invalid glob '{}'", pattern))?
+            .compile_matcher();
+        rules.push((glob, action));
+    }
+    Ok(Overrides { rules })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pragma_opens_a_comment_near_the_top() {
+        assert_eq!(
+            pragma("// flat:full\nfn main() {}\n"),
+            Some(OverrideAction::Full)
+        );
+        assert_eq!(
+            pragma("#!/usr/bin/env python\n#  flat:skip  (generated)\n"),
+            Some(OverrideAction::Skip)
+        );
+        assert_eq!(
+            pragma("<!-- flat:compress -->\n# Notes\n"),
+            Some(OverrideAction::Compress)
+        );
+        // Mentioned, not declared
+        assert_eq!(pragma("// mark it with flat:full\n"), None);
+        assert_eq!(pragma("// flat:fullest\n"), None);
+        let late = format!("{}// flat:full\n", "\n".repeat(PRAGMA_LINES));
+        assert_eq!(pragma(&late), None);
+    }
+
+    #[test]
+    fn test_parse_keeps_file_order_and_last_match_wins() {
+        let overrides = parse("'src/**' = \"compress\"\n'src/api.rs' = \"full\"\n").unwrap();
+        let lookup = |path: &str| overrides.action_for(|g| g.is_match(path));
+        assert_eq!(lookup("src/api.rs"), Some(OverrideAction::Full));
+        assert_eq!(lookup("src/db.rs"), Some(OverrideAction::Compress));
+        assert_eq!(lookup("README.md"), None);
+    }
+
+    #[test]
+    fn test_parse_rejects_unknown_action() {
+        let err = parse("'*.snap' = \"drop\"").unwrap_err();
+        assert!(err.to_string().contains("must be"));
+    }
+}
@@ -1,3 +1,8 @@
+use crate::config::{BudgetShare, LineRange};
+use crate::tokens::{Model, MODELS};
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+
 /// Parse a human-friendly number with decimal (SI) suffixes.
 ///
 /// - `k` / `K` = ×1,000
@@ -54,6 +59,105 @@ pub fn parse_binary_number(input: &str) -> Result<u64, String> {
         .ok_or_else(|| format!("number too large: '{input}'"))
 }
 
+/// Parse a share of the token budget: `15%` or an absolute count like `2k`.
+pub fn parse_budget_share(input: &str) -> Result<BudgetShare, String> {
+    let input = input.trim();
+    match input.strip_suffix('%') {
+        Some(pct) => {
+            let pct: f64 = pct
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid percentage: '{input}'"))?;
+            if !(0.0..=100.0).contains(&pct) {
+                return Err(format!("percentage must be between 0 and 100: '{input}'"));
+            }
+            Ok(BudgetShare::Percent(pct))
+        }
+        None => parse_decimal_number(input).map(BudgetShare::Tokens),
+    }
+}
+
+/// Parse a `--model` name, listing the known ones when it isn't one of them
+pub fn parse_model(input: &str) -> Result<Model, String> {
+    Model::find(input).ok_or_else(|| {
+        let names: Vec<&str> = MODELS.iter().map(|model| model.name).collect();
+        format!(
+            "unknown model '{}' (known: {}); use --tokens N for others",
+            input.trim(),
+            names.join(", ")
+        )
+    })
+}
+
+/// Parse a duration in seconds, fractional allowed (`0.5`, `30`), for timeouts
+pub fn parse_seconds(input: &str) -> Result<Duration, String> {
+    let input = input.trim();
+    let secs: f64 = input
+        .strip_suffix('s')
+        .unwrap_or(input)
+        .parse()
+        .map_err(|_| format!("invalid number of seconds: '{input}'"))?;
+    Duration::try_from_secs_f64(secs).map_err(|_| format!("invalid number of seconds: '{input}'"))
+}
+
+/// Parse an `--attach NAME=PATH` artifact, splitting at the first `=`. NAME is
+/// the relative path the artifact is written under.
+pub fn parse_attachment(input: &str) -> Result<(String, PathBuf), String> {
+    let Some((name, path)) = input.split_once('=') else {
+        return Err("expected NAME=PATH, e.g. test-output.log=target/test.log".to_string());
+    };
+    let (name, path) = (name.trim(), path.trim());
+    if name.is_empty() || path.is_empty() {
+        return Err("expected NAME=PATH, e.g. test-output.log=target/test.log".to_string());
+    }
+    let plain = Path::new(name)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if !plain {
+        return Err(format!(
+            "the name '{}' must be a relative path without '.' or '..'",
+            name
+        ));
+    }
+    Ok((name.to_string(), PathBuf::from(path)))
+}
+
+/// Parse a `--quota DIR=SHARE` cap, splitting at the last `=`: `src/=60%` or
+/// `tests=5k`. DIR is relative to the scanned root; a trailing `/` is dropped.
+pub fn parse_directory_quota(input: &str) -> Result<(PathBuf, BudgetShare), String> {
+    let Some((dir, share)) = input.rsplit_once('=') else {
+        return Err("expected DIR=SHARE, e.g. src/=60%".to_string());
+    };
+    let dir = dir.trim().trim_end_matches('/');
+    if dir.is_empty() {
+        return Err("expected DIR=SHARE, e.g. src/=60%".to_string());
+    }
+    let plain = Path::new(dir)
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)));
+    if !plain {
+        return Err(format!(
+            "the directory '{}' must be a relative path without '.' or '..'",
+            dir
+        ));
+    }
+    Ok((PathBuf::from(dir), parse_budget_share(share)?))
+}
+
+/// Split a trailing line range off a path or glob: `src/main.rs:100-400`, or
+/// `src/main.rs:42` for one line. Returns `None` when there's no valid range,
+/// so a path that merely contains a colon is left alone.
+pub fn split_line_range(spec: &str) -> Option<(&str, LineRange)> {
+    let (path, range) = spec.rsplit_once(':')?;
+    let (start, end) = range.split_once('-').unwrap_or((range, range));
+    let start: usize = start.trim().parse().ok()?;
+    let end: usize = end.trim().parse().ok()?;
+    if path.is_empty() || start == 0 || end < start {
+        return None;
+    }
+    Some((path, LineRange { start, end }))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -204,4 +308,89 @@ mod tests {
         let huge = format!("{}G", u64::MAX);
         assert!(parse_binary_number(&huge).is_err());
     }
+
+    // ── Budget shares ────────────────────────────────────────────────
+
+    #[test]
+    fn budget_share_percent_and_tokens() {
+        assert_eq!(
+            parse_budget_share("10%").unwrap(),
+            BudgetShare::Percent(10.0)
+        );
+        assert_eq!(
+            parse_budget_share("2k").unwrap(),
+            BudgetShare::Tokens(2_000)
+        );
+        assert!(parse_budget_share("150%").is_err());
+        assert!(parse_budget_share("ten%").is_err());
+    }
+
+    #[test]
+    fn directory_quota_dir_and_share() {
+        assert_eq!(
+            parse_directory_quota("src/=60%").unwrap(),
+            (PathBuf::from("src"), BudgetShare::Percent(60.0))
+        );
+        assert_eq!(
+            parse_directory_quota("crates/core=5k").unwrap(),
+            (PathBuf::from("crates/core"), BudgetShare::Tokens(5_000))
+        );
+        assert!(parse_directory_quota("src").is_err());
+        assert!(parse_directory_quota("../src=10%").is_err());
+        assert!(parse_directory_quota("/=10%").is_err());
+    }
+
+    // ── Seconds ──────────────────────────────────────────────────────
+
+    #[test]
+    fn seconds_plain_fractional_and_suffixed() {
+        assert_eq!(parse_seconds("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_seconds("0.5").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_seconds("2s").unwrap(), Duration::from_secs(2));
+        assert!(parse_seconds("-1").is_err());
+        assert!(parse_seconds("soon").is_err());
+    }
+
+    // ── Attachments ──────────────────────────────────────────────────
+
+    #[test]
+    fn attachment_name_and_path() {
+        assert_eq!(
+            parse_attachment("logs/test.log=target/out=1.log").unwrap(),
+            (
+                "logs/test.log".to_string(),
+                PathBuf::from("target/out=1.log")
+            )
+        );
+        assert!(parse_attachment("test.log").is_err());
+        assert!(parse_attachment("=out.log").is_err());
+        assert!(parse_attachment("../up.log=out.log").is_err());
+        assert!(parse_attachment("/abs.log=out.log").is_err());
+    }
+
+    // ── Line ranges ──────────────────────────────────────────────────
+
+    #[test]
+    fn line_range_split_from_path() {
+        let range = |start, end| LineRange { start, end };
+        assert_eq!(
+            split_line_range("src/big.rs:100-400"),
+            Some(("src/big.rs", range(100, 400)))
+        );
+        assert_eq!(split_line_range("a.rs:42"), Some(("a.rs", range(42, 42))));
+        assert_eq!(split_line_range("a.rs:0-3"), None);
+        assert_eq!(split_line_range("a.rs:9-3"), None);
+        assert_eq!(split_line_range("C:/src/a.rs"), None);
+        assert_eq!(split_line_range("a.rs"), None);
+    }
+
+    #[test]
+    fn line_range_slice_keeps_line_endings() {
+        let text = "one\ntwo\nthree\nfour";
+        let slice = |start, end| LineRange { start, end }.slice(text);
+        assert_eq!(slice(2, 3), "two\nthree\n");
+        assert_eq!(slice(1, 1), "one\n");
+        assert_eq!(slice(3, 99), "three\nfour");
+        assert_eq!(slice(7, 9), "");
+    }
 }
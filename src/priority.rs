@@ -1,41 +1,183 @@
+use crate::tokens::is_prose_extension;
+use anyhow::{bail, Context, Result};
+use globset::{Glob, GlobMatcher};
+use serde::Serialize;
 use std::path::Path;
 
-/// Score a file for priority ordering in token budget allocation.
+/// Score given to files referenced from README/docs links under `--follow-doc-links`:
+/// just below a README, above entry points
+pub const DOC_LINK_SCORE: u32 = 95;
+
+/// Score given under `--related FILE` to that file and to the files its import
+/// lines name: just below a README, above doc links
+pub const RELATED_SCORE: u32 = 96;
+
+/// Score under `--entry FILE` of that file: below doc links, above entry points
+pub const ENTRY_SCORE: u32 = 94;
+
+/// Lowest score `--entry` gives a file it reaches, just above any source file
+/// scored on its own, so reachable code goes before unrelated modules
+pub const ENTRY_REACHABLE_SCORE: u32 = 71;
+
+/// Score under `--entry` of a file `distance` import hops from the entry file:
+/// [`ENTRY_SCORE`], less 2 per hop, down to [`ENTRY_REACHABLE_SCORE`]
+pub fn entry_score(distance: usize) -> u32 {
+    let penalty = u32::try_from(distance).map_or(u32::MAX, |hops| hops.saturating_mul(2));
+    ENTRY_SCORE
+        .saturating_sub(penalty)
+        .max(ENTRY_REACHABLE_SCORE)
+}
+
+/// Score of an `--attach` artifact: asked for by name, so just below a README
+pub const ATTACHMENT_SCORE: u32 = 97;
+
+/// Score of docs and design docs under `--demote-docs`: below tests, above fixtures
+pub const DEMOTED_DOCS_SCORE: u32 = 20;
+
+/// Highest score a `--priority-boost` or `--priority-drop` rule can name, a README's
+pub const MAX_SCORE: u32 = 100;
+
+/// Parse a `GLOB=SCORE` rule for `--priority-boost` / `--priority-drop`, splitting
+/// at the last `=` so the glob itself may contain one
+pub fn parse_priority_rule(rule: &str) -> Result<(GlobMatcher, u32)> {
+    let Some((glob, score)) = rule.rsplit_once('=') else {
+        bail!("expected GLOB=SCORE, e.g. 'src/core/**=95'");
+    };
+    let score: u32 = score
+        .trim()
+        .parse()
+        .with_context(|| format!("score '{}' is not a whole number", score.trim()))?;
+    if score > MAX_SCORE {
+        bail!("score {} is above the maximum of {}", score, MAX_SCORE);
+    }
+    let glob = Glob::new(glob.trim()).with_context(|| format!("invalid glob '{}'", glob))?;
+    Ok((glob.compile_matcher(), score))
+}
+
+/// What part a file plays in a project, as far as budget allocation cares
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Role {
+    Readme,
+    EntryPoint,
+    Config,
+    /// Architecture and design docs, and architecture decision records
+    Design,
+    Docs,
+    Source,
+    Test,
+    Fixture,
+}
+
+/// Output sections for `--layout sections`, in the order they are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Section {
+    Project,
+    Configuration,
+    Source,
+    Tests,
+    Docs,
+}
+
+impl Section {
+    pub fn title(self) -> &'static str {
+        match self {
+            Section::Project => "Project metadata",
+            Section::Configuration => "Configuration",
+            Section::Source => "Source",
+            Section::Tests => "Tests",
+            Section::Docs => "Docs",
+        }
+    }
+}
+
+impl Role {
+    /// Name as written in output attributes and JSON
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Readme => "readme",
+            Role::EntryPoint => "entry-point",
+            Role::Config => "config",
+            Role::Design => "design",
+            Role::Docs => "docs",
+            Role::Source => "source",
+            Role::Test => "test",
+            Role::Fixture => "fixture",
+        }
+    }
+
+    /// The `--layout sections` section this role is written under
+    pub fn section(self) -> Section {
+        match self {
+            Role::Readme => Section::Project,
+            Role::Config => Section::Configuration,
+            Role::EntryPoint | Role::Source => Section::Source,
+            Role::Test | Role::Fixture => Section::Tests,
+            Role::Design | Role::Docs => Section::Docs,
+        }
+    }
+}
+
+/// Classify a file by its path relative to `base_path`.
 ///
-/// Higher scores = higher priority (included first in budget).
-/// Per PDR spec:
-/// - READMEs: 100
-/// - Entry points (main.*, index.*, app.*): 90
-/// - Config files: 80
-/// - Source code: 70 - (depth * 10), min 10
-/// - Tests: 30
-/// - Fixtures/generated: 5
-pub fn score_file(path: &Path, base_path: &Path) -> u32 {
+/// Categories are checked in order, so a README under `tests/fixtures/` is a
+/// fixture and a markdown file under `tests/` is a test. Design docs come
+/// before tests, so `docs/adr/0004-test-strategy.md` is still a design doc.
+pub fn classify(path: &Path, base_path: &Path) -> Role {
     let file_name = path
         .file_name()
         .map(|f| f.to_string_lossy().to_lowercase())
         .unwrap_or_default();
 
     let relative = path.strip_prefix(base_path).unwrap_or(path);
-    let depth = relative.components().count().saturating_sub(1); // depth of file, not dir
-
     let path_str = relative.to_string_lossy().to_lowercase();
 
-    // Check categories in priority order (highest score wins)
     if is_fixture(&path_str) {
-        5
+        Role::Fixture
+    } else if is_design(relative, &file_name) {
+        Role::Design
     } else if is_test(&path_str, &file_name) {
-        30
+        Role::Test
     } else if is_readme(&file_name) {
-        100
+        Role::Readme
     } else if is_entry_point(&file_name) {
-        90
+        Role::EntryPoint
     } else if is_config(&file_name) {
-        80
+        Role::Config
+    } else if is_docs(relative, &file_name) {
+        Role::Docs
     } else {
-        // Source code with depth penalty
-        let score = 70u32.saturating_sub((depth as u32) * 10);
-        score.max(10)
+        Role::Source
+    }
+}
+
+/// Score a file for priority ordering in token budget allocation.
+///
+/// Higher scores = higher priority (included first in budget).
+/// Per PDR spec:
+/// - READMEs: 100
+/// - Entry points (main.*, index.*, app.*): 90
+/// - Design docs (DESIGN.md, ARCHITECTURE.md, docs/adr/*): 85
+/// - Config files: 80
+/// - Source code and docs: 70 - (depth * 10), min 10
+/// - Tests: 30
+/// - Fixtures/generated: 5
+pub fn score_file(path: &Path, base_path: &Path) -> u32 {
+    let relative = path.strip_prefix(base_path).unwrap_or(path);
+    let depth = relative.components().count().saturating_sub(1); // depth of file, not dir
+
+    match classify(path, base_path) {
+        Role::Fixture => 5,
+        Role::Test => 30,
+        Role::Readme => 100,
+        Role::EntryPoint => 90,
+        Role::Design => 85,
+        Role::Config => 80,
+        Role::Docs | Role::Source => {
+            // Depth penalty
+            let score = 70u32.saturating_sub((depth as u32) * 10);
+            score.max(10)
+        }
     }
 }
 
@@ -74,6 +216,28 @@ fn is_config(file_name: &str) -> bool {
         || file_name.ends_with(".json") && !file_name.contains("test")
 }
 
+fn is_design(relative: &Path, file_name: &str) -> bool {
+    let (stem, ext) = file_name.rsplit_once('.').unwrap_or((file_name, ""));
+    if !is_prose_extension(ext) {
+        return false;
+    }
+    // `docs/adr/`, `doc/adr/`, or a top-level `adr/`
+    let in_adr_dir = relative.parent().is_some_and(|dir| {
+        dir.components()
+            .any(|c| matches!(c.as_os_str().to_str(), Some("adr" | "adrs")))
+    });
+    in_adr_dir || matches!(stem, "design" | "architecture")
+}
+
+fn is_docs(relative: &Path, file_name: &str) -> bool {
+    let in_docs_dir = relative
+        .components()
+        .next()
+        .is_some_and(|c| matches!(c.as_os_str().to_str(), Some("docs" | "doc")));
+    let ext = file_name.rsplit_once('.').map(|(_, e)| e).unwrap_or("");
+    in_docs_dir || is_prose_extension(ext)
+}
+
 fn is_test(path_str: &str, file_name: &str) -> bool {
     path_str.contains("test")
         || path_str.contains("spec")
@@ -99,6 +263,19 @@ mod tests {
         score_file(Path::new(path), Path::new("/project"))
     }
 
+    #[test]
+    fn test_parse_priority_rule() {
+        let (glob, score) = parse_priority_rule("src/core/**=95").unwrap();
+        assert_eq!(glob.glob().glob(), "src/core/**");
+        assert_eq!(score, 95);
+        let (glob, _) = parse_priority_rule("**/a=b.rs=10").unwrap();
+        assert_eq!(glob.glob().glob(), "**/a=b.rs");
+
+        assert!(parse_priority_rule("src/**").is_err());
+        assert!(parse_priority_rule("src/**=high").is_err());
+        assert!(parse_priority_rule("src/**=101").is_err());
+    }
+
     #[test]
     fn test_readme_highest() {
         assert_eq!(score("/project/README.md"), 100);
@@ -112,6 +289,12 @@ mod tests {
         assert_eq!(score("/project/src/lib.rs"), 90);
     }
 
+    #[test]
+    fn test_design_docs_between_entry_points_and_config() {
+        assert_eq!(score("/project/DESIGN.md"), 85);
+        assert_eq!(score("/project/docs/adr/0001-use-tree-sitter.md"), 85);
+    }
+
     #[test]
     fn test_config_files() {
         assert_eq!(score("/project/Cargo.toml"), 80);
@@ -148,6 +331,24 @@ mod tests {
         assert_eq!(score("/project/tests/fixtures/README.md"), 5);
     }
 
+    #[test]
+    fn test_classify_roles() {
+        let role = |path: &str| classify(Path::new(path), Path::new("/project"));
+        assert_eq!(role("/project/README.md"), Role::Readme);
+        assert_eq!(role("/project/docs/guide.md"), Role::Docs);
+        assert_eq!(role("/project/docs/diagram.dot"), Role::Docs);
+        assert_eq!(role("/project/CHANGELOG.md"), Role::Docs);
+        assert_eq!(role("/project/src/utils.rs"), Role::Source);
+        assert_eq!(role("/project/tests/notes.md"), Role::Test);
+        assert_eq!(role("/project/ARCHITECTURE.md"), Role::Design);
+        assert_eq!(role("/project/docs/design.md"), Role::Design);
+        assert_eq!(
+            role("/project/docs/adr/0004-test-strategy.md"),
+            Role::Design
+        );
+        assert_eq!(role("/project/src/design.rs"), Role::Source);
+    }
+
     #[test]
     fn test_sorting_order() {
         let base = PathBuf::from("/project");
@@ -0,0 +1,216 @@
+//! `.flat.toml` directory profiles: rules for one part of the tree, such as a
+//! `docs/.flat.toml` that compresses everything under `docs/`, or a team's
+//! package leaving out its fixtures. As with `.editorconfig`, a profile applies
+//! to its directory and everything below it, a nearer profile's keys win over a
+//! farther one's, and `root = true` stops inheriting from the profiles above.
+//!
+//! ```toml
+//! compress = true                  # compress here, with or without --compress
+//! include = ["md", "rst"]          # only these extensions here
+//! exclude = ["snap"]
+//! exclude-match = ["fixtures/**"]  # relative to this directory
+//! full-match = ["README.md"]
+//! max-size = "100k"
+//! ```
+//!
+//! Filters only ever tighten the command line's: a file must pass both.
+//! `exclude-match` and `full-match` globs add up down the tree; every other key
+//! is replaced by a nearer profile's.
+
+use crate::config::NO_EXTENSION;
+use crate::config_file::binary_number;
+use anyhow::{Context, Result};
+use globset::{Glob, GlobMatcher};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of a directory profile, looked up in every walked directory
+pub const PROFILE_FILE_NAME: &str = ".flat.toml";
+
+/// One `.flat.toml` as written
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ProfileFile {
+    #[serde(default)]
+    root: bool,
+    compress: Option<bool>,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
+    #[serde(default)]
+    exclude_match: Vec<String>,
+    #[serde(default)]
+    full_match: Vec<String>,
+    #[serde(default, deserialize_with = "binary_number")]
+    max_size: Option<u64>,
+}
+
+/// The rules in force in one directory: its own profile over its ancestors'
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// Compress (or don't) regardless of `--compress`
+    pub compress: Option<bool>,
+    /// Only these extensions; [`NO_EXTENSION`] for files without one
+    pub include: Option<Vec<String>>,
+    pub exclude: Option<Vec<String>>,
+    /// Files over this many bytes are skipped, if it is below `--max-size`
+    pub max_size: Option<u64>,
+    /// Globs skipping files, each with the directory it is relative to
+    pub exclude_match: Vec<(PathBuf, GlobMatcher)>,
+    /// Globs keeping files uncompressed, each with the directory it is relative to
+    pub full_match: Vec<(PathBuf, GlobMatcher)>,
+}
+
+impl Profile {
+    /// Whether `path`'s extension passes this profile's `include` and `exclude`
+    pub fn allows_extension(&self, path: &Path) -> bool {
+        let ext = path.extension().map(|e| e.to_string_lossy());
+        let ext = ext.as_deref().unwrap_or(NO_EXTENSION);
+        let named = |list: &[String]| list.iter().any(|e| e.eq_ignore_ascii_case(ext));
+        self.include.as_deref().is_none_or(named) && !self.exclude.as_deref().is_some_and(named)
+    }
+
+    pub fn excludes(&self, path: &Path) -> bool {
+        any_matches(&self.exclude_match, path)
+    }
+
+    pub fn keeps_full(&self, path: &Path) -> bool {
+        any_matches(&self.full_match, path)
+    }
+
+    /// `file`, read from `dir`, applied over this profile
+    fn extended(&self, dir: &Path, file: ProfileFile) -> Result<Profile> {
+        let mut profile = if file.root {
+            Profile::default()
+        } else {
+            self.clone()
+        };
+        profile.compress = file.compress.or(profile.compress);
+        profile.include = file.include.or(profile.include);
+        profile.exclude = file.exclude.or(profile.exclude);
+        profile.max_size = file.max_size.or(profile.max_size);
+        for (globs, patterns) in [
+            (&mut profile.exclude_match, file.exclude_match),
+            (&mut profile.full_match, file.full_match),
+        ] {
+            for pattern in patterns {
+                let matcher = Glob::new(&pattern)
+                    .with_context(|| format!("Invalid glob '{}'", pattern))?
+                    .compile_matcher();
+                globs.push((dir.to_path_buf(), matcher));
+            }
+        }
+        Ok(profile)
+    }
+}
+
+/// A glob with a `/` matches the path relative to its directory, any other the
+/// file name, as `--match` globs do
+fn any_matches(globs: &[(PathBuf, GlobMatcher)], path: &Path) -> bool {
+    globs.iter().any(|(dir, matcher)| {
+        if matcher.glob().glob().contains('/') {
+            path.strip_prefix(dir)
+                .is_ok_and(|relative| matcher.is_match(relative))
+        } else {
+            path.file_name().is_some_and(|name| matcher.is_match(name))
+        }
+    })
+}
+
+/// Every directory profile under a scanned root, resolved
+#[derive(Debug, Clone, Default)]
+pub struct Profiles {
+    dirs: HashMap<PathBuf, Profile>,
+}
+
+impl Profiles {
+    /// Load the `.flat.toml` in each of `dirs` that has one, each over the
+    /// profile of its nearest ancestor
+    pub fn load(dirs: impl IntoIterator<Item = PathBuf>) -> Result<Profiles> {
+        let mut found: Vec<PathBuf> = dirs
+            .into_iter()
+            .filter(|dir| dir.join(PROFILE_FILE_NAME).is_file())
+            .collect();
+        // Parents first, so every ancestor is resolved before its descendants
+        found.sort_by_key(|dir| dir.components().count());
+
+        let mut profiles = Profiles::default();
+        for dir in found {
+            let file = dir.join(PROFILE_FILE_NAME);
+            let text = fs::read_to_string(&file)
+                .with_context(|| format!("Failed to read profile: {}", file.display()))?;
+            let parsed: ProfileFile = toml::from_str(&text)
+                .with_context(|| format!("Invalid profile: {}", file.display()))?;
+            let inherited = profiles.for_dir(&dir).cloned().unwrap_or_default();
+            let profile = inherited
+                .extended(&dir, parsed)
+                .with_context(|| format!("Invalid profile: {}", file.display()))?;
+            profiles.dirs.insert(dir, profile);
+        }
+        Ok(profiles)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty()
+    }
+
+    /// The profile in force for a file at `path`
+    pub fn for_file(&self, path: &Path) -> Option<&Profile> {
+        self.for_dir(path.parent()?)
+    }
+
+    fn for_dir(&self, dir: &Path) -> Option<&Profile> {
+        if self.dirs.is_empty() {
+            return None;
+        }
+        dir.ancestors().find_map(|ancestor| self.dirs.get(ancestor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_nearer_profiles_override_and_globs_add_up() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        let docs = root.join("docs");
+        let api = docs.join("api");
+        let vendor = root.join("vendor");
+        for d in [&api, &vendor] {
+            fs::create_dir_all(d).unwrap();
+        }
+        let write = |d: &Path, text: &str| fs::write(d.join(PROFILE_FILE_NAME), text).unwrap();
+        write(&root, "exclude-match = [\"*.snap\"]\n");
+        write(
+            &docs,
+            "compress = true\ninclude = [\"md\"]\nexclude-match = [\"drafts/**\"]\n",
+        );
+        write(&api, "include = [\"md\", \"yaml\"]\n");
+        write(&vendor, "root = true\nmax-size = \"1k\"\n");
+
+        let profiles =
+            Profiles::load([root.clone(), docs.clone(), api.clone(), vendor.clone()]).unwrap();
+
+        let spec = profiles.for_file(&api.join("spec.yaml")).unwrap();
+        assert_eq!(spec.compress, Some(true));
+        assert!(spec.allows_extension(&api.join("spec.yaml")));
+        assert!(spec.excludes(&api.join("x.snap")));
+        assert!(!spec.excludes(&api.join("drafts/x.md")));
+        assert!(spec.excludes(&docs.join("drafts/x.md")));
+
+        let guide = profiles.for_file(&docs.join("guide/intro.rs")).unwrap();
+        assert!(!guide.allows_extension(&docs.join("guide/intro.rs")));
+
+        let vendored = profiles.for_file(&vendor.join("lib/a.snap")).unwrap();
+        assert_eq!(vendored.max_size, Some(1024));
+        assert!(!vendored.excludes(&vendor.join("lib/a.snap")));
+
+        assert!(Profiles::load([root.join("missing")]).unwrap().is_empty());
+        write(&root, "compres = true\n");
+        assert!(Profiles::load([root]).is_err());
+    }
+}
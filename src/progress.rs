@@ -0,0 +1,195 @@
+//! Progress reporting and cancellation for embedders: a [`FlattenHandle`] runs
+//! a flatten while reporting each file to a callback, and can be cancelled from
+//! another thread.
+
+use crate::config::Config;
+use crate::output::Statistics;
+use anyhow::Result;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// The stage a run is in, in the order they happen
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Walking the tree and filtering files; the total isn't known yet
+    #[default]
+    Walking,
+    /// Under `--tokens`: reading and scoring every candidate
+    Reading,
+    /// Under `--tokens`: deciding what fits, compressing as needed
+    Allocating,
+    /// Compressing and writing each file (tallying it, under `--stats` or `--dry-run`)
+    Writing,
+}
+
+/// Where a run is, as passed to the progress callback
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub phase: Phase,
+    /// Files reached so far in this phase, counting the one in progress
+    pub files: usize,
+    /// Files this phase will go through, once known
+    pub total: Option<usize>,
+    /// Bytes of file content reached so far in this phase
+    pub bytes: u64,
+}
+
+/// Cancels a run from any thread; clones share the same flag
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The error a cancelled run returns; check for it with `err.is::<Cancelled>()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("flatten cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+type Callback = Arc<dyn Fn(&Progress) + Send + Sync>;
+
+/// Runs a flatten with a progress callback and a cancellation token.
+///
+/// A cancelled run stops at the next file (or inside a parse) and returns
+/// [`Cancelled`]; an `-o` destination is left as it was.
+#[derive(Clone, Default)]
+pub struct FlattenHandle {
+    cancel: CancelToken,
+    callback: Option<Callback>,
+}
+
+impl fmt::Debug for FlattenHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FlattenHandle")
+            .field("cancel", &self.cancel)
+            .field("callback", &self.callback.is_some())
+            .finish()
+    }
+}
+
+impl FlattenHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call `callback` as the run enters each phase and reaches each file.
+    /// It runs on the flattening thread, so it should return quickly.
+    pub fn on_progress(mut self, callback: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        self.callback = Some(Arc::new(callback));
+        self
+    }
+
+    /// A token that cancels this handle's runs, to hand to another thread
+    pub fn cancel_token(&self) -> CancelToken {
+        self.cancel.clone()
+    }
+
+    pub fn cancel(&self) {
+        self.cancel.cancel();
+    }
+
+    /// [`walk_and_flatten`](crate::walk_and_flatten), reporting to this handle
+    pub fn flatten(&self, config: &Config) -> Result<Statistics> {
+        crate::walker::walk_and_flatten_with(config, ProgressTracker::new(self.clone()))
+    }
+}
+
+/// A run's progress so far, kept on [`Statistics`] alongside its warnings
+#[derive(Debug, Default)]
+pub struct ProgressTracker {
+    handle: FlattenHandle,
+    progress: Progress,
+}
+
+impl ProgressTracker {
+    pub fn new(handle: FlattenHandle) -> Self {
+        Self {
+            handle,
+            progress: Progress::default(),
+        }
+    }
+
+    /// Enter `phase`, which will go through `total` files if known
+    pub fn start(&mut self, phase: Phase, total: Option<usize>) -> Result<()> {
+        self.progress = Progress {
+            phase,
+            files: 0,
+            total,
+            bytes: 0,
+        };
+        self.report()
+    }
+
+    /// Count one more file of `bytes` in the current phase
+    pub fn advance(&mut self, bytes: u64) -> Result<()> {
+        self.progress.files += 1;
+        self.progress.bytes += bytes;
+        self.report()
+    }
+
+    /// Fail with [`Cancelled`] if the run was cancelled
+    pub fn check(&self) -> Result<()> {
+        if self.handle.cancel.is_cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+
+    pub fn cancel_token(&self) -> &CancelToken {
+        &self.handle.cancel
+    }
+
+    fn report(&self) -> Result<()> {
+        self.check()?;
+        if let Some(callback) = &self.handle.callback {
+            callback(&self.progress);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_tracker_reports_phases_and_stops_when_cancelled() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&seen);
+        let handle = FlattenHandle::new().on_progress(move |p| log.lock().unwrap().push(*p));
+        let mut tracker = ProgressTracker::new(handle.clone());
+
+        tracker.start(Phase::Writing, Some(2)).unwrap();
+        tracker.advance(10).unwrap();
+        assert_eq!(
+            seen.lock().unwrap().last(),
+            Some(&Progress {
+                phase: Phase::Writing,
+                files: 1,
+                total: Some(2),
+                bytes: 10,
+            })
+        );
+
+        handle.cancel_token().cancel();
+        let err = tracker.advance(5).unwrap_err();
+        assert!(err.is::<Cancelled>());
+        assert_eq!(seen.lock().unwrap().len(), 2);
+    }
+}
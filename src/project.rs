@@ -0,0 +1,109 @@
+use std::path::Path;
+
+/// Ecosystem of the scanned project, detected from its manifests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProjectType {
+    Rust,
+    Node,
+    Python,
+    Go,
+    /// More than one ecosystem's manifest at the root
+    Mixed,
+}
+
+/// Manifest files that identify each ecosystem
+const MANIFESTS: &[(ProjectType, &[&str])] = &[
+    (ProjectType::Rust, &["Cargo.toml"]),
+    (ProjectType::Node, &["package.json"]),
+    (
+        ProjectType::Python,
+        &[
+            "pyproject.toml",
+            "setup.py",
+            "setup.cfg",
+            "requirements.txt",
+        ],
+    ),
+    (ProjectType::Go, &["go.mod"]),
+];
+
+impl ProjectType {
+    pub fn name(self) -> &'static str {
+        match self {
+            ProjectType::Rust => "rust",
+            ProjectType::Node => "node",
+            ProjectType::Python => "python",
+            ProjectType::Go => "go",
+            ProjectType::Mixed => "mixed",
+        }
+    }
+
+    /// Globs for files that add bulk but rarely context in this ecosystem:
+    /// lockfiles, minified bundles, source maps, generated mocks.
+    pub fn default_exclusions(self) -> Vec<&'static str> {
+        match self {
+            ProjectType::Rust => vec!["Cargo.lock"],
+            ProjectType::Node => vec![
+                "package-lock.json",
+                "yarn.lock",
+                "pnpm-lock.yaml",
+                "*.min.js",
+                "*.min.css",
+                "*.map",
+            ],
+            ProjectType::Python => vec!["poetry.lock", "uv.lock", "Pipfile.lock"],
+            ProjectType::Go => vec!["go.sum"],
+            ProjectType::Mixed => [
+                ProjectType::Rust,
+                ProjectType::Node,
+                ProjectType::Python,
+                ProjectType::Go,
+            ]
+            .into_iter()
+            .flat_map(ProjectType::default_exclusions)
+            .collect(),
+        }
+    }
+}
+
+/// Detect the project type from manifests in `root`. Returns None when no
+/// known manifest is present.
+pub fn detect(root: &Path) -> Option<ProjectType> {
+    let found: Vec<ProjectType> = MANIFESTS
+        .iter()
+        .filter(|(_, files)| files.iter().any(|f| root.join(f).is_file()))
+        .map(|(project, _)| *project)
+        .collect();
+
+    match found.as_slice() {
+        [] => None,
+        [single] => Some(*single),
+        _ => Some(ProjectType::Mixed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_detect_project_types() {
+        let dir = TempDir::new().unwrap();
+        assert_eq!(detect(dir.path()), None);
+
+        fs::write(dir.path().join("Cargo.toml"), "[package]\n").unwrap();
+        assert_eq!(detect(dir.path()), Some(ProjectType::Rust));
+
+        fs::write(dir.path().join("package.json"), "{}").unwrap();
+        assert_eq!(detect(dir.path()), Some(ProjectType::Mixed));
+    }
+
+    #[test]
+    fn test_mixed_combines_exclusions() {
+        let exclusions = ProjectType::Mixed.default_exclusions();
+        assert!(exclusions.contains(&"Cargo.lock"));
+        assert!(exclusions.contains(&"go.sum"));
+    }
+}
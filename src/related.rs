@@ -0,0 +1,182 @@
+//! String-level import scanning for `--related` and `--entry`: no parsing or
+//! module resolution, just the names that appear on a file's import lines.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+/// How far into the related file to look; imports live at the top
+const PEEK_LINES: usize = 200;
+
+/// Line prefixes that introduce an import across the supported languages
+const IMPORT_PREFIXES: &[&str] = &[
+    "use ",
+    "pub use ",
+    "mod ",
+    "pub mod ",
+    "extern crate ",
+    "import ",
+    "from ",
+    "export ",
+    "#include",
+    "require",
+    "using ",
+];
+
+/// Keywords and noise that show up on import lines but never name a file
+const STOP_WORDS: &[&str] = &[
+    "use",
+    "pub",
+    "mod",
+    "crate",
+    "self",
+    "super",
+    "extern",
+    "import",
+    "from",
+    "export",
+    "as",
+    "include",
+    "require",
+    "require_relative",
+    "using",
+    "static",
+    "type",
+    "std",
+];
+
+/// Lowercased names mentioned on the import lines near the top of `source`
+pub fn imported_names(source: &str) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for line in source.lines().take(PEEK_LINES) {
+        let trimmed = line.trim_start();
+        let is_import = IMPORT_PREFIXES.iter().any(|p| trimmed.starts_with(p))
+            || trimmed.contains("require(")
+            || trimmed.contains("import(");
+        if !is_import {
+            continue;
+        }
+        for word in trimmed.split(|c: char| !(c.is_alphanumeric() || c == '_' || c == '-')) {
+            let word = word.trim_matches('-').to_lowercase();
+            if !word.is_empty() && !STOP_WORDS.contains(&word.as_str()) {
+                names.insert(word);
+            }
+        }
+    }
+    names
+}
+
+/// The name an import would use for `path`: its file stem, or the directory name
+/// for module index files (`mod.rs`, `index.ts`, `__init__.py`)
+pub fn import_name(path: &Path) -> Option<String> {
+    let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+    if matches!(stem.as_str(), "mod" | "index" | "__init__") {
+        let parent = path.parent()?.file_name()?;
+        return Some(parent.to_string_lossy().to_lowercase());
+    }
+    Some(stem)
+}
+
+/// Import hops from the file at `entry`, whose content is `entry_source`, to
+/// each of `files` it reaches: a file is one hop further than the nearest file
+/// whose import lines name it (see [`import_name`]). The entry is at distance 0;
+/// files it doesn't reach are left out. Only the files reached are `read`,
+/// one at a time.
+pub fn import_distances(
+    entry: &Path,
+    entry_source: &str,
+    files: &[PathBuf],
+    read: impl Fn(&Path) -> Option<String>,
+) -> HashMap<PathBuf, usize> {
+    let mut by_name: HashMap<String, Vec<usize>> = HashMap::new();
+    for (i, path) in files.iter().enumerate() {
+        if let Some(name) = import_name(path) {
+            by_name.entry(name).or_default().push(i);
+        }
+    }
+
+    // Breadth first, so each file is reached by its shortest chain of imports
+    let mut distances = HashMap::from([(entry.to_path_buf(), 0)]);
+    let mut frontier = imported_names(entry_source);
+    let mut distance = 0;
+    while !frontier.is_empty() {
+        distance += 1;
+        let mut next = HashSet::new();
+        for name in frontier {
+            for &i in by_name.get(&name).into_iter().flatten() {
+                let path = &files[i];
+                if !distances.contains_key(path) {
+                    distances.insert(path.clone(), distance);
+                    if let Some(source) = read(path) {
+                        next.extend(imported_names(&source));
+                    }
+                }
+            }
+        }
+        frontier = next;
+    }
+    distances
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_imported_names_across_languages() {
+        let source = "\
+use crate::walker::{analyze, Config};
+import { UserService } from './user-service';
+from app.models import Account
+#include \"parser.h\"
+const db = require('../db');
+
+fn main() { let x = config_value(); }
+";
+        let names = imported_names(source);
+        for expected in [
+            "walker",
+            "analyze",
+            "user-service",
+            "models",
+            "parser",
+            "db",
+        ] {
+            assert!(names.contains(expected), "missing {}", expected);
+        }
+        assert!(!names.contains("crate"));
+        assert!(!names.contains("config_value"));
+    }
+
+    #[test]
+    fn test_import_distances_follow_imports_from_the_entry() {
+        let files = [
+            (PathBuf::from("src/main.rs"), "mod walker;\nmod output;\n"),
+            (PathBuf::from("src/walker.rs"), "use crate::filters;\n"),
+            (PathBuf::from("src/output.rs"), "use std::io;\n"),
+            (PathBuf::from("src/filters.rs"), "use crate::walker;\n"),
+            (PathBuf::from("src/unused.rs"), "use crate::output;\n"),
+        ];
+        let paths: Vec<PathBuf> = files.iter().map(|(path, _)| path.clone()).collect();
+        let distances = import_distances(Path::new("src/main.rs"), files[0].1, &paths, |path| {
+            let (_, content) = files.iter().find(|(p, _)| p == path)?;
+            Some(content.to_string())
+        });
+
+        let distance = |path: &str| distances.get(Path::new(path)).copied();
+        assert_eq!(distance("src/main.rs"), Some(0));
+        assert_eq!(distance("src/walker.rs"), Some(1));
+        assert_eq!(distance("src/output.rs"), Some(1));
+        assert_eq!(distance("src/filters.rs"), Some(2));
+        assert_eq!(distance("src/unused.rs"), None);
+    }
+
+    #[test]
+    fn test_import_name_uses_directory_for_index_files() {
+        assert_eq!(import_name(Path::new("src/walker.rs")).unwrap(), "walker");
+        assert_eq!(import_name(Path::new("src/db/index.ts")).unwrap(), "db");
+        assert_eq!(
+            import_name(Path::new("app/models/__init__.py")).unwrap(),
+            "models"
+        );
+    }
+}
@@ -0,0 +1,76 @@
+use crate::priority::{classify, score_file, Role};
+use crate::tokens::{estimate_tokens_from_len, is_prose_extension};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+/// What flat did with a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Decision {
+    /// Written with its full content
+    Full,
+    /// Written compressed
+    Compressed,
+    /// Only its start written, cut to fit the token budget (`--truncate`)
+    Truncated,
+    /// Passed the filters but did not fit the token budget
+    Excluded,
+    /// Rejected by a filter (secret, binary, gitignore, --match, ...)
+    Skipped,
+}
+
+/// The decision engine's view of one file, for integrations that build their
+/// own UI over flat's analysis (see [`crate::walker::analyze`]).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct FileReport {
+    pub path: PathBuf,
+    /// Priority score used to order files under `--tokens` (higher goes first)
+    pub score: u32,
+    pub role: Role,
+    /// Size of the full content in bytes
+    pub bytes: usize,
+    /// Estimated tokens of the full content
+    pub tokens_full: usize,
+    /// Estimated tokens of the compressed or truncated content, when it was cut down
+    pub tokens_compressed: Option<usize>,
+    pub decision: Decision,
+    /// Why a file was skipped or excluded
+    pub reason: Option<String>,
+}
+
+impl FileReport {
+    /// Report for `path` (scored relative to `base`) whose full content is `full_len` bytes
+    pub(crate) fn new(path: &Path, base: &Path, full_len: usize, decision: Decision) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            score: score_file(path, base),
+            role: classify(path, base),
+            bytes: full_len,
+            tokens_full: estimate_tokens_from_len(full_len, is_prose(path)),
+            tokens_compressed: None,
+            decision,
+            reason: None,
+        }
+    }
+
+    pub(crate) fn with_compressed_len(mut self, len: usize) -> Self {
+        self.tokens_compressed = Some(estimate_tokens_from_len(len, is_prose(&self.path)));
+        self
+    }
+
+    pub(crate) fn with_reason(mut self, reason: impl Into<String>) -> Self {
+        self.reason = Some(reason.into());
+        self
+    }
+
+    /// Estimated tokens of what was written: the compressed or truncated
+    /// content when it was cut down, otherwise the full content
+    pub fn tokens_written(&self) -> usize {
+        self.tokens_compressed.unwrap_or(self.tokens_full)
+    }
+}
+
+fn is_prose(path: &Path) -> bool {
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    is_prose_extension(ext)
+}
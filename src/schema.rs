@@ -0,0 +1,425 @@
+//! The machine-readable output schema, and the types serialized under it.
+//!
+//! Every JSON value flat writes carries `"flat_schema": SCHEMA_VERSION`. Within a
+//! version, fields and event kinds may be added but never renamed, retyped, or
+//! removed; any such change bumps the version. Consumers should ignore fields
+//! and events they don't know. `flat --schema` prints [`document`].
+
+use crate::bundle::BUNDLE_VERSION;
+use crate::config::LineRange;
+use crate::filters::SkipReason;
+use crate::output::{FileMode, Statistics, EXCLUSION_REASONS};
+use crate::priority::Role;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+/// Version of the JSON output schema (the unversioned JSONL events were 1, and
+/// 2 spelled skip reasons as prose: "too large", "no match")
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// A single event in `--format jsonl-events` output
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "kebab-case")]
+pub enum Event<'a> {
+    FileIncluded {
+        path: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        mode: Option<FileMode>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        truncated: bool,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        lines: Option<LineRange>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        role: Option<Role>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        score: Option<u32>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        added: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        removed: Option<usize>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        owners: Option<&'a str>,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        generated: bool,
+        bytes: usize,
+        tokens: usize,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        sha256: Option<&'a str>,
+        content: &'a str,
+    },
+    FileListed {
+        path: &'a str,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        annotation: Option<&'a str>,
+    },
+    FileSymlink {
+        path: &'a str,
+        target: &'a str,
+    },
+    FileSkipped {
+        path: &'a str,
+        reason: &'a str,
+    },
+    FileCompressed {
+        path: &'a str,
+        original_bytes: usize,
+        compressed_bytes: usize,
+    },
+    Summary {
+        #[serde(flatten)]
+        stats: &'a Statistics,
+    },
+    Header {
+        text: &'a str,
+    },
+    Footer {
+        text: &'a str,
+    },
+    Tree {
+        text: &'a str,
+    },
+    SectionStart {
+        name: &'a str,
+    },
+    SectionEnd {
+        name: &'a str,
+        files: usize,
+        tokens: usize,
+    },
+    PartStart {
+        index: usize,
+    },
+    PartEnd {
+        index: usize,
+        files: usize,
+        tokens: usize,
+    },
+}
+
+/// One entry of the `files` array in `--format json`: a written file (with
+/// `content`), a symlink stub, a budget-excluded stub, or a dry-run listing
+#[derive(Default, Serialize)]
+pub struct JsonFile<'a> {
+    pub path: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub section: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub mode: Option<FileMode>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<LineRange>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<Role>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub score: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub added: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub removed: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub owners: Option<&'a str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub generated: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub symlink_to: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub excluded_reason: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bytes: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tokens: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<&'a str>,
+    /// Last, so a streamed file's content can follow the rest of the object
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Versioned<'a, T: Serialize> {
+    flat_schema: u32,
+    #[serde(flatten)]
+    value: &'a T,
+}
+
+/// Serialize `value` as one JSON object stamped with the schema version
+pub fn to_json<T: Serialize>(value: &T) -> serde_json::Result<String> {
+    serde_json::to_string(&Versioned {
+        flat_schema: SCHEMA_VERSION,
+        value,
+    })
+}
+
+/// JSON Schema describing every JSON value flat writes
+pub fn document() -> Value {
+    let string = json!({ "type": "string" });
+    let count = json!({ "type": "integer", "minimum": 0 });
+    let sha256 = json!({ "type": "string", "pattern": "^[0-9a-f]{64}$" });
+    let role = json!({
+        "enum": ["readme", "entry-point", "config", "design", "docs", "source", "test", "fixture"]
+    });
+    // A `file-skipped` event is also how JSONL tells of a file left out after the filters
+    let skip_or_exclusion = {
+        let mut codes: Vec<&str> = SkipReason::ALL.iter().map(|r| r.code()).collect();
+        codes.extend(EXCLUSION_REASONS);
+        json!({ "enum": codes })
+    };
+    // Every file's decision, as the manifests list them
+    let decisions = json!({
+        "type": "array",
+        "items": {
+            "type": "object",
+            "properties": {
+                "path": string,
+                "score": count,
+                "role": role,
+                "bytes": count,
+                "tokens_full": count,
+                "tokens_compressed": { "type": ["integer", "null"] },
+                "decision": { "enum": ["full", "compressed", "truncated", "excluded", "skipped"] },
+                "reason": { "type": ["string", "null"] },
+            },
+            "required": ["path", "decision"]
+        }
+    });
+    let event = |name: &str, properties: Value, required: &[&str]| {
+        let mut properties = properties;
+        properties["flat_schema"] = json!({ "const": SCHEMA_VERSION });
+        properties["event"] = json!({ "const": name });
+        let mut required: Vec<&str> = required.to_vec();
+        required.extend(["flat_schema", "event"]);
+        json!({ "type": "object", "properties": properties, "required": required })
+    };
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "flat machine output",
+        "flat_schema": SCHEMA_VERSION,
+        "$defs": {
+            "json-document": {
+                "description": "The whole of --format json",
+                "type": "object",
+                "properties": {
+                    "flat_schema": { "const": SCHEMA_VERSION },
+                    "header": string,
+                    "files": {
+                        "type": "array",
+                        "items": {
+                            "type": "object",
+                            "properties": {
+                                "path": string,
+                                "section": string,
+                                "mode": { "enum": ["full", "compressed", "truncated", "outline"] },
+                                "truncated": { "type": "boolean" },
+                                "lines": { "type": "string", "pattern": "^[0-9]+-[0-9]+$" },
+                                "role": role,
+                                "score": count,
+                                "added": count,
+                                "removed": count,
+                                "owners": string,
+                                "generated": { "type": "boolean" },
+                                "symlink_to": string,
+                                "excluded_reason": string,
+                                "annotation": string,
+                                "bytes": count,
+                                "tokens": count,
+                                "sha256": sha256,
+                                "content": string,
+                            },
+                            "required": ["path"]
+                        }
+                    },
+                    "summary": { "type": "object", "properties": summary_properties() },
+                    "tree": string,
+                    "footer": string,
+                },
+                "required": ["flat_schema", "files", "summary"]
+            },
+            "manifest": {
+                "description": "The --manifest file",
+                "type": "object",
+                "properties": {
+                    "flat_schema": { "const": SCHEMA_VERSION },
+                    "flat_version": string,
+                    "format": string,
+                    "files": decisions,
+                    "summary": { "type": "object", "properties": summary_properties() },
+                },
+                "required": ["flat_schema", "files", "summary"]
+            },
+            "bundle-manifest": {
+                "description": "manifest.json inside a --bundle zip",
+                "type": "object",
+                "properties": {
+                    "flat_schema": { "const": SCHEMA_VERSION },
+                    "bundle_version": { "const": BUNDLE_VERSION },
+                    "flat_version": string,
+                    "format": string,
+                    "members": { "type": "object", "additionalProperties": string },
+                    "files": decisions,
+                    "summary": { "type": "object", "properties": summary_properties() },
+                },
+                "required": ["flat_schema", "bundle_version", "members", "files", "summary"]
+            },
+            "jsonl-event": {
+                "description": "One line of --format jsonl-events",
+                "oneOf": [
+                    event("file-included", json!({
+                        "path": string,
+                        "mode": { "enum": ["full", "compressed", "truncated", "outline"] },
+                        "truncated": { "type": "boolean" },
+                        "lines": { "type": "string", "pattern": "^[0-9]+-[0-9]+$" },
+                        "role": role,
+                        "score": count,
+                        "added": count,
+                        "removed": count,
+                        "owners": string,
+                        "generated": { "type": "boolean" },
+                        "bytes": count,
+                        "tokens": count,
+                        "sha256": sha256,
+                        "content": string,
+                    }), &["path", "bytes", "tokens", "content"]),
+                    event("file-listed", json!({
+                        "path": string,
+                        "annotation": string,
+                    }), &["path"]),
+                    event("file-symlink", json!({
+                        "path": string,
+                        "target": string,
+                    }), &["path", "target"]),
+                    event("file-skipped", json!({
+                        "path": string,
+                        "reason": skip_or_exclusion,
+                    }), &["path", "reason"]),
+                    event("file-compressed", json!({
+                        "path": string,
+                        "original_bytes": count,
+                        "compressed_bytes": count,
+                    }), &["path", "original_bytes", "compressed_bytes"]),
+                    event("summary", summary_properties(), &["total_files", "included_files"]),
+                    event("header", json!({ "text": string }), &["text"]),
+                    event("footer", json!({ "text": string }), &["text"]),
+                    event("tree", json!({ "text": string }), &["text"]),
+                    event("section-start", json!({ "name": string }), &["name"]),
+                    event("section-end", json!({
+                        "name": string,
+                        "files": count,
+                        "tokens": count,
+                    }), &["name", "files", "tokens"]),
+                    event("part-start", json!({ "index": count }), &["index"]),
+                    event("part-end", json!({
+                        "index": count,
+                        "files": count,
+                        "tokens": count,
+                    }), &["index", "files", "tokens"]),
+                ]
+            }
+        }
+    })
+}
+
+/// The stable code of every [`SkipReason`]
+fn skip_reason() -> Value {
+    let codes: Vec<&str> = SkipReason::ALL.iter().map(|r| r.code()).collect();
+    json!({ "enum": codes })
+}
+
+/// Fields of [`Statistics`] as serialized in the summary
+fn summary_properties() -> Value {
+    let count = json!({ "type": "integer", "minimum": 0 });
+    let counts = json!({ "type": "object", "additionalProperties": count });
+    json!({
+        "total_files": count,
+        "included_files": count,
+        "skipped_by_reason": {
+            "type": "object",
+            "propertyNames": skip_reason(),
+            "additionalProperties": count,
+        },
+        "included_by_extension": counts,
+        "output_size": count,
+        "compressed_files": count,
+        "redactions": count,
+        "minified_files": count,
+        "transcoded_files": count,
+        "line_numbers": { "type": ["string", "null"] },
+        "token_budget": { "type": ["integer", "null"] },
+        "reserved_tokens": count,
+        "tokens_used": count,
+        "excluded_by_budget": { "type": "array", "items": { "type": "string" } },
+        "truncated_files": count,
+        "doc_link_boosts": count,
+        "related_boosts": count,
+        "entry_boosts": count,
+        "coverage": {
+            "type": "object",
+            "properties": {
+                "text_files": count,
+                "included_files": count,
+                "text_bytes": count,
+                "included_bytes": count,
+                "text_tokens": count,
+                "included_tokens": count,
+            },
+        },
+        "symlinks": count,
+        "project": { "type": ["string", "null"] },
+        "command": { "type": ["string", "null"] },
+        "pruned_dirs": {
+            "type": "array",
+            "items": {
+                "type": "object",
+                "properties": {
+                    "path": { "type": "string" },
+                    "reason": { "type": "string" },
+                    "files": count,
+                    "files_capped": { "type": "boolean" },
+                }
+            }
+        },
+        "directory_sizes": {
+            "type": "object",
+            "additionalProperties": {
+                "type": "object",
+                "properties": { "files": count, "bytes": count, "tokens": count }
+            }
+        },
+        "directories": {
+            "type": "object",
+            "additionalProperties": {
+                "type": "object",
+                "properties": { "included": count, "excluded_by_reason": counts }
+            }
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events_are_stamped_with_the_schema_version() {
+        let line = to_json(&Event::Header { text: "hi" }).unwrap();
+        assert_eq!(
+            line,
+            format!(
+                r#"{{"flat_schema":{},"event":"header","text":"hi"}}"#,
+                SCHEMA_VERSION
+            )
+        );
+    }
+
+    #[test]
+    fn test_document_covers_every_summary_field() {
+        let stats = serde_json::to_value(Statistics::default()).unwrap();
+        let documented = summary_properties();
+        for field in stats.as_object().unwrap().keys() {
+            assert!(documented.get(field).is_some(), "undocumented: {}", field);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! `--since`: flatten only what changed recently, going by modification time
+//! (`--since 7d`) or by git history (`--since HEAD~5`).
+
+use crate::git;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Units of a `--since` age, longest first so an age is printed in the largest that fits
+const AGE_UNITS: &[(char, u64)] = &[
+    ('w', 7 * 24 * 60 * 60),
+    ('d', 24 * 60 * 60),
+    ('h', 60 * 60),
+    ('m', 60),
+    ('s', 1),
+];
+
+/// What `--since` measures recency against
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Since {
+    /// Modified on disk within this long: `30m`, `12h`, `7d`, `2w`
+    Age(Duration),
+    /// Changed since this git revision, committed or not: `HEAD~5`, `v1.2.0`
+    Revision(String),
+}
+
+impl Since {
+    /// Parse `--since`: a number with a unit is an age, anything else a git revision
+    pub fn parse(input: &str) -> Result<Self, String> {
+        let input = input.trim();
+        if input.is_empty() {
+            return Err("expected an age like 7d or a git revision like HEAD~5".to_string());
+        }
+        let age = input.char_indices().last().and_then(|(i, unit)| {
+            let count: u64 = input[..i].parse().ok()?;
+            let (_, secs) = AGE_UNITS.iter().find(|(u, _)| *u == unit)?;
+            Some(Duration::from_secs(count.checked_mul(*secs)?))
+        });
+        Ok(match age {
+            Some(age) => Since::Age(age),
+            None => Since::Revision(input.to_string()),
+        })
+    }
+
+    /// The flag value that parses back to this
+    pub fn to_arg(&self) -> String {
+        match self {
+            Since::Age(age) => {
+                let secs = age.as_secs();
+                let (unit, size) = AGE_UNITS
+                    .iter()
+                    .find(|(_, size)| secs % size == 0)
+                    .expect("seconds divide everything");
+                format!("{}{}", secs / size, unit)
+            }
+            Since::Revision(revision) => revision.clone(),
+        }
+    }
+}
+
+/// Where the walker learns whether a file changed within `--since`
+#[derive(Debug, Clone)]
+pub enum ChangeSource {
+    /// The file's modification time is at or after this
+    ModifiedAfter(SystemTime),
+    /// git reports the file as changed since the revision, or it is untracked
+    Git(HashSet<PathBuf>),
+}
+
+impl ChangeSource {
+    /// Resolve `since` for the files under `dir`, asking git for a revision's changes
+    pub fn resolve(since: &Since, dir: &Path) -> Result<Self> {
+        match since {
+            Since::Age(age) => {
+                let cutoff = SystemTime::now()
+                    .checked_sub(*age)
+                    .unwrap_or(SystemTime::UNIX_EPOCH);
+                Ok(ChangeSource::ModifiedAfter(cutoff))
+            }
+            Since::Revision(revision) => {
+                let changed = git::changed_files(dir, revision)
+                    .with_context(|| format!("--since {}", revision))?;
+                Ok(ChangeSource::Git(
+                    changed.into_iter().map(|(path, _)| path).collect(),
+                ))
+            }
+        }
+    }
+
+    /// Whether `path` changed recently. A file with no modification time on
+    /// disk (an overlay entry) counts as changed.
+    pub fn changed(&self, path: &Path) -> bool {
+        match self {
+            ChangeSource::ModifiedAfter(cutoff) => fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .map_or(true, |modified| modified >= *cutoff),
+            ChangeSource::Git(changed) => changed.contains(path),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_since_age_or_revision() {
+        assert_eq!(
+            Since::parse("7d"),
+            Ok(Since::Age(Duration::from_secs(7 * 24 * 60 * 60)))
+        );
+        assert_eq!(
+            Since::parse("90m"),
+            Ok(Since::Age(Duration::from_secs(5400)))
+        );
+        assert_eq!(
+            Since::parse("HEAD~5"),
+            Ok(Since::Revision("HEAD~5".to_string()))
+        );
+        // Not a number with a unit, so a ref name
+        assert_eq!(Since::parse("7x"), Ok(Since::Revision("7x".to_string())));
+        assert!(Since::parse(" ").is_err());
+
+        assert_eq!(Since::parse("14d").unwrap().to_arg(), "2w");
+        assert_eq!(Since::parse("90m").unwrap().to_arg(), "90m");
+    }
+
+    #[test]
+    fn test_modified_after_cutoff() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let path = dir.path().join("old.txt");
+        let file = fs::File::create(&path).unwrap();
+        file.set_modified(SystemTime::now() - Duration::from_secs(3 * 24 * 60 * 60))
+            .unwrap();
+
+        let week = ChangeSource::resolve(&Since::Age(Duration::from_secs(7 * 86400)), dir.path());
+        let day = ChangeSource::resolve(&Since::Age(Duration::from_secs(86400)), dir.path());
+        assert!(week.unwrap().changed(&path));
+        assert!(!day.unwrap().changed(&path));
+        assert!(ChangeSource::ModifiedAfter(SystemTime::now()).changed(&dir.path().join("new.rs")));
+    }
+}
@@ -0,0 +1,327 @@
+use crate::encoding::{read_transcoded, sniff_utf16, strip_bom, Bom};
+use crate::filters::{exceeds_size_limit, is_binary_content, is_minified, is_minified_content};
+use crate::overrides::{self, OverrideAction};
+use anyhow::{Context, Result};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Where file contents come from during flattening.
+///
+/// In-memory entries are consulted first; paths not held in memory are read
+/// from disk unless the source is memory-only.
+#[derive(Debug, Default)]
+pub(crate) struct ContentSource {
+    entries: HashMap<PathBuf, String>,
+    memory_only: bool,
+    /// Read CRLF line endings as LF (`--normalize-newlines`)
+    normalize_newlines: bool,
+    /// On-disk files read so far that weren't UTF-8 and were transcoded to it
+    transcoded: RefCell<HashSet<PathBuf>>,
+}
+
+impl ContentSource {
+    /// Read everything from the filesystem
+    pub(crate) fn disk() -> Self {
+        Self::default()
+    }
+
+    /// Serve only the given entries; the filesystem is never touched
+    pub(crate) fn memory(entries: Vec<(PathBuf, String)>) -> Self {
+        Self {
+            entries: entries.into_iter().collect(),
+            memory_only: true,
+            ..Self::default()
+        }
+    }
+
+    /// Read from disk, but let the given entries override on-disk contents
+    pub(crate) fn overlay(entries: Vec<(PathBuf, String)>) -> Self {
+        Self {
+            entries: entries.into_iter().collect(),
+            memory_only: false,
+            ..Self::default()
+        }
+    }
+
+    /// Load an overlay from a JSON object mapping paths to contents.
+    ///
+    /// Relative paths are resolved against `root` so they line up with walked paths.
+    pub(crate) fn overlay_from_json(file: &Path, root: &Path) -> Result<Self> {
+        let text = fs::read_to_string(file)
+            .with_context(|| format!("Failed to read overlay file: {}", file.display()))?;
+        let map: BTreeMap<String, String> = serde_json::from_str(&text).with_context(|| {
+            format!(
+                "Invalid overlay file (expected a JSON object of path to content): {}",
+                file.display()
+            )
+        })?;
+
+        let entries = map
+            .into_iter()
+            .map(|(path, content)| {
+                let path = PathBuf::from(path);
+                let resolved = if path.is_absolute() {
+                    path
+                } else {
+                    root.join(path)
+                };
+                (resolved, content)
+            })
+            .collect();
+        Ok(Self::overlay(entries))
+    }
+
+    /// Read CRLF line endings as LF, so a Windows checkout costs no more tokens
+    pub(crate) fn with_normalized_newlines(self, normalize_newlines: bool) -> Self {
+        Self {
+            normalize_newlines,
+            ..self
+        }
+    }
+
+    /// Hold `content` at `path`, which need not exist on disk
+    pub(crate) fn attach(&mut self, path: PathBuf, content: String) {
+        self.entries.insert(path, content);
+    }
+
+    /// Whether `path`'s content is held in memory
+    pub(crate) fn holds(&self, path: &Path) -> bool {
+        self.entries.contains_key(path)
+    }
+
+    /// Paths held in memory, sorted for deterministic processing
+    pub(crate) fn memory_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.entries.keys().cloned().collect();
+        paths.sort();
+        paths
+    }
+
+    /// A file's text, decoded by its byte order mark and without the mark, or
+    /// transcoded from what it looks to be in (see [`crate::encoding::decode`])
+    pub(crate) fn read(&self, path: &Path) -> io::Result<String> {
+        let text = match self.entries.get(path) {
+            Some(content) => strip_bom(content).to_string(),
+            None if self.memory_only => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "not present in the provided entries",
+                ))
+            }
+            None => {
+                let (text, encoding) = read_transcoded(path)?;
+                if encoding.is_some() {
+                    self.transcoded.borrow_mut().insert(path.to_path_buf());
+                }
+                text
+            }
+        };
+        if self.normalize_newlines && text.contains("\r\n") {
+            return Ok(text.replace("\r\n", "\n"));
+        }
+        Ok(text)
+    }
+
+    /// Whether reading `path` so far found it in another encoding than UTF-8
+    pub(crate) fn is_transcoded(&self, path: &Path) -> bool {
+        self.transcoded.borrow().contains(path)
+    }
+
+    /// Stream a file from disk in UTF-8 chunks; `None` for contents held in memory,
+    /// which [`Self::read`] serves without a copy on disk, and for files with a
+    /// byte order mark or in UTF-16 without one, which it decodes whole, as it
+    /// does every file when normalizing newlines. Other text that isn't UTF-8
+    /// fails the stream with `InvalidData`, for `read` to transcode instead.
+    pub(crate) fn stream(&self, path: &Path) -> Option<io::Result<Utf8Chunks<fs::File>>> {
+        if self.memory_only
+            || self.normalize_newlines
+            || self.entries.contains_key(path)
+            || is_wide_or_marked(path)
+        {
+            return None;
+        }
+        Some(fs::File::open(path).map(Utf8Chunks::new))
+    }
+
+    /// Size in bytes, if known
+    pub(crate) fn len(&self, path: &Path) -> Option<u64> {
+        match self.entries.get(path) {
+            Some(content) => Some(content.len() as u64),
+            None if self.memory_only => None,
+            None => fs::metadata(path).ok().map(|m| m.len()),
+        }
+    }
+
+    pub(crate) fn exceeds_size_limit(&self, path: &Path, max_size: u64) -> bool {
+        match self.entries.get(path) {
+            Some(content) => content.len() as u64 > max_size,
+            None => !self.memory_only && exceeds_size_limit(path, max_size),
+        }
+    }
+
+    /// The `flat:` pragma near the top of a file, if it has one. Only the first
+    /// 4KB of an on-disk file is read.
+    pub(crate) fn pragma(&self, path: &Path) -> Option<OverrideAction> {
+        match self.entries.get(path) {
+            Some(content) => overrides::pragma(strip_bom(content)),
+            None if self.memory_only => None,
+            None => {
+                let mut head = Vec::new();
+                fs::File::open(path)
+                    .and_then(|file| file.take(4096).read_to_end(&mut head))
+                    .ok()?;
+                overrides::pragma(strip_bom(&String::from_utf8_lossy(&head)))
+            }
+        }
+    }
+
+    /// Null-byte sniff over the first 8KB, mirroring `is_binary_content`
+    pub(crate) fn is_binary(&self, path: &Path) -> bool {
+        match self.entries.get(path) {
+            Some(content) => content.as_bytes().iter().take(8192).any(|&b| b == 0),
+            None => !self.memory_only && is_binary_content(path),
+        }
+    }
+
+    /// Minification sniff over the first 64KB, mirroring `is_minified_content`
+    pub(crate) fn is_minified(&self, path: &Path) -> bool {
+        match self.entries.get(path) {
+            Some(content) => is_minified(content.as_bytes()),
+            None => !self.memory_only && is_minified_content(path),
+        }
+    }
+}
+
+fn is_wide_or_marked(path: &Path) -> bool {
+    let mut start = Vec::new();
+    let read = fs::File::open(path).and_then(|file| file.take(8192).read_to_end(&mut start));
+    read.is_ok_and(|_| Bom::detect(&start).is_some() || sniff_utf16(&start).is_some())
+}
+
+/// Bytes read per chunk when streaming
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// A reader's content as UTF-8 `String` chunks of about [`CHUNK_SIZE`] bytes.
+/// Invalid UTF-8 is an `InvalidData` error, as with `fs::read_to_string`.
+pub(crate) struct Utf8Chunks<R> {
+    reader: R,
+    /// Bytes of a character split across reads
+    carry: Vec<u8>,
+    done: bool,
+}
+
+impl<R: Read> Utf8Chunks<R> {
+    pub(crate) fn new(reader: R) -> Self {
+        Self {
+            reader,
+            carry: Vec::new(),
+            done: false,
+        }
+    }
+
+    fn next_chunk(&mut self) -> io::Result<Option<String>> {
+        let mut buf = std::mem::take(&mut self.carry);
+        let start = buf.len();
+        buf.resize(start + CHUNK_SIZE, 0);
+        let read = loop {
+            match self.reader.read(&mut buf[start..]) {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                other => break other?,
+            }
+        };
+        buf.truncate(start + read);
+        if read == 0 {
+            self.done = true;
+            if !buf.is_empty() {
+                return Err(invalid_utf8());
+            }
+            return Ok(None);
+        }
+
+        match std::str::from_utf8(&buf) {
+            Ok(_) => {}
+            // A character cut off at the end of the read: keep it for the next one
+            Err(e) if e.error_len().is_none() => self.carry = buf.split_off(e.valid_up_to()),
+            Err(_) => return Err(invalid_utf8()),
+        }
+        String::from_utf8(buf).map(Some).map_err(|_| invalid_utf8())
+    }
+}
+
+impl<R: Read> Iterator for Utf8Chunks<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let chunk = self.next_chunk();
+        if chunk.is_err() {
+            self.done = true;
+        }
+        chunk.transpose()
+    }
+}
+
+fn invalid_utf8() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "stream did not contain valid UTF-8",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory_source_never_reads_disk() {
+        let source = ContentSource::memory(vec![(PathBuf::from("a.rs"), "fn a() {}".into())]);
+        assert_eq!(source.read(Path::new("a.rs")).unwrap(), "fn a() {}");
+        assert!(source.read(Path::new("Cargo.toml")).is_err());
+        assert_eq!(source.len(Path::new("a.rs")), Some(9));
+        assert!(source.exceeds_size_limit(Path::new("a.rs"), 4));
+        assert!(!source.is_binary(Path::new("a.rs")));
+    }
+
+    #[test]
+    fn test_utf8_chunks_keep_split_characters_whole() {
+        let text = "é".repeat(CHUNK_SIZE);
+        let chunks: Vec<String> = Utf8Chunks::new(text.as_bytes())
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), text);
+
+        let invalid = [b'a', 0xff, b'b'];
+        assert!(Utf8Chunks::new(&invalid[..]).any(|c| c.is_err()));
+    }
+
+    #[test]
+    fn test_overlay_prefers_entries_over_disk() {
+        let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let source = ContentSource::overlay(vec![(manifest.clone(), "[package]".into())]);
+        assert_eq!(source.read(&manifest).unwrap(), "[package]");
+
+        let lib = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/lib.rs");
+        assert!(source.read(&lib).unwrap().contains("pub mod"));
+    }
+
+    #[test]
+    fn test_normalized_newlines_read_crlf_as_lf() {
+        let entries = vec![(
+            PathBuf::from("a.rs"),
+            "fn a() {}\r\n\r\nfn b() {}\r\n".into(),
+        )];
+        let source = ContentSource::memory(entries.clone());
+        assert!(source.read(Path::new("a.rs")).unwrap().contains('\r'));
+
+        let source = ContentSource::memory(entries).with_normalized_newlines(true);
+        assert_eq!(
+            source.read(Path::new("a.rs")).unwrap(),
+            "fn a() {}\n\nfn b() {}\n"
+        );
+    }
+}
@@ -0,0 +1,199 @@
+use crate::git;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Values available to placeholders in header/footer snippets and output paths
+#[derive(Debug, Clone, Default)]
+pub struct TemplateVars {
+    pub date: String,
+    pub time: String,
+    pub timestamp: String,
+    pub git_sha: String,
+    pub path: String,
+}
+
+impl TemplateVars {
+    /// Collect variables for a run rooted at `root`
+    pub fn for_root(root: &Path) -> Self {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Self {
+            date: format_date(secs),
+            time: format_time(secs),
+            timestamp: secs.to_string(),
+            git_sha: git::head_sha(root).unwrap_or_else(|| "unknown".to_string()),
+            path: root.display().to_string(),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> Option<&str> {
+        match name {
+            "date" => Some(&self.date),
+            "time" => Some(&self.time),
+            "timestamp" => Some(&self.timestamp),
+            "git_sha" => Some(&self.git_sha),
+            "path" => Some(&self.path),
+            _ => None,
+        }
+    }
+}
+
+/// Expand `{{name}}` placeholders. Unknown names are left untouched.
+pub fn expand(template: &str, vars: &TemplateVars) -> String {
+    expand_delimited(template, "{{", "}}", vars)
+}
+
+/// Expand `{name}` placeholders in an output path, e.g. `context-{git_sha}-{date}.txt`.
+/// Unknown names are left untouched.
+pub fn expand_path(template: &str, vars: &TemplateVars) -> String {
+    expand_delimited(template, "{", "}", vars)
+}
+
+/// Whether an output path contains anything `expand_path` would substitute
+pub fn has_path_placeholders(template: &str) -> bool {
+    let vars = TemplateVars::default();
+    expand_path(template, &vars) != template
+}
+
+/// A glob for every name `expand_path` can make of `template`, placeholders
+/// standing for any text: `ctx-{timestamp}.xml` gives `ctx-*.xml`
+pub fn path_glob(template: &str) -> String {
+    let any = "\0".to_string();
+    let vars = TemplateVars {
+        date: any.clone(),
+        time: any.clone(),
+        timestamp: any.clone(),
+        git_sha: any.clone(),
+        path: any,
+    };
+    globset::escape(&expand_path(template, &vars)).replace('\0', "*")
+}
+
+fn expand_delimited(template: &str, open: &str, close: &str, vars: &TemplateVars) -> String {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find(open) {
+        result.push_str(&rest[..start]);
+        let after = &rest[start + open.len()..];
+        match after.find(close) {
+            Some(end) => {
+                let name = after[..end].trim();
+                match vars.lookup(name) {
+                    Some(value) => result.push_str(value),
+                    None => result.push_str(&rest[start..start + open.len() + end + close.len()]),
+                }
+                rest = &after[end + close.len()..];
+            }
+            None => {
+                result.push_str(&rest[start..]);
+                rest = "";
+            }
+        }
+    }
+
+    result.push_str(rest);
+    result
+}
+
+/// UTC date as `YYYY-MM-DD`
+fn format_date(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86_400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// UTC time of day as `HHMMSS` (safe in file names)
+fn format_time(secs: u64) -> String {
+    let day_secs = secs % 86_400;
+    format!(
+        "{:02}{:02}{:02}",
+        day_secs / 3_600,
+        (day_secs % 3_600) / 60,
+        day_secs % 60
+    )
+}
+
+/// Convert days since 1970-01-01 to a (year, month, day) civil date.
+///
+/// Howard Hinnant's algorithm; valid for the whole proleptic Gregorian calendar.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars() -> TemplateVars {
+        TemplateVars {
+            date: "2024-05-01".to_string(),
+            time: "134501".to_string(),
+            timestamp: "1714571101".to_string(),
+            git_sha: "abc1234".to_string(),
+            path: "src".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_expand_known_variables() {
+        assert_eq!(
+            expand("Context for {{path}} at {{git_sha}} ({{ date }})", &vars()),
+            "Context for src at abc1234 (2024-05-01)"
+        );
+    }
+
+    #[test]
+    fn test_expand_leaves_unknown_and_unclosed() {
+        assert_eq!(expand("{{nope}} {{date", &vars()), "{{nope}} {{date");
+    }
+
+    #[test]
+    fn test_expand_path_single_braces() {
+        assert_eq!(
+            expand_path("out/context-{git_sha}-{date}T{time}.txt", &vars()),
+            "out/context-abc1234-2024-05-01T134501.txt"
+        );
+        assert_eq!(expand_path("{unknown}.txt", &vars()), "{unknown}.txt");
+    }
+
+    #[test]
+    fn test_has_path_placeholders() {
+        assert!(has_path_placeholders("ctx-{date}.xml"));
+        assert!(!has_path_placeholders("ctx.xml"));
+        assert!(!has_path_placeholders("ctx-{other}.xml"));
+    }
+
+    #[test]
+    fn test_path_glob_matches_every_expansion() {
+        assert_eq!(path_glob("ctx-{timestamp}.xml"), "ctx-*.xml");
+        let glob = globset::Glob::new(&path_glob("flat-{git_sha}-{date}[1].{other}"))
+            .unwrap()
+            .compile_matcher();
+        assert!(glob.is_match("flat-abc1234-2024-05-01[1].{other}"));
+        assert!(!glob.is_match("flat-abc1234-2024-05-01[1].xml"));
+    }
+
+    #[test]
+    fn test_format_date_and_time() {
+        assert_eq!(format_date(1_714_571_101), "2024-05-01");
+        assert_eq!(format_time(1_714_571_101), "134501");
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(19_844), (2024, 5, 1));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+    }
+}
@@ -1,3 +1,155 @@
+use crate::config::BudgetShare;
+use std::collections::BTreeMap;
+
+/// How token counts are computed (`--tokenizer`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum Tokenizer {
+    /// Byte-length estimate: bytes/3 for code, bytes/4 for prose
+    #[default]
+    Heuristic,
+    /// OpenAI's cl100k_base BPE (GPT-4, GPT-3.5)
+    #[value(name = "cl100k_base")]
+    Cl100kBase,
+    /// OpenAI's o200k_base BPE (GPT-4o and later)
+    #[value(name = "o200k_base")]
+    O200kBase,
+}
+
+impl Tokenizer {
+    /// Whether this build can count with this tokenizer (BPE needs the `tiktoken` feature)
+    pub fn is_available(self) -> bool {
+        self == Tokenizer::Heuristic || cfg!(feature = "tiktoken")
+    }
+
+    /// Tokens in `content`; `is_prose` only matters to the heuristic
+    pub fn count(self, content: &str, is_prose: bool) -> usize {
+        match self {
+            Tokenizer::Heuristic => estimate_tokens(content, is_prose),
+            #[cfg(feature = "tiktoken")]
+            Tokenizer::Cl100kBase => tiktoken_rs::cl100k_base_singleton()
+                .lock()
+                .encode_ordinary(content)
+                .len(),
+            #[cfg(feature = "tiktoken")]
+            Tokenizer::O200kBase => tiktoken_rs::o200k_base_singleton()
+                .lock()
+                .encode_ordinary(content)
+                .len(),
+            #[cfg(not(feature = "tiktoken"))]
+            Tokenizer::Cl100kBase | Tokenizer::O200kBase => estimate_tokens(content, is_prose),
+        }
+    }
+}
+
+/// Counts the tokens in a file's content
+pub trait TokenEstimator {
+    /// Tokens in `content`, from a file with extension `ext` (`""` for none)
+    fn estimate(&self, content: &str, ext: &str) -> usize;
+}
+
+/// The heuristic and the BPE encodings, as chosen with `--tokenizer`
+impl TokenEstimator for Tokenizer {
+    fn estimate(&self, content: &str, ext: &str) -> usize {
+        self.count(content, is_prose_extension(ext))
+    }
+}
+
+/// Bytes per token by lowercased file extension, as measured by `flat calibrate`
+/// and kept in the `[token-ratios]` table of `flat.toml`
+pub type TokenRatios = BTreeMap<String, f64>;
+
+/// Per-language ratios in place of the heuristic's two: a file whose extension
+/// has a ratio counts its bytes over it, any other goes to `fallback`. A BPE
+/// `fallback` counts exactly, so it counts every file.
+#[derive(Debug, Clone, Copy)]
+pub struct PerLanguage<'a> {
+    pub ratios: &'a TokenRatios,
+    pub fallback: Tokenizer,
+}
+
+impl PerLanguage<'_> {
+    /// Tokens in `byte_count` bytes of a file with extension `ext`, or `None`
+    /// when the count needs the content itself: a BPE `fallback` does
+    pub fn estimate_len(&self, byte_count: usize, ext: &str) -> Option<usize> {
+        if self.fallback != Tokenizer::Heuristic && self.fallback.is_available() {
+            return None;
+        }
+        Some(match self.ratio(ext) {
+            Some(bytes_per_token) => (byte_count as f64 / bytes_per_token).ceil() as usize,
+            None => estimate_tokens_from_len(byte_count, is_prose_extension(ext)),
+        })
+    }
+
+    fn ratio(&self, ext: &str) -> Option<f64> {
+        match self.fallback {
+            Tokenizer::Heuristic => self.ratios.get(&ext.to_ascii_lowercase()).copied(),
+            Tokenizer::Cl100kBase | Tokenizer::O200kBase => None,
+        }
+    }
+}
+
+impl TokenEstimator for PerLanguage<'_> {
+    fn estimate(&self, content: &str, ext: &str) -> usize {
+        match self.ratio(ext) {
+            Some(bytes_per_token) => (content.len() as f64 / bytes_per_token).ceil() as usize,
+            None => self.fallback.estimate(content, ext),
+        }
+    }
+}
+
+/// A model `--model` can name, by its context window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Model {
+    pub name: &'static str,
+    /// Context window in tokens
+    pub context: usize,
+    /// The tokenizer counting closest to the model's own
+    pub tokenizer: Tokenizer,
+}
+
+/// Models `--model` knows, by family. Add new ones here; `--tokens` covers the rest.
+pub const MODELS: &[Model] = &[
+    model("claude-opus", 200_000, Tokenizer::Heuristic),
+    model("claude-sonnet", 200_000, Tokenizer::Heuristic),
+    model("claude-haiku", 200_000, Tokenizer::Heuristic),
+    model("gpt-4o", 128_000, Tokenizer::O200kBase),
+    model("gpt-4o-mini", 128_000, Tokenizer::O200kBase),
+    model("gpt-4.1", 1_047_576, Tokenizer::O200kBase),
+    model("o3", 200_000, Tokenizer::O200kBase),
+    model("gpt-4-turbo", 128_000, Tokenizer::Cl100kBase),
+    model("gpt-4", 8_192, Tokenizer::Cl100kBase),
+    model("gemini-pro", 1_048_576, Tokenizer::Heuristic),
+    model("gemini-flash", 1_048_576, Tokenizer::Heuristic),
+    model("llama-3", 128_000, Tokenizer::Heuristic),
+];
+
+const fn model(name: &'static str, context: usize, tokenizer: Tokenizer) -> Model {
+    Model {
+        name,
+        context,
+        tokenizer,
+    }
+}
+
+/// Share of a model's context `--model` leaves for the prompt and the answer,
+/// unless `--headroom` says otherwise
+pub const DEFAULT_HEADROOM: BudgetShare = BudgetShare::Percent(20.0);
+
+impl Model {
+    /// The model named `name`, ignoring case
+    pub fn find(name: &str) -> Option<Model> {
+        MODELS
+            .iter()
+            .find(|model| model.name.eq_ignore_ascii_case(name.trim()))
+            .copied()
+    }
+
+    /// Tokens left for flat's output once `headroom` is set aside
+    pub fn budget(self, headroom: BudgetShare) -> usize {
+        self.context - headroom.resolve(self.context)
+    }
+}
+
 /// Estimate the number of tokens for a piece of content.
 ///
 /// Uses pessimistic (conservative) estimation per PDR spec:
@@ -6,7 +158,11 @@
 ///
 /// This intentionally overestimates to stay within context windows.
 pub fn estimate_tokens(content: &str, is_prose: bool) -> usize {
-    let byte_count = content.len();
+    estimate_tokens_from_len(content.len(), is_prose)
+}
+
+/// [`estimate_tokens`] for content known only by its size in bytes
+pub fn estimate_tokens_from_len(byte_count: usize, is_prose: bool) -> usize {
     if is_prose {
         byte_count / 4
     } else {
@@ -26,6 +182,37 @@ pub fn is_prose_extension(ext: &str) -> bool {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_per_language_ratios_replace_the_heuristic() {
+        let ratios = TokenRatios::from([("rs".to_string(), 4.0)]);
+        let estimator = PerLanguage {
+            ratios: &ratios,
+            fallback: Tokenizer::Heuristic,
+        };
+        let code = "x".repeat(400);
+        assert_eq!(estimator.estimate(&code, "RS"), 100);
+        assert_eq!(estimator.estimate(&code, "py"), 133);
+        assert_eq!(estimator.estimate(&code, "md"), 100);
+        for ext in ["RS", "py", "md"] {
+            assert_eq!(
+                estimator.estimate_len(code.len(), ext),
+                Some(estimator.estimate(&code, ext))
+            );
+        }
+    }
+
+    #[test]
+    fn test_model_budget_leaves_headroom() {
+        let sonnet = Model::find("Claude-Sonnet").unwrap();
+        assert_eq!(sonnet.budget(DEFAULT_HEADROOM), 160_000);
+        assert_eq!(sonnet.budget(BudgetShare::Tokens(50_000)), 150_000);
+        assert_eq!(
+            Model::find("gpt-4o").unwrap().tokenizer,
+            Tokenizer::O200kBase
+        );
+        assert!(Model::find("gpt-5-ultra").is_none());
+    }
+
     #[test]
     fn test_estimate_tokens_code() {
         // 300 bytes of code = 100 tokens (300/3)
@@ -46,6 +233,20 @@ mod tests {
         assert_eq!(estimate_tokens("", true), 0);
     }
 
+    #[test]
+    fn test_heuristic_tokenizer_matches_estimate() {
+        let code = "x".repeat(300);
+        assert_eq!(Tokenizer::Heuristic.count(&code, false), 100);
+    }
+
+    #[cfg(feature = "tiktoken")]
+    #[test]
+    fn test_bpe_tokenizers_count_real_tokens() {
+        assert_eq!(Tokenizer::Cl100kBase.count("hello world", false), 2);
+        assert_eq!(Tokenizer::O200kBase.count("hello world", false), 2);
+        assert_eq!(Tokenizer::Cl100kBase.count("", false), 0);
+    }
+
     #[test]
     fn test_is_prose_extension() {
         assert!(is_prose_extension("md"));
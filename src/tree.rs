@@ -0,0 +1,64 @@
+//! Plain-text directory trees, for `--outline-only` and the `tree.txt` member
+//! of a `--bundle`.
+
+use std::collections::BTreeMap;
+
+/// Directory tree of `files` (relative, `/`-separated paths, each with an
+/// annotation written after its name), directories first and each level
+/// sorted by name, two spaces per level
+pub fn render(files: &[(String, Option<String>)]) -> String {
+    #[derive(Default)]
+    struct Node<'a> {
+        dirs: BTreeMap<&'a str, Node<'a>>,
+        files: Vec<(&'a str, Option<&'a str>)>,
+    }
+
+    fn write(node: &Node, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        for (name, child) in &node.dirs {
+            out.push_str(&format!("{}{}/\n", indent, name));
+            write(child, depth + 1, out);
+        }
+        for (name, annotation) in &node.files {
+            match annotation {
+                Some(annotation) => out.push_str(&format!("{}{} {}\n", indent, name, annotation)),
+                None => out.push_str(&format!("{}{}\n", indent, name)),
+            }
+        }
+    }
+
+    let mut sorted: Vec<_> = files.iter().collect();
+    sorted.sort();
+    let mut root = Node::default();
+    for (path, annotation) in sorted {
+        let mut components: Vec<&str> = path.split('/').collect();
+        let Some(file) = components.pop() else {
+            continue;
+        };
+        let dir = components
+            .into_iter()
+            .fold(&mut root, |node, name| node.dirs.entry(name).or_default());
+        dir.files.push((file, annotation.as_deref()));
+    }
+    let mut out = String::new();
+    write(&root, 0, &mut out);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tree_nests_directories_before_files() {
+        let files = [
+            ("README.md".to_string(), None),
+            ("src/lib.rs".to_string(), Some("(~40 tokens)".to_string())),
+            ("src/output/html.rs".to_string(), None),
+        ];
+        assert_eq!(
+            render(&files),
+            "src/\n  output/\n    html.rs\n  lib.rs (~40 tokens)\nREADME.md\n"
+        );
+    }
+}
@@ -1,147 +1,1309 @@
-use crate::compress::{compress_source, language_for_path, CompressResult};
-use crate::config::Config;
+use crate::bundle::write_bundle;
+use crate::cache::{self, CompressionCache};
+use crate::compress::{
+    compress_file, language_for_path, CompressLanguage, CompressResult, NodeRules,
+};
+use crate::config::{Config, DiffSelection, NO_EXTENSION, PART_PLACEHOLDER};
+use crate::doc_links::{extract_link_targets, normalize, resolve_targets};
+use crate::encoding::read_text;
+use crate::export::{entry_name, export_selection};
 use crate::filters::{
-    exceeds_size_limit, is_binary_content, is_binary_extension, is_secret_file, SkipReason,
+    is_binary_extension, is_default_excluded_dir, is_minified, is_secret_file, SkipReason,
+};
+use crate::format::TRUNCATION_MARKER;
+use crate::git::{self, LineChanges};
+use crate::lock::{AtomicFile, LockMode};
+use crate::manifest::write_manifest;
+use crate::minified::{self, Shortening};
+use crate::output::{
+    FileAttrs, FileMode, Layout, Order, OutputWriter, PrunedDir, Statistics, BUDGET_EXCLUSION,
+};
+use crate::overrides::OverrideAction;
+use crate::priority::{
+    classify, entry_score, score_file, Role, ATTACHMENT_SCORE, DEMOTED_DOCS_SCORE, DOC_LINK_SCORE,
+    RELATED_SCORE,
 };
-use crate::output::{OutputWriter, Statistics};
-use crate::priority::score_file;
-use crate::tokens::{estimate_tokens, is_prose_extension};
-use anyhow::{Context, Result};
+use crate::profiles::Profiles;
+use crate::progress::{Phase, ProgressTracker};
+use crate::redact::redact;
+use crate::related::{import_distances, import_name, imported_names};
+use crate::report::{Decision, FileReport};
+use crate::since::ChangeSource;
+use crate::source::ContentSource;
+use crate::template::{expand, TemplateVars};
+use crate::tokens::{estimate_tokens_from_len, is_prose_extension};
+use crate::tree;
+use crate::warnings::Warnings;
+use anyhow::{bail, Context, Result};
+use globset::GlobMatcher;
 use ignore::WalkBuilder;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
-/// A file candidate with its content and metadata for budget allocation
+/// A file in the running for the token budget, ranked before its content is read
 struct FileCandidate {
     path: PathBuf,
-    content: String,
+    /// Size on disk
+    len: u64,
     score: u32,
-    is_prose: bool,
+    role: Role,
+}
+
+/// A candidate's content, read when its turn comes in the allocation and
+/// dropped once its decision is made
+struct LoadedFile {
+    content: String,
+    /// Tokens `content` costs written whole
+    full_tokens: usize,
+    /// Values masked in `content` under `--redact`
+    redactions: usize,
+    /// How `content` was cut down, if the file was minified
+    shortening: Option<Shortening>,
+}
+
+impl Allocation<'_> {
+    /// How much of the file is written, as its tag and its `--dry-run` line say
+    fn attrs<'a>(&self) -> FileAttrs<'a> {
+        match self.decision {
+            FileDecision::IncludeFull(_) => {
+                shortened_attrs(FileAttrs::with_mode(Some(FileMode::Full)), self.shortening)
+            }
+            FileDecision::IncludeCompressed(_) => {
+                FileAttrs::with_mode(Some(compressed_mode(&self.candidate.path)))
+            }
+            FileDecision::IncludeTruncated(_) => FileAttrs::with_mode(Some(FileMode::Truncated)),
+            FileDecision::Excluded => FileAttrs::default(),
+        }
+    }
+}
+
+/// What the budget made of one candidate. Only the written form of an
+/// included file is held, so what stays in memory is bounded by the budget.
+struct Allocation<'a> {
+    candidate: &'a FileCandidate,
+    decision: FileDecision,
+    /// Tokens charged against the budget
+    charged: usize,
+    /// Bytes of the file as read, before any compression or truncation
+    full_len: usize,
+    full_tokens: usize,
+    shortening: Option<Shortening>,
+}
+
+/// Caps on tokens inside the overall budget: of tests and docs
+/// (`--tests-budget`, `--docs-budget`), and of directories (`--quota`)
+struct CategoryQuotas {
+    tests: Option<usize>,
+    docs: Option<usize>,
+    tests_used: usize,
+    docs_used: usize,
+    /// Each capped directory, its cap, and what its files have used
+    dirs: Vec<(PathBuf, usize, usize)>,
+}
+
+impl CategoryQuotas {
+    fn new(config: &Config, budget: usize) -> Self {
+        Self {
+            tests: config.tests_budget.map(|share| share.resolve(budget)),
+            docs: config.docs_budget.map(|share| share.resolve(budget)),
+            tests_used: 0,
+            docs_used: 0,
+            dirs: config
+                .directory_quotas
+                .iter()
+                .map(|(dir, share)| (config.path.join(dir), share.resolve(budget), 0))
+                .collect(),
+        }
+    }
+
+    /// Tokens still available to the file at `path` of this role, if its
+    /// category or a directory it is in is capped; nested caps all apply
+    fn available(&self, path: &Path, role: Role) -> Option<usize> {
+        let category = match role {
+            Role::Test | Role::Fixture => self.tests.map(|cap| cap.saturating_sub(self.tests_used)),
+            Role::Design | Role::Docs => self.docs.map(|cap| cap.saturating_sub(self.docs_used)),
+            _ => None,
+        };
+        self.dirs
+            .iter()
+            .filter(|(dir, _, _)| path.starts_with(dir))
+            .map(|(_, cap, used)| cap.saturating_sub(*used))
+            .chain(category)
+            .min()
+    }
+
+    fn charge(&mut self, path: &Path, role: Role, tokens: usize) {
+        match role {
+            Role::Test | Role::Fixture => self.tests_used += tokens,
+            Role::Design | Role::Docs => self.docs_used += tokens,
+            _ => {}
+        }
+        for (dir, _, used) in &mut self.dirs {
+            if path.starts_with(dir) {
+                *used += tokens;
+            }
+        }
+    }
 }
 
 /// Result of budget allocation for a single file
 enum FileDecision {
     IncludeFull(String),
     IncludeCompressed(String),
+    /// The start of the file, ending in the truncation marker (`--truncate`)
+    IncludeTruncated(String),
     Excluded,
 }
 
 pub fn walk_and_flatten(config: &Config) -> Result<Statistics> {
+    walk_and_flatten_with(config, ProgressTracker::default())
+}
+
+/// [`walk_and_flatten`], reporting to `progress` (see [`FlattenHandle`](crate::progress::FlattenHandle))
+pub(crate) fn walk_and_flatten_with(
+    config: &Config,
+    progress: ProgressTracker,
+) -> Result<Statistics> {
+    if let Some(template) = split_output_template(config) {
+        let stats = write_split_files(config, &template, progress)?;
+        if let Some(manifest) = &config.manifest {
+            write_manifest(manifest, config, &stats)?;
+        }
+        return Ok(stats);
+    }
+
+    // Create output writer; a file destination only changes once the run succeeds
+    let mut atomic_output = None;
+    let writer: Box<dyn Write> = match &config.output_file {
+        Some(path) => {
+            let (atomic, file) = AtomicFile::create(path, config.lock_timeout)?;
+            atomic_output = Some(atomic);
+            Box::new(file)
+        }
+        None => Box::new(std::io::stdout()),
+    };
+    let stats = match &config.bundle {
+        Some(bundle) => {
+            // The bundle needs the output too, so it is produced in memory first
+            let mut context = Vec::new();
+            let stats = flatten_walked(
+                config,
+                Box::new(&mut context),
+                Warnings::new(config.verbose),
+                progress,
+            )?;
+            let mut writer = writer;
+            writer
+                .write_all(&context)
+                .and_then(|_| writer.flush())
+                .context("Failed to write output")?;
+            write_bundle(bundle, config, &context, &stats)?;
+            stats
+        }
+        None => flatten_walked(config, writer, Warnings::new(config.verbose), progress)?,
+    };
+    if let Some(atomic) = atomic_output {
+        atomic.commit()?;
+    }
+    if let Some(manifest) = &config.manifest {
+        write_manifest(manifest, config, &stats)?;
+    }
+    Ok(stats)
+}
+
+/// The `-o` path under `--split-tokens` when it has a `{n}` for the part number
+fn split_output_template(config: &Config) -> Option<String> {
+    config.split_tokens?;
+    let path = config.output_file.as_ref()?.to_string_lossy().into_owned();
+    path.contains(PART_PLACEHOLDER).then_some(path)
+}
+
+/// Flatten in memory, then write each `--split-tokens` part to its own file,
+/// numbered from 1. The summary goes in the last part.
+fn write_split_files(
+    config: &Config,
+    template: &str,
+    progress: ProgressTracker,
+) -> Result<Statistics> {
+    let mut context = Vec::new();
+    let stats = flatten_walked(
+        config,
+        Box::new(&mut context),
+        Warnings::new(config.verbose),
+        progress,
+    )?;
+    // Whatever precedes the first part (a header, symlink stubs) opens part 1
+    let mut bounds = stats.part_offsets.clone();
+    match bounds.first_mut() {
+        Some(first) => *first = 0,
+        None => bounds.push(0),
+    }
+    bounds.push(context.len());
+
+    let mut parts = Vec::new();
+    for (n, range) in bounds.windows(2).enumerate() {
+        let path = PathBuf::from(template.replace(PART_PLACEHOLDER, &(n + 1).to_string()));
+        let (atomic, mut file) = AtomicFile::create(&path, config.lock_timeout)?;
+        file.write_all(&context[range[0]..range[1]])
+            .and_then(|_| file.flush())
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+        parts.push((atomic, path));
+    }
+    for (atomic, _) in parts {
+        atomic.commit()?;
+    }
+    if let Some(bundle) = &config.bundle {
+        write_bundle(bundle, config, &context, &stats)?;
+    }
+    Ok(stats)
+}
+
+/// Run the decision engine over `config.path` without producing output, and
+/// return what it decided for every file it saw: skipped by a filter, excluded
+/// by the budget, or included full or compressed.
+pub fn analyze(config: &Config) -> Result<Vec<FileReport>> {
+    analyze_with(config, Warnings::new(config.verbose))
+}
+
+/// [`analyze`], reporting to `warnings` — a silent collector for a second pass
+/// over files whose warnings were already printed
+pub(crate) fn analyze_with(config: &Config, warnings: Warnings) -> Result<Vec<FileReport>> {
+    let config = Config {
+        output_file: None,
+        dry_run: false,
+        stats_only: false,
+        export_selection: None,
+        ..config.clone()
+    };
+    let stats = flatten_walked(
+        &config,
+        Box::new(std::io::sink()),
+        warnings,
+        ProgressTracker::default(),
+    )?;
+    Ok(stats.file_reports)
+}
+
+/// Committers named per file under `--owners`
+const MAX_OWNERS: usize = 2;
+
+/// Walk `config.path` and flatten it into `writer`
+fn flatten_walked(
+    config: &Config,
+    writer: Box<dyn Write + '_>,
+    warnings: Warnings,
+    progress: ProgressTracker,
+) -> Result<Statistics> {
     let mut stats = Statistics::new();
+    stats.warnings = warnings;
+    stats.progress = progress;
+    stats.command = Some(config.to_command_line());
+    stats.project = config.project_type.map(|p| p.name().to_string());
+    stats.line_numbers = config.line_numbers.clone();
 
-    // Build the walker with gitignore support
-    let mut builder = WalkBuilder::new(&config.path);
-    builder.standard_filters(true);
+    let _cache_lock = match &config.cache_dir {
+        Some(root) => Some(cache::lock(root, LockMode::Shared, config.lock_timeout)?),
+        None => None,
+    };
 
-    if let Some(ref gitignore_path) = config.gitignore_path {
-        builder.add_custom_ignore_filename(gitignore_path);
+    let mut output = OutputWriter::with_format(writer, config.format)
+        .with_tokenizer(config.tokenizer)
+        .with_token_ratios(config.token_ratios.clone())
+        .with_split(config.split_tokens)
+        .with_line_numbers(config.line_numbers.clone())
+        .with_summary(config.summary)
+        .with_file_meta(config.file_meta);
+    let changes = match &config.diff_base {
+        Some(base) => Some(git::changed_files(&config.path, base)?),
+        None if config.staged => Some(git::staged_files(&config.path)?),
+        None => None,
+    };
+    let mut source = match &config.overlay_file {
+        Some(file) => ContentSource::overlay_from_json(file, &config.path)?,
+        // Staged files are flattened as staged, not as they are on disk
+        None if config.staged => {
+            let staged: Vec<PathBuf> = changes.iter().flatten().map(|(p, _)| p.clone()).collect();
+            ContentSource::overlay(git::index_contents(&config.path, &staged)?)
+        }
+        None => ContentSource::disk(),
     }
+    .with_normalized_newlines(config.normalize_newlines);
+    let attached = load_attachments(config, &mut source)?;
 
-    // Create output writer
-    let writer: Box<dyn Write> = match &config.output_file {
-        Some(path) => Box::new(
-            fs::File::create(path)
-                .with_context(|| format!("Failed to create output file: {}", path.display()))?,
+    // The run works from a copy of the config that knows what the diff touched,
+    // what changed recently, and who owns each file
+    let resolved;
+    let config = if changes.is_some() || config.since.is_some() || config.owners {
+        resolved = Config {
+            diff: changes.map(|changes| resolve_diff(config, changes, &source)),
+            recent: config
+                .since
+                .as_ref()
+                .map(|since| ChangeSource::resolve(since, &config.path))
+                .transpose()?,
+            file_owners: config
+                .owners
+                .then(|| git::owners(&config.path, MAX_OWNERS))
+                .transpose()?,
+            ..config.clone()
+        };
+        &resolved
+    } else {
+        config
+    };
+    let diff_files: Option<Vec<PathBuf>> = config.diff.as_ref().map(|diff| {
+        let mut files: Vec<_> = diff.changes.keys().chain(&diff.context).cloned().collect();
+        files.sort();
+        files
+    });
+    let listed = config.files_from.as_ref().or(diff_files.as_ref());
+    stats.progress.start(Phase::Walking, None)?;
+    let walk = match listed {
+        Some(_) => Walk::default(),
+        None => walk_tree(config, &mut stats)?,
+    };
+    let profiles = load_profiles(config, listed, &walk)?;
+    let profiled;
+    let config = if profiles.is_empty() {
+        config
+    } else {
+        profiled = Config {
+            profiles,
+            ..config.clone()
+        };
+        &profiled
+    };
+    let (mut files, symlinks) = match listed {
+        Some(list) => (
+            collect_listed_files(config, list, &source, &mut output, &mut stats)?,
+            Vec::new(),
         ),
-        None => Box::new(std::io::stdout()),
+        None => filter_walked_files(config, walk, &source, &mut output, &mut stats)?,
     };
 
-    let mut output = OutputWriter::new(writer);
+    // Overlay entries with no file on disk (e.g. new unsaved buffers) join the walked set
+    for path in source.memory_paths() {
+        if !path.exists()
+            && !attached.contains(&path)
+            && !files.contains(&path)
+            && filter_file(&path, config, &source, &mut output, &mut stats)?
+        {
+            files.push(path);
+        }
+    }
+    // Attachments were asked for by name, so no filter applies to them
+    for path in attached {
+        stats.add_included(path.extension().and_then(|e| e.to_str()));
+        if let Some(dir) = top_level_dir(&path, &config.path) {
+            stats.add_directory_included(&dir);
+        }
+        files.push(path);
+    }
+    if listed.is_none() {
+        record_unwalked_dirs(config, &mut stats);
+    }
+    let selection = config.export_selection.as_ref().map(|_| files.clone());
+    flatten_files(config, &source, files, &symlinks, &mut output, &mut stats)?;
+    stats.part_offsets = output.part_offsets().to_vec();
+    drop(output);
+
+    if let (Some(archive), Some(selection)) = (&config.export_selection, selection) {
+        write_selection_archive(archive, config, &source, selection, &stats)?;
+    }
+
+    Ok(stats)
+}
+
+/// Read the `--attach` artifacts into `source` at their places among the scanned
+/// files, and return those places
+fn load_attachments(config: &Config, source: &mut ContentSource) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for (name, file) in &config.attachments {
+        let path = config.attachment_path(name);
+        if path.exists() || paths.contains(&path) {
+            bail!(
+                "--attach {}={}: the scanned directory already has a file named {}",
+                name,
+                file.display(),
+                name
+            );
+        }
+        let content = read_text(file)
+            .with_context(|| format!("Failed to read --attach file: {}", file.display()))?;
+        source.attach(path.clone(), content);
+        paths.push(path);
+    }
+    Ok(paths)
+}
 
-    // First pass: collect all files
-    let mut files_to_process = Vec::new();
+/// The `.flat.toml` profiles for this run: those in every directory the walk
+/// entered, or for listed files, those in their directories and the ones
+/// above them up to the root
+fn load_profiles(config: &Config, listed: Option<&Vec<PathBuf>>, walk: &Walk) -> Result<Profiles> {
+    match listed {
+        Some(list) => Profiles::load(
+            list.iter()
+                .flat_map(|path| {
+                    let dirs = path.ancestors().skip(1);
+                    dirs.take_while(|dir| dir.starts_with(&config.path))
+                })
+                .map(Path::to_path_buf)
+                .collect::<HashSet<_>>(),
+        ),
+        None => Profiles::load(walk.dirs.iter().cloned()),
+    }
+}
 
-    for result in builder.build() {
+/// Archive the raw contents of every file that made it into the output
+fn write_selection_archive(
+    archive: &Path,
+    config: &Config,
+    source: &ContentSource,
+    mut selection: Vec<PathBuf>,
+    stats: &Statistics,
+) -> Result<()> {
+    selection.sort();
+    let excluded: HashSet<&str> = stats
+        .excluded_by_budget
+        .iter()
+        .map(String::as_str)
+        .collect();
+    let mut files = Vec::new();
+    for path in selection {
+        if excluded.contains(config.display_path(&path).as_str()) {
+            continue;
+        }
+        let content = source
+            .read(&path)
+            .with_context(|| format!("Failed to read {} for export", path.display()))?;
+        // Masked values stay masked in the archive
+        let content = if config.redacts(&path) {
+            redact(&content).0
+        } else {
+            content
+        };
+        files.push((path, content));
+    }
+    export_selection(archive, &config.path, &files)
+}
+
+/// Flatten caller-provided file contents instead of walking the filesystem.
+///
+/// Entries go through the same filtering, compression, budget, and formatting
+/// as a directory walk; `config.path` is only used as the base for relative
+/// matching and priority scoring. The rendered output is returned instead of
+/// being written to `config.output_file` or stdout.
+pub fn flatten_entries(entries: Vec<(PathBuf, String)>, config: &Config) -> Result<String> {
+    let mut stats = Statistics::new();
+    stats.warnings = Warnings::new(config.verbose);
+    let mut buffer = Vec::new();
+    let source = ContentSource::memory(entries).with_normalized_newlines(config.normalize_newlines);
+
+    {
+        let mut output = OutputWriter::with_format(Box::new(&mut buffer), config.format)
+            .with_tokenizer(config.tokenizer)
+            .with_token_ratios(config.token_ratios.clone())
+            .with_split(config.split_tokens)
+            .with_line_numbers(config.line_numbers.clone())
+            .with_summary(config.summary)
+            .with_file_meta(config.file_meta);
+        let mut files = Vec::new();
+        for path in source.memory_paths() {
+            if filter_file(&path, config, &source, &mut output, &mut stats)? {
+                files.push(path);
+            }
+        }
+        flatten_files(config, &source, files, &[], &mut output, &mut stats)?;
+    }
+
+    Ok(String::from_utf8_lossy(&buffer).into_owned())
+}
+
+/// Under `--follow-symlinks`, the files already reached, so a file linked into
+/// several places is flattened once
+struct ReachedFiles {
+    /// The scanned root as walked, and canonicalized; None when symlinks aren't followed
+    root: Option<(PathBuf, PathBuf)>,
+    seen: HashSet<PathBuf>,
+}
+
+impl ReachedFiles {
+    fn new(config: &Config) -> Self {
+        let canonical = config
+            .follow_symlinks
+            .then(|| fs::canonicalize(&config.path).ok())
+            .flatten();
+        Self {
+            root: canonical.map(|canonical| (config.path.clone(), canonical)),
+            seen: HashSet::new(),
+        }
+    }
+
+    /// Whether `path` is the first way the walk reached its file. A link to a
+    /// file inside the root never is, since the walk reaches the file itself.
+    fn is_first(&mut self, path: &Path) -> bool {
+        let Some((walked, canonical)) = &self.root else {
+            return true;
+        };
+        let Ok(real) = fs::canonicalize(path) else {
+            return true;
+        };
+        let linked = path
+            .strip_prefix(walked)
+            .map_or(true, |relative| canonical.join(relative) != real);
+        if linked && real.starts_with(canonical) {
+            return false;
+        }
+        self.seen.insert(real)
+    }
+}
+
+/// Whether a walk error is `--follow-symlinks` finding a link to one of its own ancestors
+fn is_symlink_loop(err: &ignore::Error) -> bool {
+    match err {
+        ignore::Error::Loop { .. } => true,
+        ignore::Error::WithPath { err, .. }
+        | ignore::Error::WithDepth { err, .. }
+        | ignore::Error::WithLineNumber { err, .. } => is_symlink_loop(err),
+        _ => false,
+    }
+}
+
+/// A symlink the walker did not follow, rendered as a stub pointing at its target
+struct Symlink {
+    path: PathBuf,
+    target: PathBuf,
+}
+
+/// What walking the tree found, before any file is filtered
+#[derive(Default)]
+struct Walk {
+    /// Files, and symlinks not followed, in walk order
+    entries: Vec<Walked>,
+    /// Directories the walk entered, where `.flat.toml` profiles are looked for
+    dirs: HashSet<PathBuf>,
+}
+
+enum Walked {
+    File(PathBuf),
+    Symlink(PathBuf),
+}
+
+/// First pass, part one: walk the directory tree once, noting its files and
+/// the directories entered. Filtering waits for the profiles those hold.
+fn walk_tree(config: &Config, stats: &mut Statistics) -> Result<Walk> {
+    let artifacts = OwnArtifacts::of(config);
+    let mut walk = Walk::default();
+
+    for result in walk_builder(config, &artifacts).build() {
         match result {
             Ok(entry) => {
                 let path = entry.path();
-
-                if path.is_dir() {
-                    continue;
+                if entry.depth() > 0 && entry.path_is_symlink() && !config.follow_symlinks {
+                    walk.entries.push(Walked::Symlink(path.to_path_buf()));
+                } else if path.is_dir() {
+                    // A directory at --max-depth is listed but not entered
+                    if config.max_depth.is_none_or(|depth| entry.depth() < depth) {
+                        walk.dirs.insert(path.to_path_buf());
+                    }
+                } else {
+                    stats.progress.advance(0)?;
+                    walk.entries.push(Walked::File(path.to_path_buf()));
                 }
+            }
+            Err(e) if is_symlink_loop(&e) => {
+                stats
+                    .warnings
+                    .emit("symlink loops", &format!("Not following symlink: {}", e));
+            }
+            Err(e) => {
+                stats
+                    .warnings
+                    .emit("walk errors", &format!("Error walking directory: {}", e));
+                stats.add_skipped(SkipReason::ReadError);
+            }
+        }
+    }
+    Ok(walk)
+}
 
-                if let Some(reason) = should_skip(path, config) {
-                    stats.add_skipped(reason.clone());
-                    if !config.stats_only {
-                        eprintln!("Skipping {}: {}", path.display(), reason);
+/// First pass, part two: keep the walked files that pass every filter
+fn filter_walked_files(
+    config: &Config,
+    walk: Walk,
+    source: &ContentSource,
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<(Vec<PathBuf>, Vec<Symlink>)> {
+    let artifacts = OwnArtifacts::of(config);
+    let mut files = Vec::new();
+    let mut symlinks = Vec::new();
+    let mut reached = ReachedFiles::new(config);
+
+    for entry in walk.entries {
+        let path = match entry {
+            Walked::Symlink(path) => {
+                if filter_symlink(&path, config, output, stats)? {
+                    if let Ok(target) = fs::read_link(&path) {
+                        symlinks.push(Symlink { path, target });
                     }
-                    continue;
                 }
+                continue;
+            }
+            Walked::File(path) => path,
+        };
+
+        // A previous run's `-o` target would otherwise double the output
+        if artifacts.is_file(&path) {
+            record_skip(&path, SkipReason::OwnOutput, config, output, stats)?;
+            continue;
+        }
+        if !reached.is_first(&path) {
+            record_skip(&path, SkipReason::Duplicate, config, output, stats)?;
+            continue;
+        }
+
+        if filter_file(&path, config, source, output, stats)? {
+            files.push(path);
+        }
+    }
+
+    record_pruned_dirs(config, &walk.dirs, &artifacts, stats);
+    Ok((files, symlinks))
+}
 
-                files_to_process.push(path.to_path_buf());
-                let extension = path.extension().and_then(|e| e.to_str());
-                stats.add_included(extension);
+/// A walker over `config.path` (or its `roots`) that honors the ignore files and never descends
+/// into default-excluded directories or flat's own artifacts
+fn walk_builder(config: &Config, artifacts: &OwnArtifacts) -> WalkBuilder {
+    let mut builder = match config.roots.split_first() {
+        Some((first, rest)) => {
+            let mut builder = WalkBuilder::new(first);
+            for root in rest {
+                builder.add(root);
             }
-            Err(e) => {
-                eprintln!("Error walking directory: {}", e);
-                stats.add_skipped(SkipReason::ReadError);
+            builder
+        }
+        None => WalkBuilder::new(&config.path),
+    };
+    builder
+        .standard_filters(true)
+        .hidden(!config.include_hidden)
+        .git_ignore(config.use_gitignore)
+        .git_exclude(config.use_gitignore)
+        .git_global(config.use_gitignore);
+    builder.add_custom_ignore_filename(FLATIGNORE_FILE_NAME);
+    if config.follow_symlinks {
+        // Sorted, so which of several links to the same file is kept doesn't vary
+        builder
+            .follow_links(true)
+            .sort_by_file_name(|a, b| a.cmp(b));
+    }
+
+    if let Some(ref gitignore_path) = config.gitignore_path {
+        builder.add_custom_ignore_filename(gitignore_path);
+    }
+
+    // Directories past --max-depth are never read, rather than read and filtered
+    builder.max_depth(config.max_depth);
+    let default_excludes = config.applies_default_excludes();
+    let pruned = artifacts.clone();
+    let prune_config = config.clone();
+    builder.filter_entry(move |entry| {
+        let is_dir = entry.depth() > 0 && entry.file_type().is_some_and(|t| t.is_dir());
+        let name = entry.file_name().to_string_lossy();
+        // Only reachable under --hidden; a repository's internals are never source
+        let excluded = name == ".git" || (default_excludes && is_default_excluded_dir(&name));
+        !(is_dir
+            && (excluded || pruned.is_dir(entry.path()) || prune_config.is_pruned(entry.path())))
+    });
+    builder
+}
+
+/// Complete the files `--diff` or `--staged` found changed, with their line
+/// changes, by the unchanged source files whose names appear on their import lines
+fn resolve_diff(
+    config: &Config,
+    changes: Vec<(PathBuf, Option<LineChanges>)>,
+    source: &ContentSource,
+) -> DiffSelection {
+    let changes: HashMap<PathBuf, Option<LineChanges>> = changes.into_iter().collect();
+
+    let mut names = HashSet::new();
+    for path in changes.keys() {
+        if language_for_path(path).is_some() {
+            if let Ok(content) = source.read(path) {
+                names.extend(imported_names(&content));
             }
         }
     }
 
+    let mut context = HashSet::new();
+    if !names.is_empty() {
+        let builder = walk_builder(config, &OwnArtifacts::of(config));
+        for entry in builder.build().flatten() {
+            let path = entry.path();
+            let is_context = entry.file_type().is_some_and(|t| t.is_file())
+                && !changes.contains_key(path)
+                && language_for_path(path).is_some()
+                && import_name(path).is_some_and(|name| names.contains(&name));
+            if is_context {
+                context.insert(path.to_path_buf());
+            }
+        }
+    }
+    DiffSelection { changes, context }
+}
+
+/// Files and directories flat itself writes that sit inside the scanned root:
+/// the `-o` target, the `--manifest`, `--bundle` and `--export-selection` archives,
+/// and the cache
+#[derive(Clone)]
+struct OwnArtifacts {
+    files: Vec<PathBuf>,
+    dirs: Vec<PathBuf>,
+    /// Earlier outputs of a templated `-o`: its directory, and the names it gives
+    outputs: Option<(PathBuf, GlobMatcher)>,
+}
+
+impl OwnArtifacts {
+    fn of(config: &Config) -> Self {
+        // Compared by location, since `-o ./out.xml` and the walked
+        // `root/out.xml` are spelled differently
+        let root = fs::canonicalize(&config.path).unwrap_or_else(|_| config.path.clone());
+        let under_root = |path: &PathBuf| {
+            let relative = path.strip_prefix(&root).ok()?;
+            Some(config.path.join(relative))
+        };
+        // `out.xml` has an empty parent, meaning the working directory
+        let parent_of = |file: &PathBuf| {
+            let parent = file.parent().filter(|p| !p.as_os_str().is_empty());
+            fs::canonicalize(parent.unwrap_or(Path::new("."))).ok()
+        };
+        let files = [
+            &config.output_file,
+            &config.bundle,
+            &config.manifest,
+            &config.export_selection,
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|file| under_root(&parent_of(file)?.join(file.file_name()?)))
+        .collect();
+        let dirs = config
+            .cache_dir
+            .iter()
+            .filter_map(|dir| under_root(&fs::canonicalize(dir).ok()?))
+            .collect();
+        let outputs = config.output_file.as_ref().and_then(|file| {
+            let names = config.output_names.clone()?;
+            Some((under_root(&parent_of(file)?)?, names))
+        });
+        Self {
+            files,
+            dirs,
+            outputs,
+        }
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        let is_output = |(dir, names): &(PathBuf, GlobMatcher)| {
+            path.parent() == Some(dir.as_path())
+                && path.file_name().is_some_and(|name| names.is_match(name))
+        };
+        self.files.iter().any(|file| file == path) || self.outputs.as_ref().is_some_and(is_output)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.dirs.iter().any(|dir| dir == path)
+    }
+}
+
+/// Gitignore-syntax file, at the root or in any directory, for files that are
+/// tracked but never belong in the output
+pub const FLATIGNORE_FILE_NAME: &str = ".flatignore";
+
+/// First pass for `--files-from`: filter the listed paths without walking.
+/// Listed files bypass gitignore but not the secret, binary, size, and match rules.
+fn collect_listed_files(
+    config: &Config,
+    list: &[PathBuf],
+    source: &ContentSource,
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut files = Vec::new();
+    for path in list {
+        if !seen.insert(path) {
+            continue;
+        }
+        stats.progress.advance(0)?;
+        // Deleted files still show up in `git diff --name-only`; a file deleted
+        // after it was staged is still read from the index
+        if !path.is_file() && !source.holds(path) {
+            stats.warnings.emit(
+                "listed files not found",
+                &format!("Skipping {}: not a file", path.display()),
+            );
+            stats.add_skipped(SkipReason::ReadError);
+            continue;
+        }
+        if filter_file(path, config, source, output, stats)? {
+            files.push(path.clone());
+        }
+    }
+    Ok(files)
+}
+
+/// Apply the skip rules to one file, recording the outcome. Returns true if the file is kept.
+fn filter_file(
+    path: &Path,
+    config: &Config,
+    source: &ContentSource,
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<bool> {
+    if let Some(reason) = should_skip_in(path, config, source) {
+        record_skip(path, reason, config, output, stats)?;
+        return Ok(false);
+    }
+    if is_too_large_compressed(path, config, source, stats) {
+        record_skip(path, SkipReason::TooLarge, config, output, stats)?;
+        return Ok(false);
+    }
+
+    let extension = path.extension().and_then(|e| e.to_str());
+    stats.add_included(extension);
+    if let Some(dir) = top_level_dir(path, &config.path) {
+        stats.add_directory_included(&dir);
+    }
+    Ok(true)
+}
+
+/// Apply the name-based skip rules to an unfollowed symlink. Returns true if its stub is kept.
+fn filter_symlink(
+    path: &Path,
+    config: &Config,
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<bool> {
+    if let Some(reason) = should_skip_by_name(path, config) {
+        record_skip(path, reason, config, output, stats)?;
+        return Ok(false);
+    }
+
+    stats.symlinks += 1;
+    Ok(true)
+}
+
+fn record_skip(
+    path: &Path,
+    reason: SkipReason,
+    config: &Config,
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<()> {
+    if let Some(dir) = top_level_dir(path, &config.path) {
+        stats.add_directory_excluded(&dir, &reason.to_string());
+    }
+    if !config.stats_only {
+        stats.warnings.emit(
+            &format!("files skipped ({})", reason),
+            &format!("Skipping {}: {}", path.display(), reason),
+        );
+        output.write_skipped(&config.display_path(path), &reason.to_string())?;
+    }
+    let len = fs::metadata(path).map(|m| m.len() as usize).unwrap_or(0);
+    stats.add_file_report(
+        FileReport::new(path, &config.path, len, Decision::Skipped).with_reason(reason.to_string()),
+    );
+    stats.add_skipped(reason);
+    Ok(())
+}
+
+/// A file that passed the filters but couldn't be read: skipped as
+/// `undecodable` when its bytes aren't text, no longer counted as included,
+/// and a warning otherwise
+fn read_failed(
+    path: &Path,
+    error: &std::io::Error,
+    config: &Config,
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<()> {
+    if error.kind() == std::io::ErrorKind::InvalidData {
+        stats.remove_included(path.extension().and_then(|e| e.to_str()));
+        if let Some(dir) = top_level_dir(path, &config.path) {
+            stats.remove_directory_included(&dir);
+        }
+        return record_skip(path, SkipReason::Undecodable, config, output, stats);
+    }
+    stats.warnings.emit(
+        "read errors",
+        &format!("Error reading {}: {}", path.display(), error),
+    );
+    Ok(())
+}
+
+/// Count the files written from another encoding than UTF-8, for the summary
+fn count_transcoded(source: &ContentSource, stats: &mut Statistics) {
+    stats.transcoded_files = stats
+        .file_reports
+        .iter()
+        .filter(|report| !matches!(report.decision, Decision::Excluded | Decision::Skipped))
+        .filter(|report| source.is_transcoded(&report.path))
+        .count();
+}
+
+/// Under `--stats`, count a file of `len` output bytes toward the totals and
+/// its directory's breakdown
+fn add_size_estimate(config: &Config, path: &Path, len: usize, stats: &mut Statistics) {
+    stats.add_file_size_estimate(len as u64, config.display_path(path).len());
+    let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+    let tokens = estimate_tokens_from_len(len, is_prose_extension(ext));
+    let dir = top_level_dir(path, &config.path).unwrap_or_else(|| ".".to_string());
+    stats.add_directory_size(&dir, len, tokens);
+}
+
+/// The first path component below `root`, for files nested in a directory
+fn top_level_dir(path: &Path, root: &Path) -> Option<String> {
+    let relative = path.strip_prefix(root).ok()?;
+    let mut components = relative.components();
+    let first = components.next()?;
+    components.next()?;
+    Some(first.as_os_str().to_string_lossy().into_owned())
+}
+
+/// Record top-level directories the walker never entered (ignored or hidden)
+fn record_unwalked_dirs(config: &Config, stats: &mut Statistics) {
+    let Ok(entries) = fs::read_dir(&config.path) else {
+        return;
+    };
+    let artifacts = OwnArtifacts::of(config);
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+        if !is_dir
+            || name == ".git"
+            || stats.directories.contains_key(&name)
+            || !config.walks(&entry.path())
+        {
+            continue;
+        }
+        let is_empty = fs::read_dir(entry.path())
+            .map(|mut d| d.next().is_none())
+            .unwrap_or(true);
+        if is_empty {
+            continue;
+        }
+        stats.add_directory_excluded(&name, &prune_reason(config, &artifacts, &entry.path()));
+    }
+}
+
+/// Why the walker would not have descended into the directory `dir`
+fn prune_reason(config: &Config, artifacts: &OwnArtifacts, dir: &Path) -> String {
+    if artifacts.is_dir(dir) {
+        return SkipReason::OwnOutput.to_string();
+    }
+    let name = dir
+        .file_name()
+        .map(|n| n.to_string_lossy())
+        .unwrap_or_default();
+    let reason = if config.is_pruned(dir) {
+        "prune"
+    } else if config
+        .max_depth
+        .is_some_and(|depth| config.walk_depth(dir) >= depth)
+    {
+        "max-depth"
+    } else if config.applies_default_excludes() && is_default_excluded_dir(&name) {
+        "default exclude"
+    } else if name.starts_with('.') && !config.include_hidden {
+        "hidden"
+    } else {
+        "gitignore"
+    };
+    reason.to_string()
+}
+
+/// Stop counting a pruned directory's files here; the count is an estimate of
+/// skipped work, not worth a full walk of `node_modules`
+const PRUNED_COUNT_LIMIT: usize = 10_000;
+
+/// Record subdirectories of walked directories that the walker never entered,
+/// with a bounded count of the files inside each
+fn record_pruned_dirs(
+    config: &Config,
+    walked: &HashSet<PathBuf>,
+    artifacts: &OwnArtifacts,
+    stats: &mut Statistics,
+) {
+    let mut pruned = Vec::new();
+    for dir in walked {
+        let Ok(entries) = fs::read_dir(dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let is_dir = entry.file_type().is_ok_and(|t| t.is_dir());
+            if !is_dir || entry.file_name() == ".git" || walked.contains(&path) {
+                continue;
+            }
+            let files = count_files(&path, PRUNED_COUNT_LIMIT);
+            if files == 0 {
+                continue;
+            }
+            let relative = path.strip_prefix(&config.path).unwrap_or(&path);
+            pruned.push(PrunedDir {
+                path: relative.display().to_string(),
+                reason: prune_reason(config, artifacts, &path),
+                files,
+                files_capped: files >= PRUNED_COUNT_LIMIT,
+            });
+        }
+    }
+    pruned.sort_by(|a, b| a.path.cmp(&b.path));
+    stats.pruned_dirs = pruned;
+}
+
+/// Count the regular files under `dir` without following symlinks, stopping at `limit`
+fn count_files(dir: &Path, limit: usize) -> usize {
+    let mut count = 0;
+    let mut pending = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            match entry.file_type() {
+                Ok(t) if t.is_dir() => pending.push(entry.path()),
+                Ok(t) if t.is_file() => {
+                    count += 1;
+                    if count >= limit {
+                        return count;
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    count
+}
+
+/// Second pass: order, transform, and write the collected files
+fn flatten_files(
+    config: &Config,
+    source: &ContentSource,
+    mut files_to_process: Vec<PathBuf>,
+    symlinks: &[Symlink],
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<()> {
+    // What the files to write ask for themselves; `flat:skip` ones were filtered out
+    let pragmas: HashMap<PathBuf, OverrideAction> = files_to_process
+        .iter()
+        .filter_map(|path| Some((path.clone(), source.pragma(path)?)))
+        .filter(|(_, action)| *action != OverrideAction::Skip)
+        .collect();
+    let with_pragmas;
+    let config = if pragmas.is_empty() {
+        config
+    } else {
+        with_pragmas = Config {
+            pragmas,
+            ..config.clone()
+        };
+        &with_pragmas
+    };
     // Sort files by path for deterministic output
     files_to_process.sort();
+    if config.order == Some(Order::Priority) {
+        // Stable, so files of equal priority stay path-sorted
+        files_to_process.sort_by_cached_key(|path| {
+            Reverse(priority_score(config, path, classify(path, &config.path)))
+        });
+    }
+    sort_by_measure(config, source, &mut files_to_process, |path| path);
+    if config.layout == Layout::Sections {
+        // Stable, so files keep their order within each section
+        files_to_process.sort_by_key(|path| classify(path, &config.path).section());
+    }
+
+    // Header/footer snippets wrap whatever goes to the output destination
+    let template_vars = if config.header.is_some() || config.footer.is_some() {
+        Some(TemplateVars::for_root(&config.path))
+    } else {
+        None
+    };
+    if let (Some(header), Some(vars)) = (&config.header, &template_vars) {
+        if !config.stats_only {
+            output.write_header(&expand(header, vars))?;
+        }
+    }
 
-    // Handle token budget mode
-    if let Some(budget) = config.token_budget {
+    // Symlink stubs are tiny, so they go first and never count against a budget
+    if !config.stats_only {
+        for link in symlinks {
+            let path = config.display_path(&link.path);
+            let target = link.target.display().to_string();
+            if config.dry_run {
+                output.write_file_listing(&path, Some(&format!("-> {}", target)))?;
+            } else {
+                output.write_symlink(&path, &target)?;
+            }
+        }
+    }
+
+    let phase = match config.token_budget {
+        Some(_) if !config.outline_only => Phase::Reading,
+        _ => Phase::Writing,
+    };
+    stats.progress.start(phase, Some(files_to_process.len()))?;
+
+    if config.outline_only {
+        write_outline(config, source, &files_to_process, output, stats)?;
+    } else if let Some(budget) = config.allocation_budget() {
         stats.token_budget = Some(budget);
-        write_with_budget(config, &files_to_process, &mut output, &mut stats, budget)?;
+        stats.reserved_tokens = config.reserved_tokens();
+        write_with_budget(config, source, &files_to_process, output, stats, budget)?;
     } else if config.stats_only {
         for path in &files_to_process {
-            let path_str = path.display().to_string();
-            if config.compress {
-                let file_name = path
-                    .file_name()
-                    .map(|f| f.to_string_lossy().to_string())
-                    .unwrap_or_default();
-                let is_full = config.is_full_match(&file_name);
-                if !is_full {
-                    if let Some(lang) = language_for_path(path) {
-                        if let Ok(content) = fs::read_to_string(path) {
-                            match compress_source(&content, lang) {
-                                CompressResult::Compressed(compressed) => {
-                                    stats.add_file_size_estimate(
-                                        compressed.len() as u64,
-                                        path_str.len(),
-                                    );
-                                    stats.add_compressed();
-                                    continue;
-                                }
-                                CompressResult::Fallback(original, _) => {
-                                    stats.add_file_size_estimate(
-                                        original.len() as u64,
-                                        path_str.len(),
-                                    );
-                                    continue;
-                                }
+            stats.progress.advance(source.len(path).unwrap_or(0))?;
+            if config.shortens_minified(path) && source.is_minified(path) {
+                if let Ok(content) = source.read(path) {
+                    let (content, shortening) = shorten_minified(config, path, content, stats);
+                    stats.minified_files += usize::from(shortening.is_some());
+                    add_size_estimate(config, path, content.len(), stats);
+                    stats.add_file_report(FileReport::new(
+                        path,
+                        &config.path,
+                        content.len(),
+                        Decision::Full,
+                    ));
+                    continue;
+                }
+            }
+            if config.should_compress(path) {
+                if let Some(lang) = language_for_path(path) {
+                    if let Ok(content) = source.read(path) {
+                        match compress_cached(config, path, &content, lang, stats) {
+                            CompressResult::Compressed(compressed) => {
+                                add_size_estimate(config, path, compressed.len(), stats);
+                                stats.add_compressed();
+                                stats.add_file_report(
+                                    FileReport::new(
+                                        path,
+                                        &config.path,
+                                        content.len(),
+                                        Decision::Compressed,
+                                    )
+                                    .with_compressed_len(compressed.len()),
+                                );
+                                continue;
+                            }
+                            CompressResult::Fallback(original, _) => {
+                                add_size_estimate(config, path, original.len(), stats);
+                                stats.add_file_report(FileReport::new(
+                                    path,
+                                    &config.path,
+                                    original.len(),
+                                    Decision::Full,
+                                ));
+                                continue;
                             }
                         }
                     }
                 }
             }
             // Non-compress mode, full-match files, or non-compressible files: use raw size
-            if let Ok(metadata) = fs::metadata(path) {
-                stats.add_file_size_estimate(metadata.len(), path_str.len());
+            if let Some(len) = source.len(path) {
+                add_size_estimate(config, path, len as usize, stats);
+                stats.add_file_report(FileReport::new(
+                    path,
+                    &config.path,
+                    len as usize,
+                    Decision::Full,
+                ));
             }
         }
         eprintln!("{}", stats.format_summary());
     } else if config.dry_run {
+        let mut total = 0;
         for path in &files_to_process {
-            output.write_file_path(&path.display().to_string())?;
+            stats.progress.advance(source.len(path).unwrap_or(0))?;
+            enter_section(config, path, output)?;
+            let len = source.len(path).unwrap_or(0) as usize;
+            let mut report = FileReport::new(path, &config.path, len, Decision::Full);
+            // Files are only read to estimate what they compress to
+            let lang = language_for_path(path).filter(|_| config.should_compress(path));
+            if let (Some(lang), Ok(content)) = (lang, source.read(path)) {
+                match compress_cached(config, path, &content, lang, stats) {
+                    CompressResult::Compressed(compressed) => {
+                        report = FileReport {
+                            decision: Decision::Compressed,
+                            ..report.with_compressed_len(compressed.len())
+                        };
+                    }
+                    CompressResult::Fallback(_, Some(reason)) => {
+                        warn_compression_failed(stats, &path.display().to_string(), &reason);
+                    }
+                    CompressResult::Fallback(_, None) => {}
+                }
+            }
+            total += report.tokens_written();
+            let written = report
+                .tokens_compressed
+                .map(|tokens| ("compressed", tokens));
+            output.write_file_listing(
+                &config.display_path(path),
+                Some(&listing_tokens(report.tokens_full, written, Some(total))),
+            )?;
+            stats.add_file_report(report);
         }
+        output.close_section()?;
+        count_transcoded(source, stats);
         stats.add_output_bytes(output.bytes_written());
-        output.write_summary(&stats)?;
+        output.write_summary(stats)?;
     } else {
-        write_normal(config, &files_to_process, &mut output, &mut stats)?;
+        write_normal(config, source, &files_to_process, output, stats)?;
     }
 
-    Ok(stats)
+    stats.warnings.flush();
+
+    if let (Some(footer), Some(vars)) = (&config.footer, &template_vars) {
+        if !config.stats_only {
+            output.write_footer(&expand(footer, vars))?;
+        }
+    }
+    if !config.stats_only {
+        output.finish()?;
+    }
+
+    Ok(())
+}
+
+/// A file's priority with `--demote-docs` and `--priority-boost`/`--priority-drop` applied,
+/// before any doc-link or `--related` boost
+fn priority_score(config: &Config, path: &Path, role: Role) -> u32 {
+    let score = match role {
+        _ if config.is_attachment(path) => ATTACHMENT_SCORE,
+        Role::Design | Role::Docs if config.demote_docs => DEMOTED_DOCS_SCORE,
+        _ => score_file(path, &config.path),
+    };
+    config.adjust_priority(path, score)
 }
 
-/// Write files with token budget allocation
+/// Write files with token budget allocation.
+///
+/// Files are ranked by path and size alone, then read one at a time in rank
+/// order as the budget is handed out, so a huge tree never sits in memory:
+/// only what will be written is kept until it is.
 fn write_with_budget(
     config: &Config,
+    source: &ContentSource,
     files: &[PathBuf],
     output: &mut OutputWriter,
     stats: &mut Statistics,
@@ -149,161 +1311,253 @@ fn write_with_budget(
 ) -> Result<()> {
     let base_path = &config.path;
 
-    // Read all file contents and compute scores
+    // Rank without reading
     let mut candidates: Vec<FileCandidate> = Vec::new();
     for path in files {
-        match fs::read_to_string(path) {
-            Ok(content) => {
-                let score = score_file(path, base_path);
-                let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
-                let is_prose = is_prose_extension(ext);
-                candidates.push(FileCandidate {
-                    path: path.clone(),
-                    content,
-                    score,
-                    is_prose,
-                });
-            }
-            Err(e) => {
-                eprintln!("Error reading {}: {}", path.display(), e);
-            }
-        }
+        let len = source.len(path).unwrap_or(0);
+        stats.progress.advance(len)?;
+        let role = classify(path, base_path);
+        candidates.push(FileCandidate {
+            path: path.clone(),
+            len,
+            score: priority_score(config, path, role),
+            role,
+        });
+    }
+
+    if config.follow_doc_links {
+        boost_doc_links(&mut candidates, source, base_path, stats);
+    }
+    if let Some(related) = &config.related {
+        boost_related(&mut candidates, source, related, base_path, stats)?;
+    }
+    if let Some(entry) = &config.entry {
+        boost_entry(&mut candidates, source, entry, base_path, stats)?;
     }
 
     // Sort by (score DESC, path ASC) — stable sort
     candidates.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
 
     let mut remaining_budget = budget;
+    let mut quotas = CategoryQuotas::new(config, budget);
+    let mut allocations: Vec<Allocation> = Vec::new();
 
-    // Allocate full-match files first (if --tokens + --compress + --full-match)
-    let mut decisions: Vec<(&FileCandidate, FileDecision)> = Vec::new();
-
+    stats
+        .progress
+        .start(Phase::Allocating, Some(candidates.len()))?;
     for candidate in &candidates {
-        let display_path = candidate.path.display().to_string();
-        let file_name = candidate
-            .path
-            .file_name()
-            .map(|f| f.to_string_lossy().to_string())
-            .unwrap_or_default();
-        let full_tokens = estimate_tokens(&candidate.content, candidate.is_prose);
-
-        if config.compress && config.is_full_match(&file_name) {
-            // Full-match files: always use full content, never compress
-            if full_tokens <= remaining_budget {
-                remaining_budget -= full_tokens;
-                stats.tokens_used += full_tokens;
-                decisions.push((
-                    candidate,
-                    FileDecision::IncludeFull(candidate.content.clone()),
-                ));
-            } else {
-                stats.excluded_by_budget.push(display_path);
-                decisions.push((candidate, FileDecision::Excluded));
-            }
-        } else if full_tokens <= remaining_budget {
-            // File fits in full
-            remaining_budget -= full_tokens;
-            stats.tokens_used += full_tokens;
-            if config.compress {
-                // Even though it fits, still compress if possible (per flag behavior)
-                let content = maybe_compress(config, &candidate.path, &candidate.content, stats);
-                decisions.push((candidate, content));
-            } else {
-                decisions.push((
-                    candidate,
-                    FileDecision::IncludeFull(candidate.content.clone()),
-                ));
-            }
-        } else if config.compress {
-            // Try compressed version
-            if let Some(lang) = language_for_path(&candidate.path) {
-                match compress_source(&candidate.content, lang) {
-                    CompressResult::Compressed(compressed) => {
-                        let compressed_tokens = estimate_tokens(&compressed, candidate.is_prose);
-                        if compressed_tokens <= remaining_budget {
-                            remaining_budget -= compressed_tokens;
-                            stats.tokens_used += compressed_tokens;
-                            stats.add_compressed();
-                            decisions
-                                .push((candidate, FileDecision::IncludeCompressed(compressed)));
-                        } else {
-                            stats.excluded_by_budget.push(display_path);
-                            decisions.push((candidate, FileDecision::Excluded));
-                        }
-                    }
-                    CompressResult::Fallback(original, reason) => {
-                        if let Some(reason) = &reason {
-                            eprintln!(
-                                "Warning: compression failed for {}: {}, including full content",
-                                display_path, reason
-                            );
-                        }
-                        // Fallback is full size, which we already know doesn't fit
-                        let fallback_tokens = estimate_tokens(&original, candidate.is_prose);
-                        if fallback_tokens <= remaining_budget {
-                            remaining_budget -= fallback_tokens;
-                            stats.tokens_used += fallback_tokens;
-                            decisions.push((candidate, FileDecision::IncludeFull(original)));
-                        } else {
-                            stats.excluded_by_budget.push(display_path);
-                            decisions.push((candidate, FileDecision::Excluded));
-                        }
-                    }
+        stats.progress.advance(candidate.len)?;
+        let file = match read_selected(config, source, &candidate.path) {
+            Ok((content, redactions)) => {
+                let (content, shortening) =
+                    shorten_minified(config, &candidate.path, content, stats);
+                LoadedFile {
+                    full_tokens: config.emitted_tokens(&candidate.path, &content),
+                    content,
+                    redactions,
+                    shortening,
                 }
-            } else {
-                // Unsupported for compression, and full doesn't fit
-                stats.excluded_by_budget.push(display_path);
-                decisions.push((candidate, FileDecision::Excluded));
             }
-        } else {
-            // No compression, doesn't fit
-            stats.excluded_by_budget.push(display_path);
-            decisions.push((candidate, FileDecision::Excluded));
+            Err(e) => {
+                read_failed(&candidate.path, &e, config, output, stats)?;
+                continue;
+            }
+        };
+        let available = match quotas.available(&candidate.path, candidate.role) {
+            Some(quota) => quota.min(remaining_budget),
+            None => remaining_budget,
+        };
+        let (decision, charged) = allocate(config, &candidate.path, &file, available, stats);
+        match decision {
+            FileDecision::Excluded => {
+                stats
+                    .excluded_by_budget
+                    .push(config.display_path(&candidate.path));
+            }
+            _ => {
+                remaining_budget -= charged;
+                stats.tokens_used += charged;
+                stats.redactions += file.redactions;
+                stats.minified_files += usize::from(file.shortening.is_some());
+                stats.truncated_files +=
+                    usize::from(matches!(decision, FileDecision::IncludeTruncated(_)));
+                quotas.charge(&candidate.path, candidate.role, charged);
+            }
+        }
+        allocations.push(Allocation {
+            candidate,
+            decision,
+            charged,
+            full_len: file.content.len(),
+            full_tokens: file.full_tokens,
+            shortening: file.shortening,
+        });
+    }
+
+    for allocation in &allocations {
+        if matches!(allocation.decision, FileDecision::Excluded) {
+            if let Some(dir) = top_level_dir(&allocation.candidate.path, &config.path) {
+                stats.add_directory_budget_exclusion(&dir);
+            }
         }
     }
 
+    for allocation in &allocations {
+        let candidate = allocation.candidate;
+        let report = FileReport::new(
+            &candidate.path,
+            &config.path,
+            allocation.full_len,
+            Decision::Full,
+        );
+        let report = match &allocation.decision {
+            FileDecision::IncludeFull(_) => report,
+            FileDecision::IncludeCompressed(content) => FileReport {
+                decision: Decision::Compressed,
+                ..report.with_compressed_len(content.len())
+            },
+            FileDecision::IncludeTruncated(content) => FileReport {
+                decision: Decision::Truncated,
+                ..report.with_compressed_len(content.len())
+            },
+            FileDecision::Excluded => FileReport {
+                decision: Decision::Excluded,
+                ..report.with_reason("budget")
+            },
+        };
+        // The allocation score, including any doc-link boost
+        stats.add_file_report(FileReport {
+            score: candidate.score,
+            ..report
+        });
+    }
+
+    // Allocations are in score order, which stays unless --order asks for
+    // another; sections regroup them by role, path-sorted within each unless
+    // --order priority keeps score order there too
+    let reorder = match config.order {
+        None => config.layout == Layout::Sections,
+        Some(Order::Priority) => false,
+        Some(_) => true,
+    };
+    if reorder {
+        allocations.sort_by(|a, b| a.candidate.path.cmp(&b.candidate.path));
+        sort_by_measure(config, source, &mut allocations, |a| &a.candidate.path);
+    }
+    if config.layout == Layout::Sections {
+        allocations.sort_by_key(|a| a.candidate.role.section());
+    }
+
     // Write output
+    stats
+        .progress
+        .start(Phase::Writing, Some(allocations.len()))?;
     if config.stats_only {
-        for (candidate, decision) in &decisions {
-            match decision {
-                FileDecision::IncludeFull(content) | FileDecision::IncludeCompressed(content) => {
-                    let path_str = candidate.path.display().to_string();
-                    stats.add_file_size_estimate(content.len() as u64, path_str.len());
+        for allocation in &allocations {
+            stats.progress.advance(allocation.full_len as u64)?;
+            match &allocation.decision {
+                FileDecision::IncludeFull(content)
+                | FileDecision::IncludeCompressed(content)
+                | FileDecision::IncludeTruncated(content) => {
+                    add_size_estimate(config, &allocation.candidate.path, content.len(), stats);
                 }
                 FileDecision::Excluded => {}
             }
         }
         eprintln!("{}", stats.format_summary());
     } else if config.dry_run {
-        for (candidate, decision) in &decisions {
-            let display_path = candidate.path.display().to_string();
-            let annotation = match decision {
-                FileDecision::IncludeFull(_) => "[FULL]",
-                FileDecision::IncludeCompressed(_) => "[COMPRESSED]",
-                FileDecision::Excluded => "[EXCLUDED]",
+        let mut total = 0;
+        for allocation in &allocations {
+            let candidate = allocation.candidate;
+            stats.progress.advance(allocation.full_len as u64)?;
+            if config.layout == Layout::Sections {
+                output.enter_section(candidate.role.section().title())?;
+            }
+            let display_path = config.display_path(&candidate.path);
+            let cut = |how: &'static str, content: &str| {
+                let tokens = config.emitted_tokens(&candidate.path, content);
+                Some((how, tokens))
             };
-            output.write_file_path(&format!("{} {}", display_path, annotation))?;
+            let cut = match &allocation.decision {
+                FileDecision::IncludeCompressed(content) => cut("compressed", content),
+                FileDecision::IncludeTruncated(content) => cut("truncated", content),
+                FileDecision::IncludeFull(_) | FileDecision::Excluded => None,
+            };
+            // Spelled as the output's tags and stubs spell them
+            let (label, tokens) = if matches!(allocation.decision, FileDecision::Excluded) {
+                let tokens = listing_tokens(allocation.full_tokens, None, None);
+                (format!("excluded-reason={}", BUDGET_EXCLUSION), tokens)
+            } else {
+                total += allocation.charged;
+                let tokens = listing_tokens(allocation.full_tokens, cut, Some(total));
+                (allocation.attrs().listing_label(), tokens)
+            };
+            output.write_file_listing(&display_path, Some(&format!("{} {}", label, tokens)))?;
         }
+        output.close_section()?;
+        count_transcoded(source, stats);
         stats.add_output_bytes(output.bytes_written());
         output.write_summary(stats)?;
     } else {
-        for (candidate, decision) in &decisions {
-            let display_path = candidate.path.display().to_string();
-            match decision {
+        for allocation in &allocations {
+            let candidate = allocation.candidate;
+            stats.progress.advance(allocation.full_len as u64)?;
+            let display_path = config.display_path(&candidate.path);
+            if config.layout == Layout::Sections
+                && !matches!(allocation.decision, FileDecision::Excluded)
+            {
+                output.enter_section(candidate.role.section().title())?;
+            }
+            match &allocation.decision {
                 FileDecision::IncludeFull(content) => {
-                    let mode = if config.compress { Some("full") } else { None };
-                    output.write_file_content_with_mode(&display_path, content, mode)?;
+                    output.write_file_content_with_attrs(
+                        &display_path,
+                        content,
+                        allocation
+                            .attrs()
+                            .with_lines(config.line_range_for(&candidate.path))
+                            .with_annotation(annotation(config, candidate.role, candidate.score))
+                            .with_changes(config.line_changes_for(&candidate.path))
+                            .with_owners(config.owners_for(&candidate.path)),
+                    )?;
                 }
                 FileDecision::IncludeCompressed(content) => {
-                    output.write_file_content_with_mode(
+                    output.write_compressed(&display_path, allocation.full_len, content.len())?;
+                    output.write_file_content_with_attrs(
+                        &display_path,
+                        content,
+                        allocation
+                            .attrs()
+                            .with_annotation(annotation(config, candidate.role, candidate.score))
+                            .with_changes(config.line_changes_for(&candidate.path))
+                            .with_owners(config.owners_for(&candidate.path)),
+                    )?;
+                }
+                FileDecision::IncludeTruncated(content) => {
+                    output.write_file_content_with_attrs(
                         &display_path,
                         content,
-                        Some("compressed"),
+                        allocation
+                            .attrs()
+                            .with_annotation(annotation(config, candidate.role, candidate.score))
+                            .with_changes(config.line_changes_for(&candidate.path))
+                            .with_owners(config.owners_for(&candidate.path)),
                     )?;
                 }
                 FileDecision::Excluded => {}
             }
         }
+        output.close_section()?;
+        // Stubs for what didn't fit go after the content, outside any section
+        for allocation in &allocations {
+            if matches!(allocation.decision, FileDecision::Excluded) {
+                output.write_excluded(
+                    &config.display_path(&allocation.candidate.path),
+                    BUDGET_EXCLUSION,
+                )?;
+            }
+        }
+        count_transcoded(source, stats);
         stats.add_output_bytes(output.bytes_written());
         output.write_summary(stats)?;
     }
@@ -311,77 +1565,536 @@ fn write_with_budget(
     Ok(())
 }
 
+/// Reorder path-sorted `items` smallest or newest first under `--order size`,
+/// `tokens`, or `mtime`. Stable, so ties stay path-sorted; a file with no
+/// modification time (an overlay entry) goes last.
+fn sort_by_measure<T>(
+    config: &Config,
+    source: &ContentSource,
+    items: &mut [T],
+    path: impl Fn(&T) -> &Path,
+) {
+    match config.order {
+        Some(Order::Size) => items.sort_by_cached_key(|item| source.len(path(item)).unwrap_or(0)),
+        Some(Order::Tokens) => items.sort_by_cached_key(|item| {
+            let path = path(item);
+            let ext = path.extension().and_then(|e| e.to_str()).unwrap_or("");
+            let len = source.len(path).unwrap_or(0) as usize;
+            estimate_tokens_from_len(len, is_prose_extension(ext))
+        }),
+        Some(Order::Mtime) => items.sort_by_cached_key(|item| {
+            Reverse(fs::metadata(path(item)).and_then(|m| m.modified()).ok())
+        }),
+        Some(Order::Path | Order::Priority) | None => {}
+    }
+}
+
+/// Token counts on a `--dry-run` line: `~1,200 tokens, ~300 compressed; 4,500
+/// total`, where `cut` is how and to what the file was cut down, and `total`
+/// the running count of tokens written, for files that are
+fn listing_tokens(full: usize, cut: Option<(&str, usize)>, total: Option<usize>) -> String {
+    let mut tokens = format!("~{} tokens", Statistics::format_tokens(full));
+    if let Some((how, written)) = cut {
+        tokens.push_str(&format!(
+            ", ~{} {}",
+            Statistics::format_tokens(written),
+            how
+        ));
+    }
+    if let Some(total) = total {
+        tokens.push_str(&format!("; {} total", Statistics::format_tokens(total)));
+    }
+    tokens
+}
+
+/// Raise the score of files that the README or docs link to; documentation
+/// usually names the modules that matter most
+fn boost_doc_links(
+    candidates: &mut [FileCandidate],
+    source: &ContentSource,
+    base_path: &Path,
+    stats: &mut Statistics,
+) {
+    let mut linked = HashSet::new();
+    for candidate in candidates.iter() {
+        let is_markdown = candidate
+            .path
+            .extension()
+            .is_some_and(|e| e.eq_ignore_ascii_case("md") || e.eq_ignore_ascii_case("markdown"));
+        if !is_markdown || !matches!(candidate.role, Role::Readme | Role::Design | Role::Docs) {
+            continue;
+        }
+        // Unreadable docs are warned about when their turn comes to be allocated
+        if let Ok(content) = source.read(&candidate.path) {
+            let targets = extract_link_targets(&content);
+            linked.extend(resolve_targets(&candidate.path, base_path, &targets));
+        }
+    }
+
+    for candidate in candidates.iter_mut() {
+        if candidate.score < DOC_LINK_SCORE && linked.contains(&normalize(&candidate.path)) {
+            candidate.score = DOC_LINK_SCORE;
+            stats.doc_link_boosts += 1;
+        }
+    }
+}
+
+/// Raise the score of the `--related` file and of the files whose names appear
+/// on its import lines
+fn boost_related(
+    candidates: &mut [FileCandidate],
+    source: &ContentSource,
+    related: &Path,
+    base_path: &Path,
+    stats: &mut Statistics,
+) -> Result<()> {
+    // Given relative to the working directory, or else to the scanned root
+    let related = if related.exists() {
+        related.to_path_buf()
+    } else {
+        base_path.join(related)
+    };
+    let related = normalize(&related);
+    let content = match candidates.iter().find(|c| normalize(&c.path) == related) {
+        Some(candidate) => source.read(&candidate.path),
+        None => fs::read_to_string(&related),
+    }
+    .with_context(|| format!("Failed to read --related file: {}", related.display()))?;
+    let names = imported_names(&content);
+
+    for candidate in candidates.iter_mut() {
+        let is_related = normalize(&candidate.path) == related
+            || import_name(&candidate.path).is_some_and(|name| names.contains(&name));
+        if candidate.score < RELATED_SCORE && is_related {
+            candidate.score = RELATED_SCORE;
+            stats.related_boosts += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Raise the score of the `--entry` file and of every file reachable from it
+/// through imports, by how few hops away it is
+fn boost_entry(
+    candidates: &mut [FileCandidate],
+    source: &ContentSource,
+    entry: &Path,
+    base_path: &Path,
+    stats: &mut Statistics,
+) -> Result<()> {
+    // Given relative to the working directory, or else to the scanned root
+    let entry = if entry.exists() {
+        entry.to_path_buf()
+    } else {
+        base_path.join(entry)
+    };
+    let entry = normalize(&entry);
+    // Each file by its normalized path, to be read if the imports reach it
+    let paths: HashMap<PathBuf, &Path> = candidates
+        .iter()
+        .map(|c| (normalize(&c.path), c.path.as_path()))
+        .collect();
+    let content = match paths.get(&entry) {
+        Some(path) => source.read(path),
+        None => fs::read_to_string(&entry),
+    }
+    .with_context(|| format!("Failed to read --entry file: {}", entry.display()))?;
+    let files: Vec<PathBuf> = paths.keys().cloned().collect();
+    let distances = import_distances(&entry, &content, &files, |path| {
+        source.read(paths.get(path)?).ok()
+    });
+
+    for candidate in candidates.iter_mut() {
+        let Some(&distance) = distances.get(&normalize(&candidate.path)) else {
+            continue;
+        };
+        let score = entry_score(distance);
+        if candidate.score < score {
+            candidate.score = score;
+            stats.entry_boosts += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Decide how one candidate fits into `available` tokens, returning the decision
+/// and the tokens it costs
+fn allocate(
+    config: &Config,
+    path: &Path,
+    file: &LoadedFile,
+    available: usize,
+    stats: &mut Statistics,
+) -> (FileDecision, usize) {
+    let full_tokens = file.full_tokens;
+
+    if file.shortening.is_some() || !config.should_compress(path) {
+        // Full content or nothing (no --compress, a full-match/override file, or
+        // a minified file already cut down)
+        if full_tokens <= available {
+            (FileDecision::IncludeFull(file.content.clone()), full_tokens)
+        } else {
+            excluded_or_truncated(config, path, &file.content, available)
+        }
+    } else if full_tokens <= available {
+        // Even though it fits, still compress if possible (per flag behavior)
+        let decision = maybe_compress(config, path, &file.content, stats);
+        (decision, full_tokens)
+    } else {
+        // Try compressed version
+        let Some(lang) = language_for_path(path) else {
+            // Unsupported for compression, and full doesn't fit
+            return excluded_or_truncated(config, path, &file.content, available);
+        };
+        match compress_cached(config, path, &file.content, lang, stats) {
+            CompressResult::Compressed(compressed) => {
+                let compressed_tokens = config.emitted_tokens(path, &compressed);
+                if compressed_tokens <= available {
+                    stats.add_compressed();
+                    (
+                        FileDecision::IncludeCompressed(compressed),
+                        compressed_tokens,
+                    )
+                } else {
+                    excluded_or_truncated(config, path, &compressed, available)
+                }
+            }
+            CompressResult::Fallback(original, reason) => {
+                if let Some(reason) = &reason {
+                    let display_path = path.display().to_string();
+                    warn_compression_failed(stats, &display_path, reason);
+                }
+                // Fallback is full size, which we already know doesn't fit
+                let fallback_tokens = config.emitted_tokens(path, &original);
+                if fallback_tokens <= available {
+                    (FileDecision::IncludeFull(original), fallback_tokens)
+                } else {
+                    excluded_or_truncated(config, path, &original, available)
+                }
+            }
+        }
+    }
+}
+
+/// A file whose smallest form, `content`, doesn't fit `available` tokens: left
+/// out, or under `--truncate` cut to the most whole lines from its start that
+/// fit along with the truncation marker. Only a file not even one line of
+/// which fits is still left out.
+fn excluded_or_truncated(
+    config: &Config,
+    path: &Path,
+    content: &str,
+    available: usize,
+) -> (FileDecision, usize) {
+    if !config.truncate {
+        return (FileDecision::Excluded, 0);
+    }
+    let cut = |end: usize| format!("{}{}\n", &content[..end], TRUNCATION_MARKER);
+    let tokens = |text: &str| config.emitted_tokens(path, text);
+    let line_ends: Vec<usize> = content.match_indices('\n').map(|(i, _)| i + 1).collect();
+    // Longer cuts never cost fewer tokens, so the ones that fit are a prefix
+    let fitting = line_ends.partition_point(|&end| tokens(&cut(end)) <= available);
+    match fitting.checked_sub(1) {
+        Some(last) => {
+            let truncated = cut(line_ends[last]);
+            let cost = tokens(&truncated);
+            (FileDecision::IncludeTruncated(truncated), cost)
+        }
+        None => (FileDecision::Excluded, 0),
+    }
+}
+
+/// Write the tree of the files that would be flattened, each annotated with
+/// its estimated tokens, and the summary; no file is read
+fn write_outline(
+    config: &Config,
+    source: &ContentSource,
+    files: &[PathBuf],
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<()> {
+    let mut entries = Vec::new();
+    for path in files {
+        let len = source.len(path).unwrap_or(0) as usize;
+        stats.progress.advance(len as u64)?;
+        let report = FileReport::new(path, &config.path, len, Decision::Full);
+        let tokens = Statistics::format_tokens(report.tokens_full);
+        entries.push((
+            entry_name(path, &config.path),
+            Some(format!("(~{} tokens)", tokens)),
+        ));
+        stats.add_file_report(report);
+    }
+    output.write_tree(&tree::render(&entries))?;
+    stats.add_output_bytes(output.bytes_written());
+    output.write_summary(stats)?;
+    Ok(())
+}
+
+/// Files written uncompressed above this size are streamed rather than read whole
+const STREAM_THRESHOLD: u64 = 256 * 1024;
+
 /// Write files without token budget (normal mode)
 fn write_normal(
     config: &Config,
+    source: &ContentSource,
     files: &[PathBuf],
     output: &mut OutputWriter,
     stats: &mut Statistics,
 ) -> Result<()> {
     for path in files {
-        match fs::read_to_string(path) {
-            Ok(content) => {
-                let display_path = path.display().to_string();
-
-                if config.compress {
-                    let file_name = path
-                        .file_name()
-                        .map(|f| f.to_string_lossy().to_string())
-                        .unwrap_or_default();
-                    let is_full = config.is_full_match(&file_name);
-
-                    if is_full {
-                        output.write_file_content_with_mode(
-                            &display_path,
-                            &content,
-                            Some("full"),
-                        )?;
-                    } else if let Some(lang) = language_for_path(path) {
-                        match compress_source(&content, lang) {
-                            CompressResult::Compressed(compressed) => {
-                                output.write_file_content_with_mode(
-                                    &display_path,
-                                    &compressed,
-                                    Some("compressed"),
-                                )?;
-                                stats.add_compressed();
-                            }
-                            CompressResult::Fallback(original, reason) => {
-                                if let Some(reason) = reason {
-                                    eprintln!(
-                                        "Warning: compression failed for {}: {}, including full content",
-                                        display_path, reason
-                                    );
-                                }
-                                output.write_file_content_with_mode(
-                                    &display_path,
-                                    &original,
-                                    Some("full"),
-                                )?;
+        stats.progress.advance(source.len(path).unwrap_or(0))?;
+        let written_whole = !config.should_compress(path) || language_for_path(path).is_none();
+        let lines = config.line_range_for(path);
+        // Redacted files are masked line by line, minified ones are cut
+        // down, and numbered ones grow, so none is streamed as-is
+        let streamable = written_whole
+            && lines.is_none()
+            && config.line_numbers.is_none()
+            && !config.redacts(path)
+            && !(config.shortens_minified(path) && source.is_minified(path));
+        if streamable && write_streamed(config, source, path, output, stats)? {
+            continue;
+        }
+        match read_selected(config, source, path) {
+            Ok((content, redactions)) => {
+                stats.redactions += redactions;
+                let (content, shortening) = shorten_minified(config, path, content, stats);
+                let display_path = config.display_path(path);
+                enter_section(config, path, output)?;
+
+                let mut report = FileReport::new(path, &config.path, content.len(), Decision::Full);
+                let annotation = annotation(config, report.role, report.score);
+                // Files that aren't compressed still say so when --compress is on
+                let changes = config.line_changes_for(path);
+                let owners = config.owners_for(path);
+                let full = FileAttrs::with_mode(config.compress.then_some(FileMode::Full))
+                    .with_lines(lines)
+                    .with_annotation(annotation)
+                    .with_changes(changes)
+                    .with_owners(owners);
+
+                if shortening.is_some() {
+                    stats.minified_files += 1;
+                    let attrs = shortened_attrs(full, shortening);
+                    output.write_file_content_with_attrs(&display_path, &content, attrs)?;
+                } else if !config.should_compress(path) {
+                    output.write_file_content_with_attrs(&display_path, &content, full)?;
+                } else if let Some(lang) = language_for_path(path) {
+                    match compress_cached(config, path, &content, lang, stats) {
+                        CompressResult::Compressed(compressed) => {
+                            output.write_compressed(
+                                &display_path,
+                                content.len(),
+                                compressed.len(),
+                            )?;
+                            output.write_file_content_with_attrs(
+                                &display_path,
+                                &compressed,
+                                FileAttrs::with_mode(Some(compressed_mode(path)))
+                                    .with_annotation(annotation)
+                                    .with_changes(changes)
+                                    .with_owners(owners),
+                            )?;
+                            stats.add_compressed();
+                            report = FileReport {
+                                decision: Decision::Compressed,
+                                ..report.with_compressed_len(compressed.len())
+                            };
+                        }
+                        CompressResult::Fallback(original, reason) => {
+                            if let Some(reason) = reason {
+                                warn_compression_failed(stats, &display_path, &reason);
                             }
+                            output.write_file_content_with_attrs(&display_path, &original, full)?;
                         }
-                    } else {
-                        output.write_file_content_with_mode(
-                            &display_path,
-                            &content,
-                            Some("full"),
-                        )?;
                     }
                 } else {
-                    output.write_file_content(&display_path, &content)?;
+                    output.write_file_content_with_attrs(&display_path, &content, full)?;
                 }
+                stats.add_file_report(report);
             }
-            Err(e) => {
-                eprintln!("Error reading {}: {}", path.display(), e);
-            }
+            Err(e) => read_failed(path, &e, config, output, stats)?,
         }
     }
 
+    output.close_section()?;
+    count_transcoded(source, stats);
     stats.add_output_bytes(output.bytes_written());
     output.write_summary(stats)?;
     Ok(())
 }
 
+/// Read a file, keeping only its selected lines when a `path:100-400` range
+/// applies and masking its values under `--redact`. Returns the content and
+/// how many values were masked.
+fn read_selected(
+    config: &Config,
+    source: &ContentSource,
+    path: &Path,
+) -> std::io::Result<(String, usize)> {
+    let content = source.read(path)?;
+    let content = match config.line_range_for(path) {
+        Some(lines) => lines.slice(&content).to_string(),
+        None => content,
+    };
+    if config.redacts(path) {
+        return Ok(redact(&content));
+    }
+    Ok((content, 0))
+}
+
+/// Cut down `content` if it looks minified and the config doesn't keep it
+/// whole, saying so once per file
+fn shorten_minified(
+    config: &Config,
+    path: &Path,
+    content: String,
+    stats: &mut Statistics,
+) -> (String, Option<Shortening>) {
+    if !config.shortens_minified(path) || !is_minified(content.as_bytes()) {
+        return (content, None);
+    }
+    let (shortened, how) = minified::shorten(&content);
+    stats.warnings.emit(
+        "minified files",
+        &format!(
+            "Minified file {} {} ({} of {} bytes kept); use --keep-minified to include it whole",
+            path.display(),
+            how.as_str(),
+            shortened.len(),
+            content.len()
+        ),
+    );
+    (shortened, Some(how))
+}
+
+/// How a compressed file is labeled: Markdown cut to its headings is an outline
+fn compressed_mode(path: &Path) -> FileMode {
+    match language_for_path(path) {
+        Some(CompressLanguage::Markdown) => FileMode::Outline,
+        _ => FileMode::Compressed,
+    }
+}
+
+/// Attributes for a minified file's content: an outline is a structural
+/// compression, and a cut-off start is truncated
+fn shortened_attrs(attrs: FileAttrs<'_>, shortening: Option<Shortening>) -> FileAttrs<'_> {
+    match shortening {
+        Some(Shortening::Outlined) => FileAttrs {
+            mode: Some(FileMode::Compressed),
+            ..attrs
+        },
+        Some(Shortening::Truncated) => attrs.with_truncated(true),
+        None => attrs,
+    }
+}
+
+/// Stream a large on-disk file straight to the output, returning false when the
+/// file is small or in memory and should be read whole instead
+fn write_streamed(
+    config: &Config,
+    source: &ContentSource,
+    path: &Path,
+    output: &mut OutputWriter,
+    stats: &mut Statistics,
+) -> Result<bool> {
+    let len = match source.len(path) {
+        Some(len) if len > STREAM_THRESHOLD => len as usize,
+        _ => return Ok(false),
+    };
+    // Check the encoding first, so a bad file is skipped instead of half-written
+    let checked = source
+        .stream(path)
+        .map(|chunks| chunks.and_then(|mut chunks| chunks.try_for_each(|c| c.map(drop))));
+    match checked {
+        None => return Ok(false),
+        Some(Ok(())) => {}
+        // Not UTF-8, so read whole and transcoded
+        Some(Err(e)) if e.kind() == std::io::ErrorKind::InvalidData => return Ok(false),
+        Some(Err(e)) => {
+            stats.warnings.emit(
+                "read errors",
+                &format!("Error reading {}: {}", path.display(), e),
+            );
+            return Ok(true);
+        }
+    }
+
+    enter_section(config, path, output)?;
+    let report = FileReport::new(path, &config.path, len, Decision::Full);
+    let attrs = FileAttrs::with_mode(config.compress.then_some(FileMode::Full))
+        .with_annotation(annotation(config, report.role, report.score))
+        .with_changes(config.line_changes_for(path))
+        .with_owners(config.owners_for(path));
+    output.write_file_stream(&config.display_path(path), len, attrs, || {
+        source.stream(path).expect("on-disk file")
+    })?;
+    stats.add_file_report(report);
+    Ok(true)
+}
+
+/// The role and score to write on a file's tag, under `--annotate`
+fn annotation(config: &Config, role: Role, score: u32) -> Option<(Role, u32)> {
+    config.annotate.then_some((role, score))
+}
+
+/// Under `--layout sections`, open the section for `path` before writing it
+fn enter_section(config: &Config, path: &Path, output: &mut OutputWriter) -> Result<()> {
+    if config.layout == Layout::Sections {
+        output.enter_section(classify(path, &config.path).section().title())?;
+    }
+    Ok(())
+}
+
+/// Compress a file, going through the on-disk cache when `--cache` is on
+fn compress_cached(
+    config: &Config,
+    path: &Path,
+    content: &str,
+    lang: CompressLanguage,
+    stats: &Statistics,
+) -> CompressResult {
+    let cancel = stats.progress.cancel_token();
+    let default_rules = NodeRules::default();
+    let rules = config.compress_rules.get(&lang).unwrap_or(&default_rules);
+    let Some(root) = &config.cache_dir else {
+        return compress_file(
+            path,
+            content,
+            lang,
+            rules,
+            config.compress_level,
+            &config.parse_limits,
+            cancel,
+        );
+    };
+    let cache = CompressionCache::new(root);
+    if let Some(compressed) = cache.get(path, content, lang, rules, config.compress_level) {
+        return CompressResult::Compressed(compressed);
+    }
+    let result = compress_file(
+        path,
+        content,
+        lang,
+        rules,
+        config.compress_level,
+        &config.parse_limits,
+        cancel,
+    );
+    if let CompressResult::Compressed(compressed) = &result {
+        cache.put(
+            path,
+            content,
+            lang,
+            rules,
+            config.compress_level,
+            compressed,
+        );
+    }
+    result
+}
+
 /// Helper: Try to compress a file if applicable, returning the appropriate decision
 fn maybe_compress(
     config: &Config,
@@ -389,28 +2102,15 @@ fn maybe_compress(
     content: &str,
     stats: &mut Statistics,
 ) -> FileDecision {
-    let file_name = path
-        .file_name()
-        .map(|f| f.to_string_lossy().to_string())
-        .unwrap_or_default();
-
-    if config.is_full_match(&file_name) {
-        return FileDecision::IncludeFull(content.to_string());
-    }
-
     if let Some(lang) = language_for_path(path) {
-        match compress_source(content, lang) {
+        match compress_cached(config, path, content, lang, stats) {
             CompressResult::Compressed(compressed) => {
                 stats.add_compressed();
                 FileDecision::IncludeCompressed(compressed)
             }
             CompressResult::Fallback(original, reason) => {
                 if let Some(reason) = reason {
-                    eprintln!(
-                        "Warning: compression failed for {}: {}, including full content",
-                        path.display(),
-                        reason
-                    );
+                    warn_compression_failed(stats, &path.display().to_string(), &reason);
                 }
                 FileDecision::IncludeFull(original)
             }
@@ -420,35 +2120,126 @@ fn maybe_compress(
     }
 }
 
-/// Check if a file should be skipped, returning the reason if so
+/// Report a compression fallback, grouping repeats of the same failure reason
+fn warn_compression_failed(stats: &mut Statistics, path: &str, reason: &str) {
+    stats.warnings.emit(
+        &format!("compression failures ({})", reason),
+        &format!(
+            "Warning: compression failed for {}: {}, including full content",
+            path, reason
+        ),
+    );
+}
+
+/// Check if a file on disk should be skipped, returning the reason if so
+#[cfg(test)]
 fn should_skip(path: &Path, config: &Config) -> Option<SkipReason> {
-    if let Some(file_name) = path.file_name() {
-        if !config.should_include_by_match(&file_name.to_string_lossy()) {
-            return Some(SkipReason::Match);
-        }
+    should_skip_in(path, config, &ContentSource::disk())
+}
+
+/// Check if a file should be skipped, reading size and content through `source`
+fn should_skip_in(path: &Path, config: &Config, source: &ContentSource) -> Option<SkipReason> {
+    if let Some(reason) = should_skip_by_name(path, config) {
+        return Some(reason);
+    }
+
+    if config
+        .recent
+        .as_ref()
+        .is_some_and(|recent| !recent.changed(path))
+    {
+        return Some(SkipReason::NotRecent);
+    }
+
+    if source.exceeds_size_limit(path, config.read_size_limit(path)) {
+        return Some(SkipReason::TooLarge);
+    }
+
+    if source.is_binary(path) {
+        return Some(SkipReason::BinaryContent);
+    }
+
+    // A flat.overrides rule for the file outranks its pragma
+    if config.override_for(path).is_none() && source.pragma(path) == Some(OverrideAction::Skip) {
+        return Some(SkipReason::Pragma);
+    }
+
+    None
+}
+
+/// Whether a file over `--max-size` that was read anyway, to be compressed
+/// (see [`Config::read_size_limit`]), is still over it compressed. It is
+/// compressed here and again when written, unless `--cache` keeps the result.
+fn is_too_large_compressed(
+    path: &Path,
+    config: &Config,
+    source: &ContentSource,
+    stats: &Statistics,
+) -> bool {
+    let limit = config.max_file_size_for(path);
+    if !source.exceeds_size_limit(path, limit) {
+        return false;
+    }
+    let Some(lang) = language_for_path(path) else {
+        return true;
+    };
+    // A read error is reported when the file is written
+    let Ok(content) = source.read(path) else {
+        return false;
+    };
+    match compress_cached(config, path, &content, lang, stats) {
+        CompressResult::Compressed(compressed) => compressed.len() as u64 > limit,
+        CompressResult::Fallback(..) => true,
+    }
+}
+
+/// The skip rules that only look at the path, never the file contents
+fn should_skip_by_name(path: &Path, config: &Config) -> Option<SkipReason> {
+    if !config.should_include_path_by_match(path) {
+        return Some(SkipReason::GlobFilter);
+    }
+
+    if config.is_excluded_by_match(path) {
+        return Some(SkipReason::GlobExclude);
     }
 
-    if is_secret_file(path) {
+    if is_secret_file(path) && !config.redact {
         return Some(SkipReason::Secret);
     }
 
-    if let Some(ext) = path.extension() {
-        let ext_str = ext.to_string_lossy();
-        if !config.should_include_extension(&ext_str) {
-            return Some(SkipReason::Extension);
-        }
+    if config.is_project_default_excluded(path) {
+        return Some(SkipReason::ProjectDefault);
+    }
 
-        if is_binary_extension(path) {
-            return Some(SkipReason::Binary);
-        }
+    if config.override_for(path) == Some(OverrideAction::Skip) {
+        return Some(SkipReason::Override);
     }
 
-    if exceeds_size_limit(path, config.max_file_size) {
-        return Some(SkipReason::TooLarge);
+    match path.extension() {
+        Some(ext) => {
+            let ext_str = ext.to_string_lossy();
+            if !config.should_include_extension(&ext_str) {
+                return Some(SkipReason::ExtensionFilter);
+            }
+
+            if is_binary_extension(path) {
+                return Some(SkipReason::BinaryExtension);
+            }
+        }
+        None => {
+            if !config.should_include_extension(NO_EXTENSION) {
+                return Some(SkipReason::ExtensionFilter);
+            }
+        }
     }
 
-    if is_binary_content(path) {
-        return Some(SkipReason::Binary);
+    if let Some(profile) = config.profile_for(path) {
+        if !profile.allows_extension(path) {
+            return Some(SkipReason::ExtensionFilter);
+        }
+        if profile.excludes(path) {
+            return Some(SkipReason::GlobExclude);
+        }
     }
 
     None
@@ -476,11 +2267,11 @@ mod tests {
         let config = Config::default();
         assert_eq!(
             should_skip(Path::new("image.png"), &config),
-            Some(SkipReason::Binary)
+            Some(SkipReason::BinaryExtension)
         );
         assert_eq!(
             should_skip(Path::new("binary.exe"), &config),
-            Some(SkipReason::Binary)
+            Some(SkipReason::BinaryExtension)
         );
     }
 
@@ -493,7 +2284,7 @@ mod tests {
 
         assert_eq!(
             should_skip(Path::new("file.json"), &config),
-            Some(SkipReason::Extension)
+            Some(SkipReason::ExtensionFilter)
         );
         assert_eq!(should_skip(Path::new("file.rs"), &config), None);
     }
@@ -509,8 +2300,139 @@ mod tests {
 
         assert_eq!(
             should_skip(Path::new("main.go"), &config),
-            Some(SkipReason::Match)
+            Some(SkipReason::GlobFilter)
         );
         assert_eq!(should_skip(Path::new("user_test.go"), &config), None);
     }
+
+    #[test]
+    fn test_should_skip_exclude_match() {
+        let config = Config {
+            path: PathBuf::from("/repo"),
+            exclude_match_patterns: Some(vec![globset::Glob::new("migrations/**")
+                .unwrap()
+                .compile_matcher()]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            should_skip(Path::new("/repo/migrations/0001_init.py"), &config),
+            Some(SkipReason::GlobExclude)
+        );
+        assert_eq!(
+            should_skip(Path::new("/repo/app/migrations.py"), &config),
+            None
+        );
+    }
+
+    #[test]
+    fn test_flatten_entries_applies_filters_and_formatting() {
+        let entries = vec![
+            (PathBuf::from("src/main.rs"), "fn main() {}\n".to_string()),
+            (PathBuf::from(".env"), "SECRET=1\n".to_string()),
+            (PathBuf::from("blob.dat"), "a\0b".to_string()),
+        ];
+
+        let output = flatten_entries(entries, &Config::default()).unwrap();
+
+        assert!(output.contains("<file path=\"src/main.rs\">\nfn main() {}\n</file>"));
+        assert!(!output.contains("SECRET"));
+        assert!(!output.contains("blob.dat\">"));
+        assert!(output.contains("Included: 1"));
+        assert!(output.contains("Skipped: 2"));
+    }
+
+    #[test]
+    fn test_flatten_entries_compresses_in_memory_content() {
+        let entries = vec![(
+            PathBuf::from("lib.rs"),
+            "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n".to_string(),
+        )];
+        let config = Config {
+            compress: true,
+            ..Default::default()
+        };
+
+        let output = flatten_entries(entries, &config).unwrap();
+
+        assert!(output.contains("mode=\"compressed\""));
+        assert!(output.contains("pub fn add(a: i32, b: i32) -> i32 { ... }"));
+        assert!(!output.contains("a + b"));
+    }
+
+    #[test]
+    fn test_analyze_reports_every_decision() {
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("README.md"), "# Demo\n").unwrap();
+        fs::write(
+            dir.path().join("lib.rs"),
+            format!(
+                "pub fn add(a: i32, b: i32) -> i32 {{\n{}}}\n",
+                "    a + b;\n".repeat(50)
+            ),
+        )
+        .unwrap();
+        fs::write(dir.path().join("credentials.json"), "{}\n").unwrap();
+
+        let config = Config {
+            path: dir.path().to_path_buf(),
+            compress: true,
+            token_budget: Some(40),
+            ..Config::default()
+        };
+        let reports = analyze(&config).unwrap();
+        let find = |name: &str| {
+            reports
+                .iter()
+                .find(|r| r.path.ends_with(name))
+                .unwrap_or_else(|| panic!("no report for {}", name))
+        };
+
+        assert_eq!(find("credentials.json").decision, Decision::Skipped);
+        assert_eq!(find("credentials.json").reason.as_deref(), Some("secret"));
+        assert_eq!(find("README.md").decision, Decision::Full);
+        assert_eq!(find("README.md").role, Role::Readme);
+        let lib = find("lib.rs");
+        assert_eq!(lib.decision, Decision::Compressed);
+        assert!(lib.tokens_compressed.unwrap() < lib.tokens_full);
+    }
+
+    #[test]
+    fn test_flatten_handle_reports_progress_and_cancels() {
+        use crate::progress::{Cancelled, FlattenHandle, Phase, Progress};
+        use std::sync::{Arc, Mutex};
+
+        let dir = tempfile::TempDir::new().unwrap();
+        fs::write(dir.path().join("a.rs"), "fn a() {}\n").unwrap();
+        fs::write(dir.path().join("b.rs"), "fn b() {}\n").unwrap();
+        let output_file = dir.path().join("out.xml");
+        let config = Config {
+            path: dir.path().to_path_buf(),
+            output_file: Some(output_file.clone()),
+            ..Config::default()
+        };
+
+        let seen: Arc<Mutex<Vec<Progress>>> = Arc::default();
+        let log = Arc::clone(&seen);
+        let handle = FlattenHandle::new().on_progress(move |p| log.lock().unwrap().push(*p));
+        handle.flatten(&config).unwrap();
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.first().map(|p| p.phase), Some(Phase::Walking));
+        assert_eq!(
+            seen.last(),
+            Some(&Progress {
+                phase: Phase::Writing,
+                files: 2,
+                total: Some(2),
+                bytes: 20,
+            })
+        );
+
+        fs::remove_file(&output_file).unwrap();
+        let handle = FlattenHandle::new();
+        handle.cancel_token().cancel();
+        let err = handle.flatten(&config).unwrap_err();
+        assert!(err.is::<Cancelled>(), "{:#}", err);
+        assert!(!output_file.exists());
+    }
 }
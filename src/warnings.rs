@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+
+/// How many messages of one kind are printed before the rest are summarized
+const DEFAULT_LIMIT: usize = 5;
+
+/// Collects stderr diagnostics and prints repeated kinds only a few times.
+///
+/// Messages are grouped by a short description (e.g. "compression failures:
+/// parse tree contains ERROR nodes"). The first few messages of each group are
+/// printed as they happen; `flush` then reports how many were held back.
+#[derive(Debug)]
+pub struct Warnings {
+    verbose: bool,
+    /// Count messages without printing any
+    silent: bool,
+    limit: usize,
+    counts: BTreeMap<String, usize>,
+}
+
+impl Default for Warnings {
+    fn default() -> Self {
+        Self::new(false)
+    }
+}
+
+impl Warnings {
+    /// Create a collector; `verbose` prints every message
+    pub fn new(verbose: bool) -> Self {
+        Self {
+            verbose,
+            silent: false,
+            limit: DEFAULT_LIMIT,
+            counts: BTreeMap::new(),
+        }
+    }
+
+    /// Create a collector that prints nothing, for a pass whose warnings an
+    /// earlier pass already printed
+    pub fn silent() -> Self {
+        Self {
+            silent: true,
+            ..Self::new(false)
+        }
+    }
+
+    /// Record a message in `group`, printing it if the group is under its limit
+    pub fn emit(&mut self, group: &str, message: &str) {
+        let count = self.counts.entry(group.to_string()).or_insert(0);
+        *count += 1;
+        if !self.silent && (self.verbose || *count <= self.limit) {
+            eprintln!("{}", message);
+        }
+    }
+
+    /// Number of messages held back so far, per group
+    pub fn suppressed(&self) -> Vec<(&str, usize)> {
+        if self.verbose || self.silent {
+            return Vec::new();
+        }
+        self.counts
+            .iter()
+            .filter(|(_, &count)| count > self.limit)
+            .map(|(group, &count)| (group.as_str(), count - self.limit))
+            .collect()
+    }
+
+    /// Print one "...and N more" line for every group that hit its limit
+    pub fn flush(&mut self) {
+        for (group, hidden) in self.suppressed() {
+            eprintln!("...and {} more {} (rerun with -v for all)", hidden, group);
+        }
+        self.counts.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suppresses_after_limit() {
+        let mut warnings = Warnings::new(false);
+        for i in 0..8 {
+            warnings.emit("read errors", &format!("Error reading f{}", i));
+        }
+        warnings.emit("files skipped (binary)", "Skipping a.png: binary");
+
+        assert_eq!(warnings.suppressed(), vec![("read errors", 3)]);
+    }
+
+    #[test]
+    fn test_verbose_never_suppresses() {
+        let mut warnings = Warnings::new(true);
+        for _ in 0..20 {
+            warnings.emit("read errors", "Error reading f");
+        }
+        assert!(warnings.suppressed().is_empty());
+    }
+}
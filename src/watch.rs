@@ -0,0 +1,167 @@
+//! `--watch`: stay running and rewrite the `-o` file whenever the flattened
+//! tree changes, so editors and agents always find a fresh context file.
+
+use crate::config::Config;
+use crate::output::Statistics;
+use crate::walker::{walk_and_flatten, FLATIGNORE_FILE_NAME};
+use anyhow::{Context, Result};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use notify::{Event, EventKind, RecursiveMode, Watcher};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::Duration;
+
+/// How long the tree has to stay quiet before a burst of changes (a branch
+/// switch, a formatter run) is flattened, once
+const SETTLE: Duration = Duration::from_millis(300);
+
+/// Flatten `config` now and again after every change under its root, passing
+/// each run's outcome to `report`. A failed run is reported and waited out
+/// like any other; only losing the watch itself ends the loop.
+pub fn watch(config: &Config, mut report: impl FnMut(Result<Statistics>)) -> Result<()> {
+    let (sender, events) = mpsc::channel();
+    let mut watcher =
+        notify::recommended_watcher(sender).context("Failed to start watching for changes")?;
+    watcher
+        .watch(&config.path, RecursiveMode::Recursive)
+        .with_context(|| format!("Failed to watch {}", config.path.display()))?;
+    let changes = Relevance::of(config);
+
+    report(walk_and_flatten(config));
+    loop {
+        wait_for_change(&events, &changes, None)?;
+        while wait_for_change(&events, &changes, Some(SETTLE))? {}
+        report(walk_and_flatten(config));
+    }
+}
+
+/// Block until a relevant change arrives, or `timeout` passes (returning false)
+fn wait_for_change(
+    events: &Receiver<notify::Result<Event>>,
+    changes: &Relevance,
+    timeout: Option<Duration>,
+) -> Result<bool> {
+    loop {
+        let event = match timeout {
+            Some(timeout) => match events.recv_timeout(timeout) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => return Ok(false),
+                Err(RecvTimeoutError::Disconnected) => anyhow::bail!("File watcher stopped"),
+            },
+            None => events.recv().context("File watcher stopped")?,
+        };
+        // A dropped event or an overflowed queue could hide anything
+        let Ok(event) = event else {
+            return Ok(true);
+        };
+        if !matches!(event.kind, EventKind::Access(_))
+            && event.paths.iter().any(|path| changes.matters(path))
+        {
+            return Ok(true);
+        }
+    }
+}
+
+/// Which changed paths can change the output: not flat's own writes, not git's
+/// internals, and not what the root's ignore files rule out. Nested ignore files
+/// aren't consulted, so a change they hide costs a needless rewrite, nothing more.
+struct Relevance {
+    root: PathBuf,
+    outputs: Vec<PathBuf>,
+    ignored: Gitignore,
+}
+
+impl Relevance {
+    fn of(config: &Config) -> Self {
+        let mut builder = GitignoreBuilder::new(&config.path);
+        let mut ignore_files = vec![config.path.join(FLATIGNORE_FILE_NAME)];
+        if config.use_gitignore {
+            ignore_files.push(config.path.join(".gitignore"));
+        }
+        ignore_files.extend(config.gitignore_path.clone());
+        for file in ignore_files.iter().filter(|file| file.is_file()) {
+            // A malformed line only makes the filter looser
+            let _ = builder.add(file);
+        }
+        let outputs = [
+            &config.output_file,
+            &config.bundle,
+            &config.export_selection,
+        ]
+        .into_iter()
+        .flatten()
+        .filter_map(|file| located(file))
+        .collect();
+        Self {
+            root: config.path.clone(),
+            outputs,
+            ignored: builder.build().unwrap_or_else(|_| Gitignore::empty()),
+        }
+    }
+
+    fn matters(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if relative
+            .components()
+            .any(|c| c == Component::Normal(".git".as_ref()))
+        {
+            return false;
+        }
+        // The output itself, and the hidden temp file it is written through
+        if let Some(path) = located(path) {
+            let is_output = self.outputs.iter().any(|output| {
+                let temp_prefix = output
+                    .file_name()
+                    .map(|name| format!(".{}.tmp", name.to_string_lossy()));
+                output == &path
+                    || (output.parent() == path.parent()
+                        && temp_prefix.is_some_and(|prefix| {
+                            path.file_name()
+                                .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+                        }))
+            });
+            if is_output {
+                return false;
+            }
+        }
+        !self
+            .ignored
+            .matched_path_or_any_parents(relative, path.is_dir())
+            .is_ignore()
+    }
+}
+
+/// `path` with its directory resolved, so differently spelled paths to the
+/// same file compare equal even once the file itself is gone
+fn located(path: &Path) -> Option<PathBuf> {
+    let parent = path.parent().filter(|p| !p.as_os_str().is_empty());
+    let parent = fs::canonicalize(parent.unwrap_or(Path::new("."))).ok()?;
+    Some(parent.join(path.file_name()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_relevance_skips_own_output_git_and_ignored_paths() {
+        let dir = TempDir::new().unwrap();
+        let root = dir.path().to_path_buf();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        let config = Config {
+            path: root.clone(),
+            output_file: Some(root.join("context.xml")),
+            ..Default::default()
+        };
+        let changes = Relevance::of(&config);
+
+        assert!(changes.matters(&root.join("src/main.rs")));
+        assert!(changes.matters(&root.join(".gitignore")));
+        assert!(!changes.matters(&root.join("context.xml")));
+        assert!(!changes.matters(&root.join(".context.xml.tmp4242")));
+        assert!(!changes.matters(&root.join(".git/index")));
+        assert!(!changes.matters(&root.join("target/debug/flat")));
+    }
+}
@@ -17,6 +17,18 @@ fn create_test_file(dir: &std::path::Path, path: &str, content: &str) {
     fs::write(file_path, content).unwrap();
 }
 
+/// Helper to run git in a test repository
+fn git(dir: &std::path::Path, args: &[&str]) {
+    let status = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["-c", "user.name=flat", "-c", "user.email=flat@example.com"])
+        .args(args)
+        .status()
+        .unwrap();
+    assert!(status.success(), "git {:?}", args);
+}
+
 // ============================================================================
 // Basic Functionality Tests
 // ============================================================================
@@ -96,7 +108,7 @@ fn test_large_files_excluded() {
         .arg("tests/fixtures/sample_project")
         .assert()
         .success()
-        .stderr(predicate::str::contains("large_file.txt: too large"));
+        .stderr(predicate::str::contains("large_file.txt: too-large"));
 }
 
 // ============================================================================
@@ -116,6 +128,104 @@ fn test_gitignore_respected() {
     assert!(!stdout.contains("target/debug/binary.exe"));
 }
 
+#[test]
+fn test_flatignore_layers_on_gitignore() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/lib.rs", "fn lib() {}");
+    create_test_file(temp_dir.path(), "src/schema.sql", "CREATE TABLE t;");
+    create_test_file(temp_dir.path(), "tests/__snap__/a.snap", "snapshot");
+    create_test_file(temp_dir.path(), "tests/keep.rs", "fn keep() {}");
+    create_test_file(temp_dir.path(), ".flatignore", "*.sql\n");
+    create_test_file(temp_dir.path(), "tests/.flatignore", "__snap__/\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fn lib() {}"))
+        .stdout(predicate::str::contains("fn keep() {}"))
+        .stdout(predicate::str::contains("CREATE TABLE").not())
+        .stdout(predicate::str::contains("snapshot").not());
+}
+
+#[test]
+fn test_no_gitignore_and_hidden_widen_the_walk() {
+    let temp_dir = TempDir::new().unwrap();
+    git(temp_dir.path(), &["init", "-q"]);
+    create_test_file(temp_dir.path(), ".gitignore", "generated/\n");
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}");
+    create_test_file(temp_dir.path(), "generated/api.rs", "fn api() {}");
+    create_test_file(temp_dir.path(), ".config/settings.toml", "level = 1");
+    create_test_file(temp_dir.path(), ".env", "TOKEN=abc");
+
+    let run = |extra: &[&str]| {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(extra)
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let default = run(&[]);
+    assert!(default.contains("src/main.rs"));
+    assert!(!default.contains("generated/api.rs"));
+    assert!(!default.contains(".config/settings.toml"));
+
+    let unignored = run(&["--no-gitignore"]);
+    assert!(unignored.contains("generated/api.rs"));
+    assert!(!unignored.contains(".config/settings.toml"));
+
+    let hidden = run(&["--hidden"]);
+    assert!(hidden.contains(".config/settings.toml"));
+    assert!(hidden.contains(".gitignore"));
+    assert!(!hidden.contains("generated/api.rs"));
+    assert!(
+        !hidden.contains(".git/"),
+        "the repository itself is never walked"
+    );
+    assert!(!hidden.contains("TOKEN=abc"), "secrets are still skipped");
+}
+
+#[test]
+fn test_default_excludes_without_gitignore() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}");
+    create_test_file(
+        temp_dir.path(),
+        "node_modules/left-pad/index.js",
+        "module.exports = 1;",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "target/debug/build.rs",
+        "fn generated() {}",
+    );
+
+    let output = flat_cmd().arg(temp_dir.path()).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("src/main.rs"));
+    assert!(!stdout.contains("left-pad"));
+    assert!(!stdout.contains("target/debug"));
+    assert!(stdout.contains("node_modules ✗ default exclude"));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--no-default-excludes")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("left-pad/index.js"));
+    assert!(stdout.contains("target/debug/build.rs"));
+
+    // A .gitignore takes over: it decides what's excluded
+    create_test_file(temp_dir.path(), ".gitignore", "*.log\n");
+    let output = flat_cmd().arg(temp_dir.path()).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("left-pad/index.js"));
+}
+
 // ============================================================================
 // Extension Filtering Tests
 // ============================================================================
@@ -179,6 +289,32 @@ fn test_combined_filters() {
     assert!(!stdout.contains("README.md"));
 }
 
+#[test]
+fn test_extensionless_files_follow_extension_filters() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}");
+    create_test_file(temp_dir.path(), "Makefile", "all:\n\tcargo build");
+
+    let run = |args: &[&str]| {
+        let output = flat_cmd().arg(temp_dir.path()).args(args).output().unwrap();
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let stdout = run(&["--include", "rs"]);
+    assert!(stdout.contains("main.rs"));
+    assert!(!stdout.contains("Makefile"));
+
+    let stdout = run(&["--include", "rs,_noext"]);
+    assert!(stdout.contains("Makefile"));
+
+    let stdout = run(&["--include", "rs", "--no-extension", "include"]);
+    assert!(stdout.contains("Makefile"));
+
+    let stdout = run(&["--no-extension", "exclude"]);
+    assert!(stdout.contains("main.rs"));
+    assert!(!stdout.contains("Makefile"));
+}
+
 // ============================================================================
 // Output Mode Tests
 // ============================================================================
@@ -215,6 +351,227 @@ fn test_stats_mode() {
         .stderr(predicate::str::contains("Skipped:"));
 }
 
+#[test]
+fn test_outline_only_writes_tree_and_summary() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/lib.rs", &"x".repeat(300));
+    create_test_file(temp_dir.path(), "README.md", "# Demo\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--outline-only")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.starts_with("<tree>\nsrc/\n  lib.rs (~100 tokens)\nREADME.md (~1 tokens)\n</tree>")
+    );
+    assert!(stdout.contains("<summary>"));
+    assert!(!stdout.contains("<file"));
+    assert!(!stdout.contains("xxx"));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--outline-only", "--format", "json"])
+        .output()
+        .expect("Failed to execute command");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(json["files"], serde_json::json!([]));
+    assert!(json["tree"].as_str().unwrap().contains("lib.rs"));
+}
+
+#[test]
+fn test_stats_breaks_down_size_by_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/a.rs", &"x".repeat(300));
+    create_test_file(temp_dir.path(), "src/b.rs", &"x".repeat(300));
+    create_test_file(temp_dir.path(), "tests/t.rs", &"x".repeat(30));
+    create_test_file(temp_dir.path(), "build.rs", &"x".repeat(3));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--stats")
+        .output()
+        .expect("Failed to execute command");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let breakdown = stderr.split("By directory:\n").nth(1).expect("breakdown");
+    let lines: Vec<&str> = breakdown.lines().take(3).collect();
+    assert_eq!(lines[0], "  src        2 files   600 bytes  ~200 tokens");
+    assert_eq!(lines[1], "  tests      1 files    30 bytes  ~10 tokens");
+    assert_eq!(lines[2], "  .          1 files     3 bytes  ~1 tokens");
+}
+
+#[test]
+fn test_summary_rerun_command() {
+    flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .args(["--include", "rs", "--match", "!*_test.rs", "--tokens", "8k"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Re-run: flat tests/fixtures/sample_project --include rs --not-match '*_test.rs' --tokens 8000\n",
+        ));
+}
+
+#[test]
+fn test_summary_directory_map() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}");
+    create_test_file(temp_dir.path(), "src/lib.rs", "pub fn lib() {}");
+    create_test_file(temp_dir.path(), "assets/logo.png", "fake png");
+    create_test_file(temp_dir.path(), "vendor/dep.rs", "fn dep() {}");
+    create_test_file(temp_dir.path(), ".ignore", "vendor/\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--stats")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Directories: assets ✗ binary-ext | src ✓ (2 files) | vendor ✗ gitignore",
+        ));
+}
+
+#[test]
+fn test_summary_reports_pruned_directories() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}");
+    create_test_file(temp_dir.path(), "src/gen/a.rs", "fn a() {}");
+    create_test_file(temp_dir.path(), "src/gen/b.rs", "fn b() {}");
+    create_test_file(temp_dir.path(), "node_modules/pkg/index.js", "x");
+    create_test_file(temp_dir.path(), ".ignore", "src/gen/\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--stats")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains(
+            "Pruned: 2 directories, 3 files not scanned (src/gen 2 files, node_modules 1 files)",
+        ));
+}
+
+#[test]
+fn test_max_depth_and_prune_stop_the_walk() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "README.md", "# Top\n");
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}");
+    create_test_file(temp_dir.path(), "src/deep/nested.rs", "fn nested() {}");
+    create_test_file(temp_dir.path(), "generated/out.rs", "fn out() {}");
+    create_test_file(temp_dir.path(), "web/generated/app.js", "x");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--max-depth", "2", "--prune", "generated"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("fn main() {}"));
+    assert!(!stdout.contains("fn nested"));
+    assert!(!stdout.contains("fn out"));
+    assert!(!stdout.contains("app.js"));
+    assert!(stdout.contains("generated ✗ prune"), "{}", stdout);
+    assert!(
+        stdout.contains("Pruned: 3 directories, 3 files not scanned"),
+        "{}",
+        stdout
+    );
+
+    // A path prunes only that directory
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--prune", "web/generated/"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("fn out() {}"))
+        .stdout(predicate::str::contains("app.js").not());
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--max-depth", "0"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--max-depth 0"));
+}
+
+#[test]
+fn test_own_output_and_cache_excluded_from_rescan() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "lib.rs", "pub fn add() {}\n");
+
+    let run = || {
+        flat_cmd()
+            .current_dir(temp_dir.path())
+            .env("FLAT_CACHE_DIR", temp_dir.path().join("cache"))
+            .args([".", "-o", "context.txt", "--compress", "--cache"])
+            .assert()
+            .success();
+        fs::read_to_string(temp_dir.path().join("context.txt")).unwrap()
+    };
+    run();
+    let second = run();
+    assert!(!second.contains("<file path=\"./context.txt\""));
+    assert!(!second.contains("<file path=\"./cache/"));
+    assert!(second.contains("Skipped: 1 (1 own-output)"), "{}", second);
+    assert!(second.contains("cache ✗ own-output"), "{}", second);
+    assert!(second.contains("(cache 2 files)"), "{}", second);
+}
+
+#[test]
+fn test_earlier_templated_outputs_excluded_from_rescan() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "lib.rs", "pub fn add() {}\n");
+    create_test_file(
+        temp_dir.path(),
+        "ctx-1700000000.xml",
+        "<file path=\"lib.rs\">\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "out/flat-abc1234-2024-01-01.xml",
+        "<file path=\"lib.rs\">\n",
+    );
+    create_test_file(temp_dir.path(), "ctx-notes.md", "# Notes\n");
+
+    let written = |output: &str, dir: &str| {
+        flat_cmd()
+            .current_dir(temp_dir.path())
+            .args([".", "-o", output])
+            .assert()
+            .success();
+        let new = fs::read_dir(temp_dir.path().join(dir))
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.ends_with(".xml") && !name.contains("1700000000") && !name.contains("abc1234")
+            })
+            .unwrap();
+        let text = fs::read_to_string(&new).unwrap();
+        fs::remove_file(new).unwrap();
+        text
+    };
+
+    let templated = written("ctx-{timestamp}.xml", ".");
+    assert!(
+        !templated.contains("ctx-1700000000.xml\">"),
+        "{}",
+        templated
+    );
+    assert!(templated.contains("ctx-notes.md\">"), "{}", templated);
+    assert!(templated.contains("1 own-output"), "{}", templated);
+
+    let generated = written("out/", "out");
+    assert!(
+        !generated.contains("flat-abc1234-2024-01-01.xml\">"),
+        "{}",
+        generated
+    );
+    assert!(generated.contains("1 own-output"), "{}", generated);
+}
+
 #[test]
 fn test_output_to_file() {
     let temp_dir = TempDir::new().unwrap();
@@ -234,6 +591,205 @@ fn test_output_to_file() {
     assert!(content.contains("src/main.rs"));
 }
 
+#[test]
+fn test_output_path_placeholders_expand() {
+    let project = TempDir::new().unwrap();
+    let out_dir = TempDir::new().unwrap();
+    create_test_file(project.path(), "main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(project.path())
+        .arg("-o")
+        .arg(out_dir.path().join("context-{git_sha}-{date}.txt"))
+        .assert()
+        .success();
+
+    let names: Vec<String> = fs::read_dir(out_dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+        .collect();
+    assert_eq!(names.len(), 1);
+    // Temp dirs are not git repositories, so the SHA falls back to "unknown"
+    assert!(
+        names[0].starts_with("context-unknown-20"),
+        "got {}",
+        names[0]
+    );
+    assert!(names[0].ends_with(".txt"));
+    assert!(!names[0].contains('{'));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_symlink_emitted_as_stub() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "shared/config.yml", "key: value");
+    create_test_file(temp_dir.path(), "app/main.py", "print('hi')");
+    std::os::unix::fs::symlink(
+        "../shared/config.yml",
+        temp_dir.path().join("app/config.yml"),
+    )
+    .unwrap();
+
+    let output = flat_cmd().arg(temp_dir.path()).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.contains("<file path=\"app/config.yml\" symlink-to=\"../shared/config.yml\"/>"));
+    assert_eq!(stdout.matches("key: value").count(), 1);
+    assert!(stdout.contains("Symlinks: 1 (not followed)"));
+}
+
+#[cfg(unix)]
+#[test]
+fn test_follow_symlinks_flattens_linked_packages_once() {
+    use std::os::unix::fs::symlink;
+
+    let temp_dir = TempDir::new().unwrap();
+    let root = temp_dir.path();
+    create_test_file(root, "packages/shared/lib.rs", "pub fn shared() {}\n");
+    create_test_file(root, "apps/web/main.rs", "fn main() {}\n");
+    create_test_file(root, "apps/api/main.rs", "fn main() {}\n");
+    symlink("../../packages/shared", root.join("apps/web/shared")).unwrap();
+    symlink("../../packages/shared", root.join("apps/api/shared")).unwrap();
+    symlink("..", root.join("apps/web/up")).unwrap();
+
+    let output = flat_cmd()
+        .arg(root)
+        .arg("--follow-symlinks")
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    // Inside the root, the package is written once, under its real path
+    assert_eq!(stdout.matches("pub fn shared()").count(), 1);
+    assert!(stdout.contains("packages/shared/lib.rs\">"));
+    assert!(!stdout.contains("symlink-to="));
+    assert!(stderr.contains("Not following symlink"), "{}", stderr);
+
+    // Scanning just the apps, the package is only reachable through the links
+    let output = flat_cmd()
+        .arg(root.join("apps"))
+        .arg("--follow-symlinks")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.matches("pub fn shared()").count(), 1);
+    assert!(stdout.contains("<file path=\"api/shared/lib.rs\">"));
+    assert!(stdout.contains("1 duplicate"));
+}
+
+#[test]
+fn test_export_selection_zip_matches_budget() {
+    let temp_dir = TempDir::new().unwrap();
+    let project = temp_dir.path().join("project");
+    create_test_file(&project, "README.md", "# Project\n");
+    create_test_file(&project, "src/main.rs", "fn main() {}\n");
+    create_test_file(&project, "src/big.rs", &"b".repeat(3000));
+    let archive = temp_dir.path().join("selection.zip");
+
+    flat_cmd()
+        .arg(&project)
+        .args(["--tokens", "100", "--export-selection"])
+        .arg(&archive)
+        .assert()
+        .success();
+
+    let mut zip = zip::ZipArchive::new(fs::File::open(&archive).unwrap()).unwrap();
+    let mut names: Vec<String> = zip.file_names().map(|n| n.unwrap().into_owned()).collect();
+    names.sort();
+    assert_eq!(names, vec!["README.md", "src/main.rs"]);
+
+    let mut content = String::new();
+    std::io::Read::read_to_string(&mut zip.by_name("src/main.rs").unwrap(), &mut content).unwrap();
+    assert_eq!(content, "fn main() {}\n");
+}
+
+#[test]
+fn test_bundle_holds_context_manifest_tree_and_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let project = temp_dir.path().join("project");
+    create_test_file(&project, "README.md", "# Project\n");
+    create_test_file(&project, "src/main.rs", "fn main() {}\n");
+    create_test_file(&project, "logo.png", "fake png");
+    let bundle = temp_dir.path().join("run.ctx");
+
+    let output = flat_cmd()
+        .arg(&project)
+        .arg("--bundle")
+        .arg(&bundle)
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let mut zip = zip::ZipArchive::new(fs::File::open(&bundle).unwrap()).unwrap();
+    let mut read = |name: &str| {
+        let mut content = String::new();
+        std::io::Read::read_to_string(&mut zip.by_name(name).unwrap(), &mut content).unwrap();
+        content
+    };
+
+    assert_eq!(read("context.txt"), String::from_utf8_lossy(&output.stdout));
+    assert_eq!(read("tree.txt"), "src/\n  main.rs\nREADME.md\n");
+
+    let manifest: serde_json::Value = serde_json::from_str(&read("manifest.json")).unwrap();
+    assert_eq!(manifest["bundle_version"], 1);
+    let decisions: Vec<(String, String)> = manifest["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|f| {
+            (
+                f["path"].as_str().unwrap().to_string(),
+                f["decision"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect();
+    assert!(decisions.contains(&("src/main.rs".into(), "full".into())));
+    assert!(decisions.contains(&("logo.png".into(), "skipped".into())));
+
+    let config: serde_json::Value = serde_json::from_str(&read("config.json")).unwrap();
+    assert!(config["command"].as_str().unwrap().starts_with("flat "));
+    assert!(config["flat.toml"].is_null());
+}
+
+#[test]
+fn test_manifest_lists_every_decision_beside_the_output() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "credentials.json", "{\"token\": \"x\"}\n");
+    let output = temp_dir.path().join("context.xml");
+    let manifest = temp_dir.path().join("manifest.json");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("-o")
+        .arg(&output)
+        .arg("--manifest")
+        .arg(&manifest)
+        .assert()
+        .success();
+
+    let manifest: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(&manifest).unwrap()).unwrap();
+    assert!(manifest["flat_schema"].as_u64().is_some());
+    assert!(manifest.get("bundle_version").is_none());
+    let files = manifest["files"].as_array().unwrap();
+    let main = files.iter().find(|f| f["path"] == "src/main.rs").unwrap();
+    assert_eq!(main["decision"], "full");
+    assert!(main["score"].as_u64().is_some());
+    assert!(main["tokens_full"].as_u64().unwrap() > 0);
+    let secret = files
+        .iter()
+        .find(|f| f["path"] == "credentials.json")
+        .unwrap();
+    assert_eq!(secret["decision"], "skipped");
+    assert_eq!(secret["reason"], "secret");
+    assert_eq!(files.len(), 2, "{:?}", files);
+    assert_eq!(manifest["summary"]["included_files"], 1);
+    assert_eq!(manifest["summary"]["skipped_by_reason"]["secret"], 1);
+}
+
 // ============================================================================
 // Exit Code Tests
 // ============================================================================
@@ -304,6 +860,36 @@ fn test_js_project_structure() {
     assert!(stdout.contains("package.json"));
 }
 
+#[test]
+fn test_reader_closing_early_is_not_an_error() {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    let temp_dir = TempDir::new().unwrap();
+    for i in 0..40 {
+        let body = format!("fn f{}() {{}}\n", i).repeat(2000);
+        create_test_file(temp_dir.path(), &format!("src/m{}.rs", i), &body);
+    }
+
+    for format in ["xml", "json", "markdown"] {
+        let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_flat"))
+            .arg(temp_dir.path())
+            .args(["--format", format])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        // Read a little, then hang up like `head` does
+        let mut first = [0u8; 64];
+        child.stdout.take().unwrap().read_exact(&mut first).unwrap();
+        let output = child.wait_with_output().unwrap();
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success(), "{}: {}", format, stderr);
+        assert!(!stderr.contains("Broken pipe"), "{}: {}", format, stderr);
+    }
+}
+
 #[test]
 fn test_js_project_secrets_excluded() {
     let output = flat_cmd()
@@ -319,6 +905,77 @@ fn test_js_project_secrets_excluded() {
     assert!(!stdout.contains("super_secret_api_key"));
 }
 
+#[test]
+fn test_redact_includes_secret_files_with_values_masked() {
+    let output = flat_cmd()
+        .args(["tests/fixtures/js_project", "--redact"])
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("config/secrets.json"));
+    assert!(stdout.contains("  \"api_key\": \"***REDACTED***\",\n  \"database\": {\n"));
+    assert!(!stdout.contains("super_secret_api_key"));
+    assert!(!stdout.contains("db_password_secret"));
+    assert!(!stdout.contains("oauth_client_secret_abc123"));
+    assert!(stdout.contains("Redacted: 4 values"));
+}
+
+#[test]
+fn test_minified_files_are_shortened() {
+    let temp_dir = TempDir::new().unwrap();
+    let rows: Vec<String> = (0..2000)
+        .map(|i| format!("{{\"id\":{},\"label\":\"row {}\"}}", i, i))
+        .collect();
+    create_test_file(
+        temp_dir.path(),
+        "data.json",
+        &format!("{{\"rows\":[{}]}}", rows.join(",")),
+    );
+    create_test_file(
+        temp_dir.path(),
+        "vendor.js",
+        &format!("!function(){{{}}}();", "var a=1;".repeat(5000)),
+    );
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    for budget in [None, Some("100k")] {
+        let mut cmd = flat_cmd();
+        cmd.arg(temp_dir.path());
+        if let Some(budget) = budget {
+            cmd.args(["--tokens", budget]);
+        }
+        let output = cmd.output().expect("Failed to execute command");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(output.status.success());
+        assert!(
+            stdout.contains(
+                "data.json\" mode=\"compressed\">\n{\n  \"rows\": [\n    {\n      \"id\": 0,"
+            ),
+            "{}",
+            stdout
+        );
+        assert!(stdout.contains("\"... 1999 more items\""));
+        assert!(
+            stdout.contains("vendor.js\" mode=\"full\" truncated=\"true\">")
+                || stdout.contains("vendor.js\" truncated=\"true\">")
+        );
+        assert!(stdout.len() < 10_000);
+        assert!(stdout.contains("Minified: 2 files shortened"));
+        assert!(stderr.contains("--keep-minified"));
+    }
+
+    let output = flat_cmd()
+        .args([temp_dir.path().to_str().unwrap(), "--keep-minified"])
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("{\"id\":1999,\"label\":\"row 1999\"}"));
+    assert!(!stdout.contains("Minified:"));
+}
+
 #[test]
 fn test_js_project_node_modules_excluded() {
     let output = flat_cmd()
@@ -390,24 +1047,236 @@ fn test_js_project_with_filters() {
 }
 
 #[test]
-fn test_js_project_stats() {
+fn test_js_project_defaults_skip_lockfile() {
     flat_cmd()
         .arg("tests/fixtures/js_project")
-        .arg("--stats")
         .assert()
         .success()
-        .stderr(predicate::str::contains("Total files:"))
-        .stderr(predicate::str::contains("binary"))
-        .stderr(predicate::str::contains("secret"));
-}
-
-// ============================================================================
-// Match Pattern Filtering Tests
-// ============================================================================
+        .stdout(predicate::str::contains("package.json"))
+        .stdout(predicate::str::contains("package-lock.json").not())
+        .stdout(predicate::str::contains("Project: node"));
 
-#[test]
-fn test_match_filter_go_test_pattern() {
-    let temp_dir = TempDir::new().unwrap();
+    flat_cmd()
+        .arg("tests/fixtures/js_project")
+        .arg("--no-project-defaults")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("package-lock.json"));
+
+    // An explicit --match overrides the curated exclusion
+    flat_cmd()
+        .arg("tests/fixtures/js_project")
+        .args(["--match", "package-lock.json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "<file path=\"package-lock.json\">",
+        ));
+}
+
+#[test]
+fn test_paths_are_relative_to_the_scanned_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<file path=\"src/main.rs\">"));
+
+    flat_cmd()
+        .current_dir(temp_dir.path())
+        .arg("src/main.rs")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<file path=\"main.rs\">"));
+
+    let absolute = temp_dir.path().canonicalize().unwrap().join("src/main.rs");
+    flat_cmd()
+        .arg(temp_dir.path().canonicalize().unwrap())
+        .args(["--absolute-paths", "--format", "json"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "\"path\":{}",
+            serde_json::to_string(&absolute.display().to_string()).unwrap()
+        )));
+}
+
+#[test]
+fn test_format_messages_splits_files_into_user_messages() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/a.rs", &"fn a() {}\n".repeat(30));
+    create_test_file(temp_dir.path(), "src/b.rs", &"fn b() {}\n".repeat(30));
+    create_test_file(temp_dir.path(), "flat.toml", "header = \"Review this.\"\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--format", "messages", "--include", "rs"])
+        .args(["--split-tokens", "150"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let messages: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let messages = messages.as_array().unwrap();
+    assert_eq!(messages.len(), 3);
+
+    assert_eq!(messages[0]["role"], "system");
+    let system = messages[0]["content"].as_str().unwrap();
+    assert!(system.starts_with("Review this.\n"), "{}", system);
+    assert!(
+        system.contains("<tree>\nsrc/\n  a.rs\n  b.rs\n</tree>"),
+        "{}",
+        system
+    );
+    assert!(system.contains("<summary>"), "{}", system);
+
+    assert_eq!(messages[1]["role"], "user");
+    let first = messages[1]["content"].as_str().unwrap();
+    assert!(first.starts_with("<file path=\"src/a.rs\">\n"), "{}", first);
+    assert!(!first.contains("src/b.rs"), "{}", first);
+    assert!(messages[2]["content"]
+        .as_str()
+        .unwrap()
+        .starts_with("<file path=\"src/b.rs\">\n"));
+
+    // Without --split-tokens, all files share one user message
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--format", "messages", "--include", "rs"])
+        .output()
+        .expect("Failed to execute command");
+    let messages: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(messages.as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn test_multiple_roots_flatten_into_one_output() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "backend/src/api.rs", "fn api() {}\n");
+    create_test_file(temp_dir.path(), "frontend/src/app.ts", "export {};\n");
+    create_test_file(temp_dir.path(), "shared/util.py", "def util(): pass\n");
+    create_test_file(temp_dir.path(), "docs/notes.md", "# Not asked for\n");
+
+    let output = flat_cmd()
+        .current_dir(temp_dir.path())
+        .args([
+            "backend/src",
+            "frontend/src",
+            "shared/",
+            "backend/src/api.rs",
+        ])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("<file path=\"backend/src/api.rs\">"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("<file path=\"frontend/src/app.ts\">"),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("<file path=\"shared/util.py\">"),
+        "{}",
+        stdout
+    );
+    assert_eq!(stdout.matches("fn api()").count(), 1);
+    assert!(!stdout.contains("notes.md"), "{}", stdout);
+    assert!(stdout.contains("Re-run: flat backend/src frontend/src shared\n"));
+
+    flat_cmd()
+        .current_dir(temp_dir.path())
+        .args(["backend", "missing"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "No such file or directory: missing",
+        ));
+}
+
+#[test]
+fn test_attach_adds_artifacts_under_the_budget() {
+    let temp_dir = TempDir::new().unwrap();
+    let project = temp_dir.path().join("project");
+    create_test_file(&project, "src/main.rs", &"fn main() {}\n".repeat(40));
+    create_test_file(temp_dir.path(), "out/test.log", "thread 'main' panicked\n");
+    let log = temp_dir.path().join("out/test.log");
+
+    // The attachment outranks source files for a budget that only fits one
+    let output = flat_cmd()
+        .arg(&project)
+        .args(["--include", "rs", "--tokens", "60"])
+        .arg("--attach")
+        .arg(format!("logs/test.log={}", log.display()))
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(
+            "<file path=\"logs/test.log\" mode=\"full\">\nthread 'main' panicked\n</file>"
+        ),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("<file path=\"src/main.rs\" excluded-reason=\"budget\"/>"),
+        "{}",
+        stdout
+    );
+
+    // A priority drop moves it behind the source again
+    let output = flat_cmd()
+        .arg(&project)
+        .args(["--include", "rs", "--tokens", "176"])
+        .arg("--attach")
+        .arg(format!("logs/test.log={}", log.display()))
+        .args(["--priority-drop", "logs/*=10"])
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("<file path=\"logs/test.log\" excluded-reason=\"budget\"/>"),
+        "{}",
+        stdout
+    );
+
+    flat_cmd()
+        .arg(&project)
+        .arg("--attach")
+        .arg(format!("src/main.rs={}", log.display()))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "already has a file named src/main.rs",
+        ));
+}
+
+#[test]
+fn test_js_project_stats() {
+    flat_cmd()
+        .arg("tests/fixtures/js_project")
+        .arg("--stats")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Total files:"))
+        .stderr(predicate::str::contains("binary"))
+        .stderr(predicate::str::contains("secret"));
+}
+
+// ============================================================================
+// Match Pattern Filtering Tests
+// ============================================================================
+
+#[test]
+fn test_match_filter_go_test_pattern() {
+    let temp_dir = TempDir::new().unwrap();
 
     create_test_file(temp_dir.path(), "main.go", "package main");
     create_test_file(temp_dir.path(), "handler.go", "package main");
@@ -572,7 +1441,7 @@ fn test_match_stats_shows_skips() {
         .arg("--stats")
         .assert()
         .success()
-        .stderr(predicate::str::contains("no match"));
+        .stderr(predicate::str::contains("glob-filter"));
 }
 
 #[test]
@@ -596,6 +1465,50 @@ fn test_match_backward_compat_regex_alias() {
     assert!(!stdout.contains("\"main.go\""));
 }
 
+#[test]
+fn test_match_negated_pattern() {
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(temp_dir.path(), "src/lib.rs", "pub fn lib() {}");
+    create_test_file(temp_dir.path(), "src/generated/api.rs", "pub fn api() {}");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--match")
+        .arg("!**/generated/**")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.contains("lib.rs"));
+    assert!(!stdout.contains("api.rs"));
+}
+
+#[test]
+fn test_not_match_combined_with_match() {
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(temp_dir.path(), "main.go", "package main");
+    create_test_file(temp_dir.path(), "main_test.go", "package main");
+    create_test_file(temp_dir.path(), "slow_test.go", "package main");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--match")
+        .arg("*_test.go")
+        .arg("--not-match")
+        .arg("slow_*")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.contains("main_test.go"));
+    assert!(!stdout.contains("slow_test.go"));
+    assert!(!stdout.contains("/main.go"));
+}
+
 // ============================================================================
 // Compression Tests
 // ============================================================================
@@ -645,6 +1558,102 @@ fn test_compress_strips_function_body() {
     assert!(!stdout.contains("let greeting"));
 }
 
+#[test]
+fn test_estimate_compress_compares_both_allocations() {
+    let temp_dir = TempDir::new().unwrap();
+
+    let body = "    let x = 1;\n".repeat(200);
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        &format!("pub fn big() {{\n{}}}\n", body),
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "500", "--estimate-compress"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("without --compress"), "{}", stdout);
+    let row = |label: &str| -> Vec<String> {
+        let line = stdout.lines().find(|l| l.starts_with(label)).unwrap();
+        line[label.len()..]
+            .split_whitespace()
+            .map(String::from)
+            .collect()
+    };
+    assert_eq!(row("Files included"), ["0", "1"]);
+    assert_eq!(row("Excluded by budget"), ["1", "0"]);
+    assert!(!stdout.contains("<file"));
+}
+
+#[test]
+fn test_compress_level_minimal_and_docs() {
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "use std::fmt;\n\n/// Public\npub fn api() -> u8 {\n    1\n}\n\n// internal\nfn helper() -> u8 {\n    2\n}\n",
+    );
+
+    let run = |level: &str| {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(["--compress", "--compress-level", level])
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    let minimal = run("minimal");
+    assert!(minimal.contains("pub fn api() -> u8 { ... }"));
+    for dropped in ["use std", "/// Public", "// internal", "fn helper"] {
+        assert!(!minimal.contains(dropped), "{} kept: {}", dropped, minimal);
+    }
+
+    let docs = run("docs");
+    assert!(docs.contains("/// Public"));
+    assert!(docs.contains("fn helper() -> u8 { ... }"));
+    assert!(!docs.contains("// internal"));
+    assert!(!docs.contains("use std"));
+}
+
+#[test]
+fn test_public_only_drops_private_items_whole() {
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "use std::fmt;\n\n/// Public\npub fn api() -> u8 {\n    1\n}\n\n// internal\nfn helper() -> u8 {\n    2\n}\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--public-only")
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("mode=\"compressed\""), "{}", stdout);
+    assert!(stdout.contains("use std::fmt;"), "{}", stdout);
+    assert!(stdout.contains("/// Public"), "{}", stdout);
+    assert!(stdout.contains("pub fn api() -> u8 { ... }"), "{}", stdout);
+    assert!(!stdout.contains("// internal"), "{}", stdout);
+    assert!(!stdout.contains("helper"), "{}", stdout);
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--public-only", "--compress-level", "docs"])
+        .assert()
+        .failure();
+}
+
 #[test]
 fn test_compress_no_mode_without_flag() {
     let temp_dir = TempDir::new().unwrap();
@@ -738,552 +1747,2294 @@ fn test_full_match_skips_compression() {
 }
 
 #[test]
-fn test_full_match_without_compress_warns() {
-    let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
-
-    let output = flat_cmd()
-        .arg(temp_dir.path())
-        .arg("--full-match")
-        .arg("*.rs")
-        .output()
-        .expect("Failed to execute command");
-
-    let stderr = String::from_utf8_lossy(&output.stderr);
-
-    assert!(stderr.contains("--full-match has no effect without --compress"));
-    // Should not have mode attribute
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(!stdout.contains("mode="));
-}
-
-#[test]
-fn test_compress_full_match_all_produces_full_output() {
-    // INV-6: --compress + --full-match '*' should produce same content as no --compress
+fn test_no_compress_ext_keeps_full_content() {
     let temp_dir = TempDir::new().unwrap();
-
     create_test_file(
         temp_dir.path(),
-        "main.rs",
-        "fn main() {\n    println!(\"hello\");\n}\n",
+        "main.py",
+        "def main():\n    print('hello')\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
     );
 
-    let output_full = flat_cmd()
+    let output = flat_cmd()
         .arg(temp_dir.path())
         .arg("--compress")
-        .arg("--full-match")
-        .arg("*")
+        .arg("--no-compress-ext")
+        .arg("py")
         .output()
         .expect("Failed to execute command");
 
-    let stdout = String::from_utf8_lossy(&output_full.stdout);
-
-    // All files should have full content
-    assert!(stdout.contains("println!(\"hello\")"));
-    assert!(stdout.contains("mode=\"full\""));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("main.py\" mode=\"full\">"));
+    assert!(stdout.contains("print('hello')"));
+    assert!(stdout.contains("pub fn add(a: i32, b: i32) -> i32 { ... }"));
 }
 
-// ============================================================================
-// Token Budget Tests
-// ============================================================================
-
 #[test]
-fn test_tokens_budget_limits_output() {
+fn test_files_from_stdin_flattens_only_listed_files() {
     let temp_dir = TempDir::new().unwrap();
-
-    // Create files with known sizes
-    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(900)); // 300 tokens (900/3)
-    create_test_file(temp_dir.path(), "small.rs", &"y".repeat(30)); // 10 tokens (30/3)
+    create_test_file(temp_dir.path(), "src/b.rs", "fn b() {}");
+    create_test_file(temp_dir.path(), "src/a.rs", "fn a() {}");
+    create_test_file(temp_dir.path(), "src/unlisted.rs", "fn unlisted() {}");
+    create_test_file(temp_dir.path(), "ignored.rs", "fn ignored() {}");
+    create_test_file(temp_dir.path(), ".env", "SECRET=1");
+    create_test_file(temp_dir.path(), ".gitignore", "ignored.rs\n");
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("50") // Only small.rs should fit
+        .arg("--files-from")
+        .arg("-")
+        .write_stdin("src/b.rs\n./src/a.rs\nignored.rs\n.env\ndeleted.rs\n")
         .output()
         .expect("Failed to execute command");
 
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
-
-    // small.rs should be included
-    assert!(stdout.contains("small.rs"));
-    // big.rs should be excluded
+    let a = stdout.find("fn a() {}").expect("a.rs included");
+    let b = stdout.find("fn b() {}").expect("b.rs included");
+    assert!(a < b, "listed files are sorted by path");
     assert!(
-        !stdout.contains("<file")
-            || !stdout.contains("big.rs")
-            || stdout.contains("Excluded by budget")
+        stdout.contains("fn ignored() {}"),
+        "listing bypasses gitignore"
     );
+    assert!(!stdout.contains("unlisted"));
+    assert!(!stdout.contains("SECRET=1"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("deleted.rs: not a file"));
 }
 
 #[test]
-fn test_tokens_zero_produces_summary_only() {
+fn test_diff_flattens_files_changed_since_a_ref() {
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "src/same.rs", "fn same() {}");
+    create_test_file(temp_dir.path(), "src/edited.rs", "fn before() {}");
+    create_test_file(temp_dir.path(), "src/deleted.rs", "fn deleted() {}");
+    git(temp_dir.path(), &["init", "-q"]);
+    git(temp_dir.path(), &["add", "."]);
+    git(temp_dir.path(), &["commit", "-qm", "base"]);
+    git(temp_dir.path(), &["tag", "base"]);
+
+    create_test_file(temp_dir.path(), "src/edited.rs", "fn after() {}");
+    create_test_file(temp_dir.path(), "src/new.rs", "fn new() {}");
+    fs::remove_file(temp_dir.path().join("src/deleted.rs")).unwrap();
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("0")
+        .args(["--diff", "base"])
         .output()
         .expect("Failed to execute command");
-
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("fn after() {}"));
+    assert!(stdout.contains("fn new() {}"), "untracked files count");
+    assert!(!stdout.contains("fn same"));
+    assert!(String::from_utf8_lossy(&output.stderr).is_empty());
 
-    // Should have summary but no file content
-    assert!(stdout.contains("<summary>"));
-    assert!(stdout.contains("Excluded by budget"));
-    assert!(!stdout.contains("<file path="));
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--diff", "no-such-ref"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no-such-ref"));
 }
 
 #[test]
-fn test_tokens_summary_shows_budget_info() {
+fn test_staged_flattens_index_contents() {
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "src/same.rs", "fn same() {}\n");
+    create_test_file(temp_dir.path(), "src/edited.rs", "fn before() {}\n");
+    git(temp_dir.path(), &["init", "-q"]);
+    git(temp_dir.path(), &["add", "."]);
+    git(temp_dir.path(), &["commit", "-qm", "base"]);
+
+    create_test_file(temp_dir.path(), "src/edited.rs", "fn staged() {}\n");
+    create_test_file(temp_dir.path(), "src/added.rs", "fn added() {}\n");
+    create_test_file(temp_dir.path(), "src/untracked.rs", "fn untracked() {}\n");
+    git(temp_dir.path(), &["add", "src/edited.rs", "src/added.rs"]);
+    // Edited again after staging, then removed: the index still has both
+    create_test_file(temp_dir.path(), "src/edited.rs", "fn unstaged() {}\n");
+    fs::remove_file(temp_dir.path().join("src/added.rs")).unwrap();
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("1000")
+        .arg("--staged")
         .output()
         .expect("Failed to execute command");
-
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("edited.rs\" added=\"1\" removed=\"1\">\nfn staged() {}"));
+    assert!(stdout.contains("added.rs\" added=\"1\" removed=\"0\">\nfn added() {}"));
+    assert_eq!(stdout.matches("fn added() {}").count(), 1);
+    assert!(!stdout.contains("fn unstaged"));
+    assert!(!stdout.contains("fn untracked"));
+    assert!(!stdout.contains("fn same"));
 
-    assert!(stdout.contains("Token budget:"));
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--staged", "--diff", "HEAD"])
+        .assert()
+        .failure();
 }
 
 #[test]
-fn test_tokens_dry_run_shows_annotations() {
+fn test_since_selects_recent_files_by_age_or_revision() {
     let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "src/old.rs",
+        "fn old() {}
+",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "src/recent.rs",
+        "fn recent() {}
+",
+    );
+    git(temp_dir.path(), &["init", "-q"]);
+    git(temp_dir.path(), &["add", "."]);
+    git(temp_dir.path(), &["commit", "-qm", "first"]);
+    create_test_file(temp_dir.path(), "src/recent.rs", "fn recent() { 1 }\n");
+    git(temp_dir.path(), &["commit", "-qam", "second"]);
+
+    let month_ago = std::time::SystemTime::now() - std::time::Duration::from_secs(30 * 86400);
+    fs::File::options()
+        .write(true)
+        .open(temp_dir.path().join("src/old.rs"))
+        .unwrap()
+        .set_modified(month_ago)
+        .unwrap();
+
+    for since in ["7d", "HEAD~1"] {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(["--since", since])
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(
+            stdout.contains("fn recent() { 1 }"),
+            "{}: {}",
+            since,
+            stdout
+        );
+        assert!(
+            stdout.contains("Skipped: 1 (1 not-recent)"),
+            "{}: {}",
+            since,
+            stdout
+        );
+        assert!(!stdout.contains("fn old"));
+    }
 
-    create_test_file(temp_dir.path(), "small.rs", "fn main() {}\n");
-    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(9000));
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--since", "no-such-ref"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("no-such-ref"));
+}
+
+#[test]
+fn test_diff_annotates_line_changes_and_adds_imported_context() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "src/main.rs",
+        "mod parser;\n\nfn main() {\n    parser::run();\n}\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "src/parser.rs",
+        "pub fn run() {\n    println!(\"parsing\");\n}\n",
+    );
+    create_test_file(temp_dir.path(), "src/unrelated.rs", "fn unrelated() {}\n");
+    git(temp_dir.path(), &["init", "-q"]);
+    git(temp_dir.path(), &["add", "."]);
+    git(temp_dir.path(), &["commit", "-qm", "base"]);
+
+    create_test_file(
+        temp_dir.path(),
+        "src/main.rs",
+        "mod parser;\n\nfn main() {\n    parser::run();\n    parser::run();\n}\n",
+    );
+    create_test_file(temp_dir.path(), "src/new.rs", "fn a() {}\nfn b() {}\n");
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("100")
-        .arg("--dry-run")
+        .args(["--diff", "HEAD"])
         .output()
         .expect("Failed to execute command");
-
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("main.rs\" added=\"1\" removed=\"0\">"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("new.rs\" added=\"2\" removed=\"0\">"));
+    // Imported by a changed file, so included as compressed context
+    assert!(stdout.contains("parser.rs\" mode=\"compressed\">"));
+    assert!(stdout.contains("pub fn run() { ... }"));
+    assert!(!stdout.contains("println!(\"parsing\")"));
+    assert!(!stdout.contains("unrelated"));
 
-    // Should show annotations
-    assert!(stdout.contains("[FULL]") || stdout.contains("[EXCLUDED]"));
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--diff", "HEAD", "--format", "json"])
+        .output()
+        .expect("Failed to execute command");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let main = json["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|f| f["path"].as_str().unwrap().ends_with("main.rs"))
+        .unwrap();
+    assert_eq!(
+        (main["added"].as_u64(), main["removed"].as_u64()),
+        (Some(1), Some(0))
+    );
 }
 
 #[test]
-fn test_tokens_priority_ordering() {
+fn test_owners_names_top_committers() {
     let temp_dir = TempDir::new().unwrap();
-
-    // README gets highest priority (100), main.rs gets 90
-    create_test_file(temp_dir.path(), "README.md", "# Project\n");
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
-    create_test_file(temp_dir.path(), "utils.rs", &"x".repeat(9000));
+    create_test_file(temp_dir.path(), "a.rs", "fn a() {}\n");
+    create_test_file(temp_dir.path(), "b.rs", "fn b() {}\n");
+    git(temp_dir.path(), &["init", "-q"]);
+    git(temp_dir.path(), &["add", "."]);
+    git(temp_dir.path(), &["commit", "-qm", "base"]);
+    for (author, body) in [("Alice", "1"), ("Bob", "2"), ("Alice", "3")] {
+        create_test_file(temp_dir.path(), "a.rs", &format!("fn a() {{ {} }}\n", body));
+        git(
+            temp_dir.path(),
+            &[
+                "commit",
+                "-qam",
+                "edit",
+                &format!("--author={} <{}@example.com>", author, author),
+            ],
+        );
+    }
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("100")
-        .arg("--dry-run")
+        .arg("--owners")
         .output()
         .expect("Failed to execute command");
-
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("a.rs\" owners=\"Alice,Bob\">"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("b.rs\" owners=\"flat\">"));
 
-    // README should appear first (highest priority)
-    let readme_pos = stdout.find("README.md");
-    let main_pos = stdout.find("main.rs");
-    assert!(readme_pos.is_some());
-    assert!(main_pos.is_some());
-    assert!(readme_pos.unwrap() < main_pos.unwrap());
+    // Opt-in: nothing about committers without the flag
+    flat_cmd()
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("owners=").not());
 }
 
 #[test]
-fn test_tokens_without_compress_no_mode_attr() {
-    // INV-7: --tokens without --compress never adds mode attributes
+fn test_file_meta_tags_tokens_bytes_and_hash() {
+    const ABC_SHA256: &str = "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad";
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "a.txt", "abc");
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {\n    run();\n}\n");
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("1000")
+        .arg("--file-meta")
         .output()
         .expect("Failed to execute command");
-
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("bytes=\"3\" sha256=\"{}\">", ABC_SHA256)),
+        "{}",
+        stdout
+    );
+    assert!(
+        stdout.contains("<file path=\"main.rs\" tokens=\""),
+        "{}",
+        stdout
+    );
 
-    assert!(!stdout.contains("mode="));
+    // The hash is of the content as written, so it follows compression
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--file-meta", "--compress", "--format", "json"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files = json["files"].as_array().unwrap();
+    let text = files.iter().find(|f| f["path"] == "a.txt").unwrap();
+    assert_eq!(text["sha256"], ABC_SHA256);
+    let main = files.iter().find(|f| f["path"] == "main.rs").unwrap();
+    assert_eq!(main["content"], "fn main() { ... }");
+    assert_eq!(main["bytes"], "fn main() { ... }".len());
+    assert_eq!(main["sha256"].as_str().unwrap().len(), 64);
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("sha256=").not());
 }
 
 #[test]
-fn test_tokens_with_compress() {
+fn test_line_range_selects_lines_of_one_file() {
     let temp_dir = TempDir::new().unwrap();
-
     create_test_file(
         temp_dir.path(),
-        "main.rs",
-        "fn hello(name: &str) -> String {\n    let greeting = format!(\"Hello, {}!\", name);\n    greeting\n}\n",
+        "src/big.rs",
+        "fn one() {}\nfn two() {}\nfn three() {}\nfn four() {}\n",
     );
+    create_test_file(temp_dir.path(), "src/other.rs", "fn other() {}\n");
 
     let output = flat_cmd()
-        .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("1000")
+        .arg(format!(
+            "{}:2-3",
+            temp_dir.path().join("src/big.rs").display()
+        ))
         .arg("--compress")
         .output()
         .expect("Failed to execute command");
-
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("mode=\"full\" lines=\"2-3\">\nfn two() {}\nfn three() {}\n</file>"));
+    assert!(!stdout.contains("fn one"));
+    assert!(!stdout.contains("fn four"));
 
-    // Should have mode attribute and be compressed
-    assert!(stdout.contains("mode="));
-    assert!(stdout.contains("{ ... }"));
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--match", "src/big.rs:4", "--format", "json"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files = json["files"].as_array().unwrap();
+    assert_eq!(files.len(), 1, "only the matched file: {:?}", files);
+    assert_eq!(files[0]["lines"], "4-4");
+    assert_eq!(files[0]["content"], "fn four() {}\n");
 }
 
-// ============================================================================
-// Determinism Tests
-// ============================================================================
+#[test]
+fn test_max_compress_size_keeps_large_files_full() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "small.rs",
+        "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+    );
+    let large = "pub fn body() -> i32 {\n    1 + 1\n}\n".repeat(100);
+    create_test_file(temp_dir.path(), "large.rs", &large);
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .arg("--max-compress-size")
+        .arg("1k")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "pub fn add(a: i32, b: i32) -> i32 { ... }",
+        ))
+        .stdout(predicate::str::contains("large.rs\" mode=\"full\">"))
+        .stdout(predicate::str::contains("    1 + 1\n"))
+        .stderr(predicate::str::contains("--max-compress-size"));
+}
 
 #[test]
-fn test_output_is_deterministic() {
-    // INV-8: Running flat twice on the same directory produces identical output
-    let output1 = flat_cmd()
-        .arg("tests/fixtures/sample_project")
-        .output()
-        .expect("Failed to execute command");
+fn test_generated_stub_reduced_to_type_names() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "api/greeter.pb.go",
+        "package api\n\ntype HelloRequest struct {\n\tName string\n}\n\nfunc (x *HelloRequest) GetName() string {\n\treturn x.Name\n}\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "api/greeter.proto",
+        "message HelloRequest { string name = 1; }\n",
+    );
 
-    let output2 = flat_cmd()
-        .arg("tests/fixtures/sample_project")
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
         .output()
         .expect("Failed to execute command");
 
-    assert_eq!(output1.stdout, output2.stdout);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(
+        "greeter.pb.go\" mode=\"compressed\" generated=\"true\">\ntype HelloRequest\n</file>"
+    ));
+    assert!(stdout.contains("message HelloRequest { string name = 1; }"));
+    // Condensing a file of nothing but declarations saves nothing
+    assert!(
+        stdout.contains("greeter.proto\" mode=\"full\">"),
+        "{}",
+        stdout
+    );
 }
 
 #[test]
-fn test_output_order_sorted_by_path() {
+fn test_full_match_without_compress_warns() {
     let temp_dir = TempDir::new().unwrap();
-
-    // Create files in non-alphabetical order
-    create_test_file(temp_dir.path(), "c.rs", "fn c() {}");
-    create_test_file(temp_dir.path(), "a.rs", "fn a() {}");
-    create_test_file(temp_dir.path(), "b.rs", "fn b() {}");
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--dry-run")
+        .arg("--full-match")
+        .arg("*.rs")
         .output()
         .expect("Failed to execute command");
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    // Filter to only file path lines (before summary), not summary content
-    let lines: Vec<&str> = stdout
-        .lines()
-        .take_while(|l| !l.starts_with("<summary>"))
-        .filter(|l| l.ends_with(".rs"))
-        .collect();
+    let stderr = String::from_utf8_lossy(&output.stderr);
 
-    // Files should appear in alphabetical order
-    assert_eq!(lines.len(), 3);
-    assert!(lines[0].contains("a.rs"));
-    assert!(lines[1].contains("b.rs"));
-    assert!(lines[2].contains("c.rs"));
+    assert!(stderr.contains("--full-match has no effect without --compress"));
+    // Should not have mode attribute
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("mode="));
 }
 
-// ============================================================================
-// Edge Cases and Error Handling
-// ============================================================================
-
 #[test]
-fn test_max_size_option() {
-    flat_cmd()
-        .arg("tests/fixtures/sample_project")
-        .arg("--max-size")
-        .arg("10485760") // 10MB
-        .assert()
-        .success();
-}
+fn test_compress_full_match_all_produces_full_output() {
+    // INV-6: --compress + --full-match '*' should produce same content as no --compress
+    let temp_dir = TempDir::new().unwrap();
 
-#[test]
-fn test_nonexistent_directory() {
-    flat_cmd()
-        .arg("/path/that/does/not/exist")
-        .assert()
-        .failure();
-}
+    create_test_file(
+        temp_dir.path(),
+        "main.rs",
+        "fn main() {\n    println!(\"hello\");\n}\n",
+    );
 
-#[test]
-fn test_empty_include_filter() {
-    // Empty include filter matches nothing -> exit code 3
-    flat_cmd()
-        .arg("tests/fixtures/sample_project")
-        .arg("--include")
-        .arg("")
-        .assert()
-        .failure()
-        .code(3);
+    let output_full = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .arg("--full-match")
+        .arg("*")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output_full.stdout);
+
+    // All files should have full content
+    assert!(stdout.contains("println!(\"hello\")"));
+    assert!(stdout.contains("mode=\"full\""));
 }
 
 // ============================================================================
-// Real-World Workflow Tests
+// Token Budget Tests
 // ============================================================================
 
 #[test]
-fn test_workflow_rust_project() {
-    // Typical workflow: get only Rust source for AI
+fn test_tokens_budget_limits_output() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create files with known sizes
+    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(900)); // 300 tokens (900/3)
+    create_test_file(temp_dir.path(), "small.rs", &"y".repeat(30)); // 10 tokens (30/3)
+
     let output = flat_cmd()
-        .arg("tests/fixtures/sample_project")
-        .arg("--include")
-        .arg("rs,toml")
-        .arg("--exclude")
-        .arg("test")
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("50") // Only small.rs should fit
         .output()
         .expect("Failed to execute command");
 
     let stdout = String::from_utf8_lossy(&output.stdout);
 
-    assert!(stdout.contains("src/main.rs"));
-    assert!(stdout.contains("Cargo.toml"));
-}
+    // small.rs should be included
+    assert!(stdout.contains("small.rs"));
+    // big.rs should be excluded
+    assert!(
+        !stdout.contains("<file")
+            || !stdout.contains("big.rs")
+            || stdout.contains("Excluded by budget")
+    );
+}
+
+#[test]
+fn test_tokens_zero_produces_summary_only() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("0")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Should have summary but no file content, only a stub for what was left out
+    assert!(stdout.contains("<summary>"));
+    assert!(stdout.contains("Excluded by budget"));
+    assert!(!stdout.contains("</file>"));
+    assert!(stdout.contains("main.rs\" excluded-reason=\"budget\"/>"));
+}
+
+#[test]
+fn test_tokens_summary_shows_budget_info() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("1000")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.contains("Token budget:"));
+}
+
+#[test]
+fn test_reserve_tokens_shrinks_the_budget_before_allocation() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "a.rs", &"x".repeat(600)); // ~200 tokens
+    create_test_file(temp_dir.path(), "b.rs", &"y".repeat(600)); // ~200 tokens
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "500"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("Excluded by budget"), "{}", stdout);
+    assert!(!stdout.contains("Reserved:"), "{}", stdout);
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "500", "--reserve-tokens", "50%"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("/ 250 used"), "{}", stdout);
+    assert!(
+        stdout.contains("Reserved: 250 of 500 for the prompt and answer"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("Excluded by budget: 1 files"), "{}", stdout);
+    assert!(stdout.contains("--reserve-tokens 50%"), "{}", stdout);
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "500", "--reserve-tokens", "1k"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("leaves nothing of --tokens 500"));
+}
+
+#[test]
+fn test_budget_exclusions_suggest_a_follow_up_command() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    let body: String = (0..10)
+        .map(|i| {
+            format!(
+                "pub fn f{}() -> u32 {{\n{}    {}\n}}\n",
+                i,
+                "    let x = 1;\n".repeat(20),
+                i
+            )
+        })
+        .collect();
+    create_test_file(temp_dir.path(), "src/engine.rs", &body);
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "300"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let command = stderr
+        .lines()
+        .skip_while(|line| !line.starts_with("To flatten the 1 files left out"))
+        .nth(1)
+        .expect("a follow-up command")
+        .trim();
+    assert!(
+        command.ends_with("--match src/engine.rs --compress --tokens 300"),
+        "{}",
+        command
+    );
+
+    // Run it: the excluded file now fits, compressed, and nothing else comes along
+    let args: Vec<&str> = command.split_whitespace().skip(1).collect();
+    let output = flat_cmd().args(&args).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("engine.rs\" mode=\"compressed\">"),
+        "{}",
+        stdout
+    );
+    assert!(!stdout.contains("fn main()"));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains("To flatten"));
+}
+
+#[test]
+fn test_tokens_dry_run_shows_annotations() {
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(temp_dir.path(), "small.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(9000));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("100")
+        .arg("--dry-run")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Should show annotations
+    assert!(stdout.contains("mode=full") || stdout.contains("excluded-reason=budget"));
+}
+
+#[test]
+fn test_tokens_priority_ordering() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // README gets highest priority (100), main.rs gets 90
+    create_test_file(temp_dir.path(), "README.md", "# Project\n");
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "utils.rs", &"x".repeat(9000));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("100")
+        .arg("--dry-run")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // README should appear first (highest priority)
+    let readme_pos = stdout.find("README.md");
+    let main_pos = stdout.find("main.rs");
+    assert!(readme_pos.is_some());
+    assert!(main_pos.is_some());
+    assert!(readme_pos.unwrap() < main_pos.unwrap());
+}
+
+#[test]
+fn test_tests_and_docs_budget_caps() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    // ~300 tokens each: fits in --tokens 1000, but not in a 10% share
+    create_test_file(temp_dir.path(), "tests/big_test.rs", &"x".repeat(900));
+    create_test_file(temp_dir.path(), "docs/guide.md", &"y".repeat(1200));
+    create_test_file(temp_dir.path(), "docs/intro.md", "# Intro\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args([
+            "--tokens",
+            "1000",
+            "--tests-budget",
+            "10%",
+            "--docs-budget",
+            "100",
+        ])
+        .arg("--dry-run")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("big_test.rs excluded-reason=budget"));
+    assert!(stdout.contains("guide.md excluded-reason=budget"));
+    assert!(stdout.contains("intro.md mode=full"));
+    assert!(stdout.contains("main.rs mode=full"));
+}
+
+#[test]
+fn test_directory_quotas_cap_their_files() {
+    let temp_dir = TempDir::new().unwrap();
+    // ~200 tokens each: all fit in --tokens 1000, but only one in a 250-token quota
+    create_test_file(temp_dir.path(), "src/a.rs", &"a".repeat(600));
+    create_test_file(temp_dir.path(), "src/b.rs", &"b".repeat(600));
+    create_test_file(temp_dir.path(), "lib/c.rs", &"c".repeat(600));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args([
+            "--tokens",
+            "1000",
+            "--quota",
+            "src/=25%,lib=50%",
+            "--dry-run",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("a.rs mode=full"), "{}", stdout);
+    assert!(stdout.contains("b.rs excluded-reason=budget"), "{}", stdout);
+    assert!(stdout.contains("c.rs mode=full"), "{}", stdout);
+    assert!(stdout.contains("--quota 'src/=25%,lib/=50%'"), "{}", stdout);
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "1000", "--quota", "../src=10%"])
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_follow_doc_links_boosts_referenced_files() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "README.md",
+        "# Project\nThe core is [the engine](src/deep/engine.rs).\n",
+    );
+    create_test_file(temp_dir.path(), "src/deep/engine.rs", &"e".repeat(600));
+    create_test_file(temp_dir.path(), "src/other.rs", &"o".repeat(600));
+
+    let run = |follow: bool| {
+        let mut cmd = flat_cmd();
+        cmd.arg(temp_dir.path())
+            .args(["--tokens", "250", "--dry-run"]);
+        if follow {
+            cmd.arg("--follow-doc-links");
+        }
+        String::from_utf8(cmd.output().unwrap().stdout).unwrap()
+    };
+
+    let plain = run(false);
+    assert!(plain.contains("engine.rs excluded-reason=budget"));
+    assert!(plain.contains("other.rs mode=full"));
+
+    let followed = run(true);
+    assert!(followed.contains("engine.rs mode=full"));
+    assert!(followed.contains("other.rs excluded-reason=budget"));
+    assert!(followed.contains("Boosted by doc links: 1 files"));
+}
+
+#[test]
+fn test_related_boosts_imported_files() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "src/handler.rs",
+        "use crate::deep::engine::Engine;\n\nfn handle() {}\n",
+    );
+    create_test_file(temp_dir.path(), "src/deep/engine.rs", &"e".repeat(600));
+    create_test_file(temp_dir.path(), "src/other.rs", &"o".repeat(600));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args([
+            "--tokens",
+            "250",
+            "--dry-run",
+            "--related",
+            "src/handler.rs",
+        ])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.contains("engine.rs mode=full"));
+    assert!(stdout.contains("handler.rs mode=full"));
+    assert!(stdout.contains("other.rs excluded-reason=budget"));
+    assert!(stdout.contains("Boosted as related: 2 files"));
+}
+
+#[test]
+fn test_tokens_without_compress_marks_full_mode() {
+    // A budget decides per file what to write, so the mode is always spelled out
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("1000")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.contains("main.rs\" mode=\"full\">"));
+}
+
+#[test]
+fn test_tokens_with_compress() {
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "main.rs",
+        "fn hello(name: &str) -> String {\n    let greeting = format!(\"Hello, {}!\", name);\n    greeting\n}\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("1000")
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Should have mode attribute and be compressed
+    assert!(stdout.contains("mode="));
+    assert!(stdout.contains("{ ... }"));
+}
+
+// ============================================================================
+// Determinism Tests
+// ============================================================================
+
+#[test]
+fn test_output_is_deterministic() {
+    // INV-8: Running flat twice on the same directory produces identical output
+    let output1 = flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .output()
+        .expect("Failed to execute command");
+
+    let output2 = flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(output1.stdout, output2.stdout);
+}
+
+#[test]
+fn test_output_order_sorted_by_path() {
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create files in non-alphabetical order
+    create_test_file(temp_dir.path(), "c.rs", "fn c() {}");
+    create_test_file(temp_dir.path(), "a.rs", "fn a() {}");
+    create_test_file(temp_dir.path(), "b.rs", "fn b() {}");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--dry-run")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // Filter to only file path lines (before summary), not summary content
+    let lines: Vec<&str> = stdout
+        .lines()
+        .take_while(|l| !l.starts_with("<summary>"))
+        .filter(|l| l.contains(".rs "))
+        .collect();
+
+    // Files should appear in alphabetical order
+    assert_eq!(lines.len(), 3);
+    assert!(lines[0].contains("a.rs"));
+    assert!(lines[1].contains("b.rs"));
+    assert!(lines[2].contains("c.rs"));
+}
+
+// ============================================================================
+// Edge Cases and Error Handling
+// ============================================================================
+
+#[test]
+fn test_max_size_option() {
+    flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .arg("--max-size")
+        .arg("10485760") // 10MB
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_large_file_streamed_intact() {
+    let temp_dir = TempDir::new().unwrap();
+    let line = "let value = \"abcdefghijklmnopqrstuvwxyz é\";\n";
+    let big = line.repeat(20_000); // ~700KB, above the streaming threshold
+    create_test_file(temp_dir.path(), "big.rs", &big);
+    create_test_file(temp_dir.path(), "bad.txt", &"x".repeat(300_000));
+    let mut bytes = fs::read(temp_dir.path().join("bad.txt")).unwrap();
+    bytes.push(0xff);
+    fs::write(temp_dir.path().join("bad.txt"), bytes).unwrap();
+
+    let output = flat_cmd().arg(temp_dir.path()).output().unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+
+    let start = stdout.find("big.rs\">\n").unwrap() + "big.rs\">\n".len();
+    assert_eq!(&stdout[start..start + big.len()], big);
+    assert!(stdout[start + big.len()..].starts_with("</file>"));
+    assert!(!stdout.contains("bad.txt\">"));
+    assert!(stderr.contains("bad.txt"));
+}
+
+#[test]
+fn test_large_file_streamed_counts_with_token_ratios() {
+    let temp_dir = TempDir::new().unwrap();
+    let big = "let value = 1;\n".repeat(20_000); // 300KB, above the streaming threshold
+    create_test_file(temp_dir.path(), "big.rs", &big);
+    create_test_file(temp_dir.path(), "flat.toml", "[token-ratios]\nrs = 2\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--file-meta", "--include", "rs"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("tokens=\"150000\""));
+}
+
+#[test]
+fn test_nonexistent_directory() {
+    flat_cmd()
+        .arg("/path/that/does/not/exist")
+        .assert()
+        .failure();
+}
+
+#[test]
+fn test_empty_include_filter() {
+    // Empty include filter matches nothing -> exit code 3
+    flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .arg("--include")
+        .arg("")
+        .assert()
+        .failure()
+        .code(3);
+}
+
+// ============================================================================
+// Real-World Workflow Tests
+// ============================================================================
+
+#[test]
+fn test_workflow_rust_project() {
+    // Typical workflow: get only Rust source for AI
+    let output = flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .arg("--include")
+        .arg("rs,toml")
+        .arg("--exclude")
+        .arg("test")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.contains("src/main.rs"));
+    assert!(stdout.contains("Cargo.toml"));
+}
+
+#[test]
+fn test_workflow_preview_before_share() {
+    // User wants to preview what will be shared
+    flat_cmd()
+        .arg("tests/fixtures/js_project")
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("<summary>"));
+}
+
+#[test]
+fn test_workflow_stats_check() {
+    // Quick check of project size
+    flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .arg("--stats")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Total files:"))
+        .stderr(predicate::str::contains("Included:"));
+}
+
+// ============================================================================
+// Snapshot Tests — Pin Known-Good Output (Phase 3D)
+// ============================================================================
+
+#[test]
+fn test_snapshot_rust_compression() {
+    let output = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .arg("--include")
+        .arg("rs")
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let expected = fs::read_to_string("tests/fixtures/snapshot/expected_rs.txt").unwrap();
+    assert_eq!(
+        stdout.as_ref(),
+        expected.as_str(),
+        "Rust compression output changed from golden file"
+    );
+}
+
+#[test]
+fn test_snapshot_typescript_compression() {
+    let output = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .arg("--include")
+        .arg("ts")
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let expected = fs::read_to_string("tests/fixtures/snapshot/expected_ts.txt").unwrap();
+    assert_eq!(
+        stdout.as_ref(),
+        expected.as_str(),
+        "TypeScript compression output changed from golden file"
+    );
+}
+
+#[test]
+fn test_snapshot_python_compression() {
+    let output = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .arg("--include")
+        .arg("py")
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let expected = fs::read_to_string("tests/fixtures/snapshot/expected_py.txt").unwrap();
+    assert_eq!(
+        stdout.as_ref(),
+        expected.as_str(),
+        "Python compression output changed from golden file"
+    );
+}
+
+#[test]
+fn test_snapshot_go_compression() {
+    let output = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .arg("--include")
+        .arg("go")
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let expected = fs::read_to_string("tests/fixtures/snapshot/expected_go.txt").unwrap();
+    assert_eq!(
+        stdout.as_ref(),
+        expected.as_str(),
+        "Go compression output changed from golden file"
+    );
+}
+
+// ============================================================================
+// Mutation-Killing Tests — Cover Surviving Mutants
+// ============================================================================
+
+#[test]
+fn test_output_files_in_sorted_order() {
+    // Kills Mutation 8: verifies files appear in lexicographic path order
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(temp_dir.path(), "z_last.rs", "fn z() {}");
+    create_test_file(temp_dir.path(), "a_first.rs", "fn a() {}");
+    create_test_file(temp_dir.path(), "m_middle.rs", "fn m() {}");
+    // Subdirectories should also sort correctly
+    create_test_file(temp_dir.path(), "b_dir/nested.rs", "fn n() {}");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Extract file paths from <file path="..."> tags
+    let paths: Vec<&str> = stdout
+        .lines()
+        .filter(|l| l.starts_with("<file path="))
+        .collect();
+
+    assert_eq!(paths.len(), 4, "Expected 4 file tags");
+
+    // Verify lexicographic order
+    let a_pos = stdout.find("a_first.rs").expect("a_first.rs not found");
+    let b_pos = stdout
+        .find("b_dir/nested.rs")
+        .expect("b_dir/nested.rs not found");
+    let m_pos = stdout.find("m_middle.rs").expect("m_middle.rs not found");
+    let z_pos = stdout.find("z_last.rs").expect("z_last.rs not found");
+    assert!(
+        a_pos < b_pos && b_pos < m_pos && m_pos < z_pos,
+        "Files not in sorted order: a={}, b_dir={}, m={}, z={}",
+        a_pos,
+        b_pos,
+        m_pos,
+        z_pos
+    );
+}
+
+#[test]
+fn test_compress_fallback_on_syntax_error() {
+    // Kills Mutation 9: verifies parse errors fall back to full content
+    let temp_dir = TempDir::new().unwrap();
+
+    // Deliberately broken Rust syntax
+    let broken_rust = "fn broken( {\n    this is not valid rust\n}\n";
+    create_test_file(temp_dir.path(), "broken.rs", broken_rust);
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    // File should still be included (fallback to full content)
+    assert!(
+        stdout.contains("broken.rs"),
+        "broken.rs should be included in output"
+    );
+    assert!(
+        stdout.contains("this is not valid rust"),
+        "Full content should be preserved on parse error"
+    );
+    // Should have mode="full" since compression failed
+    assert!(
+        stdout.contains("mode=\"full\""),
+        "Parse error file should have mode=full"
+    );
+    // Should warn on stderr about parse error
+    assert!(
+        stderr.contains("ERROR") || stderr.contains("error") || stderr.contains("Warning"),
+        "Should warn about parse error on stderr"
+    );
+}
+
+// ============================================================================
+// Coverage Gap Tests — Additional assertions per Phase 4
+// ============================================================================
+
+#[test]
+fn test_compress_rust_preserves_imports_integration() {
+    // Integration-level test for Mutation 3 coverage gap
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "use std::path::Path;\nuse std::io::Read;\n\nfn process(p: &Path) {\n    println!(\"{}\", p.display());\n}\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        stdout.contains("use std::path::Path;"),
+        "use statement should be preserved in compressed output"
+    );
+    assert!(
+        stdout.contains("use std::io::Read;"),
+        "second use statement should be preserved"
+    );
+    assert!(
+        stdout.contains("fn process(p: &Path) { ... }"),
+        "function should show compressed signature"
+    );
+    assert!(
+        !stdout.contains("println!"),
+        "function body should be stripped"
+    );
+}
+
+#[test]
+fn test_compress_typescript_export_function() {
+    // Verifies export function declarations are compressed
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "api.ts",
+        "export function fetchData(url: string): Promise<Response> {\n  const res = await fetch(url);\n  return res.json();\n}\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        stdout.contains("export function fetchData(url: string): Promise<Response> { ... }"),
+        "export function should be compressed: got {}",
+        stdout
+    );
+    assert!(
+        !stdout.contains("await fetch(url)"),
+        "function body should be stripped from export function"
+    );
+}
+
+#[test]
+fn test_compress_python_module_constants() {
+    // Verifies module-level constants are preserved
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "config.py",
+        "MAX_SIZE = 1024\nDEBUG = True\n\ndef run():\n    print('running')\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        stdout.contains("MAX_SIZE = 1024"),
+        "Module-level constant should be preserved"
+    );
+    assert!(
+        stdout.contains("DEBUG = True"),
+        "Module-level constant should be preserved"
+    );
+    assert!(
+        !stdout.contains("print('running')"),
+        "Function body should be stripped"
+    );
+}
+
+#[test]
+fn test_compress_markdown_is_opt_in_and_labeled_outline() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "docs/guide.md",
+        "# Guide\n\nIntro.\n\nDetails nobody needs.\n\n## Setup\n\n```sh\nmake\n```\n",
+    );
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("guide.md\" mode=\"full\">"))
+        .stdout(predicate::str::contains("Details nobody needs."));
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--compress", "--compress-markdown"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "guide.md\" mode=\"outline\">\n# Guide\n\nIntro.\n\n## Setup\n\n```sh\n...\n```\n</file>",
+        ));
+}
+
+#[test]
+fn test_compress_schema_files_without_tree_sitter() {
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "db/schema.sql",
+        "CREATE TABLE users (id INT PRIMARY KEY);\nINSERT INTO users VALUES (1);\nINSERT INTO users VALUES (2);\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "api/users.proto",
+        "// Users\nmessage User {\n  string id = 1;\n}\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "api/schema.graphql",
+        "type User {\n  id: ID!\n}\n\nquery Me {\n  me { id }\n}\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(stdout.contains("CREATE TABLE users (id INT PRIMARY KEY);"));
+    assert!(stdout.contains("-- ... 2 INSERT statements"), "{}", stdout);
+    assert!(stdout.contains("message User { string id = 1; }"));
+    assert!(!stdout.contains("// Users"));
+    assert!(stdout.contains("query Me { ... }"), "{}", stdout);
+    assert_eq!(
+        stdout.matches("mode=\"compressed\"").count(),
+        3,
+        "{}",
+        stdout
+    );
+}
+
+#[test]
+fn test_priority_ordering_integration() {
+    // Integration-level test for Mutation 6 coverage gap
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "README.md",
+        "# Project\nDescription here.\n",
+    );
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}\n");
+    create_test_file(
+        temp_dir.path(),
+        "src/deep/nested/util.rs",
+        &"x".repeat(3000),
+    );
+    create_test_file(
+        temp_dir.path(),
+        "Cargo.toml",
+        "[package]\nname = \"test\"\n",
+    );
+
+    // Small budget: should include README and main.rs but exclude deep nested file
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("100")
+        .arg("--dry-run")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // README should be included (priority 100)
+    assert!(
+        stdout.contains("README.md"),
+        "README.md should be in output"
+    );
+    // Deep nested file should be excluded by budget
+    assert!(
+        stdout.contains("util.rs") && stdout.contains("excluded-reason=budget"),
+        "Deep nested file should be excluded by budget"
+    );
+}
+
+#[test]
+fn test_determinism_with_compress() {
+    // Runs flat twice with --compress and verifies identical output
+    let output1 = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let output2 = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(
+        output1.stdout, output2.stdout,
+        "Compressed output should be deterministic across runs"
+    );
+}
+
+#[test]
+fn test_determinism_with_tokens() {
+    // Runs flat twice with --tokens and verifies identical output
+    let output1 = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .arg("--tokens")
+        .arg("5000")
+        .output()
+        .expect("Failed to execute command");
+
+    let output2 = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .arg("--tokens")
+        .arg("5000")
+        .output()
+        .expect("Failed to execute command");
+
+    assert_eq!(
+        output1.stdout, output2.stdout,
+        "Token-budgeted output should be deterministic across runs"
+    );
+}
+
+#[test]
+fn test_tokenizer_counts_bpe_tokens() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "notes.txt",
+        "hello world hello world hello world",
+    );
+
+    let count = |tokenizer: &str| {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(["--format", "json", "--tokenizer", tokenizer])
+            .output()
+            .expect("Failed to execute command");
+        let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        doc["files"][0]["tokens"].as_u64().unwrap()
+    };
+    assert_eq!(count("heuristic"), 35 / 4);
+    assert_eq!(count("cl100k_base"), 6);
+}
+
+#[test]
+fn test_tokens_budget_actually_enforced() {
+    // Phase 5A: Prove token budget is enforced with math
+    let temp_dir = TempDir::new().unwrap();
+
+    // Create files with known sizes
+    create_test_file(temp_dir.path(), "a.rs", &"x".repeat(600)); // ~200 tokens
+    create_test_file(temp_dir.path(), "b.rs", &"y".repeat(600)); // ~200 tokens
+    create_test_file(temp_dir.path(), "c.rs", &"z".repeat(600)); // ~200 tokens
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("250") // Only ~1 file should fit
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Count how many files were written (excluded ones are bare stubs)
+    let file_count = stdout.matches("</file>").count();
+    assert!(
+        file_count <= 2,
+        "With budget 250 and 3x200-token files, at most 1-2 files should be included, got {}",
+        file_count
+    );
+    // Should have excluded some files by budget
+    assert!(
+        stdout.contains("Excluded by budget"),
+        "Summary should mention excluded files"
+    );
+}
+
+#[test]
+fn test_compression_ratio_is_real() {
+    // Phase 5C: Verify compression actually reduces output size
+    let full_output = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--include")
+        .arg("rs")
+        .output()
+        .expect("Failed to execute command");
+
+    let compressed_output = flat_cmd()
+        .arg("tests/fixtures/snapshot")
+        .arg("--compress")
+        .arg("--include")
+        .arg("rs")
+        .output()
+        .expect("Failed to execute command");
+
+    // The file content, not the summary after it, which compression doesn't touch
+    let content_len = |stdout: &[u8]| {
+        let stdout = String::from_utf8_lossy(stdout);
+        stdout.find("<summary>").unwrap_or(stdout.len())
+    };
+    let full_len = content_len(&full_output.stdout);
+    let compressed_len = content_len(&compressed_output.stdout);
+
+    assert!(
+        compressed_len < full_len,
+        "Compressed output ({} bytes) should be smaller than full ({} bytes)",
+        compressed_len,
+        full_len
+    );
+    let reduction_pct = ((full_len - compressed_len) * 100) / full_len;
+    assert!(
+        reduction_pct > 20,
+        "Compression should reduce output by >20%, got {}%",
+        reduction_pct
+    );
+}
+
+#[test]
+fn test_compress_unsupported_extension_passthrough() {
+    // Fallback: unknown extension gets full content
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(temp_dir.path(), "data.csv", "name,age\nalice,30\nbob,25\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        stdout.contains("alice,30"),
+        "CSV content should be included in full"
+    );
+    assert!(
+        stdout.contains("mode=\"full\""),
+        "Unsupported file should get mode=full"
+    );
+}
+
+#[test]
+fn test_compress_empty_file() {
+    // Fallback: empty file
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(temp_dir.path(), "empty.rs", "");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // Empty file should still appear
+    assert!(
+        stdout.contains("empty.rs"),
+        "Empty file should be in output"
+    );
+}
+
+#[test]
+fn test_full_match_with_compress_and_include() {
+    // INV: full-match with include filter
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "main.rs",
+        "fn main() {\n    println!(\"hello\");\n}\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "pub fn lib_fn() {\n    let x = 1;\n}\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "config.toml",
+        "[package]\nname = \"test\"\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .arg("--full-match")
+        .arg("*")
+        .arg("--include")
+        .arg("rs")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    // All .rs files should be full (because of --full-match '*')
+    assert!(
+        stdout.contains("println!(\"hello\")"),
+        "main.rs body should be preserved with --full-match '*'"
+    );
+    assert!(
+        stdout.contains("let x = 1"),
+        "lib.rs body should be preserved with --full-match '*'"
+    );
+    // .toml should not appear (filtered by --include rs)
+    assert!(
+        !stdout.contains("[package]"),
+        "config.toml should be excluded by --include rs"
+    );
+}
+
+// ============================================================================
+// Human-Friendly Number Parsing Tests
+// ============================================================================
+
+#[test]
+fn test_tokens_suffix_k_lowercase() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("1k")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Token budget:"));
+}
+
+#[test]
+fn test_tokens_suffix_k_uppercase() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("100K")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_tokens_suffix_m() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("1M")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_tokens_plain_number_still_works() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("8000")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_tokens_invalid_suffix_errors() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("abc")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid number"));
+}
+
+#[test]
+fn test_tokens_decimal_not_supported() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("1.5k")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid number"));
+}
+
+#[test]
+fn test_tokens_k_means_1000() {
+    // 1k = 1,000 tokens (decimal), files with ~10 tokens should fit easily
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "small.rs", "fn a() {}\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("1k")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("small.rs"), "File should fit in 1k (1000) token budget");
+}
 
 #[test]
-fn test_workflow_preview_before_share() {
-    // User wants to preview what will be shared
+fn test_max_size_suffix_k() {
+    let temp_dir = TempDir::new().unwrap();
+    // File is 500 bytes, 1k = 1024 bytes — should fit
+    create_test_file(temp_dir.path(), "small.rs", &"x".repeat(500));
+
     flat_cmd()
-        .arg("tests/fixtures/js_project")
-        .arg("--dry-run")
+        .arg(temp_dir.path())
+        .arg("--max-size")
+        .arg("1k")
         .assert()
         .success()
-        .stdout(predicate::str::contains("<summary>"));
+        .stdout(predicate::str::contains("small.rs"));
 }
 
 #[test]
-fn test_workflow_stats_check() {
-    // Quick check of project size
+fn test_max_size_suffix_m() {
     flat_cmd()
         .arg("tests/fixtures/sample_project")
-        .arg("--stats")
+        .arg("--max-size")
+        .arg("10M")
         .assert()
-        .success()
-        .stderr(predicate::str::contains("Total files:"))
-        .stderr(predicate::str::contains("Included:"));
+        .success();
+}
+
+#[test]
+fn test_max_size_plain_number_still_works() {
+    flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .arg("--max-size")
+        .arg("10485760")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_max_size_k_means_1024() {
+    let temp_dir = TempDir::new().unwrap();
+    // File is 1025 bytes — just over 1k (1024) limit
+    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(1025));
+    // File is 500 bytes — fits in 1k
+    create_test_file(temp_dir.path(), "small.rs", &"y".repeat(500));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--max-size")
+        .arg("1k")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(stdout.contains("small.rs"), "500-byte file should fit in 1k (1024)");
+    assert!(stderr.contains("big.rs") && stderr.contains("too-large"),
+        "1025-byte file should exceed 1k (1024) limit");
+}
+
+#[test]
+fn test_max_size_invalid_errors() {
+    flat_cmd()
+        .arg("tests/fixtures/sample_project")
+        .arg("--max-size")
+        .arg("xyz")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid number"));
+}
+
+#[test]
+fn test_tokens_and_max_size_suffixes_together() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--tokens")
+        .arg("8k")
+        .arg("--max-size")
+        .arg("1M")
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_full_match_with_wildcard_matches_all() {
+    // INV-6: --compress + --full-match '*' content = no --compress content (for matched files)
+    let temp_dir = TempDir::new().unwrap();
+
+    create_test_file(
+        temp_dir.path(),
+        "code.rs",
+        "fn compute(x: i32) -> i32 {\n    let result = x * 2 + 1;\n    result\n}\n",
+    );
+
+    // With --compress --full-match '*'
+    let output_full_match = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--compress")
+        .arg("--full-match")
+        .arg("*")
+        .output()
+        .expect("Failed to execute command");
+
+    // Without --compress
+    let output_no_compress = flat_cmd()
+        .arg(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    let full_match_stdout = String::from_utf8_lossy(&output_full_match.stdout);
+    let no_compress_stdout = String::from_utf8_lossy(&output_no_compress.stdout);
+
+    // Both should contain the function body
+    assert!(
+        full_match_stdout.contains("let result = x * 2 + 1"),
+        "Full-match should preserve function body"
+    );
+    assert!(
+        no_compress_stdout.contains("let result = x * 2 + 1"),
+        "No-compress should preserve function body"
+    );
 }
 
 // ============================================================================
-// Snapshot Tests — Pin Known-Good Output (Phase 3D)
+// Output Format Tests
 // ============================================================================
 
 #[test]
-fn test_snapshot_rust_compression() {
+fn test_format_html_report() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "src/main.rs",
+        "fn main() { if a < b {} }\n",
+    );
+
     let output = flat_cmd()
-        .arg("tests/fixtures/snapshot")
+        .arg(temp_dir.path())
+        .args(["--format", "html"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("<!DOCTYPE html>"));
+    assert!(stdout.contains("highlight.min.js"));
+    assert!(stdout.contains("<code class=\"language-rs\">fn main() { if a &lt; b {} }"));
+    assert!(stdout.contains("<h2>Summary</h2>"));
+    assert!(stdout.contains("<a href=\"#f-0\">main.rs</a>"));
+    assert!(!stdout.contains("<file path="));
+}
+
+#[test]
+fn test_format_jsonl_events_streams_one_event_per_line() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {\n    run();\n}\n");
+    create_test_file(temp_dir.path(), "logo.png", "not really a png");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--format")
+        .arg("jsonl-events")
         .arg("--compress")
-        .arg("--include")
-        .arg("rs")
         .output()
         .expect("Failed to execute command");
+
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
-    let expected = fs::read_to_string("tests/fixtures/snapshot/expected_rs.txt").unwrap();
+
+    let events: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("each line should be valid JSON"))
+        .collect();
+    let kinds: Vec<&str> = events
+        .iter()
+        .map(|e| e["event"].as_str().unwrap())
+        .collect();
+
     assert_eq!(
-        stdout.as_ref(),
-        expected.as_str(),
-        "Rust compression output changed from golden file"
+        kinds,
+        vec![
+            "file-skipped",
+            "file-compressed",
+            "file-included",
+            "summary"
+        ]
+    );
+    assert_eq!(events[0]["reason"], "binary-ext");
+    assert!(events.iter().all(|e| e["flat_schema"] == 3));
+    assert_eq!(events[2]["mode"], "compressed");
+    assert_eq!(events[3]["included_files"], 1);
+}
+
+#[test]
+fn test_format_jsonl_events_reports_budget_exclusions() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(900));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--format")
+        .arg("jsonl-events")
+        .arg("--tokens")
+        .arg("10")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains(r#""event":"file-skipped""#));
+    assert!(stdout.contains(r#""reason":"budget""#));
+    assert!(!stdout.contains("<file"));
+}
+
+#[test]
+fn test_budgeted_jsonl_events_match_the_published_schema() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(900));
+    create_test_file(temp_dir.path(), "logo.png", "not really a png");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--format", "jsonl-events", "--tokens", "50"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let schema_output = flat_cmd().arg("--schema").output().unwrap();
+    let schema: serde_json::Value = serde_json::from_slice(&schema_output.stdout).unwrap();
+    let variants = schema["$defs"]["jsonl-event"]["oneOf"].as_array().unwrap();
+
+    let mut reasons = Vec::new();
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let event: serde_json::Value = serde_json::from_str(line).unwrap();
+        let variant = variants
+            .iter()
+            .find(|v| v["properties"]["event"]["const"] == event["event"])
+            .unwrap_or_else(|| panic!("no schema for {}", line));
+        for key in variant["required"].as_array().unwrap() {
+            assert!(
+                event.get(key.as_str().unwrap()).is_some(),
+                "{} in {}",
+                key,
+                line
+            );
+        }
+        for (key, value) in event.as_object().unwrap() {
+            let property = &variant["properties"][key];
+            assert!(!property.is_null(), "{} not in the schema: {}", key, line);
+            if let Some(allowed) = property["enum"].as_array() {
+                assert!(allowed.contains(value), "{} = {} not allowed", key, value);
+            }
+        }
+        if event["event"] == "file-skipped" {
+            reasons.push(event["reason"].as_str().unwrap().to_string());
+        }
+    }
+    reasons.sort();
+    assert_eq!(reasons, ["binary-ext", "budget"]);
+}
+
+#[test]
+fn test_format_json_is_one_document() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "main.rs",
+        "fn main() {\n    println!(\"hi\");\n}\n",
+    );
+    create_test_file(temp_dir.path(), "notes.txt", "plain");
+    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(900));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--format", "json", "--compress", "--tokens", "100"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+
+    assert_eq!(doc["flat_schema"], 3);
+    let files = doc["files"].as_array().unwrap();
+    let by_name = |name: &str| {
+        files
+            .iter()
+            .find(|f| f["path"].as_str().unwrap().ends_with(name))
+            .unwrap()
+    };
+    assert_eq!(by_name("main.rs")["mode"], "compressed");
+    assert!(by_name("main.rs")["content"]
+        .as_str()
+        .unwrap()
+        .contains("{ ... }"));
+    assert_eq!(by_name("notes.txt")["mode"], "full");
+    assert_eq!(by_name("notes.txt")["content"], "plain");
+    assert_eq!(by_name("big.rs")["excluded_reason"], "budget");
+    assert!(by_name("big.rs").get("content").is_none());
+    assert_eq!(doc["summary"]["included_files"], 3);
+}
+
+#[test]
+fn test_format_markdown_fences_files_by_language() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.py", "def main():\n    pass\n");
+    create_test_file(temp_dir.path(), "notes.md", "Run:\n\n```sh\nmake\n```\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--format", "markdown"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.contains("main.py\n\n```python\ndef main():\n    pass\n```\n"));
+    // A longer fence, so the document's own fence doesn't end the block
+    assert!(stdout.contains("notes.md\n\n````markdown\nRun:\n\n```sh\nmake\n```\n````\n"));
+    assert!(stdout.contains("## Summary"));
+    assert!(!stdout.contains("<file"));
+}
+
+#[test]
+fn test_schema_flag_prints_versioned_json_schema() {
+    let output = flat_cmd().arg("--schema").output().unwrap();
+    assert!(output.status.success());
+    let schema: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(schema["flat_schema"], 3);
+    let events = schema["$defs"]["jsonl-event"]["oneOf"].as_array().unwrap();
+    assert!(events
+        .iter()
+        .any(|e| e["properties"]["event"]["const"] == "file-included"));
+}
+
+// ============================================================================
+// Config File Tests
+// ============================================================================
+
+#[test]
+fn test_config_header_and_footer_wrap_output() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(
+        temp_dir.path(),
+        "flat.toml",
+        "header = \"You are reviewing {{path}} (snapshot {{date}}).\"\nfooter = \"END OF CONTEXT\"\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    let expected_header = format!("You are reviewing {} (snapshot ", temp_dir.path().display());
+    assert!(stdout.starts_with(&expected_header));
+    assert!(!stdout.lines().next().unwrap().contains("{{"));
+    assert!(stdout.trim_end().ends_with("END OF CONTEXT"));
+
+    let header_pos = stdout.find("You are reviewing").unwrap();
+    let file_pos = stdout.find("<file path=").unwrap();
+    let summary_pos = stdout.find("<summary>").unwrap();
+    let footer_pos = stdout.rfind("END OF CONTEXT").unwrap();
+    assert!(header_pos < file_pos && file_pos < summary_pos && summary_pos < footer_pos);
+}
+
+#[test]
+fn test_config_invalid_key_errors() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "flat.toml", "hedaer = \"typo\"\n");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid config file"));
+}
+
+#[test]
+fn test_config_defaults_yield_to_cli_flags() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
+    );
+    create_test_file(temp_dir.path(), "notes.md", "# Notes\n");
+    create_test_file(
+        temp_dir.path(),
+        "flat.toml",
+        "[defaults]\ninclude = [\"rs\"]\ncompress = true\nmax-size = \"1M\"\n",
     );
+
+    // File defaults apply when no flag is given
+    flat_cmd()
+        .arg(temp_dir.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-> i32 { ... }"))
+        .stdout(predicate::str::contains("# Notes").not());
+
+    // Flags replace them
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--include", "rs,md", "--no-compress"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("    a + b"))
+        .stdout(predicate::str::contains("# Notes"));
 }
 
+// ============================================================================
+// Overlay Tests
+// ============================================================================
+
 #[test]
-fn test_snapshot_typescript_compression() {
-    let output = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--compress")
-        .arg("--include")
-        .arg("ts")
-        .output()
-        .expect("Failed to execute command");
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let expected = fs::read_to_string("tests/fixtures/snapshot/expected_ts.txt").unwrap();
-    assert_eq!(
-        stdout.as_ref(),
-        expected.as_str(),
-        "TypeScript compression output changed from golden file"
+fn test_overlay_overrides_and_adds_files() {
+    let temp_dir = TempDir::new().unwrap();
+    let overlay_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() { saved(); }\n");
+    create_test_file(temp_dir.path(), "src/lib.rs", "pub fn untouched() {}\n");
+    create_test_file(
+        overlay_dir.path(),
+        "changes.json",
+        r#"{
+            "src/main.rs": "fn main() { unsaved(); }\n",
+            "src/new.rs": "pub fn brand_new() {}\n"
+        }"#,
     );
-}
 
-#[test]
-fn test_snapshot_python_compression() {
     let output = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--compress")
-        .arg("--include")
-        .arg("py")
+        .arg(temp_dir.path())
+        .arg("--overlay")
+        .arg(overlay_dir.path().join("changes.json"))
         .output()
         .expect("Failed to execute command");
+
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
-    let expected = fs::read_to_string("tests/fixtures/snapshot/expected_py.txt").unwrap();
-    assert_eq!(
-        stdout.as_ref(),
-        expected.as_str(),
-        "Python compression output changed from golden file"
-    );
+
+    assert!(stdout.contains("unsaved();"));
+    assert!(!stdout.contains("fn main() { saved(); }"));
+    assert!(stdout.contains("pub fn untouched() {}"));
+    assert!(stdout.contains("src/new.rs"));
+    assert!(stdout.contains("pub fn brand_new() {}"));
+    assert!(stdout.contains("Included: 3"));
 }
 
 #[test]
-fn test_snapshot_go_compression() {
-    let output = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--compress")
-        .arg("--include")
-        .arg("go")
-        .output()
-        .expect("Failed to execute command");
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let expected = fs::read_to_string("tests/fixtures/snapshot/expected_go.txt").unwrap();
-    assert_eq!(
-        stdout.as_ref(),
-        expected.as_str(),
-        "Go compression output changed from golden file"
-    );
+fn test_overlay_invalid_json_errors() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "overlay.json", "[1, 2, 3]");
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--overlay")
+        .arg(temp_dir.path().join("overlay.json"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Invalid overlay file"));
 }
 
 // ============================================================================
-// Mutation-Killing Tests — Cover Surviving Mutants
+// Warning Grouping Tests
 // ============================================================================
 
+fn create_broken_rust_files(dir: &std::path::Path, count: usize) {
+    for i in 0..count {
+        create_test_file(dir, &format!("broken_{}.rs", i), "fn broken( {\n");
+    }
+}
+
 #[test]
-fn test_output_files_in_sorted_order() {
-    // Kills Mutation 8: verifies files appear in lexicographic path order
+fn test_repeated_warnings_are_summarized() {
     let temp_dir = TempDir::new().unwrap();
-
-    create_test_file(temp_dir.path(), "z_last.rs", "fn z() {}");
-    create_test_file(temp_dir.path(), "a_first.rs", "fn a() {}");
-    create_test_file(temp_dir.path(), "m_middle.rs", "fn m() {}");
-    // Subdirectories should also sort correctly
-    create_test_file(temp_dir.path(), "b_dir/nested.rs", "fn n() {}");
+    create_broken_rust_files(temp_dir.path(), 8);
 
     let output = flat_cmd()
         .arg(temp_dir.path())
+        .arg("--compress")
         .output()
         .expect("Failed to execute command");
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    // Extract file paths from <file path="..."> tags
-    let paths: Vec<&str> = stdout
-        .lines()
-        .filter(|l| l.starts_with("<file path="))
-        .collect();
-
-    assert_eq!(paths.len(), 4, "Expected 4 file tags");
-
-    // Verify lexicographic order
-    let a_pos = stdout.find("a_first.rs").expect("a_first.rs not found");
-    let b_pos = stdout
-        .find("b_dir/nested.rs")
-        .expect("b_dir/nested.rs not found");
-    let m_pos = stdout.find("m_middle.rs").expect("m_middle.rs not found");
-    let z_pos = stdout.find("z_last.rs").expect("z_last.rs not found");
-    assert!(
-        a_pos < b_pos && b_pos < m_pos && m_pos < z_pos,
-        "Files not in sorted order: a={}, b_dir={}, m={}, z={}",
-        a_pos,
-        b_pos,
-        m_pos,
-        z_pos
-    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("compression failed for").count(), 5);
+    assert!(stderr.contains("...and 3 more compression failures"));
+    assert!(stderr.contains("rerun with -v for all"));
 }
 
 #[test]
-fn test_compress_fallback_on_syntax_error() {
-    // Kills Mutation 9: verifies parse errors fall back to full content
+fn test_verbose_prints_every_warning() {
     let temp_dir = TempDir::new().unwrap();
-
-    // Deliberately broken Rust syntax
-    let broken_rust = "fn broken( {\n    this is not valid rust\n}\n";
-    create_test_file(temp_dir.path(), "broken.rs", broken_rust);
+    create_broken_rust_files(temp_dir.path(), 8);
 
     let output = flat_cmd()
         .arg(temp_dir.path())
         .arg("--compress")
+        .arg("-v")
         .output()
         .expect("Failed to execute command");
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
     let stderr = String::from_utf8_lossy(&output.stderr);
-
-    // File should still be included (fallback to full content)
-    assert!(
-        stdout.contains("broken.rs"),
-        "broken.rs should be included in output"
-    );
-    assert!(
-        stdout.contains("this is not valid rust"),
-        "Full content should be preserved on parse error"
-    );
-    // Should have mode="full" since compression failed
-    assert!(
-        stdout.contains("mode=\"full\""),
-        "Parse error file should have mode=full"
-    );
-    // Should warn on stderr about parse error
-    assert!(
-        stderr.contains("ERROR") || stderr.contains("error") || stderr.contains("Warning"),
-        "Should warn about parse error on stderr"
-    );
+    assert_eq!(stderr.matches("compression failed for").count(), 8);
+    assert!(!stderr.contains("more compression failures"));
 }
 
 // ============================================================================
-// Coverage Gap Tests — Additional assertions per Phase 4
+// flat.overrides
 // ============================================================================
 
 #[test]
-fn test_compress_rust_preserves_imports_integration() {
-    // Integration-level test for Mutation 3 coverage gap
+fn test_overrides_file_decides_per_glob() {
     let temp_dir = TempDir::new().unwrap();
-
+    let body = "fn helper() -> u32 {\n    let x = 40;\n    x + 2\n}\n";
+    create_test_file(temp_dir.path(), "legacy/old.rs", body);
+    create_test_file(temp_dir.path(), "src/main.rs", body);
+    create_test_file(temp_dir.path(), "tests/out.snap", "snapshot\n");
     create_test_file(
         temp_dir.path(),
-        "lib.rs",
-        "use std::path::Path;\nuse std::io::Read;\n\nfn process(p: &Path) {\n    println!(\"{}\", p.display());\n}\n",
+        "flat.overrides",
+        "'legacy/**' = \"compress\"\n'**/*.snap' = \"skip\"\n",
     );
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--compress")
         .output()
         .expect("Failed to execute command");
 
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
-
-    assert!(
-        stdout.contains("use std::path::Path;"),
-        "use statement should be preserved in compressed output"
-    );
-    assert!(
-        stdout.contains("use std::io::Read;"),
-        "second use statement should be preserved"
-    );
-    assert!(
-        stdout.contains("fn process(p: &Path) { ... }"),
-        "function should show compressed signature"
-    );
-    assert!(
-        !stdout.contains("println!"),
-        "function body should be stripped"
-    );
+    assert!(stdout.contains("old.rs\" mode=\"compressed\""));
+    assert!(stdout.contains("x + 2"), "src/main.rs stays full");
+    assert!(!stdout.contains("out.snap\""));
+    assert!(stdout.contains("override"));
 }
 
 #[test]
-fn test_compress_typescript_export_function() {
-    // Verifies export function declarations are compressed
+fn test_overrides_full_beats_compress_flag() {
     let temp_dir = TempDir::new().unwrap();
-
     create_test_file(
         temp_dir.path(),
-        "api.ts",
-        "export function fetchData(url: string): Promise<Response> {\n  const res = await fetch(url);\n  return res.json();\n}\n",
+        "api.rs",
+        "fn handler() -> u32 {\n    let x = 40;\n    x + 2\n}\n",
     );
+    create_test_file(temp_dir.path(), "flat.overrides", "'api.rs' = \"full\"\n");
 
     let output = flat_cmd()
         .arg(temp_dir.path())
@@ -1292,528 +4043,1297 @@ fn test_compress_typescript_export_function() {
         .expect("Failed to execute command");
 
     let stdout = String::from_utf8_lossy(&output.stdout);
-
-    assert!(
-        stdout.contains("export function fetchData(url: string): Promise<Response> { ... }"),
-        "export function should be compressed: got {}",
-        stdout
-    );
-    assert!(
-        !stdout.contains("await fetch(url)"),
-        "function body should be stripped from export function"
-    );
+    assert!(stdout.contains("api.rs\" mode=\"full\""));
+    assert!(stdout.contains("x + 2"));
 }
 
 #[test]
-fn test_compress_python_module_constants() {
-    // Verifies module-level constants are preserved
+fn test_pragmas_decide_for_their_own_file() {
     let temp_dir = TempDir::new().unwrap();
-
+    let body = "fn helper() -> u32 {\n    let x = 40;\n    x + 2\n}\n";
     create_test_file(
         temp_dir.path(),
-        "config.py",
-        "MAX_SIZE = 1024\nDEBUG = True\n\ndef run():\n    print('running')\n",
+        "core.rs",
+        &format!("// flat:full\n{}", body),
+    );
+    create_test_file(temp_dir.path(), "other.rs", body);
+    create_test_file(
+        temp_dir.path(),
+        "gen.py",
+        "# flat:skip -- generated\nTABLE = [1, 2, 3]\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "legacy.rs",
+        &format!("/* flat:compress */\n{}", body),
     );
 
     let output = flat_cmd()
         .arg(temp_dir.path())
         .arg("--compress")
         .output()
-        .expect("Failed to execute command");
-
+        .unwrap();
     let stdout = String::from_utf8_lossy(&output.stdout);
-
+    assert!(stdout.contains("core.rs\" mode=\"full\""), "{}", stdout);
     assert!(
-        stdout.contains("MAX_SIZE = 1024"),
-        "Module-level constant should be preserved"
+        stdout.contains("other.rs\" mode=\"compressed\""),
+        "{}",
+        stdout
     );
+    assert!(!stdout.contains("TABLE"));
+    assert!(stdout.contains("1 pragma"), "{}", stdout);
+
+    // Compressed even without --compress
+    let output = flat_cmd().arg(temp_dir.path()).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(
-        stdout.contains("DEBUG = True"),
-        "Module-level constant should be preserved"
+        stdout.contains("legacy.rs\" mode=\"compressed\""),
+        "{}",
+        stdout
+    );
+
+    // A flat.overrides rule outranks the pragma
+    create_test_file(
+        temp_dir.path(),
+        "flat.overrides",
+        "'core.rs' = \"compress\"\n",
     );
+    let output = flat_cmd().arg(temp_dir.path()).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
     assert!(
-        !stdout.contains("print('running')"),
-        "Function body should be stripped"
+        stdout.contains("core.rs\" mode=\"compressed\""),
+        "{}",
+        stdout
     );
 }
 
 #[test]
-fn test_priority_ordering_integration() {
-    // Integration-level test for Mutation 6 coverage gap
+fn test_output_to_directory_picks_file_name() {
     let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    let out_dir = TempDir::new().unwrap();
 
-    create_test_file(
-        temp_dir.path(),
-        "README.md",
-        "# Project\nDescription here.\n",
-    );
-    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}\n");
-    create_test_file(
-        temp_dir.path(),
-        "src/deep/nested/util.rs",
-        &"x".repeat(3000),
-    );
-    create_test_file(
-        temp_dir.path(),
-        "Cargo.toml",
-        "[package]\nname = \"test\"\n",
-    );
-
-    // Small budget: should include README and main.rs but exclude deep nested file
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("100")
-        .arg("--dry-run")
+        .arg("-o")
+        .arg(out_dir.path())
         .output()
         .expect("Failed to execute command");
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-
-    // README should be included (priority 100)
-    assert!(
-        stdout.contains("README.md"),
-        "README.md should be in output"
-    );
-    // Deep nested file should be excluded by budget
     assert!(
-        stdout.contains("util.rs") && stdout.contains("[EXCLUDED]"),
-        "Deep nested file should be excluded by budget"
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
     );
+    let written: Vec<_> = fs::read_dir(out_dir.path())
+        .unwrap()
+        .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    assert_eq!(written.len(), 1);
+    assert!(written[0].starts_with("flat-") && written[0].ends_with(".xml"));
 }
 
 #[test]
-fn test_determinism_with_compress() {
-    // Runs flat twice with --compress and verifies identical output
-    let output1 = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--compress")
+fn test_output_trailing_slash_creates_directory() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    let out_dir = TempDir::new().unwrap();
+    let target = format!("{}/exports/", out_dir.path().display());
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["-o", &target, "--format", "html"])
         .output()
         .expect("Failed to execute command");
 
-    let output2 = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--compress")
+    assert!(output.status.success());
+    let written = fs::read_dir(out_dir.path().join("exports"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap();
+    assert!(written.file_name().to_string_lossy().ends_with(".html"));
+}
+
+// ============================================================================
+// --layout sections
+// ============================================================================
+
+#[test]
+fn test_layout_sections_groups_by_role() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "README.md", "# Demo\n");
+    create_test_file(
+        temp_dir.path(),
+        "Cargo.toml",
+        "[package]\nname = \"demo\"\n",
+    );
+    create_test_file(temp_dir.path(), "src/lib.rs", "pub fn a() {}\n");
+    create_test_file(temp_dir.path(), "src/util.rs", "pub fn b() {}\n");
+    create_test_file(temp_dir.path(), "tests/it.rs", "#[test]\nfn t() {}\n");
+    create_test_file(temp_dir.path(), "docs/guide.md", "Guide\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--layout", "sections"])
         .output()
         .expect("Failed to execute command");
 
-    assert_eq!(
-        output1.stdout, output2.stdout,
-        "Compressed output should be deterministic across runs"
-    );
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let positions: Vec<usize> = [
+        "<section name=\"Project metadata\">",
+        "<section name=\"Configuration\">",
+        "<section name=\"Source\">",
+        "<section name=\"Tests\">",
+        "<section name=\"Docs\">",
+    ]
+    .iter()
+    .map(|s| stdout.find(s).unwrap_or_else(|| panic!("missing {}", s)))
+    .collect();
+    assert!(positions.windows(2).all(|w| w[0] < w[1]));
+    assert_eq!(stdout.matches("</section>").count(), 5);
+    assert!(stdout.contains("<subtotal files=\"2\" tokens="));
 }
 
 #[test]
-fn test_determinism_with_tokens() {
-    // Runs flat twice with --tokens and verifies identical output
-    let output1 = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--compress")
-        .arg("--tokens")
-        .arg("5000")
-        .output()
-        .expect("Failed to execute command");
+fn test_layout_sections_with_budget_keeps_sections_contiguous() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "README.md", "# Demo\n");
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "src/deep/util.rs", "pub fn b() {}\n");
 
-    let output2 = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--compress")
-        .arg("--tokens")
-        .arg("5000")
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--layout", "sections", "--tokens", "10k"])
         .output()
         .expect("Failed to execute command");
 
-    assert_eq!(
-        output1.stdout, output2.stdout,
-        "Token-budgeted output should be deterministic across runs"
-    );
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("<section name=\"Source\">").count(), 1);
+    assert!(stdout.find("main.rs").unwrap() < stdout.find("util.rs").unwrap());
 }
 
 #[test]
-fn test_tokens_budget_actually_enforced() {
-    // Phase 5A: Prove token budget is enforced with math
+fn test_split_tokens_cuts_output_into_parts_between_files() {
     let temp_dir = TempDir::new().unwrap();
-
-    // Create files with known sizes
-    create_test_file(temp_dir.path(), "a.rs", &"x".repeat(600)); // ~200 tokens
-    create_test_file(temp_dir.path(), "b.rs", &"y".repeat(600)); // ~200 tokens
-    create_test_file(temp_dir.path(), "c.rs", &"z".repeat(600)); // ~200 tokens
+    let body = "fn f() {}\n".repeat(200);
+    for name in ["a.rs", "b.rs", "c.rs"] {
+        create_test_file(temp_dir.path(), &format!("src/{}", name), &body);
+    }
 
     let output = flat_cmd()
-        .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("250") // Only ~1 file should fit
+        .arg(temp_dir.path().join("src"))
+        .args(["--split-tokens", "1k"])
         .output()
         .expect("Failed to execute command");
-
+    assert!(output.status.success());
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("<part index=").count(), 3);
+    assert!(stdout.contains("</file>\n\n<subtotal files=\"1\""));
 
-    // Count how many <file path= tags appear
-    let file_count = stdout.matches("<file path=").count();
-    assert!(
-        file_count <= 2,
-        "With budget 250 and 3x200-token files, at most 1-2 files should be included, got {}",
-        file_count
+    let out_dir = TempDir::new().unwrap();
+    let template = out_dir.path().join("context-{n}.xml");
+    flat_cmd()
+        .arg(temp_dir.path().join("src"))
+        .args(["--split-tokens", "1500", "-o"])
+        .arg(&template)
+        .assert()
+        .success();
+    let first = fs::read_to_string(out_dir.path().join("context-1.xml")).unwrap();
+    let second = fs::read_to_string(out_dir.path().join("context-2.xml")).unwrap();
+    assert!(first.starts_with("<part index=\"1\">"));
+    assert_eq!(first.matches("<file path=").count(), 2);
+    assert!(second.starts_with("<part index=\"2\">"));
+    assert!(second.contains("<summary>"));
+    assert!(!out_dir.path().join("context-3.xml").exists());
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--split-tokens", "1k", "--format", "json"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("can't be cut into parts"));
+}
+
+#[test]
+fn test_order_priority_without_budget() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "README.md", "# Demo\n");
+    create_test_file(temp_dir.path(), "a_test.rs", "#[test]\nfn t() {}\n");
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "src/deep/util.rs", "pub fn b() {}\n");
+
+    let paths = |extra: &[&str]| {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(["--format", "json"])
+            .args(extra)
+            .output()
+            .expect("Failed to execute command");
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                let path = f["path"].as_str().unwrap();
+                path.rsplit('/').next().unwrap().to_string()
+            })
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(
+        paths(&["--order", "priority"]),
+        ["README.md", "main.rs", "util.rs", "a_test.rs"]
     );
-    // Should have excluded some files by budget
-    assert!(
-        stdout.contains("Excluded by budget"),
-        "Summary should mention excluded files"
+    assert_eq!(paths(&[]), ["README.md", "a_test.rs", "main.rs", "util.rs"]);
+    // A budget writes in priority order unless asked for path order
+    assert_eq!(
+        paths(&["--tokens", "10k", "--order", "path"]),
+        ["README.md", "a_test.rs", "main.rs", "util.rs"]
     );
 }
 
 #[test]
-fn test_compression_ratio_is_real() {
-    // Phase 5C: Verify compression actually reduces output size
-    let full_output = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--include")
-        .arg("rs")
-        .output()
-        .expect("Failed to execute command");
-
-    let compressed_output = flat_cmd()
-        .arg("tests/fixtures/snapshot")
-        .arg("--compress")
-        .arg("--include")
-        .arg("rs")
-        .output()
-        .expect("Failed to execute command");
+fn test_sort_by_size_tokens_and_mtime() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(300));
+    create_test_file(temp_dir.path(), "mid.rs", &"y".repeat(120)); // ~40 tokens
+    create_test_file(temp_dir.path(), "notes.md", &"z".repeat(140)); // ~35 tokens as prose
+    create_test_file(
+        temp_dir.path(),
+        "small.rs",
+        "fn a() {}
+",
+    );
+    let now = std::time::SystemTime::now();
+    for (name, days_ago) in [
+        ("big.rs", 3),
+        ("mid.rs", 1),
+        ("notes.md", 4),
+        ("small.rs", 2),
+    ] {
+        fs::File::options()
+            .write(true)
+            .open(temp_dir.path().join(name))
+            .unwrap()
+            .set_modified(now - std::time::Duration::from_secs(days_ago * 24 * 60 * 60))
+            .unwrap();
+    }
 
-    let full_len = full_output.stdout.len();
-    let compressed_len = compressed_output.stdout.len();
+    let paths = |extra: &[&str]| {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(["--format", "json"])
+            .args(extra)
+            .output()
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                f["path"]
+                    .as_str()
+                    .unwrap()
+                    .rsplit('/')
+                    .next()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect::<Vec<_>>()
+    };
 
-    assert!(
-        compressed_len < full_len,
-        "Compressed output ({} bytes) should be smaller than full ({} bytes)",
-        compressed_len,
-        full_len
+    assert_eq!(
+        paths(&["--sort", "size"]),
+        ["small.rs", "mid.rs", "notes.md", "big.rs"]
     );
-    let reduction_pct = ((full_len - compressed_len) * 100) / full_len;
-    assert!(
-        reduction_pct > 20,
-        "Compression should reduce output by >20%, got {}%",
-        reduction_pct
+    assert_eq!(
+        paths(&["--sort", "tokens"]),
+        ["small.rs", "notes.md", "mid.rs", "big.rs"]
+    );
+    assert_eq!(
+        paths(&["--order", "mtime"]),
+        ["mid.rs", "small.rs", "big.rs", "notes.md"]
     );
+    // Under a budget too, once the files are chosen
+    assert_eq!(
+        paths(&["--tokens", "10k", "--sort", "size"]),
+        ["small.rs", "mid.rs", "notes.md", "big.rs"]
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--dry-run", "--sort", "size"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let small = stdout.find("small.rs").unwrap();
+    let big = stdout.find("big.rs").unwrap();
+    assert!(small < big, "{}", stdout);
 }
 
 #[test]
-fn test_compress_unsupported_extension_passthrough() {
-    // Fallback: unknown extension gets full content
+fn test_summary_first_or_none() {
     let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
 
-    create_test_file(temp_dir.path(), "data.csv", "name,age\nalice,30\nbob,25\n");
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--summary", "first"])
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("<summary>"), "{}", stdout);
+    assert!(stdout.trim_end().ends_with("</file>"), "{}", stdout);
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--compress")
+        .args(["--summary", "first", "--format", "jsonl-events"])
         .output()
-        .expect("Failed to execute command");
+        .unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let first: serde_json::Value = serde_json::from_str(stdout.lines().next().unwrap()).unwrap();
+    assert_eq!(first["event"], "summary", "{}", stdout);
 
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--summary", "none"])
+        .output()
+        .unwrap();
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("<summary>"), "{}", stdout);
+    assert!(stdout.contains("fn main() {}"));
 
-    assert!(
-        stdout.contains("alice,30"),
-        "CSV content should be included in full"
-    );
-    assert!(
-        stdout.contains("mode=\"full\""),
-        "Unsupported file should get mode=full"
-    );
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--summary", "first", "--split-tokens", "1000"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("--summary first"));
 }
 
 #[test]
-fn test_compress_empty_file() {
-    // Fallback: empty file
+fn test_normalize_newlines_drops_carriage_returns() {
     let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}\r\n");
+    // Past the size that is otherwise streamed straight from disk
+    create_test_file(temp_dir.path(), "big.txt", &"line\r\n".repeat(60_000));
 
-    create_test_file(temp_dir.path(), "empty.rs", "");
+    let output = flat_cmd().arg(temp_dir.path()).output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("fn main() {}\r\n"));
+    assert!(stdout.contains("<file path=\"src/main.rs\">"), "{}", stdout);
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--compress")
+        .arg("--normalize-newlines")
         .output()
-        .expect("Failed to execute command");
-
+        .unwrap();
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("fn main() {}\n"), "{}", stdout);
+    assert!(!stdout.contains('\r'));
+    assert!(stdout.contains("--normalize-newlines"));
+}
 
-    // Empty file should still appear
-    assert!(
-        stdout.contains("empty.rs"),
-        "Empty file should be in output"
-    );
+#[test]
+fn test_design_docs_ranked_high_unless_demoted() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "ARCHITECTURE.md", "# Layers\n");
+    create_test_file(temp_dir.path(), "docs/adr/0001-sqlite.md", "# Use SQLite\n");
+    create_test_file(temp_dir.path(), "Cargo.toml", "[package]\n");
+
+    let scores = |extra: &[&str]| {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(["--annotate", "--format", "json", "--tokens", "10k"])
+            .args(extra)
+            .output()
+            .expect("Failed to execute command");
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        json["files"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|f| {
+                let path = f["path"].as_str().unwrap();
+                let name = path.rsplit('/').next().unwrap().to_string();
+                (name, (f["role"].clone(), f["score"].as_u64().unwrap()))
+            })
+            .collect::<std::collections::HashMap<_, _>>()
+    };
+
+    let ranked = scores(&[]);
+    assert_eq!(ranked["0001-sqlite.md"], ("design".into(), 85));
+    assert_eq!(ranked["ARCHITECTURE.md"], ("design".into(), 85));
+    assert_eq!(ranked["Cargo.toml"], ("config".into(), 80));
+
+    let demoted = scores(&["--demote-docs"]);
+    assert_eq!(demoted["ARCHITECTURE.md"], ("design".into(), 20));
+    assert_eq!(demoted["Cargo.toml"], ("config".into(), 80));
 }
 
 #[test]
-fn test_full_match_with_compress_and_include() {
-    // INV: full-match with include filter
+fn test_priority_boost_and_drop_reorder_budget() {
     let temp_dir = TempDir::new().unwrap();
+    let body = "pub fn step() -> u32 {\n    42\n}\n".repeat(25);
+    create_test_file(temp_dir.path(), "src/core/engine.rs", &body);
+    create_test_file(temp_dir.path(), "examples/demo.rs", &body);
+
+    let listing = |extra: &[&str]| {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(["--dry-run", "--tokens", "300"])
+            .args(extra)
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).to_string()
+    };
+
+    // Shallower files rank higher by default
+    let default = listing(&[]);
+    assert!(
+        default.contains("examples/demo.rs mode=full"),
+        "{}",
+        default
+    );
+    assert!(default.contains("src/core/engine.rs excluded-reason=budget"));
 
-    create_test_file(
-        temp_dir.path(),
-        "main.rs",
-        "fn main() {\n    println!(\"hello\");\n}\n",
+    let boosted = listing(&["--priority-boost", "src/core/**=95"]);
+    assert!(
+        boosted.contains("src/core/engine.rs mode=full"),
+        "{}",
+        boosted
     );
+    assert!(boosted.contains("examples/demo.rs excluded-reason=budget"));
+
     create_test_file(
         temp_dir.path(),
-        "lib.rs",
-        "pub fn lib_fn() {\n    let x = 1;\n}\n",
+        "flat.toml",
+        "[defaults]\npriority-drop = [\"examples/**=10\"]\n",
     );
-    create_test_file(
-        temp_dir.path(),
-        "config.toml",
-        "[package]\nname = \"test\"\n",
+    let dropped = listing(&[]);
+    assert!(
+        dropped.contains("src/core/engine.rs mode=full"),
+        "{}",
+        dropped
     );
+    assert!(dropped.contains("examples/demo.rs excluded-reason=budget"));
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--compress")
-        .arg("--full-match")
-        .arg("*")
-        .arg("--include")
-        .arg("rs")
+        .args(["--priority-boost", "src/core/**=high"])
         .output()
         .expect("Failed to execute command");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid --priority-boost rule"));
+}
+
+#[test]
+fn test_annotate_writes_role_and_score() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "README.md", "# Demo\n");
+    create_test_file(temp_dir.path(), "tests/api_test.rs", "fn t() {}\n");
 
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .arg("--annotate")
+        .output()
+        .expect("Failed to execute command");
     let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("README.md\" role=\"readme\" score=\"100\">"));
+    assert!(stdout.contains("api_test.rs\" role=\"test\" score=\"30\">"));
 
-    // All .rs files should be full (because of --full-match '*')
-    assert!(
-        stdout.contains("println!(\"hello\")"),
-        "main.rs body should be preserved with --full-match '*'"
-    );
-    assert!(
-        stdout.contains("let x = 1"),
-        "lib.rs body should be preserved with --full-match '*'"
-    );
-    // .toml should not appear (filtered by --include rs)
-    assert!(
-        !stdout.contains("[package]"),
-        "config.toml should be excluded by --include rs"
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--annotate", "--format", "json", "--tokens", "10k"])
+        .output()
+        .expect("Failed to execute command");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let test_file = json["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|f| f["path"].as_str().unwrap().ends_with("api_test.rs"))
+        .unwrap();
+    assert_eq!(test_file["role"], "test");
+    assert_eq!(test_file["score"], 30);
+
+    let plain = flat_cmd().arg(temp_dir.path()).output().unwrap();
+    assert!(!String::from_utf8_lossy(&plain.stdout).contains("role="));
+}
+
+// ============================================================================
+// --cache and `flat cache`
+// ============================================================================
+
+#[test]
+fn test_cache_reuses_compressed_output_and_reports_stats() {
+    let temp_dir = TempDir::new().unwrap();
+    let cache_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "pub fn add(a: i32, b: i32) -> i32 {\n    a + b\n}\n",
     );
+
+    let run = || {
+        flat_cmd()
+            .env("FLAT_CACHE_DIR", cache_dir.path())
+            .arg(temp_dir.path())
+            .args(["--compress", "--cache"])
+            .output()
+            .expect("Failed to execute command")
+    };
+    let first = run();
+    let second = run();
+    assert!(first.status.success());
+    assert_eq!(first.stdout, second.stdout);
+
+    flat_cmd()
+        .env("FLAT_CACHE_DIR", cache_dir.path())
+        .args(["cache", "stats"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Entries: 1 "));
+
+    flat_cmd()
+        .env("FLAT_CACHE_DIR", cache_dir.path())
+        .args(["cache", "clear"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed 1 entries"));
+}
+
+#[test]
+fn test_subcommand_usage_names_the_flat_command() {
+    for (args, usage) in [
+        (
+            &["cache", "stats", "--bogus"][..],
+            "Usage: flat cache stats",
+        ),
+        (&["doctor", "--bogus"][..], "Usage: flat doctor"),
+        (&["calibrate", "--bogus"][..], "Usage: flat calibrate"),
+        (&["gen-fixture", "--bogus"][..], "Usage: flat gen-fixture"),
+    ] {
+        flat_cmd()
+            .args(args)
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains(usage));
+    }
+}
+
+#[test]
+fn test_doctor_reports_checks() {
+    let cache_dir = TempDir::new().unwrap();
+    flat_cmd()
+        .env("FLAT_CACHE_DIR", cache_dir.path())
+        .arg("doctor")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("grammar rust"))
+        .stdout(predicate::str::contains("end-to-end"))
+        .stdout(predicate::str::contains("FAIL").not());
 }
 
 // ============================================================================
-// Human-Friendly Number Parsing Tests
+// Locking and atomic output
 // ============================================================================
 
 #[test]
-fn test_tokens_suffix_k_lowercase() {
+fn test_output_file_is_replaced_atomically() {
     let temp_dir = TempDir::new().unwrap();
     create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    let out_dir = TempDir::new().unwrap();
+    let out = out_dir.path().join("out.xml");
+    fs::write(&out, "previous").unwrap();
 
     flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("1k")
+        .arg("-o")
+        .arg(&out)
+        .args(["--lock-timeout", "0.5"])
         .assert()
-        .success()
-        .stdout(predicate::str::contains("Token budget:"));
+        .success();
+
+    assert!(fs::read_to_string(&out).unwrap().contains("fn main() {}"));
+    // No temp or lock files left beside the output
+    assert_eq!(fs::read_dir(out_dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_config_compress_rules_keep_and_drop() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        "// noise\nlazy_static! { static ref X: u32 = 1; }\n\npub fn f() -> u32 {\n    let y = 1;\n    y\n}\n",
+    );
+    create_test_file(
+        temp_dir.path(),
+        "flat.toml",
+        "[compress.rust]\nkeep = [\"macro_invocation\"]\ndrop = [\"line_comment\", \"macro_invokation\"]\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--compress", "--include", "rs"])
+        .output()
+        .expect("Failed to execute command");
+
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!stdout.contains("// noise"));
+    assert!(stdout.contains("lazy_static!"));
+    assert!(stdout.contains("pub fn f() -> u32 { ... }"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("'macro_invokation', which is not a rust node kind"));
 }
 
 #[test]
-fn test_tokens_suffix_k_uppercase() {
+fn test_config_compress_rules_unknown_language() {
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "lib.rs", "fn a() {}\n");
+    create_test_file(
+        temp_dir.path(),
+        "flat.toml",
+        "[compress.cobol]\nkeep = [\"x\"]\n",
+    );
 
     flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("100K")
         .assert()
-        .success();
+        .failure()
+        .stderr(predicate::str::contains(
+            "Unknown language in [compress.cobol]",
+        ));
 }
 
 #[test]
-fn test_tokens_suffix_m() {
+fn test_stats_with_output_file_is_rejected() {
     let temp_dir = TempDir::new().unwrap();
     create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    let out = temp_dir.path().join("out.xml");
 
     flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("1M")
+        .arg("--stats")
+        .arg("-o")
+        .arg(&out)
         .assert()
-        .success();
+        .failure()
+        .stderr(predicate::str::contains(
+            "--stats prints its summary to stderr",
+        ));
+    assert!(!out.exists());
 }
 
 #[test]
-fn test_tokens_plain_number_still_works() {
+fn test_gen_fixture_writes_a_flattenable_project() {
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    let project = temp_dir.path().join("fixture");
 
     flat_cmd()
-        .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("8000")
+        .args([
+            "gen-fixture",
+            "--files",
+            "2",
+            "--depth",
+            "1",
+            "--lines",
+            "20",
+        ])
+        .arg(&project)
         .assert()
-        .success();
+        .success()
+        .stdout(predicate::str::contains("Wrote 35 files"));
+    assert!(project.join("src/python/d1/mod1.py").is_file());
+
+    let output = flat_cmd()
+        .arg(&project)
+        .args(["--compress", "--stats"])
+        .output()
+        .expect("Failed to execute command");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Included: 35"), "{}", stderr);
+    assert!(stderr.contains("Compressed: 33 files"), "{}", stderr);
+
+    // Never writes over an existing project
+    flat_cmd()
+        .arg("gen-fixture")
+        .arg(&project)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not empty"));
 }
 
 #[test]
-fn test_tokens_invalid_suffix_errors() {
+fn test_calibrate_writes_token_ratios_the_heuristic_uses() {
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "src/lib.rs", &"fn a() {}\n".repeat(500));
+    create_test_file(temp_dir.path(), "notes.md", "Too short to measure.\n");
+    create_test_file(temp_dir.path(), "flat.toml", "header = \"Context\"\n");
 
     flat_cmd()
+        .arg("calibrate")
+        .arg(temp_dir.path())
+        .arg("--write")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Bytes per token with o200k_base"))
+        .stdout(predicate::str::contains("too little text to keep"))
+        .stdout(predicate::str::contains("Wrote 1 ratios"));
+    let config = fs::read_to_string(temp_dir.path().join("flat.toml")).unwrap();
+    assert!(config.starts_with("header = \"Context\"\n"), "{}", config);
+    assert!(config.contains("[token-ratios]\n"), "{}", config);
+    assert!(config.contains("\nrs = "), "{}", config);
+    assert!(!config.contains("md = "), "{}", config);
+
+    // The heuristic counts .rs files with the ratio in place of bytes/3
+    create_test_file(temp_dir.path(), "flat.toml", "[token-ratios]\nrs = 2\n");
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "100k", "--dry-run", "--include", "rs"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("lib.rs mode=full ~2,500 tokens"));
+
+    flat_cmd()
+        .args(["calibrate", "--tokenizer", "heuristic"])
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("abc")
         .assert()
         .failure()
-        .stderr(predicate::str::contains("invalid number"));
+        .stderr(predicate::str::contains("measures against a BPE tokenizer"));
 }
 
 #[test]
-fn test_tokens_decimal_not_supported() {
+fn test_watch_rewrites_output_when_sources_change() {
+    use std::time::{Duration, Instant};
+
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "src/lib.rs", "pub fn before() {}\n");
+    let output = temp_dir.path().join("context.xml");
 
-    flat_cmd()
+    let mut child = std::process::Command::new(env!("CARGO_BIN_EXE_flat"))
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("1.5k")
+        .arg("--watch")
+        .arg("-o")
+        .arg(&output)
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .unwrap();
+    let wait_for = |needle: &str| {
+        let deadline = Instant::now() + Duration::from_secs(20);
+        while Instant::now() < deadline {
+            if fs::read_to_string(&output).is_ok_and(|xml| xml.contains(needle)) {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        false
+    };
+
+    let first = wait_for("pub fn before()");
+    create_test_file(temp_dir.path(), "src/lib.rs", "pub fn after() {}\n");
+    let rewritten = first && wait_for("pub fn after()");
+    child.kill().unwrap();
+    child.wait().unwrap();
+
+    assert!(first, "output was never written");
+    assert!(rewritten, "output wasn't rewritten after a change");
+    let xml = fs::read_to_string(&output).unwrap();
+    assert!(!xml.contains("before()"));
+    assert!(!xml.contains("context.xml"));
+}
+
+#[test]
+fn test_watch_requires_output() {
+    flat_cmd()
+        .arg("--watch")
         .assert()
         .failure()
-        .stderr(predicate::str::contains("invalid number"));
+        .stderr(predicate::str::contains("--output"));
 }
 
 #[test]
-fn test_tokens_k_means_1000() {
-    // 1k = 1,000 tokens (decimal), files with ~10 tokens should fit easily
+fn test_line_numbers_count_the_written_text() {
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "small.rs", "fn a() {}\n");
+    let body: String = (1..=12)
+        .map(|i| format!("    let x{} = {};\n", i, i))
+        .collect();
+    create_test_file(
+        temp_dir.path(),
+        "lib.rs",
+        &format!("/// Adds\npub fn add() {{\n{}}}\n", body),
+    );
+    create_test_file(temp_dir.path(), "notes.txt", "one\ntwo\n");
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("1k")
+        .args(["--compress", "--line-numbers"])
         .output()
         .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    // Compressed to three lines, numbered as written
+    assert!(
+        stdout.contains("1: /// Adds\n2: \n3: pub fn add() { ... }\n</file>"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("1: one\n2: two\n</file>"));
+    assert!(stdout.contains("Line numbers: on (separator \": \")"));
 
+    let output = flat_cmd()
+        .arg(format!(
+            "{}:2-3",
+            temp_dir.path().join("notes.txt").display()
+        ))
+        .args(["--line-numbers", "--line-number-separator", " | "])
+        .output()
+        .expect("Failed to execute command");
     let stdout = String::from_utf8_lossy(&output.stdout);
-    assert!(stdout.contains("small.rs"), "File should fit in 1k (1000) token budget");
+    assert!(stdout.contains(">\n2 | two\n</file>"), "{}", stdout);
 }
 
 #[test]
-fn test_max_size_suffix_k() {
+fn test_show_skipped_lists_reason_codes() {
     let temp_dir = TempDir::new().unwrap();
-    // File is 500 bytes, 1k = 1024 bytes — should fit
-    create_test_file(temp_dir.path(), "small.rs", &"x".repeat(500));
+    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    create_test_file(temp_dir.path(), "logo.png", "not really a png");
+    create_test_file(temp_dir.path(), "data.bin.txt", "a\0b");
+    create_test_file(temp_dir.path(), "notes.md", "# Notes\n");
 
-    flat_cmd()
+    let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--max-size")
-        .arg("1k")
-        .assert()
-        .success()
-        .stdout(predicate::str::contains("small.rs"));
+        .args(["--exclude", "md", "--show-skipped", "--stats"])
+        .output()
+        .expect("Failed to execute command");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(output.status.success());
+    assert!(stderr.contains("binary-ext\t") && stderr.contains("logo.png\n"));
+    assert!(stderr.contains("binary-content\t") && stderr.contains("data.bin.txt\n"));
+    assert!(stderr.contains("ext-filter\t") && stderr.contains("notes.md\n"));
+    assert!(stderr.contains("(1 binary-content, 1 binary-ext, 1 ext-filter)"));
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--format", "json", "--exclude", "md"])
+        .output()
+        .expect("Failed to execute command");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let reasons = &json["summary"]["skipped_by_reason"];
+    assert_eq!(reasons["binary-ext"], 1);
+    assert_eq!(reasons["binary-content"], 1);
+    assert_eq!(reasons["ext-filter"], 1);
 }
 
 #[test]
-fn test_max_size_suffix_m() {
-    flat_cmd()
-        .arg("tests/fixtures/sample_project")
-        .arg("--max-size")
-        .arg("10M")
-        .assert()
-        .success();
+fn test_exclude_match_drops_paths_by_glob() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "app/models.py", "class User: pass\n");
+    create_test_file(temp_dir.path(), "migrations/0001_init.py", "ops = []\n");
+    create_test_file(temp_dir.path(), "web/api.generated.ts", "export {}\n");
+    create_test_file(temp_dir.path(), "web/api.ts", "export {}\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args([
+            "--exclude-match",
+            "migrations/**,**/*.generated.ts",
+            "--match",
+            "*.py",
+            "--match",
+            "*.ts",
+        ])
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("app/models.py"));
+    assert!(stdout.contains("web/api.ts"));
+    assert!(!stdout.contains("0001_init.py"));
+    assert!(!stdout.contains("api.generated.ts"));
+    assert!(stdout.contains("Skipped: 2 (2 glob-exclude)"), "{}", stdout);
 }
 
 #[test]
-fn test_max_size_plain_number_still_works() {
-    flat_cmd()
-        .arg("tests/fixtures/sample_project")
-        .arg("--max-size")
-        .arg("10485760")
-        .assert()
-        .success();
+fn test_byte_order_marks_are_decoded_and_dropped() {
+    let temp_dir = TempDir::new().unwrap();
+    let mut utf16 = vec![0xFF, 0xFE];
+    utf16.extend(
+        "Write-Host \"héllo\"\r\n"
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes),
+    );
+    fs::write(temp_dir.path().join("greet.ps1"), utf16).unwrap();
+    fs::write(
+        temp_dir.path().join("main.py"),
+        "\u{FEFF}def main():\n    return 1\n",
+    )
+    .unwrap();
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--format", "json"])
+        .output()
+        .expect("Failed to execute command");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files = json["files"].as_array().unwrap();
+    let content = |name: &str| {
+        files
+            .iter()
+            .find(|f| f["path"].as_str().unwrap().ends_with(name))
+            .map(|f| f["content"].as_str().unwrap().to_string())
+    };
+    assert_eq!(content("greet.ps1").unwrap(), "Write-Host \"héllo\"\r\n");
+    assert_eq!(content("main.py").unwrap(), "def main():\n    return 1\n");
+    assert_eq!(json["summary"]["skipped_by_reason"], serde_json::json!({}));
 }
 
 #[test]
-fn test_max_size_k_means_1024() {
+fn test_legacy_encodings_are_transcoded_and_the_rest_skipped() {
     let temp_dir = TempDir::new().unwrap();
-    // File is 1025 bytes — just over 1k (1024) limit
-    create_test_file(temp_dir.path(), "big.rs", &"x".repeat(1025));
-    // File is 500 bytes — fits in 1k
-    create_test_file(temp_dir.path(), "small.rs", &"y".repeat(500));
+    let utf16: Vec<u8> = "Write-Host \"héllo\"\r\n"
+        .encode_utf16()
+        .flat_map(u16::to_le_bytes)
+        .collect();
+    fs::write(temp_dir.path().join("greet.ps1"), utf16).unwrap();
+    fs::write(temp_dir.path().join("notes.txt"), b"caf\xE9 cr\xE8me\n").unwrap();
+    // Over the streaming threshold, so the stream's UTF-8 check has to give way
+    let mut big = b"-- r\xE9sum\xE9\n".to_vec();
+    big.extend(b"SELECT 1;\n".repeat(30_000));
+    fs::write(temp_dir.path().join("big.sql"), big).unwrap();
+    fs::write(temp_dir.path().join("noise.txt"), b"\x01\x02\xFF\x03\n").unwrap();
 
     let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--max-size")
-        .arg("1k")
+        .args(["--format", "json"])
         .output()
         .expect("Failed to execute command");
+    assert!(output.status.success());
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let files = json["files"].as_array().unwrap();
+    let content = |name: &str| {
+        files
+            .iter()
+            .find(|f| f["path"] == name)
+            .map(|f| f["content"].as_str().unwrap().to_string())
+    };
+    assert_eq!(content("greet.ps1").unwrap(), "Write-Host \"héllo\"\r\n");
+    assert_eq!(content("notes.txt").unwrap(), "café crème\n");
+    assert!(content("big.sql").unwrap().starts_with("-- résumé\n"));
+    assert_eq!(content("noise.txt"), None);
+    assert_eq!(json["summary"]["transcoded_files"], 3);
+    assert_eq!(
+        json["summary"]["skipped_by_reason"],
+        serde_json::json!({ "undecodable": 1 })
+    );
 
+    // Under a budget too, and in the text summary
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "200000"])
+        .output()
+        .expect("Failed to execute command");
     let stdout = String::from_utf8_lossy(&output.stdout);
-    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stdout.contains("Transcoded: 3 files to UTF-8"),
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("1 undecodable"), "{}", stdout);
+    assert!(stdout.contains("café crème"), "{}", stdout);
+}
 
-    assert!(stdout.contains("small.rs"), "500-byte file should fit in 1k (1024)");
-    assert!(stderr.contains("big.rs") && stderr.contains("too large"),
-        "1025-byte file should exceed 1k (1024) limit");
+#[test]
+fn test_undecodable_files_count_as_skipped_not_included() {
+    let temp_dir = TempDir::new().unwrap();
+    for name in ["a", "b", "c", "d"] {
+        create_test_file(temp_dir.path(), &format!("docs/{name}.txt"), "text\n");
+    }
+    fs::write(
+        temp_dir.path().join("docs/noise.txt"),
+        b"\x01\x02\xFF\x03\n",
+    )
+    .unwrap();
+
+    for budget in [&[][..], &["--tokens", "10k"][..]] {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(budget)
+            .output()
+            .expect("Failed to execute command");
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(stdout.contains("Total files: 5\n"), "{}", stdout);
+        assert!(stdout.contains("Included: 4 (4 .txt)"), "{}", stdout);
+        assert!(stdout.contains("Skipped: 1 (1 undecodable)"), "{}", stdout);
+
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .args(budget)
+            .args(["--format", "json"])
+            .output()
+            .expect("Failed to execute command");
+        let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+        let summary = &json["summary"];
+        assert_eq!(json["files"].as_array().unwrap().len(), 4);
+        assert_eq!(summary["total_files"], 5);
+        assert_eq!(summary["included_files"], 4);
+        assert_eq!(summary["included_by_extension"]["txt"], 4);
+        assert_eq!(summary["directories"]["docs"]["included"], 4);
+    }
 }
 
 #[test]
-fn test_max_size_invalid_errors() {
+fn test_directory_profiles_scope_rules_to_their_subtree() {
+    let temp_dir = TempDir::new().unwrap();
+    let body = "pub fn run() {\n    let x = 1;\n    println!(\"{}\", x);\n}\n";
+    create_test_file(temp_dir.path(), "src/main.rs", body);
+    create_test_file(temp_dir.path(), "src/legacy/old.rs", body);
+    create_test_file(temp_dir.path(), "src/legacy/keep.rs", body);
+    create_test_file(
+        temp_dir.path(),
+        "src/legacy/.flat.toml",
+        "compress = true\nfull-match = [\"keep.rs\"]\n",
+    );
+    create_test_file(temp_dir.path(), "docs/guide.md", "# Guide\n");
+    create_test_file(temp_dir.path(), "docs/diagram.svg", "<svg/>\n");
+    create_test_file(temp_dir.path(), "docs/drafts/idea.md", "# Idea\n");
+    create_test_file(
+        temp_dir.path(),
+        "docs/.flat.toml",
+        "include = [\"md\"]\nexclude-match = [\"drafts/**\"]\n",
+    );
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("src/main.rs\">\npub fn run() {\n    let x = 1;"));
+    assert!(stdout.contains("src/legacy/old.rs\" mode=\"compressed\">\npub fn run() { ... }"));
+    assert!(stdout.contains("src/legacy/keep.rs\">\npub fn run() {\n    let x = 1;"));
+    assert!(stdout.contains("docs/guide.md"));
+    assert!(!stdout.contains("diagram.svg"));
+    assert!(!stdout.contains("idea.md"));
+    assert!(!stdout.contains(".flat.toml"));
+
+    create_test_file(temp_dir.path(), "docs/.flat.toml", "inclde = [\"md\"]\n");
     flat_cmd()
-        .arg("tests/fixtures/sample_project")
-        .arg("--max-size")
-        .arg("xyz")
+        .arg(temp_dir.path())
         .assert()
         .failure()
-        .stderr(predicate::str::contains("invalid number"));
+        .stderr(predicate::str::contains("Invalid profile"));
 }
 
 #[test]
-fn test_tokens_and_max_size_suffixes_together() {
+fn test_truncate_writes_the_start_of_a_file_over_budget() {
     let temp_dir = TempDir::new().unwrap();
-    create_test_file(temp_dir.path(), "main.rs", "fn main() {}\n");
+    let body: String = (1..=200)
+        .map(|i| format!("pub const LINE_{i}: u32 = {i};\n"))
+        .collect();
+    create_test_file(temp_dir.path(), "src/lib.rs", &body);
+
+    let run = |truncate: bool| {
+        let mut cmd = flat_cmd();
+        cmd.arg(temp_dir.path()).args(["--tokens", "300"]);
+        if truncate {
+            cmd.arg("--truncate");
+        }
+        let output = cmd.output().expect("Failed to execute command");
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let without = run(false);
+    assert!(without.contains("excluded-reason=\"budget\""));
+    assert!(!without.contains("LINE_1:"));
+
+    let stdout = run(true);
+    assert!(stdout.contains("src/lib.rs\" mode=\"truncated\">\npub const LINE_1: u32 = 1;\n"));
+    assert!(!stdout.contains("LINE_200"));
+    let marker = stdout.find("<!-- truncated -->\n</file>").unwrap();
+    assert!(stdout[..marker].ends_with(";\n"));
+    assert!(stdout.contains("Truncated to fit: 1 files"));
+    assert!(!stdout.contains("excluded-reason"));
+}
+
+#[test]
+fn test_entry_prioritizes_files_reachable_through_imports() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/cli.rs", "mod engine;\nfn main() {}\n");
+    create_test_file(temp_dir.path(), "src/engine.rs", "use crate::store;\n");
+    create_test_file(temp_dir.path(), "src/store.rs", "pub struct Store;\n");
+    create_test_file(temp_dir.path(), "src/unrelated.rs", "pub struct Other;\n");
+
+    let output = flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--tokens", "10k", "--annotate", "--entry", "src/cli.rs"])
+        .output()
+        .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("src/cli.rs\" mode=\"full\" role=\"source\" score=\"94\""));
+    assert!(stdout.contains("src/engine.rs\" mode=\"full\" role=\"source\" score=\"92\""));
+    assert!(stdout.contains("src/store.rs\" mode=\"full\" role=\"source\" score=\"90\""));
+    assert!(stdout.contains("src/unrelated.rs\" mode=\"full\" role=\"source\" score=\"60\""));
+    assert!(stdout.find("src/store.rs").unwrap() < stdout.find("src/unrelated.rs").unwrap());
+    assert!(stdout.contains("Reachable from entry: 3 files"));
+}
+
+#[test]
+fn test_model_sets_the_token_budget() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", "fn main() {}\n");
 
     flat_cmd()
         .arg(temp_dir.path())
-        .arg("--tokens")
-        .arg("8k")
-        .arg("--max-size")
-        .arg("1M")
+        .args(["--model", "claude-sonnet"])
         .assert()
-        .success();
+        .success()
+        .stdout(predicate::str::contains("Token budget: 4 / 160.0k used"))
+        .stdout(predicate::str::contains("--tokens 160000"));
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--model", "claude-sonnet", "--headroom", "50k"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("/ 150.0k used"));
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--model", "gpt-9"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("unknown model 'gpt-9'"));
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--model", "gpt-4o", "--tokens", "10k"])
+        .assert()
+        .failure();
 }
 
 #[test]
-fn test_full_match_with_wildcard_matches_all() {
-    // INV-6: --compress + --full-match '*' content = no --compress content (for matched files)
+fn test_summary_reports_coverage_of_text_files() {
     let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "src/main.rs", &"x".repeat(300));
+    create_test_file(temp_dir.path(), "docs/guide.md", &"y".repeat(900));
+    fs::write(temp_dir.path().join("logo.png"), [0x89, b'P', b'N', b'G']).unwrap();
 
-    create_test_file(
-        temp_dir.path(),
-        "code.rs",
-        "fn compute(x: i32) -> i32 {\n    let result = x * 2 + 1;\n    result\n}\n",
-    );
-
-    // With --compress --full-match '*'
-    let output_full_match = flat_cmd()
+    let output = flat_cmd()
         .arg(temp_dir.path())
-        .arg("--compress")
-        .arg("--full-match")
-        .arg("*")
+        .args(["--exclude", "md", "--format", "json"])
         .output()
         .expect("Failed to execute command");
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let coverage = &json["summary"]["coverage"];
+    assert_eq!(coverage["text_files"], 2);
+    assert_eq!(coverage["included_files"], 1);
+    assert_eq!(coverage["included_bytes"], 300);
+    assert_eq!(coverage["text_bytes"], 1200);
 
-    // Without --compress
-    let output_no_compress = flat_cmd()
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--exclude", "md"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Coverage: Included 1/2 text files (50%), 25% of text bytes, 30% of estimated tokens",
+        ));
+}
+
+#[test]
+fn test_max_size_applies_to_compressed_content_under_compress() {
+    let temp_dir = TempDir::new().unwrap();
+    let body: String = (0..200)
+        .map(|i| format!("pub fn f{i}() -> u32 {{\n    let x = {i};\n    x * x + 1\n}}\n\n"))
+        .collect();
+    assert!(body.len() > 8 * 1024);
+    create_test_file(temp_dir.path(), "src/big.rs", &body);
+    let signatures: String = (0..1000).map(|i| format!("pub fn s{i}() {{}}\n")).collect();
+    create_test_file(temp_dir.path(), "src/flat_api.rs", &signatures);
+
+    flat_cmd()
+        .arg(temp_dir.path())
+        .args(["--max-size", "8k"])
+        .assert()
+        .stdout(predicate::str::contains("Skipped: 2 (2 too-large)"));
+
+    let output = flat_cmd()
         .arg(temp_dir.path())
+        .args(["--max-size", "8k", "--compress"])
         .output()
         .expect("Failed to execute command");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("src/big.rs\" mode=\"compressed\">\npub fn f0() -> u32 { ... }"));
+    assert!(!stdout.contains("flat_api.rs\" mode"));
+    assert!(stdout.contains("Skipped: 1 (1 too-large)"));
+}
 
-    let full_match_stdout = String::from_utf8_lossy(&output_full_match.stdout);
-    let no_compress_stdout = String::from_utf8_lossy(&output_no_compress.stdout);
-
-    // Both should contain the function body
+#[test]
+fn test_dry_run_lists_tokens_per_file_and_running_total() {
+    let temp_dir = TempDir::new().unwrap();
+    create_test_file(temp_dir.path(), "a.txt", &"a".repeat(400));
+    let body: String = (0..50)
+        .map(|i| format!("pub fn f{i}() {{\n    let x = {i};\n}}\n"))
+        .collect();
+    create_test_file(temp_dir.path(), "b.rs", &body);
+
+    let listing = |extra: &[&str]| {
+        let output = flat_cmd()
+            .arg(temp_dir.path())
+            .arg("--dry-run")
+            .args(extra)
+            .output()
+            .expect("Failed to execute command");
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+
+    let full_tokens = body.len() / 3;
     assert!(
-        full_match_stdout.contains("let result = x * 2 + 1"),
-        "Full-match should preserve function body"
+        100 + full_tokens < 1000,
+        "counts stay without thousands separators"
     );
+    let plain = listing(&[]);
     assert!(
-        no_compress_stdout.contains("let result = x * 2 + 1"),
-        "No-compress should preserve function body"
+        plain.contains("a.txt ~100 tokens; 100 total\n"),
+        "{}",
+        plain
+    );
+    assert!(plain.contains(&format!(
+        "b.rs ~{} tokens; {} total\n",
+        full_tokens,
+        100 + full_tokens
+    )));
+
+    let compressed = listing(&["--compress"]);
+    let line = compressed.lines().find(|l| l.contains("b.rs")).unwrap();
+    assert!(
+        line.contains(&format!("b.rs ~{} tokens, ~", full_tokens)),
+        "{}",
+        line
+    );
+    assert!(line.contains(" compressed; "));
+
+    let budgeted = listing(&["--tokens", "150"]);
+    assert!(budgeted.contains("a.txt mode=full ~100 tokens; 100 total\n"));
+    assert!(budgeted.contains(&format!(
+        "b.rs excluded-reason=budget ~{} tokens\n",
+        full_tokens
+    )));
+
+    let packed = listing(&["--tokens", "600", "--compress"]);
+    assert!(
+        packed.contains(&format!("b.rs mode=compressed ~{} tokens, ~", full_tokens)),
+        "{}",
+        packed
     );
 }